@@ -0,0 +1,70 @@
+//! Standalone mailbox relay
+//!
+//! Usage: mutaba3a-relay [--config <path>] [--data-dir <path>] [--port <port>]
+
+use app_lib::sync::relay::{RelayConfig, RelayServer};
+use app_lib::sync::supervisor::TaskSupervisor;
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: mutaba3a-relay [--config <path>] [--data-dir <path>] [--port <port>]";
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let mut config = RelayConfig::default();
+    let mut data_dir = PathBuf::from("mutaba3a-relay-data");
+    let mut port: Option<u16> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" | "-c" => match args.next() {
+                Some(path) => {
+                    let content = std::fs::read_to_string(&path)
+                        .unwrap_or_else(|e| exit_with(&format!("Failed to read {}: {}", path, e)));
+                    config = toml::from_str(&content)
+                        .unwrap_or_else(|e| exit_with(&format!("Invalid config: {}", e)));
+                }
+                None => exit_with(USAGE),
+            },
+            "--data-dir" => match args.next() {
+                Some(path) => data_dir = PathBuf::from(path),
+                None => exit_with(USAGE),
+            },
+            "--port" => match args.next().and_then(|p| p.parse().ok()) {
+                Some(p) => port = Some(p),
+                None => exit_with(USAGE),
+            },
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            other => exit_with(&format!("Unknown argument '{}'\n{}", other, USAGE)),
+        }
+    }
+    if let Some(port) = port {
+        config.port = port;
+    }
+
+    let tasks = TaskSupervisor::default();
+    let (mut relay, port) = RelayServer::start(&data_dir, config, &tasks)
+        .await
+        .unwrap_or_else(|e| exit_with(&e));
+    println!(
+        "mutaba3a-relay: listening on port {} (data: {})",
+        port,
+        data_dir.display()
+    );
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        eprintln!("Failed to listen for Ctrl+C: {}", e);
+    }
+    println!("mutaba3a-relay: stopping");
+    relay.stop();
+    tasks.shutdown().await;
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
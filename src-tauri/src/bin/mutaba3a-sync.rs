@@ -0,0 +1,450 @@
+//! Command-line sync client
+//!
+//! Talks to a desktop (or `mutaba3a-syncd`) like a mobile device would:
+//! discover peers, pair, push ops from JSONL, pull ops into JSONL, directly
+//! or through a mailbox relay.
+
+use app_lib::sync::client::{
+    self, base_url, parse_pair_url, ClientState, KeyExchange, PeerState, SyncClient,
+};
+use app_lib::sync::discovery::discover_peers;
+use app_lib::sync::mailbox::{PairMailbox, RelayClient};
+use clap::{Args, Parser, Subcommand};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// The server rejects pushes with more ops than this
+const MAX_PUSH_BATCH: usize = 1000;
+/// Ops per relay message (keeps messages under the relay's size limit)
+const RELAY_BATCH_OPS: usize = 200;
+
+#[derive(Parser)]
+#[command(name = "mutaba3a-sync", about = "Command-line client for LAN sync")]
+struct Cli {
+    /// State file with this client's device id, the paired desktop and the pull cursor
+    #[arg(long, global = true, default_value = "mutaba3a-sync.json")]
+    state: PathBuf,
+
+    /// Use this host instead of the paired desktop's address
+    #[arg(long, global = true)]
+    host: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List sync peers advertised over mDNS
+    Discover {
+        /// Seconds to listen for peers
+        #[arg(long, default_value_t = 3)]
+        timeout: u64,
+    },
+    /// Pair with a desktop using its 6-digit code or QR URL
+    Pair(PairArgs),
+    /// Push ops from a JSONL file ("-" for stdin)
+    Push {
+        file: PathBuf,
+        #[arg(long, default_value_t = 500)]
+        batch_size: usize,
+    },
+    /// Pull new ops and append them to a JSONL file (stdout by default)
+    Pull {
+        #[arg(long)]
+        out: Option<PathBuf>,
+        #[arg(long)]
+        max_ops: Option<usize>,
+        /// Pull from the beginning instead of the saved cursor
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Show the paired desktop, the pull cursor and whether the desktop is reachable
+    Status,
+    /// Deposit ops from a JSONL file ("-" for stdin) in the desktop's relay mailbox
+    RelayPush {
+        /// Relay base URL, e.g. http://relay.example.com:4280
+        #[arg(long)]
+        relay: String,
+        file: PathBuf,
+    },
+    /// Fetch ops the desktop left at the relay, append them to a JSONL file (stdout by default)
+    /// and confirm receipt
+    RelayPull {
+        #[arg(long)]
+        relay: String,
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct PairArgs {
+    /// Code shown in the desktop's pairing dialog
+    #[arg(long)]
+    code: Option<String>,
+    /// `mini-crm://pair?...` URL from the QR code
+    #[arg(long)]
+    qr: Option<String>,
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Discover { timeout } => discover(timeout).await,
+        Command::Pair(ref args) => pair(&cli, args).await,
+        Command::Push {
+            ref file,
+            batch_size,
+        } => push(&cli, file, batch_size).await,
+        Command::Pull {
+            ref out,
+            max_ops,
+            reset,
+        } => pull(&cli, out.as_deref(), max_ops, reset).await,
+        Command::Status => status(&cli).await,
+        Command::RelayPush {
+            ref relay,
+            ref file,
+        } => relay_push(&cli, relay, file).await,
+        Command::RelayPull { ref relay, ref out } => relay_pull(&cli, relay, out.as_deref()).await,
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn discover(timeout: u64) -> Result<(), String> {
+    let peers = discover_peers(timeout).await?;
+    if peers.is_empty() {
+        println!("No peers found");
+    }
+    for peer in peers {
+        println!(
+            "{}\t{}\t{}\t{}",
+            base_url(&format!("{}:{}", bracket_ipv6(&peer.address), peer.port)),
+            peer.device_id,
+            peer.device_type,
+            peer.name
+        );
+    }
+    Ok(())
+}
+
+async fn pair(cli: &Cli, args: &PairArgs) -> Result<(), String> {
+    let mut state = ClientState::load_or_create(&cli.state)?;
+
+    let (url, response, exchange) = if let Some(qr) = &args.qr {
+        let pair_url = parse_pair_url(qr)?;
+        let hosts = match &cli.host {
+            Some(host) => vec![base_url(host)],
+            None => pair_url
+                .hosts
+                .iter()
+                .map(|h| base_url(&format!("{}:{}", bracket_ipv6(h), pair_url.port)))
+                .collect(),
+        };
+        let url = first_reachable(&hosts, &state.device_id).await?;
+
+        let client = SyncClient::new(&url, &state.device_id, None);
+        let exchange = client.exchange_keys_with_url(&pair_url).await?;
+        print_sas(&exchange);
+        let response = client
+            .pair_with_url(&pair_url, &state.device_name, &exchange)
+            .await?;
+        (url, response, exchange)
+    } else {
+        let code = args.code.as_deref().unwrap_or_default();
+        let url = match &cli.host {
+            Some(host) => base_url(host),
+            None => single_discovered_peer().await?,
+        };
+        let client = SyncClient::new(&url, &state.device_id, None);
+        let exchange = client.exchange_keys_with_code(code).await?;
+        print_sas(&exchange);
+        let response = client
+            .pair_with_code(code, &state.device_name, &exchange)
+            .await?;
+        (url, response, exchange)
+    };
+
+    println!("Paired with {} ({})", response.desktop_device_id, url);
+    state.peer = Some(PeerState {
+        base_url: url,
+        desktop_device_id: response.desktop_device_id,
+        token: response.session_token,
+        since_seq: 0,
+        since_hlc: String::new(),
+        relay_seq: 0,
+        pairing_secret: Some(exchange.pairing_secret),
+    });
+    state.save(&cli.state)
+}
+
+fn print_sas(exchange: &KeyExchange) {
+    println!(
+        "Check that the desktop shows: {}  {}",
+        exchange.sas.number,
+        exchange.sas.emoji.join(" ")
+    );
+    println!("Waiting for the desktop to allow this device...");
+}
+
+async fn push(cli: &Cli, file: &Path, batch_size: usize) -> Result<(), String> {
+    let state = ClientState::load_or_create(&cli.state)?;
+    let mut client = connect(cli, &state)?;
+    client.negotiate().await?;
+
+    let ops = read_ops(file)?;
+
+    let batch_size = batch_size.clamp(1, MAX_PUSH_BATCH);
+    let mut accepted = 0;
+    let mut rejected = 0;
+    for (i, batch) in ops.chunks(batch_size).enumerate() {
+        let response = client.push(batch.to_vec()).await?;
+        accepted += response.accepted;
+        rejected += response.rejected.len();
+        for op in response.rejected {
+            eprintln!(
+                "Rejected op {}: {}",
+                i * batch_size + op.index + 1,
+                op.reason
+            );
+        }
+    }
+
+    println!(
+        "Pushed {} ops: {} accepted, {} rejected",
+        ops.len(),
+        accepted,
+        rejected
+    );
+    Ok(())
+}
+
+async fn pull(
+    cli: &Cli,
+    out: Option<&Path>,
+    max_ops: Option<usize>,
+    reset: bool,
+) -> Result<(), String> {
+    let mut state = ClientState::load_or_create(&cli.state)?;
+    let mut client = connect(cli, &state)?;
+    client.negotiate().await?;
+    let mut output = open_output(out)?;
+
+    let peer = state.require_peer()?;
+    let (mut since_seq, mut since_hlc) = if reset {
+        (0, String::new())
+    } else {
+        (peer.since_seq, peer.since_hlc.clone())
+    };
+
+    let mut pulled = 0;
+    loop {
+        let page = client.pull(Some(since_seq), &since_hlc, max_ops).await?;
+        for op in &page.operations {
+            writeln!(output, "{}", op).map_err(|e| e.to_string())?;
+        }
+        output.flush().map_err(|e| e.to_string())?;
+        pulled += page.operations.len();
+
+        // Save the cursor after every page so an interrupted pull resumes
+        if let Some(next_seq) = page.next_seq {
+            since_seq = next_seq.max(0) as u64;
+        }
+        if let Some(next_hlc) = page.next_hlc {
+            since_hlc = next_hlc;
+        }
+        if let Some(peer) = state.peer.as_mut() {
+            peer.since_seq = since_seq;
+            peer.since_hlc = since_hlc.clone();
+        }
+        state.save(&cli.state)?;
+
+        if !page.has_more {
+            eprintln!("Pulled {} ops (cursor: {})", pulled, since_seq);
+            return Ok(());
+        }
+    }
+}
+
+async fn status(cli: &Cli) -> Result<(), String> {
+    let state = ClientState::load_or_create(&cli.state)?;
+    println!("Device: {} ({})", state.device_name, state.device_id);
+
+    let Some(peer) = &state.peer else {
+        println!("Not paired");
+        return Ok(());
+    };
+    println!("Paired with: {}", peer.desktop_device_id);
+    println!("Cursor: seq {}, hlc '{}'", peer.since_seq, peer.since_hlc);
+
+    let client = connect(cli, &state)?;
+    match client.hello().await {
+        Ok(hello) => println!(
+            "Desktop: {} '{}' at {} (version {})",
+            hello.status,
+            hello.device_name,
+            client.base_url(),
+            hello.version
+        ),
+        Err(e) => println!("Desktop: unreachable ({})", e),
+    }
+    Ok(())
+}
+
+/// Mailbox keys shared with the paired desktop
+fn peer_mailbox(state: &ClientState, peer: &PeerState) -> Result<PairMailbox, String> {
+    let secret = peer.pairing_secret.as_deref().ok_or_else(|| {
+        "Paired without a key exchange, run `mutaba3a-sync pair` again to use a relay".to_string()
+    })?;
+    Ok(PairMailbox::new(
+        secret,
+        &state.device_id,
+        &peer.desktop_device_id,
+    ))
+}
+
+async fn relay_push(cli: &Cli, relay: &str, file: &Path) -> Result<(), String> {
+    let state = ClientState::load_or_create(&cli.state)?;
+    let peer = state.require_peer()?;
+    let mailbox = peer_mailbox(&state, peer)?;
+    let relay = RelayClient::new(relay);
+
+    let ops = read_ops(file)?;
+    for ops in ops.chunks(RELAY_BATCH_OPS) {
+        let mut batch = mailbox.batch(ops.to_vec());
+        batch.acked_seq = (peer.relay_seq > 0).then_some(peer.relay_seq);
+        relay
+            .deposit(&mailbox.outbox_id, mailbox.seal(&batch)?)
+            .await?;
+    }
+
+    println!("Deposited {} ops for {}", ops.len(), peer.desktop_device_id);
+    Ok(())
+}
+
+async fn relay_pull(cli: &Cli, relay: &str, out: Option<&Path>) -> Result<(), String> {
+    let mut state = ClientState::load_or_create(&cli.state)?;
+    let peer = state.require_peer()?.clone();
+    let mailbox = peer_mailbox(&state, &peer)?;
+    let relay = RelayClient::new(relay);
+    let mut output = open_output(out)?;
+
+    let mut pulled = 0;
+    let mut received_seq = peer.relay_seq;
+    loop {
+        let page = relay.receive(&mailbox).await?;
+        let Some(last_seq) = page.last_seq else {
+            break;
+        };
+        for batch in &page.batches {
+            for op in &batch.ops {
+                writeln!(output, "{}", op).map_err(|e| e.to_string())?;
+                pulled += 1;
+            }
+            received_seq = received_seq.max(batch.through_seq.unwrap_or(0));
+        }
+        output.flush().map_err(|e| e.to_string())?;
+        relay.ack_inbox(&mailbox, last_seq).await?;
+        if !page.has_more {
+            break;
+        }
+    }
+
+    // Confirm receipt so the desktop can stop keeping these ops
+    if received_seq > peer.relay_seq {
+        if let Some(peer) = state.peer.as_mut() {
+            peer.relay_seq = received_seq;
+        }
+        state.save(&cli.state)?;
+        let mut batch = mailbox.batch(Vec::new());
+        batch.acked_seq = Some(received_seq);
+        relay
+            .deposit(&mailbox.outbox_id, mailbox.seal(&batch)?)
+            .await?;
+    }
+
+    eprintln!("Fetched {} ops from the relay", pulled);
+    Ok(())
+}
+
+/// Ops from a JSONL file, or stdin for "-"
+fn read_ops(file: &Path) -> Result<Vec<serde_json::Value>, String> {
+    if file == Path::new("-") {
+        return client::read_ops_jsonl(std::io::stdin().lock());
+    }
+    let f = std::fs::File::open(file)
+        .map_err(|e| format!("Failed to open {}: {}", file.display(), e))?;
+    client::read_ops_jsonl(BufReader::new(f))
+}
+
+/// Append to `out`, or write to stdout
+fn open_output(out: Option<&Path>) -> Result<Box<dyn Write>, String> {
+    Ok(match out {
+        Some(path) => Box::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
+
+/// Client for the paired desktop, or for `--host` if given
+fn connect(cli: &Cli, state: &ClientState) -> Result<SyncClient, String> {
+    let peer = state.require_peer()?;
+    let url = match &cli.host {
+        Some(host) => base_url(host),
+        None => peer.base_url.clone(),
+    };
+    Ok(SyncClient::new(&url, &state.device_id, Some(&peer.token)))
+}
+
+/// First host that answers /v1/sync/hello
+async fn first_reachable(hosts: &[String], device_id: &str) -> Result<String, String> {
+    for url in hosts {
+        match SyncClient::new(url, device_id, None).hello().await {
+            Ok(_) => return Ok(url.clone()),
+            Err(e) => log::warn!("{}", e),
+        }
+    }
+    Err(format!(
+        "None of the desktop's addresses answered: {}",
+        hosts.join(", ")
+    ))
+}
+
+/// The only peer on the network, for pairing without --host
+async fn single_discovered_peer() -> Result<String, String> {
+    let peers = discover_peers(3).await?;
+    match peers.as_slice() {
+        [peer] => Ok(base_url(&format!(
+            "{}:{}",
+            bracket_ipv6(&peer.address),
+            peer.port
+        ))),
+        [] => Err("No peers found, pass --host".to_string()),
+        _ => Err(format!(
+            "{} peers found, pass --host (see `mutaba3a-sync discover`)",
+            peers.len()
+        )),
+    }
+}
+
+fn bracket_ipv6(address: &str) -> String {
+    if address.parse::<std::net::Ipv6Addr>().is_ok() {
+        format!("[{}]", address)
+    } else {
+        address.to_string()
+    }
+}
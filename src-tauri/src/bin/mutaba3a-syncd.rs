@@ -0,0 +1,45 @@
+//! Headless sync daemon
+//!
+//! Usage: mutaba3a-syncd [--config <path>] [--pair]
+
+use app_lib::sync::daemon::{self, DaemonConfig};
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: mutaba3a-syncd [--config <path>] [--pair]";
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let mut config_path: Option<PathBuf> = None;
+    let mut pair = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" | "-c" => match args.next() {
+                Some(path) => config_path = Some(PathBuf::from(path)),
+                None => exit_with(USAGE),
+            },
+            "--pair" => pair = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            other => exit_with(&format!("Unknown argument '{}'\n{}", other, USAGE)),
+        }
+    }
+
+    let config = match config_path {
+        Some(path) => DaemonConfig::load(&path).unwrap_or_else(|e| exit_with(&e)),
+        None => DaemonConfig::default(),
+    };
+
+    if let Err(e) = daemon::run(config, pair).await {
+        exit_with(&e);
+    }
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
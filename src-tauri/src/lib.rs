@@ -1,11 +1,17 @@
-mod sync;
+pub mod sync;
 
 use sync::commands::{
-    cancel_pairing_session, clear_pending_sync_ops, decrypt_bundle, discover_lan_peers,
-    encrypt_bundle, get_hostname, get_local_sync_ops_count, get_paired_devices, get_pairing_status,
-    get_pending_sync_ops, get_sync_server_port, is_sync_server_running, revoke_paired_device,
-    start_pairing_session, start_sync_server, stop_sync_server, store_local_sync_op, SyncState,
+    ack_pending_sync_ops, approve_pairing, cancel_pairing_session, decrypt_bundle, deny_pairing,
+    discover_lan_peers, encrypt_bundle, forget_trusted_network, get_current_network, get_hostname,
+    get_legacy_route_usage, get_local_sync_ops_count, get_network_addresses, get_paired_devices,
+    get_pairing_status, get_pending_sync_ops, get_sync_audit_log, get_sync_folder,
+    get_sync_server_port, get_sync_settings, get_sync_status, get_sync_task_health,
+    get_trusted_networks, is_sync_server_running, revoke_paired_device, run_sync_diagnostics,
+    set_sync_auto_start, set_sync_diagnostics, start_folder_sync, start_pairing_session,
+    start_sync_server, stop_folder_sync, stop_sync_server, store_local_sync_op, sync_via_relay,
+    trust_current_network, update_sync_settings, SyncState,
 };
+use tauri::Manager;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -13,14 +19,27 @@ pub fn run() {
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_process::init())
         .plugin(tauri_plugin_fs::init())
-        .manage(SyncState::default())
         .invoke_handler(tauri::generate_handler![
             // Sync server
             start_sync_server,
             stop_sync_server,
             is_sync_server_running,
             get_sync_server_port,
+            get_sync_status,
+            get_legacy_route_usage,
+            get_sync_task_health,
+            get_network_addresses,
             discover_lan_peers,
+            run_sync_diagnostics,
+            // Settings
+            get_sync_settings,
+            update_sync_settings,
+            // Trusted networks
+            get_current_network,
+            get_trusted_networks,
+            trust_current_network,
+            forget_trusted_network,
+            set_sync_auto_start,
             // Encryption
             encrypt_bundle,
             decrypt_bundle,
@@ -30,23 +49,51 @@ pub fn run() {
             start_pairing_session,
             get_pairing_status,
             cancel_pairing_session,
+            approve_pairing,
+            deny_pairing,
             get_paired_devices,
             revoke_paired_device,
+            // Audit
+            get_sync_audit_log,
+            set_sync_diagnostics,
             // Sync ops (incoming from mobile)
             get_pending_sync_ops,
-            clear_pending_sync_ops,
+            ack_pending_sync_ops,
             // Local ops (outgoing to mobile)
             store_local_sync_op,
             get_local_sync_ops_count,
+            // Mailbox relay
+            sync_via_relay,
+            // Sync folder
+            start_folder_sync,
+            stop_folder_sync,
+            get_sync_folder,
         ])
         .setup(|app| {
-            if cfg!(debug_assertions) {
-                app.handle().plugin(
-                    tauri_plugin_log::Builder::default()
-                        .level(log::LevelFilter::Info)
-                        .build(),
-                )?;
-            }
+            // Log first so everything from setup on is captured. Let this
+            // crate through at any level: the level in the sync settings
+            // filters globally and can change at runtime
+            app.handle().plugin(
+                tauri_plugin_log::Builder::default()
+                    .level(log::LevelFilter::Info)
+                    .level_for("app_lib", log::LevelFilter::Trace)
+                    .build(),
+            )?;
+
+            let config_dir = app.path().app_config_dir()?;
+            app.manage(SyncState::new(config_dir, Some(app.handle().clone()))?);
+            let log_level = app
+                .state::<SyncState>()
+                .settings
+                .get()
+                .map(|settings| settings.log_level.filter())
+                .unwrap_or(log::LevelFilter::Info);
+            log::set_max_level(log_level);
+
+            // Start and stop the sync server as trusted networks come and go
+            let runtime = tauri::async_runtime::handle();
+            let _runtime = runtime.inner().enter();
+            sync::trusted::spawn_watcher(app.handle().clone());
             Ok(())
         })
         .run(tauri::generate_context!())
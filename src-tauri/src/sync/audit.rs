@@ -0,0 +1,394 @@
+//! Sync Audit Log
+//!
+//! Persistent record of pairing and sync activity on the LAN server.
+//! File location: {app_config_dir}/sync_audit.jsonl (one JSON entry per line)
+//!
+//! Entries record who did what, when and from where, plus operation counts.
+//! Operation payloads are never written to the audit log.
+
+use super::clock::{Clock, SystemClock};
+use super::persistence::PersistenceError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+const AUDIT_LOG_FILE: &str = "sync_audit.jsonl";
+const MAX_LOG_BYTES: u64 = 1024 * 1024; // Rotate after 1 MiB
+const MAX_ROTATED_FILES: usize = 3;
+const DEFAULT_QUERY_LIMIT: usize = 200;
+
+// ============================================================================
+// Types
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventKind {
+    PairingStarted,
+    PairingSucceeded,
+    PairingFailed,
+    DeviceRevoked,
+    Pull,
+    Push,
+    OpsRejected,
+    AuthFailed,
+}
+
+/// A single audit log entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    /// Set by `AuditLog::record`
+    pub timestamp: String,
+    pub kind: AuditEventKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_addr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_count: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authenticated: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(kind: AuditEventKind) -> Self {
+        Self {
+            timestamp: String::new(),
+            kind,
+            device_id: None,
+            device_name: None,
+            remote_addr: None,
+            op_count: None,
+            authenticated: None,
+            reason: None,
+        }
+    }
+
+    pub fn device(mut self, device_id: &str, device_name: Option<&str>) -> Self {
+        self.device_id = Some(device_id.to_string());
+        self.device_name = device_name.map(|n| n.to_string());
+        self
+    }
+
+    pub fn remote(mut self, remote_addr: impl ToString) -> Self {
+        self.remote_addr = Some(remote_addr.to_string());
+        self
+    }
+
+    pub fn ops(mut self, count: usize) -> Self {
+        self.op_count = Some(count);
+        self
+    }
+
+    pub fn authenticated(mut self, authenticated: bool) -> Self {
+        self.authenticated = Some(authenticated);
+        self
+    }
+
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    fn parsed_timestamp(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(&self.timestamp)
+            .ok()
+            .map(|t| t.with_timezone(&Utc))
+    }
+}
+
+/// Filter for querying the audit log (all fields optional)
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditFilter {
+    pub device_id: Option<String>,
+    pub kinds: Option<Vec<AuditEventKind>>,
+    /// RFC 3339 lower bound (inclusive)
+    pub since: Option<String>,
+    /// RFC 3339 upper bound (inclusive)
+    pub until: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl AuditFilter {
+    fn matches(
+        &self,
+        entry: &AuditEntry,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> bool {
+        if let Some(device_id) = &self.device_id {
+            if entry.device_id.as_deref() != Some(device_id.as_str()) {
+                return false;
+            }
+        }
+        if let Some(kinds) = &self.kinds {
+            if !kinds.contains(&entry.kind) {
+                return false;
+            }
+        }
+        if since.is_some() || until.is_some() {
+            let Some(ts) = entry.parsed_timestamp() else {
+                return false;
+            };
+            if since.is_some_and(|s| ts < s) || until.is_some_and(|u| ts > u) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// ============================================================================
+// AuditLog
+// ============================================================================
+
+pub struct AuditLog {
+    config_dir: PathBuf,
+    write_lock: Mutex<()>,
+    clock: Arc<dyn Clock>,
+}
+
+impl AuditLog {
+    /// Create an audit log stored in the given config directory
+    pub fn new(config_dir: PathBuf) -> Result<Arc<Self>, PersistenceError> {
+        Self::with_clock(config_dir, Arc::new(SystemClock))
+    }
+
+    /// Audit log timestamping entries with `clock`
+    pub fn with_clock(
+        config_dir: PathBuf,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Arc<Self>, PersistenceError> {
+        std::fs::create_dir_all(&config_dir)?;
+
+        Ok(Arc::new(Self {
+            config_dir,
+            write_lock: Mutex::new(()),
+            clock,
+        }))
+    }
+
+    /// Path of the active log file
+    fn file_path(&self) -> PathBuf {
+        self.config_dir.join(AUDIT_LOG_FILE)
+    }
+
+    /// Path of a rotated log file (1 = most recent)
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        self.config_dir.join(format!("sync_audit.{}.jsonl", index))
+    }
+
+    /// Timestamp and append an entry. Failures are logged, never propagated
+    /// to the sync request.
+    pub async fn record(&self, mut entry: AuditEntry) {
+        entry.timestamp = self.clock.now().to_rfc3339();
+        if let Err(e) = self.append(&entry).await {
+            log::error!("Failed to write sync audit entry: {}", e);
+        }
+    }
+
+    async fn append(&self, entry: &AuditEntry) -> Result<(), PersistenceError> {
+        let _guard = self.write_lock.lock().await;
+
+        self.rotate_if_needed().await?;
+
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.file_path())
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    /// Shift sync_audit.jsonl -> .1 -> .2 ... once the active file is too large
+    async fn rotate_if_needed(&self) -> Result<(), PersistenceError> {
+        let path = self.file_path();
+        let size = match tokio::fs::metadata(&path).await {
+            Ok(meta) => meta.len(),
+            Err(_) => return Ok(()),
+        };
+        if size < MAX_LOG_BYTES {
+            return Ok(());
+        }
+
+        let oldest = self.rotated_path(MAX_ROTATED_FILES);
+        if oldest.exists() {
+            tokio::fs::remove_file(&oldest).await?;
+        }
+        for index in (1..MAX_ROTATED_FILES).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                tokio::fs::rename(&from, self.rotated_path(index + 1)).await?;
+            }
+        }
+        tokio::fs::rename(&path, self.rotated_path(1)).await?;
+
+        Ok(())
+    }
+
+    /// Query entries matching the filter, newest first
+    pub async fn query(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>, PersistenceError> {
+        let since = parse_bound(filter.since.as_deref());
+        let until = parse_bound(filter.until.as_deref());
+        let limit = filter.limit.unwrap_or(DEFAULT_QUERY_LIMIT);
+
+        let _guard = self.write_lock.lock().await;
+
+        // Oldest file first so the combined list is chronological
+        let mut paths: Vec<PathBuf> = (1..=MAX_ROTATED_FILES)
+            .rev()
+            .map(|i| self.rotated_path(i))
+            .collect();
+        paths.push(self.file_path());
+
+        let mut entries = Vec::new();
+        for path in paths {
+            if !path.exists() {
+                continue;
+            }
+            let content = tokio::fs::read_to_string(&path).await?;
+            for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                match serde_json::from_str::<AuditEntry>(line) {
+                    Ok(entry) if filter.matches(&entry, since, until) => entries.push(entry),
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Skipping malformed sync audit entry: {}", e),
+                }
+            }
+        }
+
+        entries.reverse();
+        entries.truncate(limit);
+        Ok(entries)
+    }
+}
+
+fn parse_bound(value: Option<&str>) -> Option<DateTime<Utc>> {
+    value
+        .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+        .map(|t| t.with_timezone(&Utc))
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::clock::ManualClock;
+    use tempfile::tempdir;
+
+    fn create_test_log() -> Arc<AuditLog> {
+        let dir = tempdir().unwrap();
+        AuditLog::new(dir.keep()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_record_and_query_newest_first() {
+        let log = create_test_log();
+
+        log.record(
+            AuditEntry::new(AuditEventKind::Push)
+                .device("phone-1", None)
+                .ops(3),
+        )
+        .await;
+        log.record(
+            AuditEntry::new(AuditEventKind::Pull)
+                .device("phone-1", None)
+                .ops(7),
+        )
+        .await;
+
+        let entries = log.query(&AuditFilter::default()).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].kind, AuditEventKind::Pull);
+        assert_eq!(entries[0].op_count, Some(7));
+        assert_eq!(entries[1].kind, AuditEventKind::Push);
+    }
+
+    #[tokio::test]
+    async fn test_entries_are_timestamped_by_the_clock() {
+        let clock = ManualClock::new();
+        let log = AuditLog::with_clock(tempdir().unwrap().keep(), clock.clone()).unwrap();
+
+        clock.advance(std::time::Duration::from_secs(3600));
+        log.record(AuditEntry::new(AuditEventKind::Pull)).await;
+
+        let entries = log.query(&AuditFilter::default()).await.unwrap();
+        assert_eq!(entries[0].timestamp, clock.now().to_rfc3339());
+    }
+
+    #[tokio::test]
+    async fn test_query_filters() {
+        let log = create_test_log();
+
+        log.record(AuditEntry::new(AuditEventKind::Push).device("phone-1", None))
+            .await;
+        log.record(AuditEntry::new(AuditEventKind::Push).device("phone-2", None))
+            .await;
+        log.record(AuditEntry::new(AuditEventKind::AuthFailed).remote("10.0.0.9:5000"))
+            .await;
+
+        let by_device = AuditFilter {
+            device_id: Some("phone-2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&by_device).await.unwrap().len(), 1);
+
+        let by_kind = AuditFilter {
+            kinds: Some(vec![AuditEventKind::AuthFailed]),
+            ..Default::default()
+        };
+        let failures = log.query(&by_kind).await.unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].remote_addr.as_deref(), Some("10.0.0.9:5000"));
+
+        let future = AuditFilter {
+            since: Some((Utc::now() + chrono::Duration::hours(1)).to_rfc3339()),
+            ..Default::default()
+        };
+        assert!(log.query(&future).await.unwrap().is_empty());
+
+        let limited = AuditFilter {
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&limited).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rotation_keeps_entries_queryable() {
+        let log = create_test_log();
+
+        // Write enough entries to force at least one rotation
+        let reason = "x".repeat(1024);
+        let count = (MAX_LOG_BYTES as usize / 1024) + 10;
+        for _ in 0..count {
+            log.record(AuditEntry::new(AuditEventKind::Push).reason(reason.clone()))
+                .await;
+        }
+
+        assert!(log.rotated_path(1).exists());
+        let filter = AuditFilter {
+            limit: Some(usize::MAX),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&filter).await.unwrap().len(), count);
+    }
+}
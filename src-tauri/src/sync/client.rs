@@ -0,0 +1,772 @@
+//! Sync Client
+//!
+//! HTTP client for the `/v1/pair/*` and `/v1/sync/*` API. Backs the
+//! `mutaba3a-sync` command-line tool, which pairs with a desktop, pushes and
+//! pulls ops like a mobile device would, and is used to debug sync issues and
+//! script data imports.
+//!
+//! The tool keeps its device id, the paired desktop and its pull cursor in a
+//! JSON state file, so repeated runs continue where the last one stopped.
+//!
+//! After `negotiate`, pull/push use CBOR bodies and zstd-compressed requests
+//! when the server supports them (see `encoding`); responses are decompressed
+//! transparently.
+
+use super::encoding::{Format, CBOR_CONTENT_TYPE};
+use super::pairing::{
+    PairConfirmRequest, PairConfirmResponse, PairKeyRequest, PairKeyResponse, PairingMethod,
+};
+use super::protocol::{FEATURES, OLDEST_PROTOCOL_VERSION, PROTOCOL_HEADER, PROTOCOL_VERSION};
+use super::sas::{self, KeyPair, Sas, SasTranscript};
+use super::server::{HelloResponse, PullRequest, PullResponse, PushRequest, PushResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+use std::path::Path;
+use std::time::Duration;
+
+const DEFAULT_PORT: u16 = 4242;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// The desktop holds `/pair/confirm` open until the user allows the device
+const PAIR_TIMEOUT: Duration = Duration::from_secs(90);
+/// Attempts per request when the server answers 429
+const MAX_ATTEMPTS: u32 = 5;
+/// Request bodies smaller than this are sent uncompressed
+const COMPRESS_MIN_BYTES: usize = 1024;
+
+// ============================================================================
+// State
+// ============================================================================
+
+/// Identity of this client and the desktop it is paired with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientState {
+    pub device_id: String,
+    pub device_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer: Option<PeerState>,
+}
+
+/// Paired desktop and the pull cursor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerState {
+    pub base_url: String,
+    pub desktop_device_id: String,
+    pub token: String,
+    /// Last sequence number pulled (sent as `since_seq`)
+    #[serde(default)]
+    pub since_seq: u64,
+    /// HLC of the last op pulled
+    #[serde(default)]
+    pub since_hlc: String,
+    /// Highest desktop op sequence number received through the relay (sent
+    /// back as `ackedSeq`)
+    #[serde(default)]
+    pub relay_seq: u64,
+    /// Secret from the pairing key exchange, which keys the relay mailboxes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pairing_secret: Option<String>,
+}
+
+impl ClientState {
+    /// Load the state file, creating a new identity if it doesn't exist
+    pub fn load_or_create(path: &Path) -> Result<Self, String> {
+        if path.exists() {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            return serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid state file {}: {}", path.display(), e));
+        }
+
+        let device_name = hostname::get()
+            .ok()
+            .and_then(|h| h.into_string().ok())
+            .map(|h| format!("{} (cli)", h))
+            .unwrap_or_else(|| "mutaba3a-sync".to_string());
+        Ok(Self {
+            device_id: uuid::Uuid::new_v4().to_string(),
+            device_name,
+            peer: None,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+
+        // Atomic write: write to temp file, then rename
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, content)
+            .and_then(|_| std::fs::rename(&temp_path, path))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// The paired desktop, or an error telling the user to pair first
+    pub fn require_peer(&self) -> Result<&PeerState, String> {
+        self.peer
+            .as_ref()
+            .ok_or_else(|| "Not paired yet, run `mutaba3a-sync pair` first".to_string())
+    }
+}
+
+// ============================================================================
+// Pairing URL
+// ============================================================================
+
+/// Contents of a `mini-crm://pair?...` QR code
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairUrl {
+    pub hosts: Vec<String>,
+    pub port: u16,
+    pub pairing_id: String,
+    pub nonce: String,
+    pub exp: i64,
+    pub desktop_device_id: Option<String>,
+}
+
+/// Parse the URL encoded in the desktop's pairing QR code
+pub fn parse_pair_url(url: &str) -> Result<PairUrl, String> {
+    let query = url
+        .strip_prefix("mini-crm://pair?")
+        .ok_or_else(|| "Not a pairing URL (expected mini-crm://pair?...)".to_string())?;
+
+    let mut hosts = None;
+    let mut port = None;
+    let mut pairing_id = None;
+    let mut nonce = None;
+    let mut exp = None;
+    let mut desktop_device_id = None;
+    for pair in query.split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = urlencoding::decode(value)
+            .map_err(|_| format!("Invalid value for '{}'", key))?
+            .into_owned();
+        match key {
+            "hosts" => {
+                hosts = Some(
+                    value
+                        .split(',')
+                        .filter(|h| !h.is_empty())
+                        .map(|h| h.to_string())
+                        .collect::<Vec<_>>(),
+                )
+            }
+            "port" => port = value.parse().ok(),
+            "pairingId" => pairing_id = Some(value),
+            "nonce" => nonce = Some(value),
+            "exp" => exp = value.parse().ok(),
+            "desktopId" => desktop_device_id = Some(value),
+            _ => {}
+        }
+    }
+
+    let missing = |name: &str| format!("Pairing URL is missing '{}'", name);
+    Ok(PairUrl {
+        hosts: hosts
+            .filter(|h| !h.is_empty())
+            .ok_or_else(|| missing("hosts"))?,
+        port: port.ok_or_else(|| missing("port"))?,
+        pairing_id: pairing_id.ok_or_else(|| missing("pairingId"))?,
+        nonce: nonce.ok_or_else(|| missing("nonce"))?,
+        exp: exp.ok_or_else(|| missing("exp"))?,
+        desktop_device_id,
+    })
+}
+
+/// `http://host:port` for a host with or without scheme and port
+pub fn base_url(host: &str) -> String {
+    let host = host.trim_end_matches('/');
+    if let Ok(ip) = host.parse::<std::net::Ipv6Addr>() {
+        return format!("http://[{}]:{}", ip, DEFAULT_PORT);
+    }
+    let with_scheme = if host.contains("://") {
+        host.to_string()
+    } else {
+        format!("http://{}", host)
+    };
+
+    // Only the authority part can carry a port
+    let authority = with_scheme.split("://").nth(1).unwrap_or_default();
+    let has_port = authority.contains(':') && !authority.ends_with(']');
+    if has_port {
+        with_scheme
+    } else {
+        format!("{}:{}", with_scheme, DEFAULT_PORT)
+    }
+}
+
+// ============================================================================
+// JSONL
+// ============================================================================
+
+/// Read one op per line, skipping blank lines
+pub fn read_ops_jsonl(reader: impl BufRead) -> Result<Vec<serde_json::Value>, String> {
+    let mut ops = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read line {}: {}", index + 1, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let op: serde_json::Value = serde_json::from_str(&line)
+            .map_err(|e| format!("Invalid JSON on line {}: {}", index + 1, e))?;
+        if !op.is_object() {
+            return Err(format!("Line {} is not an op object", index + 1));
+        }
+        ops.push(op);
+    }
+    Ok(ops)
+}
+
+// ============================================================================
+// SyncClient
+// ============================================================================
+
+/// Outcome of `/v1/pair/key`: the SAS to show before confirming, and the
+/// secret to keep once paired
+#[derive(Debug, Clone)]
+pub struct KeyExchange {
+    pub pairing_id: String,
+    pub desktop_device_id: String,
+    pub sas: Sas,
+    pub pairing_secret: String,
+    /// Own public key, revealed in `/v1/pair/confirm`
+    public_key: Vec<u8>,
+}
+
+pub struct SyncClient {
+    http: reqwest::Client,
+    base_url: String,
+    device_id: String,
+    token: Option<String>,
+    /// Pull/push body format
+    format: Format,
+    /// Whether the server accepts zstd request bodies
+    compress_requests: bool,
+    /// Protocol version sent with every request
+    protocol_version: u32,
+}
+
+impl SyncClient {
+    pub fn new(base_url: &str, device_id: &str, token: Option<&str>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            device_id: device_id.to_string(),
+            token: token.map(|t| t.to_string()),
+            format: Format::Json,
+            compress_requests: false,
+            protocol_version: OLDEST_PROTOCOL_VERSION,
+        }
+    }
+
+    /// Client for the paired desktop
+    pub fn for_peer(state: &ClientState) -> Result<Self, String> {
+        let peer = state.require_peer()?;
+        Ok(Self::new(
+            &peer.base_url,
+            &state.device_id,
+            Some(&peer.token),
+        ))
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// GET /v1/sync/hello
+    pub async fn hello(&self) -> Result<HelloResponse, String> {
+        self.send(
+            || self.http.get(self.url("/v1/sync/hello")),
+            REQUEST_TIMEOUT,
+        )
+        .await
+    }
+
+    /// GET /v1/sync/hello with the protocol versions and features this client
+    /// supports, then use the agreed version and the most compact encoding
+    pub async fn negotiate(&mut self) -> Result<HelloResponse, String> {
+        let max_version = PROTOCOL_VERSION.to_string();
+        let min_version = OLDEST_PROTOCOL_VERSION.to_string();
+        let features = FEATURES.join(",");
+        let hello: HelloResponse = self
+            .send(
+                || {
+                    self.http.get(self.url("/v1/sync/hello")).query(&[
+                        ("minVersion", min_version.as_str()),
+                        ("maxVersion", max_version.as_str()),
+                        ("features", features.as_str()),
+                    ])
+                },
+                REQUEST_TIMEOUT,
+            )
+            .await?;
+        self.protocol_version = hello.protocol_version.unwrap_or(OLDEST_PROTOCOL_VERSION);
+        if hello.content_types.iter().any(|t| t == CBOR_CONTENT_TYPE) {
+            self.format = Format::Cbor;
+        }
+        self.compress_requests = hello.content_encodings.iter().any(|e| e == "zstd");
+        Ok(hello)
+    }
+
+    /// POST /v1/pair/key with a 6-digit code
+    pub async fn exchange_keys_with_code(&self, code: &str) -> Result<KeyExchange, String> {
+        self.exchange_keys(PairKeyRequest {
+            pairing_id: None,
+            method: PairingMethod::Code,
+            code: Some(code.to_string()),
+            nonce: None,
+            key_commitment: String::new(),
+        })
+        .await
+    }
+
+    /// POST /v1/pair/key with the contents of a QR code
+    pub async fn exchange_keys_with_url(&self, url: &PairUrl) -> Result<KeyExchange, String> {
+        self.exchange_keys(PairKeyRequest {
+            pairing_id: Some(url.pairing_id.clone()),
+            method: PairingMethod::QR,
+            code: None,
+            nonce: Some(url.nonce.clone()),
+            key_commitment: String::new(),
+        })
+        .await
+    }
+
+    /// Commit to a fresh key, then derive the SAS and pairing secret from the
+    /// desktop key the server reveals
+    async fn exchange_keys(&self, mut request: PairKeyRequest) -> Result<KeyExchange, String> {
+        let key_pair = KeyPair::generate()?;
+        request.key_commitment = sas::commitment(key_pair.public_key());
+        let response: PairKeyResponse = self
+            .send(
+                || self.http.post(self.url("/v1/pair/key")).json(&request),
+                REQUEST_TIMEOUT,
+            )
+            .await?;
+
+        let desktop_public_key = URL_SAFE_NO_PAD
+            .decode(&response.desktop_public_key)
+            .map_err(|_| "Invalid desktop public key".to_string())?;
+        let public_key = key_pair.public_key().to_vec();
+        let shared_secret = key_pair.agree(&desktop_public_key)?;
+        let transcript = SasTranscript {
+            pairing_id: &response.pairing_id,
+            desktop_device_id: &response.desktop_device_id,
+            mobile_device_id: &self.device_id,
+            desktop_public_key: &desktop_public_key,
+            mobile_public_key: &public_key,
+            shared_secret: &shared_secret,
+        };
+        Ok(KeyExchange {
+            sas: sas::derive(&transcript),
+            pairing_secret: sas::pairing_secret(&transcript),
+            pairing_id: response.pairing_id,
+            desktop_device_id: response.desktop_device_id,
+            public_key,
+        })
+    }
+
+    /// POST /v1/pair/confirm with a 6-digit code, revealing the exchanged key
+    pub async fn pair_with_code(
+        &self,
+        code: &str,
+        device_name: &str,
+        exchange: &KeyExchange,
+    ) -> Result<PairConfirmResponse, String> {
+        self.pair(&PairConfirmRequest {
+            pairing_id: Some(exchange.pairing_id.clone()),
+            method: PairingMethod::Code,
+            code: Some(code.to_string()),
+            nonce: None,
+            device_name: device_name.to_string(),
+            device_id: self.device_id.clone(),
+            public_key: Some(URL_SAFE_NO_PAD.encode(&exchange.public_key)),
+        })
+        .await
+    }
+
+    /// POST /v1/pair/confirm with the contents of a QR code, revealing the
+    /// exchanged key
+    pub async fn pair_with_url(
+        &self,
+        url: &PairUrl,
+        device_name: &str,
+        exchange: &KeyExchange,
+    ) -> Result<PairConfirmResponse, String> {
+        self.pair(&PairConfirmRequest {
+            pairing_id: Some(url.pairing_id.clone()),
+            method: PairingMethod::QR,
+            code: None,
+            nonce: Some(url.nonce.clone()),
+            device_name: device_name.to_string(),
+            device_id: self.device_id.clone(),
+            public_key: Some(URL_SAFE_NO_PAD.encode(&exchange.public_key)),
+        })
+        .await
+    }
+
+    async fn pair(&self, request: &PairConfirmRequest) -> Result<PairConfirmResponse, String> {
+        self.send(
+            || self.http.post(self.url("/v1/pair/confirm")).json(request),
+            PAIR_TIMEOUT,
+        )
+        .await
+    }
+
+    /// POST /v1/sync/push
+    pub async fn push(&self, ops: Vec<serde_json::Value>) -> Result<PushResponse, String> {
+        let request = PushRequest {
+            device_id: self.device_id.clone(),
+            ops,
+        };
+        self.send_sync("/v1/sync/push", &request).await
+    }
+
+    /// POST /v1/sync/pull; without `since_seq` the server goes by
+    /// `since_hlc` alone, as for apps from before seq cursors
+    pub async fn pull(
+        &self,
+        since_seq: Option<u64>,
+        since_hlc: &str,
+        max_ops: Option<usize>,
+    ) -> Result<PullResponse, String> {
+        let request = PullRequest {
+            device_id: self.device_id.clone(),
+            since_hlc: since_hlc.to_string(),
+            since_seq: since_seq.map(|seq| seq as i64),
+            max_ops,
+        };
+        self.send_sync("/v1/sync/pull", &request).await
+    }
+
+    /// POST a pull/push body in the negotiated encoding
+    async fn send_sync<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        request: &impl Serialize,
+    ) -> Result<T, String> {
+        let mut body = self.format.encode(request)?;
+        let compressed = self.compress_requests && body.len() >= COMPRESS_MIN_BYTES;
+        if compressed {
+            body = zstd::encode_all(body.as_slice(), 0).map_err(|e| e.to_string())?;
+        }
+        self.send(
+            || {
+                let mut request = self
+                    .http
+                    .post(self.url(path))
+                    .header(reqwest::header::CONTENT_TYPE, self.format.content_type())
+                    .header(reqwest::header::ACCEPT, self.format.content_type())
+                    .body(body.clone());
+                if compressed {
+                    request = request.header(reqwest::header::CONTENT_ENCODING, "zstd");
+                }
+                self.authorized(request)
+            },
+            REQUEST_TIMEOUT,
+        )
+        .await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn authorized(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Send a request, waiting out 429 responses, and decode the JSON or CBOR body
+    async fn send<T: serde::de::DeserializeOwned>(
+        &self,
+        build: impl Fn() -> reqwest::RequestBuilder,
+        timeout: Duration,
+    ) -> Result<T, String> {
+        let mut attempt = 1;
+        loop {
+            let response = build()
+                .header(PROTOCOL_HEADER, self.protocol_version)
+                .timeout(timeout)
+                .send()
+                .await
+                .map_err(|e| format!("Request to {} failed: {}", self.base_url, e))?;
+            let status = response.status();
+
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS && attempt < MAX_ATTEMPTS {
+                let wait = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1);
+                log::warn!("Rate limited, retrying in {}s", wait);
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+                attempt += 1;
+                continue;
+            }
+
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                return Err(if body.is_empty() {
+                    format!("Server answered {}", status)
+                } else {
+                    format!("Server answered {}: {}", status, body)
+                });
+            }
+
+            let format = Format::of_body(response.headers());
+            let bytes = response
+                .bytes()
+                .await
+                .map_err(|e| format!("Invalid response: {}", e))?;
+            return format
+                .decode(&bytes)
+                .map_err(|e| format!("Invalid response: {}", e));
+        }
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::inbox::PendingOpsStore;
+    use crate::sync::oplog::OpLog;
+    use crate::sync::pairing::PairingStatus;
+    use crate::sync::persistence::{PairedDevice, PairedDeviceStatus};
+    use crate::sync::protocol::{ProtocolConfig, UpgradeRequired};
+    use crate::sync::server::{ServerOptions, SyncServer};
+    use crate::sync::status::{SyncPhase, SyncStatusTracker};
+    use serde_json::json;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    /// Pair `device_id` with the server; returns its token
+    async fn pair(server: &SyncServer, device_id: &str) -> String {
+        let token = format!("token-{}", device_id);
+        server
+            .persistence
+            .add_device(PairedDevice {
+                id: device_id.to_string(),
+                name: device_id.to_string(),
+                token: token.clone(),
+                paired_at: chrono::Utc::now().to_rfc3339(),
+                last_sync_at: None,
+                status: PairedDeviceStatus::Active,
+                acked_seq: 0,
+                relay_seq: 0,
+                relay_acked_seq: 0,
+                relay_deposited_at: None,
+                pairing_secret: None,
+            })
+            .await
+            .unwrap();
+        token
+    }
+
+    #[test]
+    fn test_parse_pair_url() {
+        let url = "mini-crm://pair?v=1&hosts=192.168.1.5%2C10.0.0.2&port=4243\
+                   &pairingId=abc&nonce=n%2B1&exp=1700000000&desktopId=desk-1";
+        let parsed = parse_pair_url(url).unwrap();
+        assert_eq!(parsed.hosts, vec!["192.168.1.5", "10.0.0.2"]);
+        assert_eq!(parsed.port, 4243);
+        assert_eq!(parsed.pairing_id, "abc");
+        assert_eq!(parsed.nonce, "n+1");
+        assert_eq!(parsed.desktop_device_id.as_deref(), Some("desk-1"));
+
+        assert!(parse_pair_url("mini-crm://pair?v=1&port=4242").is_err());
+        assert!(parse_pair_url("https://example.com").is_err());
+    }
+
+    #[test]
+    fn test_base_url_and_jsonl() {
+        assert_eq!(base_url("192.168.1.5"), "http://192.168.1.5:4242");
+        assert_eq!(base_url("192.168.1.5:4300"), "http://192.168.1.5:4300");
+        assert_eq!(base_url("http://hub.local/"), "http://hub.local:4242");
+        assert_eq!(base_url("fe80::1"), "http://[fe80::1]:4242");
+
+        let input = "{\"id\":\"a\"}\n\n{\"id\":\"b\"}\n";
+        let ops = read_ops_jsonl(input.as_bytes()).unwrap();
+        assert_eq!(ops.len(), 2);
+
+        let err = read_ops_jsonl("{\"id\":\"a\"}\nnot json\n".as_bytes()).unwrap_err();
+        assert!(err.contains("line 2"));
+    }
+
+    #[tokio::test]
+    async fn test_push_then_pull_from_another_device() {
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let status = SyncStatusTracker::new(Arc::clone(&pending_ops), Arc::clone(&op_log), None);
+        let options = ServerOptions::new(
+            "desktop-1",
+            "Desktop",
+            config_dir.clone(),
+            Arc::clone(&pending_ops),
+            Arc::clone(&op_log),
+            Arc::clone(&status),
+        );
+        let (mut server, port) = SyncServer::start(options, &Default::default())
+            .await
+            .unwrap();
+        let url = format!("http://127.0.0.1:{}", port);
+        status.listening(port, None).await;
+
+        // Pull and push need a paired device's token
+        let stranger = SyncClient::new(&url, "cli-1", None);
+        assert_eq!(stranger.hello().await.unwrap().device_id, "desktop-1");
+        assert!(stranger.pull(Some(0), "", None).await.is_err());
+        assert!(stranger.push(vec![json!({ "id": "op-0" })]).await.is_err());
+
+        let importer = SyncClient::new(&url, "cli-1", Some(&pair(&server, "cli-1").await));
+        let op = json!({ "id": "op-1", "hlc": "1", "entityType": "client", "createdBy": "cli-1" });
+        let pushed = importer.push(vec![op, json!("not an op")]).await.unwrap();
+        assert_eq!(pushed.accepted, 1);
+        assert_eq!(pushed.rejected.len(), 1);
+        assert_eq!(pushed.rejected[0].index, 1);
+
+        // The push shows up in the status the UI reads
+        let snapshot = status.snapshot().await;
+        assert_eq!(snapshot.phase, SyncPhase::Listening);
+        assert_eq!(snapshot.connected_devices[0].device_id, "cli-1");
+        assert_eq!((snapshot.pending_inbound, snapshot.pending_outbound), (1, 1));
+
+        // The pushing device doesn't get its own op back; another device does
+        let own = importer.pull(Some(0), "", None).await.unwrap();
+        assert!(own.operations.is_empty());
+        let phone = SyncClient::new(&url, "phone-1", Some(&pair(&server, "phone-1").await));
+        let page = phone.pull(Some(0), "", None).await.unwrap();
+        assert_eq!(page.operations.len(), 1);
+        assert_eq!(page.operations[0]["id"], "op-1");
+
+        // Same exchange in CBOR with compressed request bodies
+        let mut tablet = SyncClient::new(&url, "tablet-1", Some(&pair(&server, "tablet-1").await));
+        let hello = tablet.negotiate().await.unwrap();
+        assert_eq!(hello.protocol_version, Some(PROTOCOL_VERSION));
+        assert_eq!(tablet.format, Format::Cbor);
+        let ops = (0..20)
+            .map(|i| json!({ "id": format!("t-{}", i), "hlc": "2", "note": "x".repeat(100) }))
+            .collect();
+        assert_eq!(tablet.push(ops).await.unwrap().accepted, 20);
+        let page = tablet.pull(Some(0), "", None).await.unwrap();
+        assert_eq!(page.operations[0]["id"], "op-1");
+        let page = phone.pull(Some(0), "", None).await.unwrap();
+        assert_eq!(page.operations.len(), 21);
+
+        server.stop();
+    }
+
+    #[tokio::test]
+    async fn test_code_pairing_agrees_on_sas_and_secret() {
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let status = SyncStatusTracker::new(Arc::clone(&pending_ops), Arc::clone(&op_log), None);
+        let options = ServerOptions::new(
+            "desktop-1",
+            "Desktop",
+            config_dir.clone(),
+            Arc::clone(&pending_ops),
+            Arc::clone(&op_log),
+            status,
+        );
+        let (server, port) = SyncServer::start(options, &Default::default())
+            .await
+            .unwrap();
+        let session = server
+            .pairing_manager
+            .create_session(Vec::new(), port)
+            .await
+            .unwrap();
+
+        let client = SyncClient::new(&format!("http://127.0.0.1:{}", port), "cli-1", None);
+        let exchange = client.exchange_keys_with_code(&session.code).await.unwrap();
+        assert_eq!(exchange.desktop_device_id, "desktop-1");
+        let confirm = tokio::spawn(async move {
+            client
+                .pair_with_code(&session.code, "CLI", &exchange)
+                .await
+                .map(|response| (response, exchange))
+        });
+
+        // The desktop shows the same SAS before the user allows the device
+        let pairing = loop {
+            let pairing = server
+                .pairing_manager
+                .get_status(&session.pairing_id)
+                .await
+                .unwrap();
+            if pairing.status == PairingStatus::AwaitingApproval {
+                break pairing;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        server
+            .pairing_manager
+            .approve(&session.pairing_id, true)
+            .await
+            .unwrap();
+        let (response, exchange) = confirm.await.unwrap().unwrap();
+        assert_eq!(pairing.sas, Some(exchange.sas.clone()));
+
+        assert_eq!(response.desktop_device_id, "desktop-1");
+
+        let device = server
+            .persistence
+            .get_device("cli-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(device.pairing_secret, Some(exchange.pairing_secret));
+    }
+
+    #[tokio::test]
+    async fn test_retired_legacy_routes_answer_upgrade_required() {
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let options = ServerOptions {
+            protocol: ProtocolConfig {
+                legacy_routes: false,
+                ..Default::default()
+            },
+            ..ServerOptions::new(
+                "desktop-1",
+                "Desktop",
+                config_dir.clone(),
+                Arc::clone(&pending_ops),
+                Arc::clone(&op_log),
+                SyncStatusTracker::new(pending_ops, op_log, None),
+            )
+        };
+        let (mut server, port) = SyncServer::start(options, &Default::default())
+            .await
+            .unwrap();
+        let url = format!("http://127.0.0.1:{}", port);
+
+        let response = reqwest::get(format!("{}/sync/status", url)).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UPGRADE_REQUIRED);
+        let body: UpgradeRequired = response.json().await.unwrap();
+        assert_eq!(body.error, "upgrade_required");
+        assert_eq!(server.legacy_usage.snapshot()["/sync/status"], 1);
+
+        // The versioned routes still work
+        let mut client = SyncClient::new(&url, "cli-1", Some(&pair(&server, "cli-1").await));
+        client.negotiate().await.unwrap();
+        assert!(client.pull(Some(0), "", None).await.is_ok());
+
+        server.stop();
+    }
+}
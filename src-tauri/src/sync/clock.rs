@@ -0,0 +1,198 @@
+//! Time and Randomness
+//!
+//! The sync server, pairing and the stores around them read the time and
+//! draw random values through `Clock` and `Entropy` rather than calling
+//! `Utc::now()`, `Instant::now()` or `thread_rng()` directly. Production code uses
+//! `SyncEnv::default()` (system clock, OS randomness); tests use
+//! `ManualClock`, which only moves when advanced, so code expiry, cooldowns
+//! and idle shutdown run without sleeping, and `SeededEntropy` to replay
+//! codes and tokens.
+
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+pub trait Clock: Send + Sync {
+    /// Wall-clock time (timestamps, expiry)
+    fn now(&self) -> DateTime<Utc>;
+    /// Monotonic time (idle timers)
+    fn instant(&self) -> Instant;
+    /// Resolves once `duration` has passed on this clock
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+pub trait Entropy: Send + Sync {
+    fn fill_bytes(&self, dest: &mut [u8]);
+    /// Uniform value in `0..bound`
+    fn below(&self, bound: u32) -> u32;
+}
+
+/// Clock and randomness shared by the server, pairing and persistence
+#[derive(Clone)]
+pub struct SyncEnv {
+    pub clock: Arc<dyn Clock>,
+    pub entropy: Arc<dyn Entropy>,
+}
+
+impl Default for SyncEnv {
+    fn default() -> Self {
+        Self {
+            clock: Arc::new(SystemClock),
+            entropy: Arc::new(OsEntropy),
+        }
+    }
+}
+
+// ============================================================================
+// System sources
+// ============================================================================
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Thread-local CSPRNG seeded by the OS
+pub struct OsEntropy;
+
+impl Entropy for OsEntropy {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        rand::thread_rng().fill_bytes(dest);
+    }
+
+    fn below(&self, bound: u32) -> u32 {
+        rand::thread_rng().gen_range(0..bound)
+    }
+}
+
+// ============================================================================
+// Test sources
+// ============================================================================
+
+/// Clock that starts at the current time and only moves when advanced.
+/// Sleeps resolve as soon as the clock is advanced past their deadline.
+pub struct ManualClock {
+    start: DateTime<Utc>,
+    start_instant: Instant,
+    elapsed: watch::Sender<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            start: Utc::now(),
+            start_instant: Instant::now(),
+            elapsed: watch::channel(Duration::ZERO).0,
+        })
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.elapsed.send_modify(|elapsed| *elapsed += by);
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.borrow()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.start + chrono::Duration::from_std(self.elapsed()).unwrap_or_default()
+    }
+
+    fn instant(&self) -> Instant {
+        self.start_instant + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let mut elapsed = self.elapsed.subscribe();
+        let deadline = *elapsed.borrow() + duration;
+        Box::pin(async move {
+            while *elapsed.borrow_and_update() < deadline {
+                if elapsed.changed().await.is_err() {
+                    // The clock is gone; it will never get there
+                    std::future::pending::<()>().await;
+                }
+            }
+        })
+    }
+}
+
+/// Deterministic randomness for tests
+pub struct SeededEntropy(Mutex<StdRng>);
+
+impl SeededEntropy {
+    pub fn new(seed: u64) -> Arc<Self> {
+        Arc::new(Self(Mutex::new(StdRng::seed_from_u64(seed))))
+    }
+}
+
+impl Entropy for SeededEntropy {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        if let Ok(mut rng) = self.0.lock() {
+            rng.fill_bytes(dest);
+        }
+    }
+
+    fn below(&self, bound: u32) -> u32 {
+        self.0
+            .lock()
+            .map(|mut rng| rng.gen_range(0..bound))
+            .unwrap_or(0)
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_manual_clock_moves_only_when_advanced() {
+        let clock = ManualClock::new();
+        let (now, instant) = (clock.now(), clock.instant());
+        assert_eq!(clock.now(), now);
+
+        let sleep = tokio::spawn(clock.sleep(Duration::from_secs(60)));
+        clock.advance(Duration::from_secs(59));
+        tokio::task::yield_now().await;
+        assert!(!sleep.is_finished());
+
+        clock.advance(Duration::from_secs(1));
+        sleep.await.unwrap();
+        assert_eq!(clock.now() - now, chrono::Duration::seconds(60));
+        assert_eq!(clock.instant() - instant, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_seeded_entropy_replays() {
+        let (a, b) = (SeededEntropy::new(7), SeededEntropy::new(7));
+        let (mut x, mut y) = ([0u8; 16], [0u8; 16]);
+        a.fill_bytes(&mut x);
+        b.fill_bytes(&mut y);
+        assert_eq!(x, y);
+        assert_eq!(a.below(1_000_000), b.below(1_000_000));
+        assert!(a.below(10) < 10);
+    }
+}
@@ -2,44 +2,89 @@
 //!
 //! IPC commands exposed to the frontend for sync operations.
 
+use super::audit::{AuditEntry, AuditEventKind, AuditFilter, AuditLog};
 use super::crypto::{decrypt, encrypt, EncryptedBundle};
+use super::diagnostics::{self, DiagnosticsReport};
 use super::discovery::{discover_peers, DiscoveredPeer, MdnsAdvertiser};
-use super::pairing::{PairStartResponse, PairStatusResponse};
-use super::persistence::{PairedDevice, PersistenceManager};
-use super::server::SyncServer;
-use serde::Serialize;
-use std::collections::VecDeque;
+use super::error::SyncCommandError;
+use super::folder::{FolderState, FolderSyncReport, FolderWatcher, SyncFolder, OP_LOG_HOLD};
+use super::inbox::{PendingOpsPage, PendingOpsStore};
+use super::mailbox::{sync_paired_devices, RelayClient, RelaySyncReport};
+use super::network::{self, LocalAddress, NetworkConfig};
+use super::oplog::OpLog;
+use super::pairing::{PairStartResponse, PairStatusResponse, PairingStatus};
+use super::persistence::{PairedDevice, PersistenceError, PersistenceManager};
+use super::protocol::ProtocolConfig;
+use super::redact;
+use super::server::{notify_ops_received, ServerOptions, SyncServer};
+use super::settings::{SettingsStore, SyncSettings, Transport};
+use super::status::{SyncStatus, SyncStatusTracker};
+use super::supervisor::{TaskHealth, TaskSupervisor};
+use super::trusted::{
+    self, NetworkChange, NetworkFingerprint, TrustedNetwork, TrustedNetworkState, TrustedNetworks,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use tauri::{Manager, State};
-use tokio::sync::Mutex as TokioMutex;
-
-/// Shared queue for pending sync operations (received from mobile)
-pub type PendingOpsQueue = Arc<TokioMutex<VecDeque<serde_json::Value>>>;
-
-/// Shared storage for local operations (created on desktop, to be synced to mobile)
-pub type LocalOpsStore = Arc<TokioMutex<Vec<serde_json::Value>>>;
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State};
 
 /// Managed state for sync operations
 pub struct SyncState {
     pub server: Mutex<Option<SyncServer>>,
     pub advertiser: Mutex<Option<MdnsAdvertiser>>,
     pub config_dir: Mutex<Option<PathBuf>>,
-    /// Shared queue of pending operations received from mobile
-    pub pending_ops: PendingOpsQueue,
-    /// Local operations created on desktop (for sync to mobile)
-    pub local_ops: LocalOpsStore,
+    /// Inbox of operations received from mobile, persisted until acknowledged
+    pub pending_ops: Arc<PendingOpsStore>,
+    /// Ops from the desktop and paired devices, kept until every paired device has them
+    pub op_log: Arc<OpLog>,
+    /// Sync folder being watched, if started
+    pub folder_sync: Mutex<Option<FolderWatcher>>,
+    /// Runs the folder watcher; unlike the server's tasks it outlives server restarts
+    pub folder_tasks: TaskSupervisor,
+    /// Background tasks of the running server, torn down on stop/restart
+    pub tasks: TaskSupervisor,
+    /// What the server is doing, reported to the UI on every change
+    pub status: Arc<SyncStatusTracker>,
+    /// Networks the server starts on by itself
+    pub trusted: TrustedNetworkState,
+    /// Settings from `sync_settings.json`
+    pub settings: SettingsStore,
 }
 
-impl Default for SyncState {
-    fn default() -> Self {
-        Self {
+impl SyncState {
+    /// Create sync state, reopening the inbox and local op log stored in the config directory.
+    /// Status changes are emitted through `app_handle`.
+    pub fn new(
+        config_dir: PathBuf,
+        app_handle: Option<AppHandle>,
+    ) -> Result<Self, PersistenceError> {
+        let op_log = OpLog::new(config_dir.clone())?;
+        // Keep ops the sync folder hasn't written yet, even before it is started
+        if let Some(folder) = FolderState::load(&config_dir) {
+            op_log.hold(OP_LOG_HOLD, folder.exported_seq);
+        }
+        let trusted = TrustedNetworkState::new(config_dir.clone());
+        let settings = SettingsStore::new(config_dir.clone());
+        let pending_ops = PendingOpsStore::new(config_dir)?;
+        let status =
+            SyncStatusTracker::new(Arc::clone(&pending_ops), Arc::clone(&op_log), app_handle);
+
+        Ok(Self {
             server: Mutex::new(None),
             advertiser: Mutex::new(None),
             config_dir: Mutex::new(None),
-            pending_ops: Arc::new(TokioMutex::new(VecDeque::new())),
-            local_ops: Arc::new(TokioMutex::new(Vec::new())),
-        }
+            pending_ops,
+            op_log,
+            folder_sync: Mutex::new(None),
+            folder_tasks: TaskSupervisor::default(),
+            tasks: TaskSupervisor::default(),
+            status,
+            trusted,
+            settings,
+        })
     }
 }
 
@@ -49,145 +94,360 @@ pub struct ServerStartResult {
     pub expires_at: Option<String>,
 }
 
-/// Start the sync server
-#[tauri::command]
-pub async fn start_sync_server(
-    app: tauri::AppHandle,
-    state: State<'_, SyncState>,
-    device_id: String,
-    device_name: String,
-    port: Option<u16>,
-    auto_shutdown_minutes: Option<u64>,
-) -> Result<ServerStartResult, String> {
-    // Get config directory
-    let config_dir = app
-        .path()
-        .app_config_dir()
-        .map_err(|_| "Failed to get config directory".to_string())?;
+/// How to start the sync server; remembered for trusted-network auto start.
+/// Anything left out comes from the sync settings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStartOptions {
+    pub device_id: Option<String>,
+    pub device_name: Option<String>,
+    pub port: Option<u16>,
+    /// Minutes without requests before the server stops (0 = never)
+    pub auto_shutdown_minutes: Option<u64>,
+    pub legacy_routes: Option<bool>,
+    pub network: Option<NetworkConfig>,
+}
+
+impl SyncState {
+    /// Start (or restart) the server and its mDNS advertiser
+    pub async fn start_server(
+        &self,
+        app: &AppHandle,
+        options: ServerStartOptions,
+    ) -> Result<ServerStartResult, SyncCommandError> {
+        self.settings.require(Transport::Lan)?;
+        let settings = self.settings.get()?;
+        let options = settings.start_options(options);
+        let device_id = options
+            .device_id
+            .ok_or_else(|| SyncCommandError::InvalidSetting {
+                field: "deviceId",
+                detail: "is not set".to_string(),
+            })?;
+        let device_name = options.device_name.unwrap_or_else(|| {
+            hostname::get()
+                .map(|h| h.to_string_lossy().to_string())
+                .unwrap_or_else(|_| "Desktop".to_string())
+        });
+
+        // Get config directory
+        let config_dir = app
+            .path()
+            .app_config_dir()
+            .map_err(|_| SyncCommandError::ConfigDirUnavailable)?;
+
+        // Store config dir for later use
+        {
+            let mut config_guard = self.config_dir.lock()?;
+            *config_guard = Some(config_dir.clone());
+        }
+
+        // Stop existing server if any, and wait for its tasks to finish
+        {
+            let mut server_guard = self.server.lock()?;
+            if let Some(mut server) = server_guard.take() {
+                server.stop();
+            }
+        }
+        self.tasks.shutdown().await;
+
+        // Start new server on the shared queues
+        let auto_shutdown_minutes = options.auto_shutdown_minutes.unwrap_or_default();
+        let server_options = ServerOptions {
+            port: options.port.unwrap_or_default(),
+            auto_shutdown_minutes,
+            app_handle: Some(app.clone()),
+            protocol: ProtocolConfig {
+                legacy_routes: options.legacy_routes.unwrap_or(true),
+                ..Default::default()
+            },
+            network: options.network.unwrap_or_default(),
+            ..ServerOptions::new(
+                device_id.clone(),
+                device_name.clone(),
+                config_dir,
+                Arc::clone(&self.pending_ops),
+                Arc::clone(&self.op_log),
+                Arc::clone(&self.status),
+            )
+        };
+        let started = SyncServer::start(server_options, &self.tasks).await;
+        let (server, actual_port) = match started {
+            Ok(started) => started,
+            Err(detail) => {
+                self.status.failed(detail.clone()).await;
+                return Err(SyncCommandError::ServerStartFailed { detail });
+            }
+        };
+
+        // Store server, and forget it again if it stops on its own
+        let addresses: Vec<IpAddr> = server.addresses().iter().map(|a| a.ip).collect();
+        let stopped = server.stopped();
+        {
+            let mut server_guard = self.server.lock()?;
+            *server_guard = Some(server);
+        }
+        let app_handle = app.clone();
+        self.tasks.spawn("server-stop-watch", async move {
+            stopped.await;
+            let state = app_handle.state::<SyncState>();
+            if let Err(e) = state.forget_stopped_server() {
+                log::error!("Failed to clean up after the sync server stopped: {}", e);
+            }
+        });
+
+        // Start mDNS advertising
+        self.stop_advertiser()?;
+        if settings.transports.mdns {
+            self.start_advertiser(&device_id, &device_name, actual_port, &addresses)?;
+        }
+
+        // No expiry when the server never stops on idle
+        let idle_timeout = Some(auto_shutdown_minutes)
+            .filter(|mins| *mins > 0)
+            .map(|mins| Duration::from_secs(mins * 60));
+        self.status.listening(actual_port, idle_timeout).await;
+        let expires_at = self.status.snapshot().await.expires_at;
 
-    // Store config dir for later use
-    {
-        let mut config_guard = state.config_dir.lock().map_err(|e| e.to_string())?;
-        *config_guard = Some(config_dir.clone());
+        Ok(ServerStartResult {
+            port: actual_port,
+            expires_at,
+        })
     }
 
-    // Stop existing server if any
-    {
-        let mut server_guard = state.server.lock().map_err(|e| e.to_string())?;
-        if let Some(mut server) = server_guard.take() {
-            server.stop();
+    /// Drop the server if it stopped by itself (idle timeout, listener error)
+    /// and stop advertising it
+    fn forget_stopped_server(&self) -> Result<(), SyncCommandError> {
+        {
+            let mut server_guard = self.server.lock()?;
+            if !server_guard.as_ref().is_some_and(SyncServer::is_stopped) {
+                return Ok(());
+            }
+            *server_guard = None;
         }
+        self.stop_advertiser()
     }
 
-    // Get the shared queues
-    let pending_ops = Arc::clone(&state.pending_ops);
-    let local_ops = Arc::clone(&state.local_ops);
-
-    // Start new server
-    let (server, actual_port) = SyncServer::start(
-        port.unwrap_or(0),
-        device_id.clone(),
-        device_name.clone(),
-        auto_shutdown_minutes.unwrap_or(30),
-        config_dir,
-        Some(app.clone()),
-        pending_ops,
-        local_ops,
-    )
-    .await?;
+    /// Stop the server and its advertiser
+    pub async fn stop_server(&self) -> Result<(), SyncCommandError> {
+        // Stop server
+        {
+            let mut server_guard = self.server.lock()?;
+            if let Some(mut server) = server_guard.take() {
+                server.stop();
+            }
+        }
+        self.tasks.shutdown().await;
+
+        // Stop advertising
+        self.stop_advertiser()?;
+
+        self.status.stopped().await;
+        Ok(())
+    }
+
+    /// Bring the running server in line with `settings`, which replaced
+    /// `previous`
+    async fn apply_settings(
+        &self,
+        app: &AppHandle,
+        previous: &SyncSettings,
+        settings: &SyncSettings,
+    ) -> Result<SettingsApplied, SyncCommandError> {
+        let running = self.server.lock()?.as_ref().map(|server| {
+            let addresses: Vec<IpAddr> = server.addresses().iter().map(|a| a.ip).collect();
+            (
+                server.device_id().to_string(),
+                server.device_name().to_string(),
+                server.port(),
+                addresses,
+            )
+        });
+        let (device_id, device_name, port, addresses) = match running {
+            Some(running) => running,
+            None => return Ok(SettingsApplied::Saved),
+        };
 
-    // Store server
-    {
-        let mut server_guard = state.server.lock().map_err(|e| e.to_string())?;
-        *server_guard = Some(server);
+        if !settings.transports.lan {
+            self.stop_server().await?;
+            return Ok(SettingsApplied::Stopped);
+        }
+        if settings.needs_restart(previous) {
+            // Keep the identity the server was started with unless the settings set one
+            let options = ServerStartOptions {
+                device_id: settings.device_id.is_none().then_some(device_id),
+                device_name: settings.device_name.is_none().then_some(device_name),
+                ..Default::default()
+            };
+            self.start_server(app, options).await?;
+            return Ok(SettingsApplied::Restarted);
+        }
+        if settings.transports.mdns != previous.transports.mdns {
+            self.stop_advertiser()?;
+            if settings.transports.mdns {
+                self.start_advertiser(&device_id, &device_name, port, &addresses)?;
+            }
+        }
+        Ok(SettingsApplied::Live)
     }
 
-    // Start mDNS advertising
-    {
-        let mut adv_guard = state.advertiser.lock().map_err(|e| e.to_string())?;
-        if let Some(adv) = adv_guard.take() {
+    fn start_advertiser(
+        &self,
+        device_id: &str,
+        device_name: &str,
+        port: u16,
+        addresses: &[IpAddr],
+    ) -> Result<(), SyncCommandError> {
+        let advertiser = MdnsAdvertiser::new(device_id, device_name, port, addresses, "")
+            .map_err(|detail| SyncCommandError::DiscoveryFailed { detail })?;
+        *self.advertiser.lock()? = Some(advertiser);
+        Ok(())
+    }
+
+    fn stop_advertiser(&self) -> Result<(), SyncCommandError> {
+        if let Some(adv) = self.advertiser.lock()?.take() {
             let _ = adv.stop();
         }
+        Ok(())
+    }
 
-        let advertiser = MdnsAdvertiser::new(&device_id, &device_name, actual_port, "")?;
-        *adv_guard = Some(advertiser);
+    /// Whether the server is running
+    pub fn server_running(&self) -> Result<bool, SyncCommandError> {
+        Ok(self.server.lock()?.is_some())
     }
 
-    // Calculate expiry time
-    let expires_at = auto_shutdown_minutes.map(|mins| {
-        let expiry = chrono::Utc::now() + chrono::Duration::minutes(mins as i64);
-        expiry.to_rfc3339()
-    });
+    /// The paired devices store: the running server's, so its cache sees
+    /// every change, or one reading `config_dir` while the server is stopped
+    fn persistence(
+        &self,
+        config_dir: PathBuf,
+    ) -> Result<Arc<PersistenceManager>, SyncCommandError> {
+        let running = {
+            let server_guard = self.server.lock()?;
+            server_guard.as_ref().map(|s| Arc::clone(&s.persistence))
+        };
+        match running {
+            Some(persistence) => Ok(persistence),
+            None => PersistenceManager::new(config_dir)
+                .map_err(SyncCommandError::storage("open_sync_data")),
+        }
+    }
+}
 
-    Ok(ServerStartResult {
-        port: actual_port,
-        expires_at,
-    })
+/// Start the sync server
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn start_sync_server(
+    app: tauri::AppHandle,
+    state: State<'_, SyncState>,
+    device_id: Option<String>,
+    device_name: Option<String>,
+    port: Option<u16>,
+    auto_shutdown_minutes: Option<u64>,
+    legacy_routes: Option<bool>,
+    network: Option<NetworkConfig>,
+) -> Result<ServerStartResult, SyncCommandError> {
+    let options = ServerStartOptions {
+        device_id,
+        device_name,
+        port,
+        auto_shutdown_minutes,
+        legacy_routes,
+        network,
+    };
+    state.start_server(&app, options).await
 }
 
 /// Stop the sync server
 #[tauri::command]
-pub async fn stop_sync_server(state: State<'_, SyncState>) -> Result<(), String> {
-    // Stop server
-    {
-        let mut server_guard = state.server.lock().map_err(|e| e.to_string())?;
-        if let Some(mut server) = server_guard.take() {
-            server.stop();
-        }
-    }
-
-    // Stop advertising
-    {
-        let mut adv_guard = state.advertiser.lock().map_err(|e| e.to_string())?;
-        if let Some(adv) = adv_guard.take() {
-            let _ = adv.stop();
-        }
-    }
+pub async fn stop_sync_server(state: State<'_, SyncState>) -> Result<(), SyncCommandError> {
+    state.stop_server().await
+}
 
-    Ok(())
+/// Everything the UI shows about sync: phase, port, expiry, connected devices,
+/// pending op counts and the last error
+#[tauri::command]
+pub async fn get_sync_status(state: State<'_, SyncState>) -> Result<SyncStatus, SyncCommandError> {
+    Ok(state.status.snapshot().await)
 }
 
 /// Check if the sync server is running
 #[tauri::command]
-pub async fn is_sync_server_running(state: State<'_, SyncState>) -> Result<bool, String> {
-    let server_guard = state.server.lock().map_err(|e| e.to_string())?;
-    Ok(server_guard.is_some())
+pub async fn is_sync_server_running(state: State<'_, SyncState>) -> Result<bool, SyncCommandError> {
+    state.server_running()
 }
 
 /// Get the sync server port
 #[tauri::command]
-pub async fn get_sync_server_port(state: State<'_, SyncState>) -> Result<Option<u16>, String> {
-    let server_guard = state.server.lock().map_err(|e| e.to_string())?;
+pub async fn get_sync_server_port(
+    state: State<'_, SyncState>,
+) -> Result<Option<u16>, SyncCommandError> {
+    let server_guard = state.server.lock()?;
     Ok(server_guard.as_ref().map(|s| s.port()))
 }
 
+/// Hits on the unversioned legacy routes since the server started, per path,
+/// and pulls and pushes served without a token
+#[tauri::command]
+pub async fn get_legacy_route_usage(
+    state: State<'_, SyncState>,
+) -> Result<BTreeMap<String, u64>, SyncCommandError> {
+    let server_guard = state.server.lock()?;
+    Ok(server_guard
+        .as_ref()
+        .map(|s| s.legacy_usage.snapshot())
+        .unwrap_or_default())
+}
+
+/// Background tasks of the sync subsystem and whether they are still running
+#[tauri::command]
+pub fn get_sync_task_health(state: State<'_, SyncState>) -> Vec<TaskHealth> {
+    let mut health = state.tasks.health();
+    health.extend(state.folder_tasks.health());
+    health.extend(state.trusted.watchers.health());
+    health
+}
+
 /// Discover peers on the local network
 #[tauri::command]
-pub async fn discover_lan_peers(timeout_secs: Option<u64>) -> Result<Vec<DiscoveredPeer>, String> {
-    discover_peers(timeout_secs.unwrap_or(5)).await
+pub async fn discover_lan_peers(
+    state: State<'_, SyncState>,
+    timeout_secs: Option<u64>,
+) -> Result<Vec<DiscoveredPeer>, SyncCommandError> {
+    state.settings.require(Transport::Mdns)?;
+    discover_peers(timeout_secs.unwrap_or(5))
+        .await
+        .map_err(|detail| SyncCommandError::DiscoveryFailed { detail })
 }
 
 /// Encrypt a sync bundle with a passphrase
 #[tauri::command]
-pub fn encrypt_bundle(data: Vec<u8>, passphrase: String) -> Result<String, String> {
-    let encrypted = encrypt(&data, &passphrase).map_err(|e| e.to_string())?;
-    serde_json::to_string(&encrypted).map_err(|e| e.to_string())
+pub fn encrypt_bundle(data: Vec<u8>, passphrase: String) -> Result<String, SyncCommandError> {
+    let encrypted = encrypt(&data, &passphrase)?;
+    serde_json::to_string(&encrypted).map_err(|e| SyncCommandError::Internal {
+        detail: e.to_string(),
+    })
 }
 
 /// Decrypt a sync bundle with a passphrase
 #[tauri::command]
-pub fn decrypt_bundle(encrypted_json: String, passphrase: String) -> Result<Vec<u8>, String> {
+pub fn decrypt_bundle(
+    encrypted_json: String,
+    passphrase: String,
+) -> Result<Vec<u8>, SyncCommandError> {
     let bundle: EncryptedBundle =
-        serde_json::from_str(&encrypted_json).map_err(|e| e.to_string())?;
-    decrypt(&bundle, &passphrase).map_err(|e| e.to_string())
+        serde_json::from_str(&encrypted_json).map_err(|_| SyncCommandError::InvalidBundle)?;
+    Ok(decrypt(&bundle, &passphrase)?)
 }
 
 /// Get the device hostname
 #[tauri::command]
-pub fn get_hostname() -> Result<String, String> {
+pub fn get_hostname() -> Result<String, SyncCommandError> {
     hostname::get()
         .map(|h| h.to_string_lossy().to_string())
-        .map_err(|e| e.to_string())
+        .map_err(|e| SyncCommandError::Internal {
+            detail: e.to_string(),
+        })
 }
 
 // ============================================================================
@@ -199,39 +459,27 @@ pub fn get_hostname() -> Result<String, String> {
 #[tauri::command]
 pub async fn start_pairing_session(
     state: State<'_, SyncState>,
-) -> Result<PairStartResponse, String> {
+) -> Result<PairStartResponse, SyncCommandError> {
     // Extract what we need from the lock, then drop it before async
-    let (pairing_manager, port) = {
-        let server_guard = state.server.lock().map_err(|e| e.to_string())?;
+    let (pairing_manager, port, host_candidates) = {
+        let server_guard = state.server.lock()?;
         let server = server_guard
             .as_ref()
-            .ok_or("Sync server not running. Start the server first.")?;
-        (Arc::clone(&server.pairing_manager), server.port())
+            .ok_or(SyncCommandError::ServerNotRunning)?;
+        (
+            Arc::clone(&server.pairing_manager),
+            server.port(),
+            server.host_candidates(),
+        )
     };
 
-    // Get local IPs
-    let mut host_candidates = Vec::new();
-    if let Ok(ip) = local_ip_address::local_ip() {
-        host_candidates.push(ip.to_string());
-    }
-    if let Ok(list) = local_ip_address::list_afinet_netifas() {
-        for (_, ip) in list {
-            let ip_str = ip.to_string();
-            if !ip_str.starts_with("127.") && !ip_str.contains(':') && !host_candidates.contains(&ip_str) {
-                host_candidates.push(ip_str);
-            }
-        }
-    }
-    if host_candidates.is_empty() {
-        host_candidates.push("127.0.0.1".to_string());
-    }
-
     // Create pairing session (lock is dropped, safe to await)
-    let response = pairing_manager
+    let session = pairing_manager
         .create_session(host_candidates, port)
-        .await;
-
-    Ok(response)
+        .await
+        .map_err(SyncCommandError::from)?;
+    state.status.pairing_started(&session.pairing_id).await;
+    Ok(session)
 }
 
 /// Get the status of a pairing session
@@ -239,20 +487,24 @@ pub async fn start_pairing_session(
 pub async fn get_pairing_status(
     state: State<'_, SyncState>,
     pairing_id: String,
-) -> Result<PairStatusResponse, String> {
+) -> Result<PairStatusResponse, SyncCommandError> {
     // Extract pairing_manager from the lock, then drop it
     let pairing_manager = {
-        let server_guard = state.server.lock().map_err(|e| e.to_string())?;
+        let server_guard = state.server.lock()?;
         let server = server_guard
             .as_ref()
-            .ok_or("Sync server not running")?;
+            .ok_or(SyncCommandError::ServerNotRunning)?;
         Arc::clone(&server.pairing_manager)
     };
 
-    pairing_manager
+    let status = pairing_manager
         .get_status(&pairing_id)
         .await
-        .map_err(|e| e.error)
+        .map_err(SyncCommandError::from)?;
+    if !matches!(status.status, PairingStatus::Pending | PairingStatus::AwaitingApproval) {
+        state.status.pairing_ended(&pairing_id).await;
+    }
+    Ok(status)
 }
 
 /// Cancel a pairing session
@@ -260,79 +512,221 @@ pub async fn get_pairing_status(
 pub async fn cancel_pairing_session(
     state: State<'_, SyncState>,
     pairing_id: String,
-) -> Result<(), String> {
+) -> Result<(), SyncCommandError> {
     // Extract pairing_manager if server exists
     let pairing_manager = {
-        let server_guard = state.server.lock().map_err(|e| e.to_string())?;
+        let server_guard = state.server.lock()?;
         server_guard.as_ref().map(|s| Arc::clone(&s.pairing_manager))
     };
 
     if let Some(pm) = pairing_manager {
         pm.cancel(&pairing_id).await;
     }
+    state.status.pairing_ended(&pairing_id).await;
     Ok(())
 }
 
+/// Allow a device that has entered the right code/nonce to sync.
+/// When the session shows a SAS, `sas_confirmed` states that the user compared it on both devices.
+#[tauri::command]
+pub async fn approve_pairing(
+    state: State<'_, SyncState>,
+    pairing_id: String,
+    sas_confirmed: Option<bool>,
+) -> Result<(), SyncCommandError> {
+    let pairing_manager = {
+        let server_guard = state.server.lock()?;
+        let server = server_guard
+            .as_ref()
+            .ok_or(SyncCommandError::ServerNotRunning)?;
+        Arc::clone(&server.pairing_manager)
+    };
+
+    pairing_manager
+        .approve(&pairing_id, sas_confirmed.unwrap_or(false))
+        .await
+        .map(|_| ())
+        .map_err(SyncCommandError::from)
+}
+
+/// Reject a device that is asking to pair
+#[tauri::command]
+pub async fn deny_pairing(
+    state: State<'_, SyncState>,
+    pairing_id: String,
+) -> Result<(), SyncCommandError> {
+    let pairing_manager = {
+        let server_guard = state.server.lock()?;
+        let server = server_guard
+            .as_ref()
+            .ok_or(SyncCommandError::ServerNotRunning)?;
+        Arc::clone(&server.pairing_manager)
+    };
+
+    pairing_manager
+        .deny(&pairing_id)
+        .await
+        .map(|_| ())
+        .map_err(SyncCommandError::from)
+}
+
+/// Paired device with its delivery status
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairedDeviceInfo {
+    #[serde(flatten)]
+    pub device: PairedDevice,
+    /// Local operations the device has not pulled yet
+    pub ops_behind: usize,
+}
+
 /// Get list of paired devices
 #[tauri::command]
 pub async fn get_paired_devices(
     app: tauri::AppHandle,
-) -> Result<Vec<PairedDevice>, String> {
+    state: State<'_, SyncState>,
+) -> Result<Vec<PairedDeviceInfo>, SyncCommandError> {
     let config_dir = app
         .path()
         .app_config_dir()
-        .map_err(|_| "Failed to get config directory".to_string())?;
+        .map_err(|_| SyncCommandError::ConfigDirUnavailable)?;
 
-    let persistence = PersistenceManager::new(config_dir)
-        .map_err(|e| format!("Failed to initialize persistence: {}", e))?;
+    let persistence = state.persistence(config_dir)?;
 
-    persistence
+    let devices = persistence
         .get_active_devices()
         .await
-        .map_err(|e| format!("Failed to load devices: {}", e))
+        .map_err(SyncCommandError::storage("load_devices"))?;
+
+    let mut infos = Vec::with_capacity(devices.len());
+    for device in devices {
+        let ops_behind = state
+            .op_log
+            .count_after(&device.id, device.delivered_seq())
+            .await;
+        infos.push(PairedDeviceInfo { device, ops_behind });
+    }
+    Ok(infos)
 }
 
 /// Revoke a paired device
 #[tauri::command]
 pub async fn revoke_paired_device(
     app: tauri::AppHandle,
+    state: State<'_, SyncState>,
     device_id: String,
-) -> Result<bool, String> {
+) -> Result<bool, SyncCommandError> {
     let config_dir = app
         .path()
         .app_config_dir()
-        .map_err(|_| "Failed to get config directory".to_string())?;
+        .map_err(|_| SyncCommandError::ConfigDirUnavailable)?;
 
-    let persistence = PersistenceManager::new(config_dir)
-        .map_err(|e| format!("Failed to initialize persistence: {}", e))?;
+    // Through the running server's store, so it stops accepting the token at once
+    let persistence = state.persistence(config_dir.clone())?;
 
-    persistence
+    let revoked = persistence
         .revoke_device(&device_id)
         .await
-        .map_err(|e| format!("Failed to revoke device: {}", e))
+        .map_err(SyncCommandError::storage("revoke_device"))?;
+
+    if revoked {
+        if let Ok(audit) = AuditLog::new(config_dir) {
+            audit
+                .record(AuditEntry::new(AuditEventKind::DeviceRevoked).device(&device_id, None))
+                .await;
+        }
+
+        // The revoked device no longer holds back pruning
+        if let Ok(devices) = persistence.load().await {
+            if let Err(e) = state.op_log.prune_acknowledged(&devices).await {
+                log::error!("Failed to prune delivered local ops: {}", e);
+            }
+        }
+    }
+
+    Ok(revoked)
+}
+
+/// Query the sync audit log (newest first)
+#[tauri::command]
+pub async fn get_sync_audit_log(
+    app: tauri::AppHandle,
+    filter: Option<AuditFilter>,
+) -> Result<Vec<AuditEntry>, SyncCommandError> {
+    let config_dir = app
+        .path()
+        .app_config_dir()
+        .map_err(|_| SyncCommandError::ConfigDirUnavailable)?;
+
+    let audit = AuditLog::new(config_dir)
+        .map_err(SyncCommandError::storage("open_audit_log"))?;
+
+    audit
+        .query(&filter.unwrap_or_default())
+        .await
+        .map_err(SyncCommandError::storage("read_audit_log"))
+}
+
+/// Addresses of every local interface, to choose which ones sync listens on
+#[tauri::command]
+pub fn get_network_addresses() -> Vec<LocalAddress> {
+    network::local_addresses()
+}
+
+/// Check why phones can't find or reach this computer, with suggested fixes
+#[tauri::command]
+pub async fn run_sync_diagnostics(
+    state: State<'_, SyncState>,
+) -> Result<DiagnosticsReport, SyncCommandError> {
+    let server = state
+        .server
+        .lock()?
+        .as_ref()
+        .map(|s| (s.port(), s.addresses().to_vec()));
+    let advertised_device_id = state
+        .advertiser
+        .lock()?
+        .as_ref()
+        .map(|a| a.device_id().to_string());
+    Ok(diagnostics::run(server, advertised_device_id).await)
+}
+
+/// Enable or disable developer sync diagnostics (unredacted operation logging)
+#[tauri::command]
+pub fn set_sync_diagnostics(enabled: bool) {
+    redact::set_diagnostics(enabled);
 }
 
-/// Fetch pending operations received from mobile
-/// Frontend should call this after receiving sync:ops_received event
+/// Fetch a page of pending operations received from mobile (sequence > `after_seq`)
+/// Frontend should call this after receiving sync:ops_received event and on startup
 #[tauri::command]
 pub async fn get_pending_sync_ops(
     state: State<'_, SyncState>,
-) -> Result<Vec<serde_json::Value>, String> {
-    let pending = state.pending_ops.lock().await;
-    let ops: Vec<serde_json::Value> = pending.iter().cloned().collect();
-    log::info!("get_pending_sync_ops: returning {} operations", ops.len());
-    Ok(ops)
+    after_seq: Option<u64>,
+    limit: Option<usize>,
+) -> Result<PendingOpsPage, SyncCommandError> {
+    let page = state.pending_ops.page(after_seq.unwrap_or(0), limit).await;
+    log::info!(
+        "get_pending_sync_ops: returning {} operations (has_more: {})",
+        page.ops.len(),
+        page.has_more
+    );
+    Ok(page)
 }
 
-/// Clear pending operations (called after frontend applies them)
+/// Acknowledge pending operations up to and including `up_to_seq`
+/// (called after frontend applies them). Returns how many were removed.
 #[tauri::command]
-pub async fn clear_pending_sync_ops(
+pub async fn ack_pending_sync_ops(
     state: State<'_, SyncState>,
-) -> Result<usize, String> {
-    let mut pending = state.pending_ops.lock().await;
-    let count = pending.len();
-    pending.clear();
-    log::info!("clear_pending_sync_ops: cleared {} operations", count);
+    up_to_seq: u64,
+) -> Result<usize, SyncCommandError> {
+    let count = state
+        .pending_ops
+        .ack(up_to_seq)
+        .await
+        .map_err(SyncCommandError::storage("acknowledge_operations"))?;
+    log::info!("ack_pending_sync_ops: removed {} operations", count);
     Ok(count)
 }
 
@@ -342,21 +736,335 @@ pub async fn clear_pending_sync_ops(
 pub async fn store_local_sync_op(
     state: State<'_, SyncState>,
     op: serde_json::Value,
-) -> Result<(), String> {
-    let mut local_ops = state.local_ops.lock().await;
-    log::info!("store_local_sync_op: storing operation {:?}", op.get("id"));
-    local_ops.push(op);
-    log::info!("store_local_sync_op: total local ops = {}", local_ops.len());
+) -> Result<(), SyncCommandError> {
+    log::debug!("store_local_sync_op: storing operation {}", redact::op(&op));
+    let seq = state
+        .op_log
+        .append(op, None)
+        .await
+        .map_err(SyncCommandError::storage("store_operation"))?;
+    match seq {
+        Some(seq) => {
+            log::info!("store_local_sync_op: stored local op with seq {}", seq);
+            let folder_guard = state.folder_sync.lock()?;
+            if let Some(watcher) = folder_guard.as_ref() {
+                watcher.trigger();
+            }
+        }
+        None => log::debug!("store_local_sync_op: operation already in op log"),
+    }
     Ok(())
 }
 
-/// Get count of local operations available for sync
+/// Get count of operations held for sync (not yet pulled by every paired device)
 #[tauri::command]
 pub async fn get_local_sync_ops_count(
     state: State<'_, SyncState>,
-) -> Result<usize, String> {
-    let local_ops = state.local_ops.lock().await;
-    Ok(local_ops.len())
+) -> Result<usize, SyncCommandError> {
+    Ok(state.op_log.count().await)
+}
+
+/// Exchange ops with paired devices through a mailbox relay: ops they left
+/// there are stored like a push, and local ops they haven't received yet are
+/// deposited for them
+#[tauri::command]
+pub async fn sync_via_relay(
+    app: tauri::AppHandle,
+    state: State<'_, SyncState>,
+    relay_url: String,
+    device_id: String,
+) -> Result<RelaySyncReport, SyncCommandError> {
+    state.settings.require(Transport::Relay)?;
+    let config_dir = app
+        .path()
+        .app_config_dir()
+        .map_err(|_| SyncCommandError::ConfigDirUnavailable)?;
+
+    // Share the running server's device cache so cursors don't go stale
+    let persistence = state.persistence(config_dir.clone())?;
+    let audit = AuditLog::new(config_dir).map_err(SyncCommandError::storage("open_audit_log"))?;
+
+    let report = sync_paired_devices(
+        &RelayClient::new(&relay_url),
+        &device_id,
+        &persistence,
+        &state.pending_ops,
+        &state.op_log,
+        &audit,
+    )
+    .await
+    .map_err(|detail| SyncCommandError::RelayFailed { detail })?;
+    log::info!(
+        "sync_via_relay: received {} ops ({} new), sent {}",
+        report.received,
+        report.stored,
+        report.sent
+    );
+
+    if report.stored > 0 || !report.conflicts.is_empty() {
+        notify_ops_received(Some(&app), report.stored, &report.conflicts);
+    }
+    Ok(report)
+}
+
+/// Start syncing through a shared folder (Syncthing, network share, USB
+/// stick). Runs one pass right away and returns its report; afterwards the
+/// folder is watched for segments from other devices.
+#[tauri::command]
+pub async fn start_folder_sync(
+    app: tauri::AppHandle,
+    state: State<'_, SyncState>,
+    folder_path: String,
+    passphrase: String,
+    device_id: String,
+) -> Result<FolderSyncReport, SyncCommandError> {
+    state.settings.require(Transport::Folder)?;
+    let config_dir = app
+        .path()
+        .app_config_dir()
+        .map_err(|_| SyncCommandError::ConfigDirUnavailable)?;
+
+    stop_folder_watcher(&state).await?;
+
+    // Key derivation is slow; keep it off the async runtime
+    let root = PathBuf::from(folder_path);
+    let folder = tokio::task::spawn_blocking(move || {
+        SyncFolder::open(&config_dir, &root, &device_id, &passphrase)
+    })
+    .await
+    .map_err(|e| SyncCommandError::Internal {
+        detail: e.to_string(),
+    })?
+    .map_err(|detail| SyncCommandError::FolderSyncFailed { detail })?;
+    let folder = Arc::new(folder);
+
+    let report = folder
+        .sync(&state.pending_ops, &state.op_log)
+        .await
+        .map_err(|detail| SyncCommandError::FolderSyncFailed { detail })?;
+    log::info!(
+        "start_folder_sync: imported {} ops ({} new), exported {}",
+        report.imported,
+        report.stored,
+        report.exported
+    );
+    if report.stored > 0 || !report.conflicts.is_empty() {
+        notify_ops_received(Some(&app), report.stored, &report.conflicts);
+    }
+
+    let watcher = FolderWatcher::start(
+        folder,
+        Arc::clone(&state.pending_ops),
+        Arc::clone(&state.op_log),
+        &state.folder_tasks,
+        move |report| {
+            if report.stored > 0 || !report.conflicts.is_empty() {
+                notify_ops_received(Some(&app), report.stored, &report.conflicts);
+            }
+        },
+    );
+    *state.folder_sync.lock()? = Some(watcher);
+
+    Ok(report)
+}
+
+/// Stop watching the sync folder
+#[tauri::command]
+pub async fn stop_folder_sync(state: State<'_, SyncState>) -> Result<(), SyncCommandError> {
+    stop_folder_watcher(&state).await
+}
+
+/// Stop the folder watcher, if any, and wait for a sync pass in progress to finish
+async fn stop_folder_watcher(state: &SyncState) -> Result<(), SyncCommandError> {
+    let watcher = state.folder_sync.lock()?.take();
+    if let Some(watcher) = watcher {
+        watcher.stop();
+    }
+    state.folder_tasks.shutdown().await;
+    Ok(())
+}
+
+/// The sync folder being watched, if any
+#[tauri::command]
+pub async fn get_sync_folder(
+    state: State<'_, SyncState>,
+) -> Result<Option<String>, SyncCommandError> {
+    let folder_guard = state.folder_sync.lock()?;
+    Ok(folder_guard
+        .as_ref()
+        .map(|w| w.folder().root().display().to_string()))
+}
+
+// ============================================================================
+// Settings Commands
+// ============================================================================
+
+/// What `update_sync_settings` did with the new settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingsApplied {
+    /// No server is running; they take effect when it starts
+    Saved,
+    /// Applied to the running server
+    Live,
+    /// The running server was restarted to pick them up
+    Restarted,
+    /// The LAN transport was switched off, so the server was stopped
+    Stopped,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncSettingsUpdate {
+    pub settings: SyncSettings,
+    pub applied: SettingsApplied,
+}
+
+/// Current sync settings
+#[tauri::command]
+pub fn get_sync_settings(state: State<'_, SyncState>) -> Result<SyncSettings, SyncCommandError> {
+    state.settings.get()
+}
+
+/// Validate, save and apply the sync settings to the running server, going
+/// back to the previous ones if they can't be applied
+#[tauri::command]
+pub async fn update_sync_settings(
+    app: AppHandle,
+    state: State<'_, SyncState>,
+    settings: SyncSettings,
+) -> Result<SyncSettingsUpdate, SyncCommandError> {
+    let previous = state.settings.replace(settings)?;
+    let settings = state.settings.get()?;
+    let applied = match state.apply_settings(&app, &previous, &settings).await {
+        Ok(applied) => applied,
+        Err(e) => {
+            // Don't keep settings the server couldn't take
+            state.settings.replace(previous)?;
+            return Err(e);
+        }
+    };
+    log::set_max_level(settings.log_level.filter());
+    if !settings.transports.folder {
+        stop_folder_watcher(&state).await?;
+    }
+
+    Ok(SyncSettingsUpdate { settings, applied })
+}
+
+// ============================================================================
+// Trusted Network Commands
+// ============================================================================
+
+/// The network this computer is on and what the watcher last did about it
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrentNetwork {
+    pub network: Option<NetworkFingerprint>,
+    /// The trusted network `network` matches
+    pub trusted: Option<TrustedNetwork>,
+    pub last_change: Option<NetworkChange>,
+}
+
+async fn detect_network() -> Result<Option<NetworkFingerprint>, SyncCommandError> {
+    tokio::task::spawn_blocking(NetworkFingerprint::current)
+        .await
+        .map_err(|e| SyncCommandError::Internal {
+            detail: e.to_string(),
+        })
+}
+
+/// Fingerprint of the current network and whether it is trusted
+#[tauri::command]
+pub async fn get_current_network(
+    state: State<'_, SyncState>,
+) -> Result<CurrentNetwork, SyncCommandError> {
+    let network = detect_network().await?;
+    let settings = state.trusted.settings()?;
+    let trusted = network
+        .as_ref()
+        .and_then(|network| settings.find(network))
+        .cloned();
+    Ok(CurrentNetwork {
+        network,
+        trusted,
+        last_change: state.trusted.last_change(),
+    })
+}
+
+/// Trusted networks and the automatic start settings
+#[tauri::command]
+pub fn get_trusted_networks(
+    state: State<'_, SyncState>,
+) -> Result<TrustedNetworks, SyncCommandError> {
+    state.trusted.settings()
+}
+
+/// Trust the network this computer is on (renaming it if already trusted)
+#[tauri::command]
+pub async fn trust_current_network(
+    app: AppHandle,
+    state: State<'_, SyncState>,
+    name: String,
+) -> Result<TrustedNetwork, SyncCommandError> {
+    // A network without a gateway can't be recognised later
+    let fingerprint = detect_network()
+        .await?
+        .filter(|network| network.gateway.is_some())
+        .ok_or(SyncCommandError::NoNetwork)?;
+
+    let network = state.trusted.update(|settings| {
+        let existing = settings
+            .networks
+            .iter_mut()
+            .find(|trusted| trusted.fingerprint.same_network(&fingerprint));
+        if let Some(existing) = existing {
+            existing.name = name;
+            existing.fingerprint = fingerprint;
+            return existing.clone();
+        }
+        let network = TrustedNetwork {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            fingerprint,
+            trusted_at: chrono::Utc::now(),
+        };
+        settings.networks.push(network.clone());
+        network
+    })?;
+    log::info!("Trusted network {}", network.name);
+
+    trusted::apply(&app, &state, true).await;
+    Ok(network)
+}
+
+/// Stop trusting a network; a server it started is stopped if this is the
+/// current network
+#[tauri::command]
+pub async fn forget_trusted_network(
+    app: AppHandle,
+    state: State<'_, SyncState>,
+    network_id: String,
+) -> Result<(), SyncCommandError> {
+    state
+        .trusted
+        .update(|settings| settings.networks.retain(|n| n.id != network_id))?;
+    trusted::apply(&app, &state, true).await;
+    Ok(())
+}
+
+/// Turn automatic start on trusted networks on (with the server settings to
+/// use) or off, and apply it to the current network right away
+#[tauri::command]
+pub async fn set_sync_auto_start(
+    app: AppHandle,
+    state: State<'_, SyncState>,
+    options: Option<ServerStartOptions>,
+) -> Result<Option<NetworkChange>, SyncCommandError> {
+    state
+        .trusted
+        .update(|settings| settings.auto_start = options)?;
+    Ok(trusted::apply(&app, &state, true).await)
 }
 
 // Add chrono dependency for timestamp handling
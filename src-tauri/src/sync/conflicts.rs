@@ -0,0 +1,266 @@
+//! Conflict Detection
+//!
+//! Detects concurrent edits when ops arrive from a device, before the webview
+//! applies them, so conflicts are recorded even when the UI is not open.
+//! Resolution still happens in the frontend (`conflict-resolver.ts`).
+//!
+//! An incoming op is compared with ops from other authors that are still in
+//! the op log, i.e. not yet delivered to every device. Two ops on the same
+//! `entityType`/`entityId` are concurrent unless the later one (by HLC) was
+//! made after its author applied the earlier one: its `baseHlc` (the latest
+//! write to that field, or entity for deletes, the author had seen) is at
+//! least the earlier op's HLC. Ops from clients that don't send `baseHlc` fall
+//! back to comparing the later op's `previousValue` with the earlier `value`.
+//! Concurrent ops conflict when:
+//! - one deletes the entity and the other updates it (`delete_vs_update`), or
+//! - both update the same `field` and wrote different values. Transactions
+//!   are classified as `money_event_version`, everything else as
+//!   `profile_field`, matching how `ops-engine.ts` applies them.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictType {
+    ProfileField,
+    MoneyEventVersion,
+    DeleteVsUpdate,
+}
+
+/// A conflict between an incoming op and one already known to the desktop
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DetectedConflict {
+    pub conflict_type: ConflictType,
+    pub entity_type: String,
+    pub entity_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    pub existing_op_id: String,
+    pub incoming_op_id: String,
+    pub existing_created_by: String,
+    pub incoming_created_by: String,
+    pub existing_value: Value,
+    pub incoming_value: Value,
+    pub detected_at: String,
+}
+
+fn str_field<'a>(op: &'a Value, key: &str) -> Option<&'a str> {
+    op.get(key).and_then(|v| v.as_str())
+}
+
+fn is_delete(op: &Value) -> bool {
+    str_field(op, "opType") == Some("delete")
+}
+
+fn is_update(op: &Value) -> bool {
+    str_field(op, "opType") == Some("update")
+}
+
+/// Entity the op applies to, as (entityType, entityId)
+pub fn entity_key(op: &Value) -> Option<(&str, &str)> {
+    Some((str_field(op, "entityType")?, str_field(op, "entityId")?))
+}
+
+/// The two ops in HLC order
+fn in_hlc_order<'a>(a: &'a Value, b: &'a Value) -> (&'a Value, &'a Value) {
+    if str_field(a, "hlc") <= str_field(b, "hlc") {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// Whether `later` was made by an author who had already applied `earlier`
+/// (None when `later` doesn't say what it was based on)
+fn builds_on(earlier: &Value, later: &Value) -> Option<bool> {
+    let base = str_field(later, "baseHlc")?;
+    Some(str_field(earlier, "hlc").is_some_and(|hlc| base >= hlc))
+}
+
+/// Check an incoming op against an existing op on the same entity, stamping
+/// any conflict with `now`
+pub fn detect(existing: &Value, incoming: &Value, now: DateTime<Utc>) -> Option<DetectedConflict> {
+    let existing_by = str_field(existing, "createdBy")?;
+    let incoming_by = str_field(incoming, "createdBy")?;
+    if existing_by == incoming_by || entity_key(existing)? != entity_key(incoming)? {
+        return None;
+    }
+
+    let (earlier, later) = in_hlc_order(existing, incoming);
+    let sequential = builds_on(earlier, later);
+    if sequential == Some(true) {
+        return None;
+    }
+
+    let conflict_type = if is_delete(existing) != is_delete(incoming) {
+        let other = if is_delete(existing) {
+            incoming
+        } else {
+            existing
+        };
+        if !is_update(other) {
+            return None;
+        }
+        ConflictType::DeleteVsUpdate
+    } else {
+        if !is_update(existing) || !is_update(incoming) {
+            return None;
+        }
+        let field = str_field(existing, "field")?;
+        if str_field(incoming, "field") != Some(field) {
+            return None;
+        }
+
+        if earlier.get("value") == later.get("value") {
+            return None;
+        }
+        if sequential.is_none() {
+            // Without a base HLC or previous value causality can't be judged
+            let previous = later.get("previousValue")?;
+            if Some(previous) == earlier.get("value") {
+                return None;
+            }
+        }
+
+        if str_field(incoming, "entityType") == Some("transaction") {
+            ConflictType::MoneyEventVersion
+        } else {
+            ConflictType::ProfileField
+        }
+    };
+
+    let (entity_type, entity_id) = entity_key(incoming)?;
+    Some(DetectedConflict {
+        conflict_type,
+        entity_type: entity_type.to_string(),
+        entity_id: entity_id.to_string(),
+        field: str_field(incoming, "field")
+            .or_else(|| str_field(existing, "field"))
+            .map(|f| f.to_string()),
+        existing_op_id: str_field(existing, "id").unwrap_or_default().to_string(),
+        incoming_op_id: str_field(incoming, "id").unwrap_or_default().to_string(),
+        existing_created_by: existing_by.to_string(),
+        incoming_created_by: incoming_by.to_string(),
+        existing_value: existing.get("value").cloned().unwrap_or(Value::Null),
+        incoming_value: incoming.get("value").cloned().unwrap_or(Value::Null),
+        detected_at: now.to_rfc3339(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn now() -> DateTime<Utc> {
+        "2026-03-01T12:00:00Z".parse().unwrap()
+    }
+
+    fn update(id: &str, by: &str, hlc: &str, previous: Value, value: Value) -> Value {
+        json!({
+            "id": id,
+            "hlc": hlc,
+            "entityType": "client",
+            "entityId": "c-1",
+            "opType": "update",
+            "field": "name",
+            "previousValue": previous,
+            "value": value,
+            "createdBy": by,
+        })
+    }
+
+    #[test]
+    fn test_concurrent_field_updates_conflict() {
+        let desktop = update("op-1", "desktop", "a", json!("Sara"), json!("Sara H."));
+        let phone = update("op-2", "phone", "b", json!("Sara"), json!("Sarah"));
+
+        let conflict = detect(&desktop, &phone, now()).unwrap();
+        assert_eq!(conflict.conflict_type, ConflictType::ProfileField);
+        assert_eq!(conflict.field.as_deref(), Some("name"));
+        assert_eq!(conflict.existing_op_id, "op-1");
+        assert_eq!(conflict.incoming_value, json!("Sarah"));
+        assert_eq!(conflict.detected_at, now().to_rfc3339());
+    }
+
+    #[test]
+    fn test_base_hlc_decides_concurrency() {
+        let desktop = update("op-1", "desktop", "b", json!("Sara"), json!("Sara H."));
+
+        // Applied the desktop edit, then renamed it back
+        let mut after = update("op-2", "phone", "c", json!("Sara H."), json!("Sara"));
+        after["baseHlc"] = json!("b");
+        assert!(detect(&desktop, &after, now()).is_none());
+
+        // Happened to start from the same value, but without having seen the
+        // desktop edit
+        let mut before = update("op-3", "phone", "c", json!("Sara H."), json!("Sarah"));
+        before["baseHlc"] = json!("a");
+        assert!(detect(&desktop, &before, now()).is_some());
+
+        // A delete made after seeing the update isn't a conflict, one made
+        // without seeing it is
+        let mut delete = json!({
+            "id": "op-4",
+            "hlc": "c",
+            "entityType": "client",
+            "entityId": "c-1",
+            "opType": "delete",
+            "createdBy": "phone",
+            "baseHlc": "b",
+        });
+        assert!(detect(&desktop, &delete, now()).is_none());
+        delete["baseHlc"] = json!("a");
+        assert_eq!(
+            detect(&desktop, &delete, now()).unwrap().conflict_type,
+            ConflictType::DeleteVsUpdate
+        );
+    }
+
+    #[test]
+    fn test_sequential_or_identical_updates_do_not_conflict() {
+        let desktop = update("op-1", "desktop", "a", json!("Sara"), json!("Sara H."));
+
+        // The phone saw the desktop edit first
+        let after = update("op-2", "phone", "b", json!("Sara H."), json!("Sarah"));
+        assert!(detect(&desktop, &after, now()).is_none());
+
+        // Both made the same change
+        let same = update("op-3", "phone", "b", json!("Sara"), json!("Sara H."));
+        assert!(detect(&desktop, &same, now()).is_none());
+
+        // Same author
+        let own = update("op-4", "desktop", "b", json!("Sara"), json!("Sarah"));
+        assert!(detect(&desktop, &own, now()).is_none());
+    }
+
+    #[test]
+    fn test_money_and_delete_classification() {
+        let mut a = update("op-1", "desktop", "a", json!(100), json!(120));
+        let mut b = update("op-2", "phone", "b", json!(100), json!(150));
+        a["entityType"] = json!("transaction");
+        b["entityType"] = json!("transaction");
+        a["field"] = json!("amountMinor");
+        b["field"] = json!("amountMinor");
+        assert_eq!(
+            detect(&a, &b, now()).unwrap().conflict_type,
+            ConflictType::MoneyEventVersion
+        );
+
+        let delete = json!({
+            "id": "op-3",
+            "hlc": "c",
+            "entityType": "transaction",
+            "entityId": "c-1",
+            "opType": "delete",
+            "createdBy": "phone",
+        });
+        assert_eq!(
+            detect(&a, &delete, now()).unwrap().conflict_type,
+            ConflictType::DeleteVsUpdate
+        );
+    }
+}
@@ -59,7 +59,7 @@ impl NonceSequence for SingleNonce {
 }
 
 /// Derive a 256-bit key from a passphrase using Argon2id
-fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], CryptoError> {
+pub(crate) fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], CryptoError> {
     let argon2 = Argon2::default();
 
     // Create a salt string from the raw bytes
@@ -141,6 +141,53 @@ pub fn decrypt(bundle: &EncryptedBundle, passphrase: &str) -> Result<Vec<u8>, Cr
     Ok(plaintext.to_vec())
 }
 
+/// Data sealed with a raw 256-bit key (no key derivation)
+#[derive(Serialize, Deserialize)]
+pub struct SealedBox {
+    /// Nonce/IV used for encryption (base64)
+    pub nonce: String,
+    /// Encrypted data (base64)
+    pub ciphertext: String,
+}
+
+/// Encrypt data with a 256-bit key
+pub fn seal(data: &[u8], key: &[u8; 32]) -> Result<SealedBox, CryptoError> {
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    getrandom::fill(&mut nonce_bytes).map_err(|_| CryptoError::Encryption)?;
+
+    let unbound_key = UnboundKey::new(&AES_256_GCM, key).map_err(|_| CryptoError::Encryption)?;
+    let mut sealing_key = SealingKey::new(unbound_key, SingleNonce::new(nonce_bytes));
+
+    let mut in_out = data.to_vec();
+    sealing_key
+        .seal_in_place_append_tag(Aad::empty(), &mut in_out)
+        .map_err(|_| CryptoError::Encryption)?;
+
+    Ok(SealedBox {
+        nonce: BASE64.encode(nonce_bytes),
+        ciphertext: BASE64.encode(&in_out),
+    })
+}
+
+/// Decrypt data sealed with `seal`
+pub fn open(sealed: &SealedBox, key: &[u8; 32]) -> Result<Vec<u8>, CryptoError> {
+    let nonce_bytes: [u8; NONCE_LEN] = BASE64
+        .decode(&sealed.nonce)
+        .map_err(|_| CryptoError::InvalidFormat)?
+        .try_into()
+        .map_err(|_| CryptoError::InvalidFormat)?;
+    let mut ciphertext = BASE64.decode(&sealed.ciphertext).map_err(|_| CryptoError::InvalidFormat)?;
+
+    let unbound_key = UnboundKey::new(&AES_256_GCM, key).map_err(|_| CryptoError::Decryption)?;
+    let mut opening_key = OpeningKey::new(unbound_key, SingleNonce::new(nonce_bytes));
+
+    let plaintext = opening_key
+        .open_in_place(Aad::empty(), &mut ciphertext)
+        .map_err(|_| CryptoError::Decryption)?;
+
+    Ok(plaintext.to_vec())
+}
+
 // Re-export getrandom for nonce generation
 mod getrandom {
     use rand::RngCore;
@@ -177,4 +224,12 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_seal_open_roundtrip() {
+        let key = [7u8; 32];
+        let sealed = seal(b"ops", &key).expect("Sealing failed");
+        assert_eq!(open(&sealed, &key).expect("Opening failed"), b"ops");
+        assert!(open(&sealed, &[8u8; 32]).is_err());
+    }
 }
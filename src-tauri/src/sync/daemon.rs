@@ -0,0 +1,522 @@
+//! Headless Sync Daemon
+//!
+//! Runs the sync server, pairing, mDNS advertiser and durable op storage
+//! without a webview, for an always-on hub (e.g. a Raspberry Pi on the office
+//! LAN). Entry point for the `mutaba3a-syncd` binary.
+//!
+//! Configured by a TOML file (see `syncd.example.toml`) and controlled by
+//! signals:
+//! - SIGINT / SIGTERM: stop
+//! - SIGHUP: start a pairing session and print its code and QR code
+//! - SIGUSR1: print paired devices and how far behind they are
+//!
+//! Without a webview nothing applies incoming ops locally: they are relayed
+//! through the op log, and the inbox is acknowledged as it fills.
+
+use super::clock::{Entropy, SyncEnv};
+use super::discovery::MdnsAdvertiser;
+use super::inbox::PendingOpsStore;
+use super::oplog::OpLog;
+use super::pairing::{PairStartResponse, PairingManager, PairingStatus};
+use super::persistence::PersistenceManager;
+use super::protocol::ProtocolConfig;
+use super::relay::{RelayConfig, RelayServer};
+use super::network::NetworkConfig;
+use super::server::{ServerOptions, SyncServer, DEFAULT_PORT};
+use super::status::SyncStatusTracker;
+use super::supervisor::TaskSupervisor;
+use serde::Deserialize;
+use std::io::IsTerminal;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const DEVICE_ID_FILE: &str = "device_id";
+const TICK: Duration = Duration::from_secs(1);
+
+// ============================================================================
+// Configuration
+// ============================================================================
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Name shown to phones when pairing (defaults to the hostname)
+    pub device_name: String,
+    /// Fixed device id; generated once and stored in `data_dir` when unset
+    pub device_id: Option<String>,
+    /// Where paired devices, the op log, inbox and audit log are kept
+    pub data_dir: PathBuf,
+    pub port: u16,
+    /// Advertise the server over mDNS
+    pub advertise: bool,
+    /// Allow devices that enter the right code without asking on the terminal.
+    /// Devices showing a verification code (SAS), which is every device that
+    /// runs the pairing key exchange, are still asked about: only a person
+    /// can compare it.
+    pub auto_approve_pairing: bool,
+    /// Also serve a mailbox relay (`[relay]` table) for devices off the LAN
+    pub relay: Option<RelayConfig>,
+    /// Protocol versions and legacy routes (`[protocol]` table)
+    pub protocol: ProtocolConfig,
+    /// Interfaces to listen on and allowed peers (`[network]` table)
+    pub network: NetworkConfig,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            device_name: hostname::get()
+                .ok()
+                .and_then(|h| h.into_string().ok())
+                .unwrap_or_else(|| "Sync Hub".to_string()),
+            device_id: None,
+            data_dir: PathBuf::from("mutaba3a-syncd-data"),
+            port: DEFAULT_PORT,
+            advertise: true,
+            auto_approve_pairing: false,
+            relay: None,
+            protocol: ProtocolConfig::default(),
+            network: NetworkConfig::default(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    /// Parse a TOML configuration
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        toml::from_str(content).map_err(|e| format!("Invalid config: {}", e))
+    }
+
+    /// Load the configuration file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::from_toml(&content)
+    }
+}
+
+/// Device id from the config, or the one stored in the data directory
+fn load_or_create_device_id(
+    config: &DaemonConfig,
+    entropy: &dyn Entropy,
+) -> Result<String, String> {
+    if let Some(id) = &config.device_id {
+        return Ok(id.clone());
+    }
+
+    let path = config.data_dir.join(DEVICE_ID_FILE);
+    if let Ok(id) = std::fs::read_to_string(&path) {
+        let id = id.trim();
+        if !id.is_empty() {
+            return Ok(id.to_string());
+        }
+    }
+
+    let mut bytes = [0u8; 16];
+    entropy.fill_bytes(&mut bytes);
+    let id = uuid::Builder::from_random_bytes(bytes)
+        .into_uuid()
+        .to_string();
+    std::fs::write(&path, &id).map_err(|e| format!("Failed to store device id: {}", e))?;
+    Ok(id)
+}
+
+// ============================================================================
+// Signals
+// ============================================================================
+
+enum DaemonSignal {
+    Stop,
+    Pair,
+    Status,
+}
+
+#[cfg(unix)]
+struct Signals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+    hangup: tokio::signal::unix::Signal,
+    user1: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> Result<Self, String> {
+        use tokio::signal::unix::{signal, SignalKind};
+        let listen =
+            |kind| signal(kind).map_err(|e| format!("Failed to listen for signals: {}", e));
+        Ok(Self {
+            interrupt: listen(SignalKind::interrupt())?,
+            terminate: listen(SignalKind::terminate())?,
+            hangup: listen(SignalKind::hangup())?,
+            user1: listen(SignalKind::user_defined1())?,
+        })
+    }
+
+    async fn next(&mut self) -> DaemonSignal {
+        tokio::select! {
+            _ = self.interrupt.recv() => DaemonSignal::Stop,
+            _ = self.terminate.recv() => DaemonSignal::Stop,
+            _ = self.hangup.recv() => DaemonSignal::Pair,
+            _ = self.user1.recv() => DaemonSignal::Status,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    fn new() -> Result<Self, String> {
+        Ok(Self)
+    }
+
+    async fn next(&mut self) -> DaemonSignal {
+        let _ = tokio::signal::ctrl_c().await;
+        DaemonSignal::Stop
+    }
+}
+
+// ============================================================================
+// Daemon
+// ============================================================================
+
+/// Run the daemon until it is asked to stop. With `pair_on_start`, a pairing
+/// session is opened right away.
+pub async fn run(config: DaemonConfig, pair_on_start: bool) -> Result<(), String> {
+    std::fs::create_dir_all(&config.data_dir)
+        .map_err(|e| format!("Failed to create {}: {}", config.data_dir.display(), e))?;
+    let env = SyncEnv::default();
+    let device_id = load_or_create_device_id(&config, env.entropy.as_ref())?;
+
+    let pending_ops = PendingOpsStore::with_clock(config.data_dir.clone(), env.clock.as_ref())
+        .map_err(|e| format!("Failed to open inbox: {}", e))?;
+    let op_log = OpLog::with_clock(config.data_dir.clone(), Arc::clone(&env.clock))
+        .map_err(|e| format!("Failed to open op log: {}", e))?;
+
+    let tasks = TaskSupervisor::default();
+    let status = SyncStatusTracker::new(Arc::clone(&pending_ops), Arc::clone(&op_log), None);
+    // Never shuts down on idle
+    let options = ServerOptions {
+        port: config.port,
+        protocol: config.protocol.clone(),
+        network: config.network.clone(),
+        env,
+        ..ServerOptions::new(
+            device_id.clone(),
+            config.device_name.clone(),
+            config.data_dir.clone(),
+            Arc::clone(&pending_ops),
+            Arc::clone(&op_log),
+            Arc::clone(&status),
+        )
+    };
+    let (mut server, port) = SyncServer::start(options, &tasks).await?;
+    status.listening(port, None).await;
+
+    let _advertiser = if config.advertise {
+        let addresses: Vec<IpAddr> = server.addresses().iter().map(|a| a.ip).collect();
+        Some(MdnsAdvertiser::new(
+            &device_id,
+            &config.device_name,
+            port,
+            &addresses,
+            "",
+        )?)
+    } else {
+        None
+    };
+
+    println!(
+        "mutaba3a-syncd: '{}' listening on port {} (data: {})",
+        config.device_name,
+        port,
+        config.data_dir.display()
+    );
+
+    let mut relay = match &config.relay {
+        Some(relay_config) => {
+            let (relay, relay_port) =
+                RelayServer::start(&config.data_dir, relay_config.clone(), &tasks).await?;
+            println!("mutaba3a-syncd: mailbox relay on port {}", relay_port);
+            Some(relay)
+        }
+        None => None,
+    };
+
+    let persistence = Arc::clone(&server.persistence);
+    let pairing_manager = Arc::clone(&server.pairing_manager);
+    let hosts = server.host_candidates();
+    let mut signals = Signals::new()?;
+    let mut pairing: Option<PairingWatch> = None;
+
+    if pair_on_start {
+        pairing = start_pairing(&pairing_manager, &hosts, port).await;
+    }
+
+    let mut tick = tokio::time::interval(TICK);
+    loop {
+        tokio::select! {
+            signal = signals.next() => match signal {
+                DaemonSignal::Stop => break,
+                DaemonSignal::Pair => pairing = start_pairing(&pairing_manager, &hosts, port).await,
+                DaemonSignal::Status => print_status(&persistence, &op_log).await,
+            },
+            _ = tick.tick() => {
+                // Nothing applies ops here; the op log relays them
+                if let Err(e) = pending_ops.ack(u64::MAX).await {
+                    log::error!("Failed to clear inbox: {}", e);
+                }
+                if let Some(watch) = pairing.as_mut() {
+                    if !watch.poll(&pairing_manager, config.auto_approve_pairing, &tasks).await {
+                        pairing = None;
+                    }
+                }
+            }
+        }
+    }
+
+    println!("mutaba3a-syncd: stopping");
+    for (path, hits) in server.legacy_usage.snapshot() {
+        println!("mutaba3a-syncd: legacy route {} used {} times", path, hits);
+    }
+    server.stop();
+    if let Some(relay) = relay.as_mut() {
+        relay.stop();
+    }
+    tasks.shutdown().await;
+    Ok(())
+}
+
+/// Open a pairing session and print how to join it
+async fn start_pairing(
+    pairing_manager: &Arc<PairingManager>,
+    hosts: &[String],
+    port: u16,
+) -> Option<PairingWatch> {
+    match pairing_manager.create_session(hosts.to_vec(), port).await {
+        Ok(session) => {
+            print_pairing(&session);
+            Some(PairingWatch {
+                pairing_id: session.pairing_id,
+                prompted: false,
+            })
+        }
+        Err(e) => {
+            eprintln!("Could not start pairing: {}", e.error);
+            None
+        }
+    }
+}
+
+fn print_pairing(session: &PairStartResponse) {
+    println!();
+    println!(
+        "Pairing code: {}  (expires {})",
+        session.code, session.expires_at
+    );
+    println!(
+        "Addresses: {}:{}",
+        session.host_candidates.join(", "),
+        session.port
+    );
+    match qrcode::QrCode::new(session.qr_payload.as_bytes()) {
+        Ok(code) => {
+            let image = code
+                .render::<qrcode::render::unicode::Dense1x2>()
+                .quiet_zone(true)
+                .build();
+            println!("{}", image);
+        }
+        Err(e) => log::warn!("Could not render pairing QR code: {}", e),
+    }
+}
+
+/// A pairing session the daemon is waiting on
+struct PairingWatch {
+    pairing_id: String,
+    prompted: bool,
+}
+
+impl PairingWatch {
+    /// Check the session, asking for approval once the device has entered
+    /// the code. The prompt runs on `tasks`. Returns false once the session
+    /// is finished.
+    async fn poll(
+        &mut self,
+        pairing_manager: &Arc<PairingManager>,
+        auto_approve: bool,
+        tasks: &TaskSupervisor,
+    ) -> bool {
+        let Ok(status) = pairing_manager.get_status(&self.pairing_id).await else {
+            return false;
+        };
+
+        match status.status {
+            PairingStatus::Pending => true,
+            PairingStatus::AwaitingApproval if !self.prompted => {
+                self.prompted = true;
+                let name = status.device_name.unwrap_or_default();
+                if let Some(sas) = &status.sas {
+                    println!(
+                        "Check that '{}' shows: {} {}",
+                        name,
+                        sas.number,
+                        sas.emoji.join(" ")
+                    );
+                }
+                if auto_approve && status.sas.is_none() {
+                    decide(pairing_manager, &self.pairing_id, &name, true).await;
+                } else if std::io::stdin().is_terminal() {
+                    let manager = Arc::clone(pairing_manager);
+                    let pairing_id = self.pairing_id.clone();
+                    let token = tasks.token();
+                    tasks.spawn("pairing-prompt", async move {
+                        let question = format!("Allow '{}' to sync? [y/N] ", name);
+                        tokio::select! {
+                            allowed = prompt_yes_no(&question) => {
+                                decide(&manager, &pairing_id, &name, allowed).await;
+                            }
+                            // Stopping; the session expires unanswered
+                            _ = token.cancelled() => {}
+                        }
+                    });
+                } else {
+                    let hint = if status.sas.is_some() {
+                        "Run with --pair interactively to compare the code."
+                    } else {
+                        "Run with --pair interactively or set auto_approve_pairing."
+                    };
+                    println!(
+                        "'{}' is waiting for approval, but there is no terminal to ask on. {}",
+                        name, hint
+                    );
+                }
+                true
+            }
+            PairingStatus::AwaitingApproval => true,
+            PairingStatus::Verified => {
+                println!("Paired with '{}'", status.device_name.unwrap_or_default());
+                false
+            }
+            PairingStatus::Denied | PairingStatus::Expired | PairingStatus::Failed => {
+                println!("Pairing session ended ({:?})", status.status);
+                false
+            }
+        }
+    }
+}
+
+async fn decide(pairing_manager: &PairingManager, pairing_id: &str, name: &str, allow: bool) {
+    // Any SAS was printed above and allowed on the terminal, so it was checked
+    let result = if allow {
+        pairing_manager.approve(pairing_id, true).await
+    } else {
+        pairing_manager.deny(pairing_id).await
+    };
+    if let Err(e) = result {
+        eprintln!(
+            "Could not {} '{}': {}",
+            if allow { "allow" } else { "deny" },
+            name,
+            e.error
+        );
+    }
+}
+
+async fn prompt_yes_no(question: &str) -> bool {
+    print!("{}", question);
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    let mut line = String::new();
+    let mut stdin = BufReader::new(tokio::io::stdin());
+    match stdin.read_line(&mut line).await {
+        Ok(_) => matches!(line.trim(), "y" | "Y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+async fn print_status(persistence: &PersistenceManager, op_log: &OpLog) {
+    let devices = match persistence.get_active_devices().await {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("Failed to load paired devices: {}", e);
+            return;
+        }
+    };
+
+    println!(
+        "{} ops held, {} paired devices",
+        op_log.count().await,
+        devices.len()
+    );
+    for device in devices {
+        let behind = op_log.count_after(&device.id, device.delivered_seq()).await;
+        println!(
+            "  {}  last sync {}  {} ops behind",
+            device.name,
+            device.last_sync_at.as_deref().unwrap_or("never"),
+            behind
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::clock::SeededEntropy;
+
+    #[test]
+    fn test_config_defaults_and_overrides() {
+        let config = DaemonConfig::from_toml(
+            r#"
+            device_name = "Office Pi"
+            data_dir = "/var/lib/mutaba3a"
+            port = 5000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.device_name, "Office Pi");
+        assert_eq!(config.data_dir, PathBuf::from("/var/lib/mutaba3a"));
+        assert_eq!(config.port, 5000);
+        assert!(config.advertise);
+        assert!(!config.auto_approve_pairing);
+        assert!(config.relay.is_none());
+
+        let with_relay = DaemonConfig::from_toml("[relay]\nmessage_ttl_hours = 48").unwrap();
+        let relay = with_relay.relay.unwrap();
+        assert_eq!(relay.message_ttl_hours, 48);
+        assert_eq!(relay.port, RelayConfig::default().port);
+        assert!(with_relay.protocol.legacy_routes);
+
+        let strict = DaemonConfig::from_toml("[protocol]\nlegacy_routes = false").unwrap();
+        assert!(!strict.protocol.legacy_routes);
+
+        let office =
+            DaemonConfig::from_toml("[network]\nsubnets = [\"192.168.1.0/24\"]").unwrap();
+        assert_eq!(office.network.subnets, ["192.168.1.0/24".parse().unwrap()]);
+        assert_eq!(office.network.allowed_peers, NetworkConfig::default().allowed_peers);
+        assert!(DaemonConfig::from_toml("[network]\nsubnets = [\"lan\"]").is_err());
+
+        assert!(DaemonConfig::from_toml("prot = 5000").is_err());
+    }
+
+    #[test]
+    fn test_device_id_is_generated_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DaemonConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let id = load_or_create_device_id(&config, SeededEntropy::new(1).as_ref()).unwrap();
+        let other = SeededEntropy::new(2);
+        assert_eq!(
+            load_or_create_device_id(&config, other.as_ref()).unwrap(),
+            id
+        );
+    }
+}
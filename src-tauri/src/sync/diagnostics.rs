@@ -0,0 +1,404 @@
+//! Sync Diagnostics
+//!
+//! Troubleshooting for "my phone can't find the desktop". `run` checks the
+//! pieces a phone depends on, in the order it uses them: which addresses we
+//! advertise, whether the sync port accepts connections on loopback and
+//! listens on each advertised address, whether each interface can join the
+//! mDNS multicast group, and whether our mDNS service shows up in our own
+//! browser. The report ends with an issue code (and its parameters) for
+//! whatever failed; the UI shows the fix from `syncDiagnostics.<code>`.
+//!
+//! Connections to our own LAN addresses never leave this machine, so the
+//! per-address check only shows the server listens there. It can't tell
+//! whether a firewall or the network blocks the phone.
+
+use super::discovery::{discover_peers, MdnsAdvertiser};
+use super::error::Severity;
+use super::network::{self, InterfaceKind, LocalAddress, NetworkConfig};
+use super::server::{bind_with_fallback, DEFAULT_PORT};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+/// Groups used by mDNS
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long to browse for our own mDNS service
+const BROWSE_SECONDS: u64 = 3;
+
+// ============================================================================
+// Types
+// ============================================================================
+
+/// Outcome of one check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Probe {
+    pub ok: bool,
+    /// Why it failed
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterfaceReport {
+    pub name: String,
+    pub address: String,
+    pub kind: InterfaceKind,
+    /// Listened on and advertised over mDNS
+    pub advertised: bool,
+    /// The sync port accepts connections on this address from this machine;
+    /// None when the address isn't advertised
+    pub listening: Option<Probe>,
+    /// This interface can join the mDNS multicast group
+    pub multicast: Probe,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MdnsReport {
+    /// The sync server's advertiser is running
+    pub registered: bool,
+    /// Our service was found by browsing, using a temporary advertiser
+    /// when the server's isn't running
+    pub visible: Probe,
+}
+
+/// Something that failed, with a suggested fix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Issue {
+    NoLanInterface,
+    PortUnavailable,
+    LoopbackUnreachable,
+    NotListening { interface: String, address: String },
+    VpnActive { interface: String },
+    VirtualAdvertised { interface: String, address: String },
+    MulticastUnavailable { interface: String },
+    MdnsNotRegistered,
+    MdnsNotVisible,
+}
+
+/// An issue found; the UI words the fix from its `code` and fields
+#[derive(Debug, Clone, Serialize)]
+pub struct Suggestion {
+    #[serde(flatten)]
+    pub issue: Issue,
+    pub severity: Severity,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsReport {
+    pub ran_at: DateTime<Utc>,
+    pub server_running: bool,
+    /// Port checked: the server's, or a temporary one when it isn't running
+    pub port: Option<u16>,
+    pub loopback: Probe,
+    pub interfaces: Vec<InterfaceReport>,
+    pub mdns: MdnsReport,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Probe {
+    fn pass() -> Self {
+        Self {
+            ok: true,
+            detail: None,
+        }
+    }
+
+    fn fail(detail: impl ToString) -> Self {
+        Self {
+            ok: false,
+            detail: Some(detail.to_string()),
+        }
+    }
+}
+
+// ============================================================================
+// Checks
+// ============================================================================
+
+/// Run every check. `server` is the running server's port and addresses and
+/// `advertised_device_id` its mDNS advertisement, if any; otherwise a
+/// temporary listener and advertiser on the default selection stand in.
+pub async fn run(
+    server: Option<(u16, Vec<LocalAddress>)>,
+    advertised_device_id: Option<String>,
+) -> DiagnosticsReport {
+    let server_running = server.is_some();
+    // Keep the temporary listeners open until the checks are done
+    let mut probe_listeners: Vec<TcpListener> = Vec::new();
+    let (port, selected) = match server {
+        Some((port, addresses)) => (Some(port), addresses),
+        None => {
+            let addresses = NetworkConfig::default().selected();
+            match bind_with_fallback(DEFAULT_PORT, &addresses).await {
+                Ok((listeners, port, _)) => {
+                    probe_listeners = listeners;
+                    (Some(port), addresses)
+                }
+                Err(e) => {
+                    log::warn!("Diagnostics could not bind a port: {}", e);
+                    (None, addresses)
+                }
+            }
+        }
+    };
+
+    let loopback = match port {
+        Some(port) => probe_tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, port))).await,
+        None => Probe::fail("No port to check"),
+    };
+
+    let mut interfaces = Vec::new();
+    for local in network::local_addresses() {
+        let advertised = selected.iter().any(|a| a.ip == local.ip);
+        let listening = match port {
+            Some(port) if advertised => Some(probe_tcp(local.socket_addr(port)).await),
+            Some(_) => None,
+            None => Some(Probe::fail("No port to check")),
+        };
+        interfaces.push(InterfaceReport {
+            address: local.ip.to_string(),
+            kind: local.kind,
+            advertised,
+            listening,
+            multicast: probe_multicast(&local).await,
+            name: local.interface,
+        });
+    }
+
+    let mdns = probe_mdns(port, &selected, advertised_device_id).await;
+    drop(probe_listeners);
+
+    let suggestions = suggest(port, &loopback, &interfaces, &mdns);
+    DiagnosticsReport {
+        ran_at: Utc::now(),
+        server_running,
+        port,
+        loopback,
+        interfaces,
+        mdns,
+        suggestions,
+    }
+}
+
+async fn probe_tcp(addr: SocketAddr) -> Probe {
+    match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => Probe::pass(),
+        Ok(Err(e)) => Probe::fail(e),
+        Err(_) => Probe::fail("Timed out"),
+    }
+}
+
+async fn probe_multicast(local: &LocalAddress) -> Probe {
+    let unspecified: IpAddr = match local.ip {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = match UdpSocket::bind(SocketAddr::new(unspecified, 0)).await {
+        Ok(socket) => socket,
+        Err(e) => return Probe::fail(e),
+    };
+    let joined = match local.ip {
+        IpAddr::V4(ip) => socket.join_multicast_v4(MDNS_GROUP, ip),
+        IpAddr::V6(_) => socket.join_multicast_v6(&MDNS_GROUP_V6, local.scope_id),
+    };
+    match joined {
+        Ok(()) => Probe::pass(),
+        Err(e) => Probe::fail(e),
+    }
+}
+
+/// Browse for our own service, advertising a temporary one if needed
+async fn probe_mdns(
+    port: Option<u16>,
+    selected: &[LocalAddress],
+    advertised_device_id: Option<String>,
+) -> MdnsReport {
+    let registered = advertised_device_id.is_some();
+    let mut probe_advertiser = None;
+    let device_id = match (advertised_device_id, port) {
+        (Some(id), _) => id,
+        (None, Some(port)) => {
+            let id = format!("diagnostics-{}", uuid::Uuid::new_v4());
+            let addresses: Vec<IpAddr> = selected.iter().map(|a| a.ip).collect();
+            match MdnsAdvertiser::new(&id, "Sync diagnostics", port, &addresses, "") {
+                Ok(advertiser) => probe_advertiser = Some(advertiser),
+                Err(e) => {
+                    return MdnsReport {
+                        registered,
+                        visible: Probe::fail(e),
+                    }
+                }
+            }
+            id
+        }
+        (None, None) => {
+            return MdnsReport {
+                registered,
+                visible: Probe::fail("No port to advertise"),
+            }
+        }
+    };
+
+    let visible = match discover_peers(BROWSE_SECONDS).await {
+        Ok(peers) if peers.iter().any(|p| p.device_id == device_id) => Probe::pass(),
+        Ok(_) => Probe::fail("Our service was not seen on the network"),
+        Err(e) => Probe::fail(e),
+    };
+    drop(probe_advertiser);
+    MdnsReport {
+        registered,
+        visible,
+    }
+}
+
+// ============================================================================
+// Suggestions
+// ============================================================================
+
+fn suggest(
+    port: Option<u16>,
+    loopback: &Probe,
+    interfaces: &[InterfaceReport],
+    mdns: &MdnsReport,
+) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    let mut add = |issue: Issue, severity: Severity| {
+        // An interface with several addresses is reported once
+        if suggestions.iter().any(|s: &Suggestion| s.issue == issue) {
+            return;
+        }
+        suggestions.push(Suggestion { issue, severity });
+    };
+
+    if port.is_none() {
+        add(Issue::PortUnavailable, Severity::Error);
+    } else if !loopback.ok {
+        add(Issue::LoopbackUnreachable, Severity::Error);
+    }
+
+    if !interfaces.iter().any(|i| i.advertised) {
+        add(Issue::NoLanInterface, Severity::Error);
+    }
+    for iface in interfaces {
+        match iface.kind {
+            InterfaceKind::Vpn => add(
+                Issue::VpnActive {
+                    interface: iface.name.clone(),
+                },
+                Severity::Warning,
+            ),
+            InterfaceKind::Virtual if iface.advertised => add(
+                Issue::VirtualAdvertised {
+                    interface: iface.name.clone(),
+                    address: iface.address.clone(),
+                },
+                Severity::Warning,
+            ),
+            _ => {}
+        }
+        if !iface.advertised {
+            continue;
+        }
+        let refused = iface.listening.as_ref().is_some_and(|probe| !probe.ok);
+        if port.is_some() && loopback.ok && refused {
+            add(
+                Issue::NotListening {
+                    interface: iface.name.clone(),
+                    address: iface.address.clone(),
+                },
+                Severity::Error,
+            );
+        }
+        if !iface.multicast.ok {
+            add(
+                Issue::MulticastUnavailable {
+                    interface: iface.name.clone(),
+                },
+                Severity::Warning,
+            );
+        }
+    }
+
+    if !mdns.registered {
+        add(Issue::MdnsNotRegistered, Severity::Warning);
+    }
+    if !mdns.visible.ok {
+        add(Issue::MdnsNotVisible, Severity::Warning);
+    }
+    suggestions
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iface(name: &str, kind: InterfaceKind, listening: bool) -> InterfaceReport {
+        InterfaceReport {
+            name: name.to_string(),
+            address: "192.168.1.20".to_string(),
+            kind,
+            advertised: true,
+            listening: Some(if listening {
+                Probe::pass()
+            } else {
+                Probe::fail("refused")
+            }),
+            multicast: Probe::pass(),
+        }
+    }
+
+    #[test]
+    fn test_suggests_fixes_for_failed_checks() {
+        let mdns = MdnsReport {
+            registered: true,
+            visible: Probe::pass(),
+        };
+        let healthy = [iface("en0", InterfaceKind::Physical, true)];
+        assert!(suggest(Some(4242), &Probe::pass(), &healthy, &mdns).is_empty());
+
+        let interfaces = [
+            iface("en0", InterfaceKind::Physical, false),
+            iface("utun3", InterfaceKind::Vpn, true),
+            iface("utun3", InterfaceKind::Vpn, true),
+            iface("docker0", InterfaceKind::Virtual, true),
+        ];
+        let found: Vec<_> = suggest(Some(4242), &Probe::pass(), &interfaces, &mdns)
+            .into_iter()
+            .map(|s| serde_json::to_value(&s).unwrap())
+            .collect();
+        let codes: Vec<_> = found.iter().map(|s| s["code"].clone()).collect();
+        assert_eq!(codes, ["not_listening", "vpn_active", "virtual_advertised"]);
+        // Parameters for the UI's text, which isn't part of the report
+        assert_eq!(found[0]["address"], "192.168.1.20");
+        assert!(found[0].get("fix").is_none());
+
+        // Nothing advertised: nothing a phone could reach
+        let mut unselected = iface("en0", InterfaceKind::Physical, true);
+        unselected.advertised = false;
+        unselected.listening = None;
+        let suggestions = suggest(None, &Probe::fail("no port"), &[unselected], &mdns);
+        assert_eq!(suggestions[0].issue, Issue::PortUnavailable);
+        assert_eq!(suggestions[1].issue, Issue::NoLanInterface);
+    }
+
+    #[tokio::test]
+    async fn test_probes_a_listening_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert!(probe_tcp(addr).await.ok);
+
+        drop(listener);
+        assert!(!probe_tcp(addr).await.ok);
+    }
+}
@@ -2,9 +2,10 @@
 //!
 //! Provides mDNS service advertisement and discovery for finding sync peers on the local network.
 
-use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock;
@@ -28,17 +29,24 @@ pub struct DiscoveredPeer {
 pub struct MdnsAdvertiser {
     daemon: ServiceDaemon,
     service_fullname: String,
+    device_id: String,
 }
 
 impl MdnsAdvertiser {
-    /// Create and start advertising the sync service
+    /// Create and start advertising the sync service with `addresses`, on
+    /// their interfaces only
     pub fn new(
         device_id: &str,
         device_name: &str,
         port: u16,
+        addresses: &[IpAddr],
         public_key_fingerprint: &str,
     ) -> Result<Self, String> {
         let daemon = ServiceDaemon::new().map_err(|e| e.to_string())?;
+        daemon
+            .disable_interface(IfKind::All)
+            .and_then(|_| daemon.enable_interface(addresses.to_vec()))
+            .map_err(|e| e.to_string())?;
 
         // Create service name from device ID (shortened for mDNS)
         let service_name = format!("{}-{}", SERVICE_NAME_PREFIX, &device_id[..8]);
@@ -60,7 +68,7 @@ impl MdnsAdvertiser {
             SERVICE_TYPE,
             &service_name,
             &format!("{}.local.", hostname),
-            "",
+            addresses,
             port,
             properties,
         )
@@ -71,9 +79,15 @@ impl MdnsAdvertiser {
         Ok(Self {
             daemon,
             service_fullname: service.get_fullname().to_string(),
+            device_id: device_id.to_string(),
         })
     }
 
+    /// Device ID in the advertised service
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
     /// Stop advertising
     pub fn stop(&self) -> Result<(), String> {
         self.daemon
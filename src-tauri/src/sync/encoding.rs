@@ -0,0 +1,218 @@
+//! Sync Payload Encoding
+//!
+//! Pull and push bodies are JSON unless the client asks for CBOR, which is
+//! more compact and cheaper to parse: a request body sent with
+//! `Content-Type: application/cbor` is decoded as CBOR, and the response is
+//! CBOR when `Accept` lists `application/cbor`.
+//!
+//! Compression (zstd, gzip) uses the standard `Content-Encoding` and
+//! `Accept-Encoding` headers and is handled by tower-http layers on the
+//! `/v1/sync` routes. Both are advertised by `/v1/sync/hello` and
+//! `/v1/sync/status`.
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+/// Body formats the sync routes accept and produce
+pub const CONTENT_TYPES: [&str; 2] = [JSON_CONTENT_TYPE, CBOR_CONTENT_TYPE];
+/// Compression the sync routes accept and produce, preferred first
+pub const CONTENT_ENCODINGS: [&str; 2] = ["zstd", "gzip"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Cbor,
+}
+
+impl Format {
+    /// Format of a request body, from its `Content-Type`
+    pub fn of_body(headers: &HeaderMap) -> Self {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        if media_type(content_type) == CBOR_CONTENT_TYPE {
+            Format::Cbor
+        } else {
+            Format::Json
+        }
+    }
+
+    /// Response format the client asked for in `Accept` (JSON by default)
+    pub fn accepted(headers: &HeaderMap) -> Self {
+        let accepts_cbor = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|entry| media_type(entry) == CBOR_CONTENT_TYPE && quality(entry) > 0.0);
+        if accepts_cbor {
+            Format::Cbor
+        } else {
+            Format::Json
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => JSON_CONTENT_TYPE,
+            Format::Cbor => CBOR_CONTENT_TYPE,
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes).map_err(|e| e.to_string())?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Format::Cbor => ciborium::from_reader(bytes).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// "application/cbor; charset=..." -> "application/cbor"
+fn media_type(value: &str) -> &str {
+    value.split(';').next().unwrap_or_default().trim()
+}
+
+/// The `q` parameter of an `Accept` entry (1 if absent)
+fn quality(entry: &str) -> f32 {
+    entry
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.trim().strip_prefix("q="))
+        .find_map(|q| q.trim().parse().ok())
+        .unwrap_or(1.0)
+}
+
+/// Request body decoded according to its `Content-Type`. JSON bodies go
+/// through axum's `Json` extractor, so its rejections are unchanged.
+pub struct Payload<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for Payload<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match Format::of_body(req.headers()) {
+            Format::Json => Json::<T>::from_request(req, state)
+                .await
+                .map(|Json(value)| Payload(value))
+                .map_err(IntoResponse::into_response),
+            Format::Cbor => {
+                let bytes = Bytes::from_request(req, state)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                Format::Cbor.decode(&bytes).map(Payload).map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Invalid CBOR body: {}", e)).into_response()
+                })
+            }
+        }
+    }
+}
+
+/// Response body in the negotiated format
+pub struct Encoded<T>(pub Format, pub T);
+
+impl<T: Serialize> IntoResponse for Encoded<T> {
+    fn into_response(self) -> Response {
+        let Encoded(format, value) = self;
+        if format == Format::Json {
+            return Json(value).into_response();
+        }
+        match format.encode(&value) {
+            Ok(bytes) => ([(header::CONTENT_TYPE, format.content_type())], bytes).into_response(),
+            Err(e) => {
+                log::error!("Failed to encode response: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    #[test]
+    fn test_negotiates_format_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(Format::of_body(&headers), Format::Json);
+        assert_eq!(Format::accepted(&headers), Format::Json);
+
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/cbor"),
+        );
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/json;q=0.5, application/cbor"),
+        );
+        assert_eq!(Format::of_body(&headers), Format::Cbor);
+        assert_eq!(Format::accepted(&headers), Format::Cbor);
+
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/cbor;q=0.5"),
+        );
+        assert_eq!(Format::accepted(&headers), Format::Cbor);
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/cbor;q=0"),
+        );
+        assert_eq!(Format::accepted(&headers), Format::Json);
+    }
+
+    #[test]
+    fn test_cbor_roundtrip_is_smaller() {
+        let ops: Vec<serde_json::Value> = (0..50)
+            .map(|i| {
+                json!({
+                    "id": format!("op-{}", i),
+                    "hlc": format!("0001700000000{:03}:0000:desktop", i),
+                    "entityType": "transaction",
+                    "entityId": "t-1",
+                    "opType": "update",
+                    "field": "amountMinor",
+                    "value": i * 100,
+                    "createdBy": "desktop",
+                })
+            })
+            .collect();
+
+        let cbor = Format::Cbor.encode(&ops).unwrap();
+        let json = Format::Json.encode(&ops).unwrap();
+        assert!(cbor.len() < json.len());
+        let decoded: Vec<serde_json::Value> = Format::Cbor.decode(&cbor).unwrap();
+        assert_eq!(decoded, ops);
+    }
+}
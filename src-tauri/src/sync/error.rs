@@ -0,0 +1,282 @@
+//! Command Errors
+//!
+//! Error type of the Tauri sync commands. It serializes to a stable `code`
+//! with structured `params`, a `severity` and, where the user can do
+//! something about it, a recovery `action`. `message` is English for logs;
+//! the frontend looks up its own localized text by code.
+
+use super::crypto::CryptoError;
+use super::pairing::PairingError;
+use super::persistence::PersistenceError;
+use super::settings::Transport;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use serde_json::{json, Value};
+use std::sync::PoisonError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Expected in normal use (wrong passphrase, expired code)
+    Warning,
+    /// Something is broken and the operation can't complete
+    Error,
+}
+
+/// What the UI can offer the user to recover
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryAction {
+    StartServer,
+    Retry,
+    /// Retry after `retryAfter` seconds
+    RetryLater,
+    RestartPairing,
+    CheckPassphrase,
+    ChooseFolder,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SyncCommandError {
+    #[error("Sync server not running. Start the server first.")]
+    ServerNotRunning,
+    #[error("Failed to start the sync server: {detail}")]
+    ServerStartFailed { detail: String },
+    #[error("Failed to get config directory")]
+    ConfigDirUnavailable,
+    /// Reading or writing sync data on disk; `operation` names what failed
+    #[error("Failed to {}: {detail}", operation.replace('_', " "))]
+    Storage {
+        operation: &'static str,
+        detail: String,
+    },
+    /// A pairing step failed; `reason` is the lowercased `PairingError` code
+    #[error("{message}")]
+    Pairing {
+        reason: String,
+        retry_after: Option<u64>,
+        message: String,
+    },
+    #[error("Wrong passphrase or corrupted data")]
+    WrongPassphrase,
+    #[error("Invalid sync bundle")]
+    InvalidBundle,
+    #[error("Encryption failed: {detail}")]
+    Crypto { detail: String },
+    #[error("Network discovery failed: {detail}")]
+    DiscoveryFailed { detail: String },
+    /// `update_sync_settings` or the settings filling in a start were rejected
+    #[error("Invalid value for {field}: {detail}")]
+    InvalidSetting { field: &'static str, detail: String },
+    #[error("Sync over {} is turned off in sync settings", transport.as_str())]
+    TransportDisabled { transport: Transport },
+    /// No network is connected, so there is nothing to trust
+    #[error("Not connected to a network")]
+    NoNetwork,
+    #[error("Relay sync failed: {detail}")]
+    RelayFailed { detail: String },
+    #[error("Folder sync failed: {detail}")]
+    FolderSyncFailed { detail: String },
+    #[error("Internal error: {detail}")]
+    Internal { detail: String },
+}
+
+impl SyncCommandError {
+    /// Stable identifier the frontend translates
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ServerNotRunning => "server_not_running",
+            Self::ServerStartFailed { .. } => "server_start_failed",
+            Self::ConfigDirUnavailable => "config_dir_unavailable",
+            Self::Storage { .. } => "storage",
+            Self::Pairing { .. } => "pairing",
+            Self::WrongPassphrase => "wrong_passphrase",
+            Self::InvalidBundle => "invalid_bundle",
+            Self::Crypto { .. } => "crypto",
+            Self::DiscoveryFailed { .. } => "discovery_failed",
+            Self::NoNetwork => "no_network",
+            Self::InvalidSetting { .. } => "invalid_setting",
+            Self::TransportDisabled { .. } => "transport_disabled",
+            Self::RelayFailed { .. } => "relay_failed",
+            Self::FolderSyncFailed { .. } => "folder_sync_failed",
+            Self::Internal { .. } => "internal",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::ServerNotRunning
+            | Self::Pairing { .. }
+            | Self::WrongPassphrase
+            | Self::InvalidBundle
+            | Self::DiscoveryFailed { .. }
+            | Self::NoNetwork
+            | Self::InvalidSetting { .. }
+            | Self::TransportDisabled { .. }
+            | Self::RelayFailed { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    pub fn action(&self) -> Option<RecoveryAction> {
+        match self {
+            Self::ServerNotRunning => Some(RecoveryAction::StartServer),
+            Self::Pairing {
+                retry_after: Some(_),
+                ..
+            } => Some(RecoveryAction::RetryLater),
+            Self::Pairing { reason, .. } => match reason.as_str() {
+                "invalid_code" | "invalid_nonce" | "sas_not_confirmed" => None,
+                _ => Some(RecoveryAction::RestartPairing),
+            },
+            Self::WrongPassphrase => Some(RecoveryAction::CheckPassphrase),
+            Self::InvalidBundle => None,
+            Self::ServerStartFailed { .. }
+            | Self::DiscoveryFailed { .. }
+            | Self::NoNetwork
+            | Self::RelayFailed { .. } => Some(RecoveryAction::Retry),
+            Self::FolderSyncFailed { .. } => Some(RecoveryAction::ChooseFolder),
+            _ => None,
+        }
+    }
+
+    /// Values the localized message may interpolate
+    pub fn params(&self) -> Value {
+        match self {
+            Self::Storage { operation, detail } => {
+                json!({ "operation": operation, "detail": detail })
+            }
+            Self::Pairing {
+                reason,
+                retry_after,
+                ..
+            } => json!({ "reason": reason, "retryAfter": retry_after }),
+            Self::InvalidSetting { field, detail } => json!({ "field": field, "detail": detail }),
+            Self::TransportDisabled { transport } => json!({ "transport": transport }),
+            Self::ServerStartFailed { detail }
+            | Self::Crypto { detail }
+            | Self::DiscoveryFailed { detail }
+            | Self::RelayFailed { detail }
+            | Self::FolderSyncFailed { detail }
+            | Self::Internal { detail } => json!({ "detail": detail }),
+            _ => json!({}),
+        }
+    }
+
+    /// Map a `PersistenceError` raised while doing `operation`
+    pub fn storage(operation: &'static str) -> impl FnOnce(PersistenceError) -> Self {
+        move |e| match e {
+            PersistenceError::NoConfigDir => Self::ConfigDirUnavailable,
+            e => Self::Storage {
+                operation,
+                detail: e.to_string(),
+            },
+        }
+    }
+}
+
+impl Serialize for SyncCommandError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("SyncCommandError", 5)?;
+        s.serialize_field("code", self.code())?;
+        s.serialize_field("params", &self.params())?;
+        s.serialize_field("severity", &self.severity())?;
+        s.serialize_field("action", &self.action())?;
+        s.serialize_field("message", &self.to_string())?;
+        s.end()
+    }
+}
+
+impl From<PersistenceError> for SyncCommandError {
+    fn from(e: PersistenceError) -> Self {
+        Self::storage("access_sync_data")(e)
+    }
+}
+
+impl From<PairingError> for SyncCommandError {
+    fn from(e: PairingError) -> Self {
+        Self::Pairing {
+            reason: e.code.to_lowercase(),
+            retry_after: e.retry_after,
+            message: e.error,
+        }
+    }
+}
+
+impl From<CryptoError> for SyncCommandError {
+    fn from(e: CryptoError) -> Self {
+        match e {
+            CryptoError::Decryption => Self::WrongPassphrase,
+            CryptoError::InvalidFormat => Self::InvalidBundle,
+            e => Self::Crypto {
+                detail: e.to_string(),
+            },
+        }
+    }
+}
+
+impl<T> From<PoisonError<T>> for SyncCommandError {
+    fn from(e: PoisonError<T>) -> Self {
+        Self::Internal {
+            detail: e.to_string(),
+        }
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serializes_code_params_and_severity() {
+        let error = SyncCommandError::from(PairingError::rate_limited(30));
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({
+                "code": "pairing",
+                "params": { "reason": "rate_limited", "retryAfter": 30 },
+                "severity": "warning",
+                "action": "retry_later",
+                "message": "Rate limited. Try again in 30 seconds",
+            })
+        );
+
+        let error = SyncCommandError::ServerNotRunning;
+        let value = serde_json::to_value(&error).unwrap();
+        assert_eq!(value["code"], "server_not_running");
+        assert_eq!(value["action"], "start_server");
+        assert_eq!(value["params"], json!({}));
+    }
+
+    #[test]
+    fn test_maps_module_errors() {
+        assert_eq!(
+            SyncCommandError::from(CryptoError::Decryption),
+            SyncCommandError::WrongPassphrase
+        );
+        assert_eq!(
+            SyncCommandError::from(PairingError::expired()).action(),
+            Some(RecoveryAction::RestartPairing)
+        );
+        assert_eq!(
+            SyncCommandError::from(PairingError::invalid_code()).action(),
+            None
+        );
+
+        let io = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        let error = SyncCommandError::storage("revoke_device")(PersistenceError::Io(io));
+        assert_eq!(
+            error.to_string(),
+            "Failed to revoke device: IO error: denied"
+        );
+        assert_eq!(error.severity(), Severity::Error);
+        assert_eq!(
+            SyncCommandError::from(PersistenceError::NoConfigDir),
+            SyncCommandError::ConfigDirUnavailable
+        );
+    }
+}
@@ -0,0 +1,834 @@
+//! Sync Folder
+//!
+//! Syncs through a shared directory instead of the network: a Syncthing or
+//! cloud-drive folder, a network share, or a USB stick carried between
+//! machines. No listener is started; devices only read and write files.
+//!
+//! Folder layout:
+//! - `mutaba3a-folder.json`: Argon2id salt for the folder key and a check
+//!   value, written by the first device that uses the folder
+//! - `segments/<device id>.<seq>.mseg`: append-only op segments, each the
+//!   JSON `SegmentBody` sealed with the folder key (`crypto::seal`)
+//! - `cursors/<device id>.json`: the last segment the device has imported
+//!   from each writer, and up to which segment its own were deleted
+//!
+//! Every device only creates and deletes its own files, so file-sync tools
+//! never see two devices editing the same file. Segments from a writer are
+//! imported in order; a gap means the missing segment hasn't arrived yet. A
+//! writer deletes its segments once every device with a cursor file has
+//! imported them, announcing it in its cursor file first. Devices that join
+//! later only get segments still in the folder: they start at the writer's
+//! first segment after the deleted ones (segment 1 until the writer's cursor
+//! file says otherwise).
+//!
+//! Local progress (own next segment, op log position, import cursors) is kept
+//! in {app_config_dir}/sync_folder.json.
+
+use super::clock::{Clock, SystemClock};
+use super::conflicts::DetectedConflict;
+use super::crypto::{self, SealedBox};
+use super::inbox::PendingOpsStore;
+use super::oplog::OpLog;
+use super::redact;
+use super::server::store_incoming_ops;
+use super::supervisor::TaskSupervisor;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use notify::{RecursiveMode, Watcher};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+const HEADER_FILE: &str = "mutaba3a-folder.json";
+const SEGMENTS_DIR: &str = "segments";
+const CURSORS_DIR: &str = "cursors";
+const SEGMENT_EXT: &str = "mseg";
+const STATE_FILE: &str = "sync_folder.json";
+const FOLDER_VERSION: u32 = 1;
+/// Sealed in the header so a wrong passphrase is caught when opening
+const CHECK_VALUE: &[u8] = b"mutaba3a-folder";
+/// Op log hold for ops not yet written to the folder
+pub const OP_LOG_HOLD: &str = "folder";
+/// Ops per segment
+const SEGMENT_OPS: usize = 500;
+/// Give file-sync tools time to finish writing before scanning
+const SETTLE_DELAY: Duration = Duration::from_secs(2);
+/// Rescan even without file events (network shares often don't send them)
+const RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+// ============================================================================
+// Types
+// ============================================================================
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FolderHeader {
+    version: u32,
+    /// Salt for deriving the folder key from the passphrase (base64)
+    salt: String,
+    check: SealedBox,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SegmentBody {
+    version: u32,
+    device_id: String,
+    seq: u64,
+    created_at: String,
+    ops: Vec<serde_json::Value>,
+}
+
+/// A device's import progress, published for the writers
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct CursorFile {
+    version: u32,
+    device_id: String,
+    /// Last imported segment per writer
+    imported: BTreeMap<String, u64>,
+    /// Own segments up to this one are deleted
+    #[serde(default)]
+    deleted_through: u64,
+}
+
+/// Local progress with one sync folder
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderState {
+    pub folder: PathBuf,
+    pub device_id: String,
+    /// Last op log sequence number written to a segment
+    pub exported_seq: u64,
+    /// Sequence number of the next own segment
+    pub next_segment: u64,
+    /// Last imported segment per writer
+    pub imported: BTreeMap<String, u64>,
+    /// Own segments up to this one are deleted (or being deleted)
+    #[serde(default)]
+    pub deleted_through: u64,
+}
+
+impl FolderState {
+    /// The saved state, if a sync folder was set up
+    pub fn load(config_dir: &Path) -> Option<Self> {
+        let content = std::fs::read_to_string(config_dir.join(STATE_FILE)).ok()?;
+        match serde_json::from_str(&content) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                log::warn!("Ignoring unreadable {}: {}", STATE_FILE, e);
+                None
+            }
+        }
+    }
+
+    fn save(&self, config_dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        write_atomic(&config_dir.join(STATE_FILE), &json)
+    }
+}
+
+/// Outcome of one pass over the sync folder
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderSyncReport {
+    /// Ops read from other devices' segments
+    pub imported: usize,
+    /// Ops that were new (stored in the inbox)
+    pub stored: usize,
+    /// Ops written to new segments
+    pub exported: usize,
+    /// Own segments deleted because every device has imported them
+    pub removed_segments: usize,
+    pub conflicts: Vec<DetectedConflict>,
+    /// Segments that could not be read (partially synced, or sealed with another key)
+    pub errors: Vec<String>,
+}
+
+// ============================================================================
+// SyncFolder
+// ============================================================================
+
+pub struct SyncFolder {
+    root: PathBuf,
+    config_dir: PathBuf,
+    device_id: String,
+    key: [u8; 32],
+    /// Also serializes sync passes
+    state: Mutex<FolderState>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SyncFolder {
+    /// Open the sync folder at `root`, setting it up if it is new. Slow: the
+    /// folder key is derived from the passphrase with Argon2id.
+    pub fn open(
+        config_dir: &Path,
+        root: &Path,
+        device_id: &str,
+        passphrase: &str,
+    ) -> Result<Self, String> {
+        Self::open_with_clock(
+            config_dir,
+            root,
+            device_id,
+            passphrase,
+            Arc::new(SystemClock),
+        )
+    }
+
+    /// Open the sync folder, timestamping segments with `clock`
+    pub fn open_with_clock(
+        config_dir: &Path,
+        root: &Path,
+        device_id: &str,
+        passphrase: &str,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, String> {
+        if !is_valid_device_id(device_id) {
+            return Err(format!(
+                "Device id '{}' can't be used in file names",
+                device_id
+            ));
+        }
+        for dir in [SEGMENTS_DIR, CURSORS_DIR] {
+            std::fs::create_dir_all(root.join(dir))
+                .map_err(|e| format!("Failed to create {}: {}", root.join(dir).display(), e))?;
+        }
+        let key = folder_key(root, passphrase)?;
+
+        let mut state = match FolderState::load(config_dir) {
+            Some(state) if state.folder == root && state.device_id == device_id => state,
+            _ => FolderState {
+                folder: root.to_path_buf(),
+                device_id: device_id.to_string(),
+                ..Default::default()
+            },
+        };
+
+        // Never reuse a segment number another device may already have seen
+        let written = list_segments(root)?
+            .into_iter()
+            .filter(|s| s.writer == device_id)
+            .map(|s| s.seq);
+        let imported = read_cursors(root, device_id)
+            .into_iter()
+            .filter_map(|c| c.imported.get(device_id).copied());
+        let last_used = written.chain(imported).max().unwrap_or(0);
+        state.next_segment = state.next_segment.max(last_used + 1);
+        state.save(config_dir)?;
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            config_dir: config_dir.to_path_buf(),
+            device_id: device_id.to_string(),
+            key,
+            state: Mutex::new(state),
+            clock,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Import new segments from other devices, write local ops the folder
+    /// doesn't have yet, publish this device's cursor and delete own
+    /// segments every device has imported
+    pub async fn sync(
+        &self,
+        pending_ops: &PendingOpsStore,
+        op_log: &OpLog,
+    ) -> Result<FolderSyncReport, String> {
+        let mut state = self.state.lock().await;
+        op_log.hold(OP_LOG_HOLD, state.exported_seq);
+
+        let mut report = FolderSyncReport::default();
+        let segments = list_segments(&self.root)?;
+        let cursors = read_cursors(&self.root, &self.device_id);
+
+        // Import, per writer in sequence order
+        let mut by_writer: BTreeMap<&str, Vec<&SegmentFile>> = BTreeMap::new();
+        for segment in segments.iter().filter(|s| s.writer != self.device_id) {
+            by_writer.entry(&segment.writer).or_default().push(segment);
+        }
+        for (writer, mut pending) in by_writer {
+            pending.sort_by_key(|s| s.seq);
+            let mut last = state.imported.get(writer).copied();
+            // Where a first import starts: earlier segments were deleted
+            let first_kept = cursors
+                .iter()
+                .find(|c| c.device_id == writer)
+                .map_or(0, |c| c.deleted_through)
+                + 1;
+            for segment in pending {
+                match last {
+                    Some(last) if segment.seq <= last => continue,
+                    // The missing segment hasn't been synced yet
+                    Some(last) if segment.seq != last + 1 => break,
+                    None if segment.seq > first_kept => break,
+                    _ => {}
+                }
+                let body = match self.read_segment(segment) {
+                    Ok(body) => body,
+                    Err(e) => {
+                        log::warn!("Skipping folder segment {}: {}", segment.name(), e);
+                        report.errors.push(format!("{}: {}", segment.name(), e));
+                        break;
+                    }
+                };
+
+                report.imported += body.ops.len();
+                // Same rule as push: only operation objects with an id
+                let ops = body
+                    .ops
+                    .into_iter()
+                    .filter(|op| op.get("id").and_then(|v| v.as_str()).is_some())
+                    .collect();
+                let (stored, conflicts) = store_incoming_ops(pending_ops, op_log, writer, ops)
+                    .await
+                    .map_err(|e| format!("Failed to store folder ops: {}", e))?;
+                report.stored += stored;
+                report.conflicts.extend(conflicts);
+
+                state.imported.insert(writer.to_string(), segment.seq);
+                state.save(&self.config_dir)?;
+                last = Some(segment.seq);
+            }
+        }
+
+        // Export ops that didn't come from the folder's own devices
+        let participants: Vec<String> = segments
+            .iter()
+            .map(|s| s.writer.clone())
+            .chain(cursors.iter().map(|c| c.device_id.clone()))
+            .filter(|id| *id != self.device_id)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        loop {
+            let page = op_log
+                .after_seq_excluding(&participants, state.exported_seq, SEGMENT_OPS)
+                .await;
+            let Some(last_seq) = page.last_seq else {
+                break;
+            };
+            if !page.ops.is_empty() {
+                report.exported += page.ops.len();
+                let ops = page.ops.into_iter().map(|e| e.op).collect();
+                self.write_segment(state.next_segment, ops)?;
+                state.next_segment += 1;
+            }
+            state.exported_seq = last_seq;
+            state.save(&self.config_dir)?;
+            op_log.hold(OP_LOG_HOLD, last_seq);
+            if !page.has_more {
+                break;
+            }
+        }
+
+        // Delete own segments once every other device has imported them,
+        // after announcing it so devices joining later don't wait for them
+        let safe = cursors
+            .iter()
+            .map(|c| c.imported.get(&self.device_id).copied().unwrap_or(0))
+            .min();
+        if let Some(safe) = safe.filter(|safe| *safe > state.deleted_through) {
+            state.deleted_through = safe;
+            state.save(&self.config_dir)?;
+        }
+        self.publish_cursor(&state)?;
+
+        if let Some(safe) = safe {
+            for segment in segments
+                .iter()
+                .filter(|s| s.writer == self.device_id && s.seq <= safe)
+            {
+                match std::fs::remove_file(&segment.path) {
+                    Ok(()) => report.removed_segments += 1,
+                    Err(e) => log::warn!("Failed to delete {}: {}", segment.name(), e),
+                }
+            }
+        }
+
+        if report.imported > 0 || report.exported > 0 {
+            log::info!(
+                "Folder sync as {}: imported {} ops ({} new), exported {}, removed {} segments",
+                redact::device(&self.device_id),
+                report.imported,
+                report.stored,
+                report.exported,
+                report.removed_segments
+            );
+        }
+        Ok(report)
+    }
+
+    fn read_segment(&self, segment: &SegmentFile) -> Result<SegmentBody, String> {
+        let content = std::fs::read(&segment.path).map_err(|e| e.to_string())?;
+        let sealed: SealedBox =
+            serde_json::from_slice(&content).map_err(|_| "Incomplete or invalid file")?;
+        let plaintext =
+            crypto::open(&sealed, &self.key).map_err(|_| "Sealed with a different key")?;
+        let body: SegmentBody =
+            serde_json::from_slice(&plaintext).map_err(|_| "Invalid segment contents")?;
+        // The name is not authenticated; the sealed body is
+        if body.device_id != segment.writer || body.seq != segment.seq {
+            return Err("File name doesn't match its contents".to_string());
+        }
+        Ok(body)
+    }
+
+    fn write_segment(&self, seq: u64, ops: Vec<serde_json::Value>) -> Result<(), String> {
+        let body = SegmentBody {
+            version: FOLDER_VERSION,
+            device_id: self.device_id.clone(),
+            seq,
+            created_at: self.clock.now().to_rfc3339(),
+            ops,
+        };
+        let plaintext = serde_json::to_vec(&body).map_err(|e| e.to_string())?;
+        let sealed = crypto::seal(&plaintext, &self.key).map_err(|e| e.to_string())?;
+        let json = serde_json::to_vec(&sealed).map_err(|e| e.to_string())?;
+        let name = format!("{}.{:010}.{}", self.device_id, seq, SEGMENT_EXT);
+        write_atomic(&self.root.join(SEGMENTS_DIR).join(name), &json)
+    }
+
+    /// Write this device's cursor file if it changed
+    fn publish_cursor(&self, state: &FolderState) -> Result<(), String> {
+        let path = self
+            .root
+            .join(CURSORS_DIR)
+            .join(format!("{}.json", self.device_id));
+        let cursor = CursorFile {
+            version: FOLDER_VERSION,
+            device_id: self.device_id.clone(),
+            imported: state.imported.clone(),
+            deleted_through: state.deleted_through,
+        };
+        let current = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|c| serde_json::from_str::<CursorFile>(&c).ok());
+        if current.as_ref() == Some(&cursor) {
+            return Ok(());
+        }
+        let json = serde_json::to_vec_pretty(&cursor).map_err(|e| e.to_string())?;
+        write_atomic(&path, &json)
+    }
+}
+
+/// A segment file found in the folder
+struct SegmentFile {
+    writer: String,
+    seq: u64,
+    path: PathBuf,
+}
+
+impl SegmentFile {
+    /// Parse `<device id>.<seq>.mseg`; other files (temp files of file-sync tools) are skipped
+    fn parse(path: PathBuf) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let (writer, seq) = name
+            .strip_suffix(SEGMENT_EXT)?
+            .strip_suffix('.')?
+            .rsplit_once('.')?;
+        if !is_valid_device_id(writer) {
+            return None;
+        }
+        Some(Self {
+            writer: writer.to_string(),
+            seq: seq.parse().ok()?,
+            path,
+        })
+    }
+
+    fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+fn list_segments(root: &Path) -> Result<Vec<SegmentFile>, String> {
+    let dir = root.join(SEGMENTS_DIR);
+    let entries =
+        std::fs::read_dir(&dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    Ok(entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| SegmentFile::parse(entry.path()))
+        .collect())
+}
+
+/// Cursor files of the devices other than `own_id`
+fn read_cursors(root: &Path, own_id: &str) -> Vec<CursorFile> {
+    let Ok(entries) = std::fs::read_dir(root.join(CURSORS_DIR)) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let stem = path
+                .file_name()?
+                .to_str()?
+                .strip_suffix(".json")?
+                .to_string();
+            if stem == own_id || !is_valid_device_id(&stem) {
+                return None;
+            }
+            let content = std::fs::read_to_string(&path).ok()?;
+            match serde_json::from_str::<CursorFile>(&content) {
+                Ok(cursor) if cursor.device_id == stem => Some(cursor),
+                Ok(_) => None,
+                Err(e) => {
+                    log::warn!("Ignoring unreadable cursor file {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// The folder key, creating the folder header on first use
+fn folder_key(root: &Path, passphrase: &str) -> Result<[u8; 32], String> {
+    let path = root.join(HEADER_FILE);
+    if path.exists() {
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let header: FolderHeader = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid {}: {}", HEADER_FILE, e))?;
+        if header.version != FOLDER_VERSION {
+            return Err(format!(
+                "Sync folder version {} is not supported",
+                header.version
+            ));
+        }
+        let salt = BASE64
+            .decode(&header.salt)
+            .map_err(|_| format!("Invalid salt in {}", HEADER_FILE))?;
+        let key = crypto::derive_key(passphrase, &salt).map_err(|e| e.to_string())?;
+        crypto::open(&header.check, &key)
+            .map_err(|_| "Wrong passphrase for this sync folder".to_string())?;
+        return Ok(key);
+    }
+
+    let mut salt = [0u8; 16];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    let key = crypto::derive_key(passphrase, &salt).map_err(|e| e.to_string())?;
+    let header = FolderHeader {
+        version: FOLDER_VERSION,
+        salt: BASE64.encode(salt),
+        check: crypto::seal(CHECK_VALUE, &key).map_err(|e| e.to_string())?,
+    };
+    let json = serde_json::to_vec_pretty(&header).map_err(|e| e.to_string())?;
+    write_atomic(&path, &json)?;
+    log::info!("Set up sync folder at {}", root.display());
+    Ok(key)
+}
+
+/// Device ids become file names: letters, digits, '-' and '_' only
+fn is_valid_device_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Write to a dot-prefixed temp file next to `path`, then rename
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> Result<(), String> {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or("Invalid file name")?;
+    let temp_path = path.with_file_name(format!(".{}.tmp", name));
+    std::fs::write(&temp_path, data)
+        .and_then(|_| std::fs::rename(&temp_path, path))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// ============================================================================
+// Watcher
+// ============================================================================
+
+/// Runs `SyncFolder::sync` when other devices' files change, when triggered
+/// (new local ops) and every `RESCAN_INTERVAL`
+pub struct FolderWatcher {
+    folder: Arc<SyncFolder>,
+    trigger: mpsc::UnboundedSender<()>,
+    /// Stops the sync loop
+    cancel: CancellationToken,
+    _watcher: Option<notify::RecommendedWatcher>,
+}
+
+impl FolderWatcher {
+    /// Watch `folder`, running the sync loop on `tasks`
+    pub fn start<F>(
+        folder: Arc<SyncFolder>,
+        pending_ops: Arc<PendingOpsStore>,
+        op_log: Arc<OpLog>,
+        tasks: &TaskSupervisor,
+        on_sync: F,
+    ) -> Self
+    where
+        F: Fn(FolderSyncReport) + Send + 'static,
+    {
+        let (trigger, mut rx) = mpsc::unbounded_channel();
+
+        // Own files and temp files are named "<own id>." or "."
+        let own_prefix = format!("{}.", folder.device_id);
+        let events = trigger.clone();
+        let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            let Ok(event) = res else {
+                return;
+            };
+            let foreign = event.paths.iter().any(|path| {
+                path.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| !n.starts_with(&own_prefix) && !n.starts_with('.'))
+            });
+            if foreign {
+                let _ = events.send(());
+            }
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(&folder.root, RecursiveMode::Recursive)?;
+            Ok(watcher)
+        });
+        let watcher = match watcher {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                log::warn!(
+                    "Not watching the sync folder ({}); rescanning every {}s",
+                    e,
+                    RESCAN_INTERVAL.as_secs()
+                );
+                None
+            }
+        };
+
+        let task_folder = Arc::clone(&folder);
+        let cancel = tasks.token();
+        let stopped = cancel.clone();
+        tasks.spawn("folder-watcher", async move {
+            let start = tokio::time::Instant::now() + RESCAN_INTERVAL;
+            let mut rescan = tokio::time::interval_at(start, RESCAN_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = stopped.cancelled() => break,
+                    received = rx.recv() => {
+                        if received.is_none() {
+                            break;
+                        }
+                        // Let file-sync tools finish writing, then coalesce events
+                        tokio::time::sleep(SETTLE_DELAY).await;
+                        while rx.try_recv().is_ok() {}
+                    }
+                    _ = rescan.tick() => {}
+                }
+                match task_folder.sync(&pending_ops, &op_log).await {
+                    Ok(report) => on_sync(report),
+                    Err(e) => log::warn!("Folder sync failed: {}", e),
+                }
+            }
+        });
+
+        Self {
+            folder,
+            trigger,
+            cancel,
+            _watcher: watcher,
+        }
+    }
+
+    pub fn folder(&self) -> &SyncFolder {
+        &self.folder
+    }
+
+    /// Sync soon, e.g. after local ops were added
+    pub fn trigger(&self) {
+        let _ = self.trigger.send(());
+    }
+
+    pub fn stop(self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Drop for FolderWatcher {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    struct Device {
+        _config: tempfile::TempDir,
+        folder: SyncFolder,
+        pending_ops: Arc<PendingOpsStore>,
+        op_log: Arc<OpLog>,
+    }
+
+    fn device(root: &Path, id: &str, passphrase: &str) -> Result<Device, String> {
+        let config = tempdir().unwrap();
+        let folder = SyncFolder::open(config.path(), root, id, passphrase)?;
+        Ok(Device {
+            pending_ops: PendingOpsStore::new(config.path().to_path_buf()).unwrap(),
+            op_log: OpLog::new(config.path().to_path_buf()).unwrap(),
+            folder,
+            _config: config,
+        })
+    }
+
+    fn op(id: &str, by: &str) -> serde_json::Value {
+        json!({ "id": id, "hlc": id, "createdBy": by })
+    }
+
+    impl Device {
+        async fn sync(&self) -> FolderSyncReport {
+            self.folder
+                .sync(&self.pending_ops, &self.op_log)
+                .await
+                .unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_devices_exchange_ops_through_folder() {
+        let root = tempdir().unwrap();
+        let desktop = device(root.path(), "desktop", "pass").unwrap();
+        let laptop = device(root.path(), "laptop", "pass").unwrap();
+
+        desktop
+            .op_log
+            .append(op("a", "desktop"), None)
+            .await
+            .unwrap();
+        assert_eq!(desktop.sync().await.exported, 1);
+
+        laptop.op_log.append(op("b", "laptop"), None).await.unwrap();
+        let report = laptop.sync().await;
+        assert_eq!((report.imported, report.stored), (1, 1));
+        // The desktop's op isn't written back
+        assert_eq!(report.exported, 1);
+
+        // Importing again is a no-op
+        assert_eq!(laptop.sync().await.imported, 0);
+
+        // The desktop gets the laptop's op and deletes its segment, which the
+        // laptop has imported
+        let report = desktop.sync().await;
+        assert_eq!((report.imported, report.stored), (1, 1));
+        assert_eq!(report.removed_segments, 1);
+        let page = desktop.pending_ops.page(0, None).await;
+        assert_eq!(page.ops[0].op["id"], "b");
+
+        let report = laptop.sync().await;
+        assert_eq!(report.removed_segments, 1);
+        assert!(list_segments(root.path()).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_missing_segment() {
+        let root = tempdir().unwrap();
+        let desktop = device(root.path(), "desktop", "pass").unwrap();
+        let laptop = device(root.path(), "laptop", "pass").unwrap();
+
+        desktop
+            .folder
+            .write_segment(1, vec![op("a", "desktop")])
+            .unwrap();
+        desktop
+            .folder
+            .write_segment(2, vec![op("b", "desktop")])
+            .unwrap();
+        assert_eq!(laptop.sync().await.imported, 2);
+
+        // Segment 4 arrives before 3
+        desktop
+            .folder
+            .write_segment(4, vec![op("d", "desktop")])
+            .unwrap();
+        assert_eq!(laptop.sync().await.imported, 0);
+        desktop
+            .folder
+            .write_segment(3, vec![op("c", "desktop")])
+            .unwrap();
+        assert_eq!(laptop.sync().await.imported, 2);
+    }
+
+    #[tokio::test]
+    async fn test_first_import_starts_at_first_kept_segment() {
+        let root = tempdir().unwrap();
+        let desktop = device(root.path(), "desktop", "pass").unwrap();
+        let laptop = device(root.path(), "laptop", "pass").unwrap();
+
+        // Segment 2 arrives before 1: a new device doesn't start from it
+        desktop
+            .folder
+            .write_segment(2, vec![op("b", "desktop")])
+            .unwrap();
+        assert_eq!(laptop.sync().await.imported, 0);
+        desktop
+            .folder
+            .write_segment(1, vec![op("a", "desktop")])
+            .unwrap();
+        assert_eq!(laptop.sync().await.imported, 2);
+
+        // Once the laptop has them the desktop deletes them; a phone joining
+        // later starts at the desktop's next segment
+        assert_eq!(desktop.sync().await.removed_segments, 2);
+        desktop
+            .folder
+            .write_segment(3, vec![op("c", "desktop")])
+            .unwrap();
+        let phone = device(root.path(), "phone", "pass").unwrap();
+        let report = phone.sync().await;
+        assert_eq!(report.imported, 1);
+        assert_eq!(phone.pending_ops.page(0, None).await.ops[0].op["id"], "c");
+    }
+
+    #[tokio::test]
+    async fn test_wrong_passphrase_is_rejected() {
+        let root = tempdir().unwrap();
+        device(root.path(), "desktop", "right").unwrap();
+        let err = device(root.path(), "laptop", "wrong").err().unwrap();
+        assert!(err.contains("Wrong passphrase"));
+
+        assert!(device(root.path(), "../escape", "right").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stopped_watcher_leaves_no_task() {
+        let root = tempdir().unwrap();
+        let desktop = device(root.path(), "desktop", "secret").unwrap();
+        let tasks = TaskSupervisor::default();
+
+        let watcher = FolderWatcher::start(
+            Arc::new(desktop.folder),
+            desktop.pending_ops,
+            desktop.op_log,
+            &tasks,
+            |_| {},
+        );
+        assert_eq!(tasks.active(), 1);
+
+        watcher.stop();
+        tasks.shutdown().await;
+        assert_eq!(tasks.active(), 0);
+    }
+}
@@ -0,0 +1,290 @@
+//! Incoming Operations Inbox
+//!
+//! Durable queue of operations pushed by mobile devices, waiting to be applied
+//! by the frontend.
+//! File location: {app_config_dir}/pending_ops.json
+//!
+//! Every operation gets a monotonically increasing sequence number. The
+//! frontend pages through the inbox with `get_pending_sync_ops(after_seq)` and
+//! acknowledges what it applied with `ack_pending_sync_ops(up_to_seq)`; only
+//! acknowledged operations are removed. Anything left unacknowledged is
+//! redelivered after a restart. An unreadable file is set aside as
+//! `pending_ops.json.corrupt-<timestamp>` and the inbox starts empty.
+
+use super::clock::{Clock, SystemClock};
+use super::conflicts::DetectedConflict;
+use super::persistence::{set_aside_corrupt, PersistenceError};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const PENDING_OPS_FILE: &str = "pending_ops.json";
+const INBOX_VERSION: u32 = 1;
+const DEFAULT_PAGE_SIZE: usize = 500;
+
+// ============================================================================
+// Types
+// ============================================================================
+
+/// An operation waiting to be applied, tagged with its inbox sequence number
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingOp {
+    pub seq: u64,
+    pub op: serde_json::Value,
+    /// Concurrent edits detected when the op arrived
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<DetectedConflict>,
+}
+
+/// One page of pending operations
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingOpsPage {
+    pub ops: Vec<PendingOp>,
+    /// Sequence number of the last op in this page (pass as `after_seq` / `up_to_seq`)
+    pub last_seq: Option<u64>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InboxFile {
+    version: u32,
+    /// Sequence number assigned to the next incoming op
+    next_seq: u64,
+    ops: VecDeque<PendingOp>,
+}
+
+impl Default for InboxFile {
+    fn default() -> Self {
+        Self {
+            version: INBOX_VERSION,
+            next_seq: 1,
+            ops: VecDeque::new(),
+        }
+    }
+}
+
+// ============================================================================
+// PendingOpsStore
+// ============================================================================
+
+pub struct PendingOpsStore {
+    /// None keeps the inbox in memory only
+    config_dir: Option<PathBuf>,
+    inbox: Mutex<InboxFile>,
+}
+
+impl PendingOpsStore {
+    /// Open the inbox stored in the given config directory
+    pub fn new(config_dir: PathBuf) -> Result<Arc<Self>, PersistenceError> {
+        Self::with_clock(config_dir, &SystemClock)
+    }
+
+    /// Open the inbox, timestamping a corrupt file set aside with `clock`
+    pub fn with_clock(
+        config_dir: PathBuf,
+        clock: &dyn Clock,
+    ) -> Result<Arc<Self>, PersistenceError> {
+        std::fs::create_dir_all(&config_dir)?;
+
+        let path = config_dir.join(PENDING_OPS_FILE);
+        let inbox = if path.exists() {
+            let content = std::fs::read_to_string(&path)?;
+            match serde_json::from_str(&content) {
+                Ok(inbox) => inbox,
+                // Don't let one bad file keep sync from starting
+                Err(e) => {
+                    set_aside_corrupt(&path, &e, clock)?;
+                    InboxFile::default()
+                }
+            }
+        } else {
+            InboxFile::default()
+        };
+
+        if !inbox.ops.is_empty() {
+            log::info!("Inbox has {} unacknowledged operations", inbox.ops.len());
+        }
+
+        Ok(Arc::new(Self {
+            config_dir: Some(config_dir),
+            inbox: Mutex::new(inbox),
+        }))
+    }
+
+    /// Inbox that is not persisted
+    #[cfg(test)]
+    pub fn in_memory() -> Arc<Self> {
+        Arc::new(Self {
+            config_dir: None,
+            inbox: Mutex::new(InboxFile::default()),
+        })
+    }
+
+    /// Append operations, attaching each detected conflict to its incoming op,
+    /// and persist them. Returns the sequence number of the last op.
+    pub async fn push(
+        &self,
+        ops: Vec<serde_json::Value>,
+        conflicts: &[DetectedConflict],
+    ) -> Result<u64, PersistenceError> {
+        let mut inbox = self.inbox.lock().await;
+        for op in ops {
+            let seq = inbox.next_seq;
+            inbox.next_seq += 1;
+            let op_id = op.get("id").and_then(|v| v.as_str());
+            let conflicts = conflicts
+                .iter()
+                .filter(|c| op_id == Some(c.incoming_op_id.as_str()))
+                .cloned()
+                .collect();
+            inbox.ops.push_back(PendingOp { seq, op, conflicts });
+        }
+        self.save(&inbox).await?;
+        Ok(inbox.next_seq - 1)
+    }
+
+    /// Operations with a sequence number greater than `after_seq`, oldest first
+    pub async fn page(&self, after_seq: u64, limit: Option<usize>) -> PendingOpsPage {
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).max(1);
+        let inbox = self.inbox.lock().await;
+
+        let start = inbox.ops.partition_point(|p| p.seq <= after_seq);
+        let ops: Vec<PendingOp> = inbox.ops.iter().skip(start).take(limit).cloned().collect();
+        let has_more = inbox.ops.len() - start > ops.len();
+
+        PendingOpsPage {
+            last_seq: ops.last().map(|p| p.seq),
+            ops,
+            has_more,
+        }
+    }
+
+    /// Number of operations waiting to be acknowledged
+    pub async fn count(&self) -> usize {
+        self.inbox.lock().await.ops.len()
+    }
+
+    /// Remove operations up to and including `up_to_seq`. Returns how many were removed.
+    pub async fn ack(&self, up_to_seq: u64) -> Result<usize, PersistenceError> {
+        let mut inbox = self.inbox.lock().await;
+        let count = inbox.ops.partition_point(|p| p.seq <= up_to_seq);
+        if count == 0 {
+            return Ok(0);
+        }
+
+        inbox.ops.drain(..count);
+        self.save(&inbox).await?;
+        Ok(count)
+    }
+
+    async fn save(&self, inbox: &InboxFile) -> Result<(), PersistenceError> {
+        let Some(config_dir) = &self.config_dir else {
+            return Ok(());
+        };
+
+        let content = serde_json::to_string(inbox)?;
+        let path = config_dir.join(PENDING_OPS_FILE);
+
+        // Atomic write: write to temp file, then rename
+        let temp_path = path.with_extension("tmp");
+        tokio::fs::write(&temp_path, content).await?;
+        tokio::fs::rename(&temp_path, &path).await?;
+
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::clock::ManualClock;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn op(id: &str) -> serde_json::Value {
+        json!({ "id": id, "opType": "create" })
+    }
+
+    #[tokio::test]
+    async fn test_ops_pushed_between_get_and_ack_survive() {
+        let store = PendingOpsStore::in_memory();
+        store.push(vec![op("a"), op("b")], &[]).await.unwrap();
+
+        let page = store.page(0, None).await;
+        assert_eq!(page.ops.len(), 2);
+
+        // Arrives while the frontend is applying the first page
+        store.push(vec![op("c")], &[]).await.unwrap();
+
+        assert_eq!(store.ack(page.last_seq.unwrap()).await.unwrap(), 2);
+        let rest = store.page(0, None).await;
+        assert_eq!(rest.ops.len(), 1);
+        assert_eq!(rest.ops[0].op["id"], "c");
+        assert_eq!(rest.ops[0].seq, 3);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_file_is_set_aside() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(PENDING_OPS_FILE);
+        std::fs::write(&path, "{\"version\":1,\"nextSeq\":3,\"ops\":[").unwrap();
+
+        let clock = ManualClock::new();
+        let store = PendingOpsStore::with_clock(dir.path().to_path_buf(), clock.as_ref()).unwrap();
+        assert_eq!(store.count().await, 0);
+        store.push(vec![op("a")], &[]).await.unwrap();
+        assert_eq!(store.page(0, None).await.ops.len(), 1);
+
+        let aside = format!(
+            "pending_ops.json.corrupt-{}",
+            clock.now().format("%Y%m%dT%H%M%S%.3fZ")
+        );
+        let kept = std::fs::read_to_string(dir.path().join(aside)).unwrap();
+        assert!(kept.ends_with("\"ops\":["));
+    }
+
+    #[tokio::test]
+    async fn test_paging() {
+        let store = PendingOpsStore::in_memory();
+        store
+            .push(vec![op("a"), op("b"), op("c")], &[])
+            .await
+            .unwrap();
+
+        let first = store.page(0, Some(2)).await;
+        assert_eq!(first.ops.len(), 2);
+        assert!(first.has_more);
+
+        let second = store.page(first.last_seq.unwrap(), Some(2)).await;
+        assert_eq!(second.ops.len(), 1);
+        assert_eq!(second.ops[0].op["id"], "c");
+        assert!(!second.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_unacked_ops_redelivered_after_restart() {
+        let dir = tempdir().unwrap();
+
+        let store = PendingOpsStore::new(dir.path().to_path_buf()).unwrap();
+        store.push(vec![op("a"), op("b")], &[]).await.unwrap();
+        store.ack(1).await.unwrap();
+        drop(store);
+
+        let reopened = PendingOpsStore::new(dir.path().to_path_buf()).unwrap();
+        let page = reopened.page(0, None).await;
+        assert_eq!(page.ops.len(), 1);
+        assert_eq!(page.ops[0].op["id"], "b");
+
+        // Sequence numbers keep increasing across restarts
+        assert_eq!(reopened.push(vec![op("c")], &[]).await.unwrap(), 3);
+    }
+}
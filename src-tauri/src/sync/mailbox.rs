@@ -0,0 +1,726 @@
+//! Relay Mailboxes (device side)
+//!
+//! Exchanges op batches with a paired device through a mailbox relay (see
+//! `relay`) when the two are not on the same network at the same time.
+//!
+//! Everything the relay must not learn is derived with HKDF-SHA256 (salt
+//! "mutaba3a-mailbox-v2") from the pairing secret of the pairing key exchange
+//! (see `sas`). Unlike the session token it never crosses the network, so
+//! watching the pairing doesn't give away the mailboxes. Devices paired
+//! without the key exchange have no pairing secret and must pair again to
+//! use a relay.
+//! - info "key": AES-256-GCM key for op batches in both directions
+//! - info "fetch:" + recipient device id: the recipient's fetch key (hex);
+//!   the mailbox id is the hex SHA-256 of the fetch key
+//!
+//! A batch is the JSON `OpBatch` sealed with `crypto::seal`, deposited as the
+//! JSON `SealedBox`.
+//!
+//! Batches from the desktop carry `throughSeq`, the op log sequence number
+//! they run through. A device confirms what it received by sending the
+//! highest `throughSeq` back as `ackedSeq` on its next batch (an empty batch
+//! when it has no ops to send). Only confirmed ops count as delivered for
+//! pruning; ops that stay unconfirmed longer than the relay keeps messages
+//! (as it reports on fetch) are deposited again.
+
+use super::audit::{AuditEntry, AuditEventKind, AuditLog};
+use super::conflicts::DetectedConflict;
+use super::crypto::{self, SealedBox};
+use super::inbox::PendingOpsStore;
+use super::oplog::OpLog;
+use super::persistence::{PairedDevice, PersistenceManager};
+use super::redact;
+use super::relay::{mailbox_id_for_key, AckRequest, DepositResponse, FetchResponse, RelayConfig};
+use super::server::store_incoming_ops;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ring::hkdf;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const MAILBOX_SALT: &[u8] = b"mutaba3a-mailbox-v2";
+const BATCH_VERSION: u32 = 1;
+/// Ops per deposited batch (keeps messages well under the relay's size limit)
+const OUTBOUND_BATCH_OPS: usize = 200;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const NO_PAIRING_SECRET: &str =
+    "Paired without a key exchange; pair the device again to sync through a relay";
+
+// ============================================================================
+// Keys
+// ============================================================================
+
+/// Mailbox keys for one pairing, seen from one of the two devices
+pub struct PairMailbox {
+    key: [u8; 32],
+    peer_device_id: String,
+    own_device_id: String,
+    /// Mailbox this device fetches from
+    pub inbox_id: String,
+    /// Fetch key for `inbox_id`
+    pub inbox_fetch_key: String,
+    /// Mailbox of the peer, where this device deposits
+    pub outbox_id: String,
+}
+
+impl PairMailbox {
+    pub fn new(pairing_secret: &str, own_device_id: &str, peer_device_id: &str) -> Self {
+        let prk =
+            hkdf::Salt::new(hkdf::HKDF_SHA256, MAILBOX_SALT).extract(pairing_secret.as_bytes());
+        let fetch_key = |recipient: &str| {
+            let okm = expand(&prk, format!("fetch:{}", recipient).as_bytes());
+            okm.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        };
+
+        let inbox_fetch_key = fetch_key(own_device_id);
+        Self {
+            key: expand(&prk, b"key"),
+            peer_device_id: peer_device_id.to_string(),
+            own_device_id: own_device_id.to_string(),
+            inbox_id: mailbox_id_for_key(&inbox_fetch_key),
+            inbox_fetch_key,
+            outbox_id: mailbox_id_for_key(&fetch_key(peer_device_id)),
+        }
+    }
+
+    /// A batch of ops from this device, without sequence numbers
+    pub fn batch(&self, ops: Vec<serde_json::Value>) -> OpBatch {
+        OpBatch {
+            version: BATCH_VERSION,
+            sender_device_id: self.own_device_id.clone(),
+            ops,
+            through_seq: None,
+            acked_seq: None,
+        }
+    }
+
+    /// Encrypt a batch for the peer
+    pub fn seal(&self, batch: &OpBatch) -> Result<Vec<u8>, String> {
+        let plaintext = serde_json::to_vec(batch).map_err(|e| e.to_string())?;
+        let sealed = crypto::seal(&plaintext, &self.key).map_err(|e| e.to_string())?;
+        serde_json::to_vec(&sealed).map_err(|e| e.to_string())
+    }
+
+    /// Decrypt a batch fetched from this device's mailbox
+    pub fn open(&self, payload: &[u8]) -> Result<OpBatch, String> {
+        let sealed: SealedBox =
+            serde_json::from_slice(payload).map_err(|_| "Not a sealed batch".to_string())?;
+        let plaintext = crypto::open(&sealed, &self.key).map_err(|e| e.to_string())?;
+        let batch: OpBatch = serde_json::from_slice(&plaintext).map_err(|e| e.to_string())?;
+        if batch.sender_device_id != self.peer_device_id {
+            return Err("Batch was not sent by the paired device".to_string());
+        }
+        Ok(batch)
+    }
+}
+
+fn expand(prk: &hkdf::Prk, info: &[u8]) -> [u8; 32] {
+    let info = [info];
+    let mut out = [0u8; 32];
+    prk.expand(&info, hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut out))
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    out
+}
+
+/// Plaintext of a deposited message
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpBatch {
+    pub version: u32,
+    pub sender_device_id: String,
+    pub ops: Vec<serde_json::Value>,
+    /// Sender's op log sequence number the ops run through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub through_seq: Option<u64>,
+    /// Highest `through_seq` the sender has received from the peer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acked_seq: Option<u64>,
+}
+
+// ============================================================================
+// RelayClient
+// ============================================================================
+
+pub struct RelayClient {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl RelayClient {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn url(&self, mailbox_id: &str) -> String {
+        format!("{}/v1/relay/mailboxes/{}", self.base_url, mailbox_id)
+    }
+
+    /// Deposit a sealed batch. Returns its sequence number in the mailbox.
+    pub async fn deposit(&self, mailbox_id: &str, payload: Vec<u8>) -> Result<u64, String> {
+        let request = self.http.post(self.url(mailbox_id)).body(payload);
+        let response: DepositResponse = self.send(request).await?;
+        Ok(response.seq)
+    }
+
+    /// Fetch messages after `after` from a mailbox
+    pub async fn fetch(
+        &self,
+        mailbox_id: &str,
+        fetch_key: &str,
+        after: u64,
+    ) -> Result<FetchResponse, String> {
+        let request = self
+            .http
+            .get(self.url(mailbox_id))
+            .query(&[("after", after)])
+            .bearer_auth(fetch_key);
+        self.send(request).await
+    }
+
+    /// Delete messages up to and including `up_to_seq`
+    pub async fn ack(
+        &self,
+        mailbox_id: &str,
+        fetch_key: &str,
+        up_to_seq: u64,
+    ) -> Result<(), String> {
+        let request = self
+            .http
+            .post(format!("{}/ack", self.url(mailbox_id)))
+            .bearer_auth(fetch_key)
+            .json(&AckRequest { up_to_seq });
+        let _: serde_json::Value = self.send(request).await?;
+        Ok(())
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, String> {
+        let response = request
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| format!("Relay {} unreachable: {}", self.base_url, e))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Relay answered {}: {}", status, body));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| format!("Invalid relay response: {}", e))
+    }
+
+    /// Fetch and decrypt the next page of this device's mailbox for a pairing.
+    /// Messages that fail to decrypt are dropped. Pass `last_seq` to
+    /// `ack_inbox` once the batches are stored.
+    pub async fn receive(&self, mailbox: &PairMailbox) -> Result<ReceivedPage, String> {
+        let page = self
+            .fetch(&mailbox.inbox_id, &mailbox.inbox_fetch_key, 0)
+            .await?;
+        let last_seq = page.messages.last().map(|m| m.seq);
+
+        let mut batches = Vec::with_capacity(page.messages.len());
+        for message in page.messages {
+            let batch = BASE64
+                .decode(&message.payload)
+                .map_err(|e| e.to_string())
+                .and_then(|payload| mailbox.open(&payload));
+            match batch {
+                Ok(batch) => batches.push(batch),
+                Err(e) => log::warn!("Dropping relay message {}: {}", message.seq, e),
+            }
+        }
+
+        Ok(ReceivedPage {
+            batches,
+            last_seq,
+            has_more: page.has_more,
+            message_ttl_hours: page.message_ttl_hours,
+        })
+    }
+
+    /// Delete received messages from this device's mailbox
+    pub async fn ack_inbox(&self, mailbox: &PairMailbox, up_to_seq: u64) -> Result<(), String> {
+        self.ack(&mailbox.inbox_id, &mailbox.inbox_fetch_key, up_to_seq)
+            .await
+    }
+}
+
+/// Decrypted batches from one fetch
+pub struct ReceivedPage {
+    pub batches: Vec<OpBatch>,
+    /// Sequence number of the last message fetched (including dropped ones)
+    pub last_seq: Option<u64>,
+    pub has_more: bool,
+    /// How long the relay keeps messages, if it says
+    pub message_ttl_hours: Option<u64>,
+}
+
+// ============================================================================
+// Desktop sync
+// ============================================================================
+
+/// Outcome of exchanging ops with every paired device through a relay
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelaySyncReport {
+    /// Ops fetched from mailboxes
+    pub received: usize,
+    /// Ops that were new (stored in the inbox)
+    pub stored: usize,
+    /// Ops deposited for paired devices
+    pub sent: usize,
+    pub conflicts: Vec<DetectedConflict>,
+    /// Devices whose exchange failed
+    pub failed: Vec<RelayDeviceError>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayDeviceError {
+    pub device_id: String,
+    pub error: String,
+}
+
+/// Fetch ops each active paired device left at the relay, store them like
+/// pushed ops, and deposit local ops the device hasn't received yet
+pub async fn sync_paired_devices(
+    relay: &RelayClient,
+    own_device_id: &str,
+    persistence: &PersistenceManager,
+    pending_ops: &PendingOpsStore,
+    op_log: &OpLog,
+    audit: &AuditLog,
+) -> Result<RelaySyncReport, String> {
+    let devices = persistence
+        .get_active_devices()
+        .await
+        .map_err(|e| format!("Failed to load devices: {}", e))?;
+
+    let mut report = RelaySyncReport::default();
+    for device in devices {
+        let result = match &device.pairing_secret {
+            Some(secret) => {
+                let mailbox = PairMailbox::new(secret, own_device_id, &device.id);
+                sync_device(
+                    relay,
+                    &mailbox,
+                    &device,
+                    persistence,
+                    pending_ops,
+                    op_log,
+                    audit,
+                    &mut report,
+                )
+                .await
+            }
+            None => Err(NO_PAIRING_SECRET.to_string()),
+        };
+        if let Err(e) = result {
+            log::warn!(
+                "Relay sync with {} failed: {}",
+                redact::device(&device.id),
+                e
+            );
+            report.failed.push(RelayDeviceError {
+                device_id: device.id.clone(),
+                error: e,
+            });
+        }
+    }
+
+    // Ops the devices confirmed no longer need to be kept
+    let pruned = match persistence.load().await {
+        Ok(devices) => op_log.prune_acknowledged(&devices).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = pruned {
+        log::error!("Failed to prune delivered local ops: {}", e);
+    }
+    Ok(report)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn sync_device(
+    relay: &RelayClient,
+    mailbox: &PairMailbox,
+    device: &PairedDevice,
+    persistence: &PersistenceManager,
+    pending_ops: &PendingOpsStore,
+    op_log: &OpLog,
+    audit: &AuditLog,
+    report: &mut RelaySyncReport,
+) -> Result<(), String> {
+    // Incoming
+    let mut received = 0;
+    let mut confirmed = device.relay_acked_seq;
+    // Relays from before they reported it keep the default
+    let mut ttl_hours = RelayConfig::default().message_ttl_hours;
+    loop {
+        let page = relay.receive(mailbox).await?;
+        ttl_hours = page.message_ttl_hours.unwrap_or(ttl_hours);
+        let Some(last_seq) = page.last_seq else {
+            break;
+        };
+        for batch in page.batches {
+            if let Some(acked) = batch.acked_seq {
+                confirmed = confirmed.max(acked);
+            }
+            received += batch.ops.len();
+            // Same rule as push: only operation objects with an id
+            let ops = batch
+                .ops
+                .into_iter()
+                .filter(|op| op.get("id").and_then(|v| v.as_str()).is_some())
+                .collect();
+            let (stored, conflicts) = store_incoming_ops(pending_ops, op_log, &device.id, ops)
+                .await
+                .map_err(|e| format!("Failed to store relayed ops: {}", e))?;
+            report.stored += stored;
+            report.conflicts.extend(conflicts);
+        }
+        relay.ack_inbox(mailbox, last_seq).await?;
+        if !page.has_more {
+            break;
+        }
+    }
+    if received > 0 {
+        audit
+            .record(
+                AuditEntry::new(AuditEventKind::Push)
+                    .device(&device.id, Some(&device.name))
+                    .ops(received)
+                    .authenticated(true)
+                    .reason("relay"),
+            )
+            .await;
+    }
+    report.received += received;
+
+    let mut device = device.clone();
+    if confirmed > device.relay_acked_seq {
+        persistence
+            .update_relay_acked_seq(&device.id, confirmed)
+            .await
+            .map_err(|e| e.to_string())?;
+        device.relay_acked_seq = confirmed;
+    }
+
+    // Outgoing: local ops after whatever the device already has, or after
+    // what it confirmed if the unconfirmed deposits may have expired
+    let ttl = chrono::Duration::hours(ttl_hours as i64);
+    let redeposit = persistence.relay_deposit_expired(&device, ttl);
+    let mut after = if redeposit {
+        device.delivered_seq()
+    } else {
+        device.delivered_seq().max(device.relay_seq)
+    };
+    let mut sent = 0;
+    loop {
+        let page = op_log
+            .after_seq(&device.id, after, OUTBOUND_BATCH_OPS)
+            .await;
+        let Some(last_seq) = page.last_seq else {
+            break;
+        };
+        let ops: Vec<_> = page.ops.into_iter().map(|e| e.op).collect();
+        if !ops.is_empty() {
+            sent += ops.len();
+            let mut batch = mailbox.batch(ops);
+            batch.through_seq = Some(last_seq);
+            relay
+                .deposit(&mailbox.outbox_id, mailbox.seal(&batch)?)
+                .await?;
+        }
+        persistence
+            .update_relay_seq(&device.id, last_seq, redeposit)
+            .await
+            .map_err(|e| e.to_string())?;
+        after = last_seq;
+        if !page.has_more {
+            break;
+        }
+    }
+    if sent > 0 {
+        audit
+            .record(
+                AuditEntry::new(AuditEventKind::Pull)
+                    .device(&device.id, Some(&device.name))
+                    .ops(sent)
+                    .authenticated(true)
+                    .reason("relay"),
+            )
+            .await;
+    }
+    report.sent += sent;
+    Ok(())
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::clock::ManualClock;
+    use crate::sync::persistence::PairedDeviceStatus;
+    use crate::sync::relay::{RelayConfig, RelayServer};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_both_sides_derive_matching_mailboxes() {
+        let desktop = PairMailbox::new("secret", "desktop", "phone");
+        let phone = PairMailbox::new("secret", "phone", "desktop");
+        assert_eq!(desktop.outbox_id, phone.inbox_id);
+        assert_eq!(phone.outbox_id, desktop.inbox_id);
+        assert_ne!(desktop.inbox_id, desktop.outbox_id);
+        assert_eq!(mailbox_id_for_key(&phone.inbox_fetch_key), phone.inbox_id);
+
+        let sealed = phone
+            .seal(&phone.batch(vec![json!({ "id": "op-1" })]))
+            .unwrap();
+        assert_eq!(desktop.open(&sealed).unwrap().ops[0]["id"], "op-1");
+
+        // The sender's own mailbox keys don't accept its batch back, nor do another pairing's
+        assert!(phone.open(&sealed).is_err());
+        assert!(PairMailbox::new("other", "desktop", "phone")
+            .open(&sealed)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_devices_without_pairing_secret_skip_the_relay() {
+        // Never contacted: there are no mailbox keys to use
+        let relay = RelayClient::new("http://127.0.0.1:9");
+
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let persistence = PersistenceManager::new(config_dir.clone()).unwrap();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let audit = AuditLog::new(config_dir).unwrap();
+        persistence
+            .add_device(PairedDevice {
+                id: "phone".to_string(),
+                name: "Phone".to_string(),
+                token: "token".to_string(),
+                paired_at: chrono::Utc::now().to_rfc3339(),
+                last_sync_at: None,
+                status: PairedDeviceStatus::Active,
+                acked_seq: 0,
+                relay_seq: 0,
+                relay_acked_seq: 0,
+                relay_deposited_at: None,
+                pairing_secret: None,
+            })
+            .await
+            .unwrap();
+        let local = json!({ "id": "op-desk", "hlc": "1", "createdBy": "desktop" });
+        op_log.append(local, None).await.unwrap();
+
+        let report = sync_paired_devices(
+            &relay,
+            "desktop",
+            &persistence,
+            &pending_ops,
+            &op_log,
+            &audit,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.sent, 0);
+        assert_eq!(report.failed[0].device_id, "phone");
+        assert_eq!(report.failed[0].error, NO_PAIRING_SECRET);
+    }
+
+    #[tokio::test]
+    async fn test_ops_relayed_both_ways() {
+        let relay_dir = tempdir().unwrap();
+        let config = RelayConfig {
+            port: 0,
+            ..RelayConfig::default()
+        };
+        let (mut server, port) = RelayServer::start(relay_dir.path(), config, &Default::default())
+            .await
+            .unwrap();
+        let relay = RelayClient::new(&format!("http://127.0.0.1:{}", port));
+
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let persistence = PersistenceManager::new(config_dir.clone()).unwrap();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let audit = AuditLog::new(config_dir).unwrap();
+        persistence
+            .add_device(PairedDevice {
+                id: "phone".to_string(),
+                name: "Phone".to_string(),
+                token: "token".to_string(),
+                paired_at: chrono::Utc::now().to_rfc3339(),
+                last_sync_at: None,
+                status: PairedDeviceStatus::Active,
+                acked_seq: 0,
+                relay_seq: 0,
+                relay_acked_seq: 0,
+                relay_deposited_at: None,
+                pairing_secret: Some("secret".to_string()),
+            })
+            .await
+            .unwrap();
+
+        // The phone logged an expense on the road; the desktop has a local op
+        let phone = PairMailbox::new("secret", "phone", "desktop");
+        let expense = json!({ "id": "op-phone", "hlc": "2", "createdBy": "phone" });
+        relay
+            .deposit(
+                &phone.outbox_id,
+                phone.seal(&phone.batch(vec![expense])).unwrap(),
+            )
+            .await
+            .unwrap();
+        let local = json!({ "id": "op-desk", "hlc": "1", "createdBy": "desktop" });
+        op_log.append(local, None).await.unwrap();
+
+        let report = sync_paired_devices(
+            &relay,
+            "desktop",
+            &persistence,
+            &pending_ops,
+            &op_log,
+            &audit,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.received, 1);
+        assert_eq!(report.stored, 1);
+        assert_eq!(report.sent, 1);
+        assert_eq!(pending_ops.page(0, None).await.ops[0].op["id"], "op-phone");
+
+        let page = relay.receive(&phone).await.unwrap();
+        // Runs through the phone's own op, logged after the desktop's
+        assert_eq!(page.batches[0].through_seq, Some(2));
+        let at_phone: Vec<_> = page.batches.into_iter().flat_map(|b| b.ops).collect();
+        relay
+            .ack_inbox(&phone, page.last_seq.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(at_phone.len(), 1);
+        assert_eq!(at_phone[0]["id"], "op-desk");
+
+        // Deposited, but kept until the phone confirms it
+        let device = persistence.get_device("phone").await.unwrap().unwrap();
+        assert_eq!((device.relay_seq, device.delivered_seq()), (2, 0));
+        assert_eq!(op_log.count().await, 2);
+
+        // Nothing is sent twice
+        let again = sync_paired_devices(
+            &relay,
+            "desktop",
+            &persistence,
+            &pending_ops,
+            &op_log,
+            &audit,
+        )
+        .await
+        .unwrap();
+        assert_eq!(again.sent + again.received, 0);
+
+        // The phone confirms with an empty batch; the desktop op is pruned
+        let mut ack = phone.batch(Vec::new());
+        ack.acked_seq = Some(2);
+        relay
+            .deposit(&phone.outbox_id, phone.seal(&ack).unwrap())
+            .await
+            .unwrap();
+        sync_paired_devices(
+            &relay,
+            "desktop",
+            &persistence,
+            &pending_ops,
+            &op_log,
+            &audit,
+        )
+        .await
+        .unwrap();
+        let device = persistence.get_device("phone").await.unwrap().unwrap();
+        assert_eq!(device.delivered_seq(), 2);
+        assert_eq!(op_log.count().await, 0);
+
+        server.stop();
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_ops_redeposited_after_relay_ttl() {
+        let relay_dir = tempdir().unwrap();
+        let config = RelayConfig {
+            port: 0,
+            message_ttl_hours: 2,
+            ..RelayConfig::default()
+        };
+        let (mut server, port) = RelayServer::start(relay_dir.path(), config, &Default::default())
+            .await
+            .unwrap();
+        let relay = RelayClient::new(&format!("http://127.0.0.1:{}", port));
+
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let clock = ManualClock::new();
+        let persistence =
+            PersistenceManager::with_clock(config_dir.clone(), clock.clone()).unwrap();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let audit = AuditLog::new(config_dir).unwrap();
+        persistence
+            .add_device(PairedDevice {
+                id: "phone".to_string(),
+                name: "Phone".to_string(),
+                token: "token".to_string(),
+                paired_at: chrono::Utc::now().to_rfc3339(),
+                last_sync_at: None,
+                status: PairedDeviceStatus::Active,
+                acked_seq: 0,
+                relay_seq: 0,
+                relay_acked_seq: 0,
+                relay_deposited_at: None,
+                pairing_secret: Some("secret".to_string()),
+            })
+            .await
+            .unwrap();
+        op_log
+            .append(json!({ "id": "op-desk", "hlc": "1" }), None)
+            .await
+            .unwrap();
+
+        let sync = || {
+            sync_paired_devices(
+                &relay,
+                "desktop",
+                &persistence,
+                &pending_ops,
+                &op_log,
+                &audit,
+            )
+        };
+        assert_eq!(sync().await.unwrap().sent, 1);
+
+        // Not confirmed, but the relay still holds it
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(sync().await.unwrap().sent, 0);
+
+        // The relay may have expired it by now, going by the TTL it reports
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(sync().await.unwrap().sent, 1);
+        assert_eq!(sync().await.unwrap().sent, 0);
+        assert_eq!(op_log.count().await, 1);
+
+        server.stop();
+    }
+}
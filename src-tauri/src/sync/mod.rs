@@ -2,11 +2,36 @@
 //!
 //! Provides LAN sync server, mDNS discovery, and encryption commands for the sync feature.
 
+pub mod audit;
+pub mod client;
+pub mod clock;
 pub mod commands;
+pub mod conflicts;
 pub mod crypto;
+pub mod daemon;
+pub mod diagnostics;
 pub mod discovery;
+pub mod encoding;
+pub mod error;
+pub mod folder;
+pub mod inbox;
+pub mod mailbox;
+pub mod network;
+pub mod openapi;
+pub mod oplog;
 pub mod pairing;
 pub mod persistence;
+pub mod protocol;
+pub mod ratelimit;
+pub mod redact;
+pub mod relay;
+pub mod sas;
 pub mod server;
+pub mod settings;
+#[cfg(test)]
+mod simulator;
+pub mod status;
+pub mod supervisor;
+pub mod trusted;
 
 pub use commands::*;
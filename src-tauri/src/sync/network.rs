@@ -0,0 +1,461 @@
+//! Network Selection
+//!
+//! Decides which local addresses the sync server listens on and advertises,
+//! and which remote addresses may connect (`[network]` in the daemon config).
+//! By default only private LAN addresses on physical interfaces are used, so
+//! VPN tunnels, container bridges and public addresses are never exposed;
+//! choosing interfaces or subnets replaces that default. Loopback is always
+//! bound so the desktop can reach its own server.
+//!
+//! IPv6 link-local addresses are bound with their interface scope and
+//! announced over mDNS, but left out of pairing host candidates: the zone a
+//! phone needs is one of its own interfaces, not ours.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::str::FromStr;
+
+/// Ranges allowed to connect unless `allowed_peers` is configured
+const PRIVATE_RANGES: &[&str] = &[
+    "127.0.0.0/8",
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "169.254.0.0/16",
+    "::1/128",
+    "fc00::/7",
+    "fe80::/10",
+];
+
+/// Name fragments of VPN adapters
+const VPN_PATTERNS: &[&str] = &[
+    "tun",
+    "tap",
+    "wg",
+    "ppp",
+    "ipsec",
+    "tailscale",
+    "zt",
+    "nordlynx",
+    "vpn",
+];
+/// Name fragments of adapters created by VMs and containers
+const VIRTUAL_PATTERNS: &[&str] = &[
+    "docker",
+    "br-",
+    "veth",
+    "virbr",
+    "vboxnet",
+    "vmnet",
+    "vethernet",
+    "virtualbox",
+    "vmware",
+    "hyper-v",
+    "lxc",
+    "cni",
+    "podman",
+];
+
+// ============================================================================
+// Subnet
+// ============================================================================
+
+/// An address range in CIDR notation; a bare address is a single host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    /// The subnet `ip` is on, given its interface netmask
+    pub fn from_netmask(ip: IpAddr, netmask: IpAddr) -> Self {
+        match (ip, netmask) {
+            (IpAddr::V4(ip), IpAddr::V4(mask)) => {
+                let mask = u32::from(mask);
+                Self {
+                    network: IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask)),
+                    prefix: mask.count_ones() as u8,
+                }
+            }
+            (IpAddr::V6(ip), IpAddr::V6(mask)) => {
+                let mask = u128::from(mask);
+                Self {
+                    network: IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask)),
+                    prefix: mask.count_ones() as u8,
+                }
+            }
+            (ip, _) => Self {
+                network: ip,
+                prefix: if ip.is_ipv4() { 32 } else { 128 },
+            },
+        }
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, normalize(*ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid subnet address: {}", s))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("Invalid subnet prefix: {}", s))?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl Serialize for Subnet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Subnet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) as plain IPv4
+fn normalize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+// ============================================================================
+// Local addresses
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterfaceKind {
+    Physical,
+    Vpn,
+    /// VM, container or bridge adapter
+    Virtual,
+}
+
+pub fn classify_interface(name: &str) -> InterfaceKind {
+    let name = name.to_lowercase();
+    if VPN_PATTERNS.iter().any(|p| name.contains(p)) {
+        InterfaceKind::Vpn
+    } else if VIRTUAL_PATTERNS.iter().any(|p| name.contains(p)) {
+        InterfaceKind::Virtual
+    } else {
+        InterfaceKind::Physical
+    }
+}
+
+/// An address of a local interface
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalAddress {
+    pub interface: String,
+    pub ip: IpAddr,
+    pub kind: InterfaceKind,
+    /// Interface index, needed to bind IPv6 link-local addresses
+    #[serde(skip)]
+    pub scope_id: u32,
+}
+
+impl LocalAddress {
+    pub fn new(interface: &str, ip: IpAddr, scope_id: u32) -> Self {
+        Self {
+            interface: interface.to_string(),
+            ip,
+            kind: classify_interface(interface),
+            scope_id,
+        }
+    }
+
+    pub fn socket_addr(&self, port: u16) -> SocketAddr {
+        match self.ip {
+            IpAddr::V6(ip) if is_link_local_v6(&ip) => {
+                SocketAddr::V6(SocketAddrV6::new(ip, port, 0, self.scope_id))
+            }
+            ip => SocketAddr::new(ip, port),
+        }
+    }
+
+    /// How a phone should write this address in a URL; None for IPv6
+    /// link-local, which only works with the phone's own zone
+    pub fn host(&self) -> Option<String> {
+        match self.ip {
+            IpAddr::V4(ip) => Some(ip.to_string()),
+            IpAddr::V6(ip) if is_link_local_v6(&ip) => None,
+            IpAddr::V6(ip) => Some(format!("[{}]", ip)),
+        }
+    }
+
+    /// Private LAN address: RFC 1918, IPv4 link-local or IPv6 ULA/link-local
+    fn is_lan(&self) -> bool {
+        match self.ip {
+            IpAddr::V4(ip) => ip.is_private() || ip.is_link_local(),
+            IpAddr::V6(ip) => is_unique_local_v6(&ip) || is_link_local_v6(&ip),
+        }
+    }
+}
+
+fn is_link_local_v6(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
+
+fn is_unique_local_v6(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xfe00 == 0xfc00
+}
+
+/// Every non-loopback address of every interface
+pub fn local_addresses() -> Vec<LocalAddress> {
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces
+            .into_iter()
+            .filter(|iface| !iface.is_loopback())
+            .map(|iface| LocalAddress::new(&iface.name, iface.ip(), iface.index.unwrap_or(0)))
+            .collect(),
+        Err(e) => {
+            log::warn!("Failed to list network interfaces: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// ============================================================================
+// NetworkConfig
+// ============================================================================
+
+/// Where the sync server listens and who may connect
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// Interfaces to listen on, by name (`en0`, `Wi-Fi`). With `subnets`
+    /// empty too, every private address on a physical interface is used.
+    pub interfaces: Vec<String>,
+    /// Also listen on local addresses inside these subnets
+    pub subnets: Vec<Subnet>,
+    /// Remote ranges allowed to connect; private ranges by default
+    pub allowed_peers: Vec<Subnet>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            interfaces: Vec::new(),
+            subnets: Vec::new(),
+            allowed_peers: PRIVATE_RANGES
+                .iter()
+                .filter_map(|range| range.parse().ok())
+                .collect(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Addresses to listen on and advertise, from `addresses`, with the
+    /// default route's address first
+    pub fn select(&self, addresses: &[LocalAddress]) -> Vec<LocalAddress> {
+        let explicit = !self.interfaces.is_empty() || !self.subnets.is_empty();
+        let mut selected: Vec<LocalAddress> = addresses
+            .iter()
+            .filter(|addr| {
+                if explicit {
+                    self.interfaces.contains(&addr.interface)
+                        || self.subnets.iter().any(|subnet| subnet.contains(&addr.ip))
+                } else {
+                    addr.kind == InterfaceKind::Physical && addr.is_lan()
+                }
+            })
+            .cloned()
+            .collect();
+
+        if let Ok(primary) = local_ip_address::local_ip() {
+            if let Some(index) = selected.iter().position(|addr| addr.ip == primary) {
+                let primary = selected.remove(index);
+                selected.insert(0, primary);
+            }
+        }
+        selected
+    }
+
+    /// `select` over the current interfaces
+    pub fn selected(&self) -> Vec<LocalAddress> {
+        self.select(&local_addresses())
+    }
+
+    /// Whether `ip` may connect. Loopback always may.
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let ip = normalize(ip);
+        ip.is_loopback() || self.allowed_peers.iter().any(|range| range.contains(&ip))
+    }
+}
+
+/// Pairing host candidates for `selected`; loopback when there are none
+pub fn host_candidates(selected: &[LocalAddress]) -> Vec<String> {
+    let mut hosts: Vec<String> = Vec::new();
+    for host in selected.iter().filter_map(LocalAddress::host) {
+        if !hosts.contains(&host) {
+            hosts.push(host);
+        }
+    }
+    if hosts.is_empty() {
+        hosts.push(Ipv4Addr::LOCALHOST.to_string());
+    }
+    hosts
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(interface: &str, ip: &str) -> LocalAddress {
+        LocalAddress::new(interface, ip.parse().unwrap(), 7)
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_subnet_parsing_and_matching() {
+        let lan: Subnet = "192.168.1.0/24".parse().unwrap();
+        assert!(lan.contains(&ip("192.168.1.77")));
+        assert!(lan.contains(&ip("::ffff:192.168.1.77")));
+        assert!(!lan.contains(&ip("192.168.2.1")));
+        assert_eq!(lan.to_string(), "192.168.1.0/24");
+
+        let ula: Subnet = "fd00::/8".parse().unwrap();
+        assert!(ula.contains(&ip("fd12:3456::1")));
+        assert!(!ula.contains(&ip("2001:db8::1")));
+
+        let host: Subnet = "10.0.0.5".parse().unwrap();
+        assert_eq!(host.to_string(), "10.0.0.5/32");
+        assert_eq!(
+            Subnet::from_netmask(ip("192.168.1.77"), ip("255.255.255.0")),
+            lan
+        );
+        assert!("0.0.0.0/0"
+            .parse::<Subnet>()
+            .unwrap()
+            .contains(&ip("8.8.8.8")));
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+        assert!("lan".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn test_classifies_vpn_and_virtual_adapters() {
+        assert_eq!(classify_interface("en0"), InterfaceKind::Physical);
+        assert_eq!(classify_interface("Wi-Fi"), InterfaceKind::Physical);
+        assert_eq!(classify_interface("utun3"), InterfaceKind::Vpn);
+        assert_eq!(classify_interface("wg0"), InterfaceKind::Vpn);
+        assert_eq!(classify_interface("tailscale0"), InterfaceKind::Vpn);
+        assert_eq!(classify_interface("docker0"), InterfaceKind::Virtual);
+        assert_eq!(
+            classify_interface("vEthernet (WSL)"),
+            InterfaceKind::Virtual
+        );
+    }
+
+    #[test]
+    fn test_default_selection_skips_vpn_virtual_and_public() {
+        let addresses = [
+            addr("en0", "192.168.1.20"),
+            addr("en0", "fe80::1"),
+            addr("en0", "fd00::20"),
+            addr("en1", "203.0.113.9"),
+            addr("utun3", "10.8.0.2"),
+            addr("docker0", "172.17.0.1"),
+        ];
+        let selected = NetworkConfig::default().select(&addresses);
+        let ips: Vec<String> = selected.iter().map(|a| a.ip.to_string()).collect();
+        assert_eq!(ips.len(), 3);
+        assert!(ips.contains(&"192.168.1.20".to_string()));
+        assert!(ips.contains(&"fe80::1".to_string()));
+        assert!(ips.contains(&"fd00::20".to_string()));
+
+        // Link-local IPv6 is bound with its scope but not handed to phones
+        let link_local = selected.iter().find(|a| a.ip == ip("fe80::1")).unwrap();
+        assert_eq!(link_local.socket_addr(4242).to_string(), "[fe80::1%7]:4242");
+        let hosts = host_candidates(&selected);
+        assert!(hosts.contains(&"[fd00::20]".to_string()));
+        assert!(!hosts.iter().any(|h| h.contains("fe80")));
+        assert_eq!(host_candidates(&[]), ["127.0.0.1"]);
+    }
+
+    #[test]
+    fn test_explicit_selection_and_allowed_peers() {
+        let addresses = [
+            addr("en0", "192.168.1.20"),
+            addr("utun3", "10.8.0.2"),
+            addr("docker0", "172.17.0.1"),
+        ];
+        let config = NetworkConfig {
+            interfaces: vec!["utun3".to_string()],
+            subnets: vec!["172.17.0.0/16".parse().unwrap()],
+            ..Default::default()
+        };
+        let ips: Vec<IpAddr> = config.select(&addresses).iter().map(|a| a.ip).collect();
+        assert_eq!(ips.len(), 2);
+        assert!(!ips.contains(&ip("192.168.1.20")));
+
+        let defaults = NetworkConfig::default();
+        assert!(defaults.allows(ip("192.168.1.50")));
+        assert!(defaults.allows(ip("::ffff:10.0.0.3")));
+        assert!(defaults.allows(ip("fe80::abcd")));
+        assert!(!defaults.allows(ip("203.0.113.9")));
+
+        let office = NetworkConfig {
+            allowed_peers: vec!["192.168.1.0/24".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(office.allows(ip("192.168.1.50")));
+        assert!(!office.allows(ip("10.0.0.3")));
+        assert!(office.allows(ip("127.0.0.1")));
+        assert!(office.allows(ip("::1")));
+    }
+}
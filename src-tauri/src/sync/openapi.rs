@@ -0,0 +1,487 @@
+//! Sync API Contract
+//!
+//! OpenAPI 3.1 description of the `/v1` sync and pairing routes, generated
+//! from the request/response types and the `#[utoipa::path]` annotations on
+//! the handlers in `server`. It is served at `GET /v1/openapi.json` so mobile
+//! clients can be written (or generated) against it.
+//!
+//! Error bodies carry a machine-readable `error` code: `PairingErrorCode` for
+//! the pairing routes, `LimitErrorCode` for rate and size limits, and
+//! "upgrade_required" for protocol version mismatches (see `protocol`).
+//!
+//! The unversioned `/sync/*` and `/pair/*` routes take the same bodies but are
+//! deprecated and left out of the document.
+
+use super::protocol::PROTOCOL_HEADER;
+use super::server;
+use utoipa::openapi::path::{Operation, Parameter, ParameterBuilder, ParameterIn};
+use utoipa::openapi::schema::{ObjectBuilder, Type};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::Required;
+use utoipa::{Modify, OpenApi};
+
+/// Routes served to every protocol version (no version header needed)
+const UNVERSIONED_PATHS: [&str; 2] = ["/v1/sync/hello", "/v1/openapi.json"];
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Mutaba3a Sync API",
+        description = "LAN sync and device pairing between the desktop app (or sync hub) and mobile devices."
+    ),
+    paths(
+        server::handle_hello,
+        server::handle_openapi,
+        server::handle_status,
+        server::handle_pull,
+        server::handle_push,
+        server::handle_pair_start,
+        server::handle_pair_key,
+        server::handle_pair_confirm,
+        server::handle_pair_status,
+    ),
+    modifiers(&SessionToken, &ProtocolHeader),
+    tags(
+        (name = "sync", description = "Exchanging operations with a paired device"),
+        (name = "pairing", description = "Pairing a mobile device with a code or QR code"),
+    )
+)]
+struct SyncApiDoc;
+
+/// The OpenAPI document of the v1 routes
+pub fn document() -> utoipa::openapi::OpenApi {
+    SyncApiDoc::openapi()
+}
+
+/// Bearer scheme for the session token returned by `/v1/pair/confirm`
+struct SessionToken;
+
+impl Modify for SessionToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let scheme = HttpBuilder::new()
+            .scheme(HttpAuthScheme::Bearer)
+            .description(Some(
+                "Session token from /v1/pair/confirm, required on pull and push.",
+            ))
+            .build();
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme("bearer", SecurityScheme::Http(scheme));
+    }
+}
+
+/// `X-Sync-Protocol` header on every version-checked route
+struct ProtocolHeader;
+
+impl ProtocolHeader {
+    fn parameter() -> Parameter {
+        ParameterBuilder::new()
+            .name(PROTOCOL_HEADER)
+            .parameter_in(ParameterIn::Header)
+            .required(Required::False)
+            .description(Some(
+                "Protocol version agreed in /v1/sync/hello (1 if absent)",
+            ))
+            .schema(Some(ObjectBuilder::new().schema_type(Type::Integer)))
+            .build()
+    }
+}
+
+impl Modify for ProtocolHeader {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if UNVERSIONED_PATHS.contains(&path.as_str()) {
+                continue;
+            }
+            let operations: [&mut Option<Operation>; 2] = [&mut item.get, &mut item.post];
+            for operation in operations.into_iter().flatten() {
+                operation
+                    .parameters
+                    .get_or_insert_with(Vec::new)
+                    .push(Self::parameter());
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+/// Conformance suite: drives an in-process server and checks every request it
+/// sends and every response it gets against the served document.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::encoding::{Format, CBOR_CONTENT_TYPE};
+    use crate::sync::inbox::PendingOpsStore;
+    use crate::sync::oplog::OpLog;
+    use crate::sync::persistence::{PairedDevice, PairedDeviceStatus};
+    use crate::sync::protocol::{self, PROTOCOL_VERSION};
+    use crate::sync::server::{ServerOptions, SyncServer};
+    use crate::sync::status::SyncStatusTracker;
+    use reqwest::{header, Method};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tempfile::{tempdir, TempDir};
+
+    struct Contract {
+        doc: Value,
+        base: String,
+        http: reqwest::Client,
+        server: SyncServer,
+        _dir: TempDir,
+    }
+
+    impl Contract {
+        /// Start a server and fetch its contract
+        async fn start() -> Self {
+            let dir = tempdir().unwrap();
+            let config_dir = dir.path().to_path_buf();
+            let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+            let op_log = OpLog::new(config_dir.clone()).unwrap();
+            let options = ServerOptions::new(
+                "desktop-1",
+                "Desktop",
+                config_dir.clone(),
+                Arc::clone(&pending_ops),
+                Arc::clone(&op_log),
+                SyncStatusTracker::new(pending_ops, op_log, None),
+            );
+            let (server, port) = SyncServer::start(options, &Default::default())
+                .await
+                .unwrap();
+            let base = format!("http://127.0.0.1:{}", port);
+            let http = reqwest::Client::new();
+            let doc = http
+                .get(format!("{}/v1/openapi.json", base))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            Self {
+                doc,
+                base,
+                http,
+                server,
+                _dir: dir,
+            }
+        }
+
+        /// Pair `device_id`; returns the `Authorization` header value for it
+        async fn pair(&self, device_id: &str) -> String {
+            let token = format!("token-{}", device_id);
+            self.server
+                .persistence
+                .add_device(PairedDevice {
+                    id: device_id.to_string(),
+                    name: device_id.to_string(),
+                    token: token.clone(),
+                    paired_at: chrono::Utc::now().to_rfc3339(),
+                    last_sync_at: None,
+                    status: PairedDeviceStatus::Active,
+                    acked_seq: 0,
+                    relay_seq: 0,
+                    relay_acked_seq: 0,
+                    relay_deposited_at: None,
+                    pairing_secret: None,
+                })
+                .await
+                .unwrap();
+            format!("Bearer {}", token)
+        }
+
+        fn operation(&self, method: &Method, path: &str) -> &Value {
+            let operation = &self.doc["paths"][path][method.as_str().to_lowercase()];
+            assert!(
+                operation.is_object(),
+                "{} {} is not in the contract",
+                method,
+                path
+            );
+            operation
+        }
+
+        /// Send a JSON request (checked against the contract) and check the
+        /// response; returns the status and the body
+        async fn call(
+            &self,
+            method: Method,
+            path_and_query: &str,
+            body: Option<Value>,
+            headers: &[(&str, &str)],
+        ) -> (u16, Value) {
+            let path = path_and_query.split('?').next().unwrap();
+            let operation = self.operation(&method, path);
+
+            let mut request = self
+                .http
+                .request(method.clone(), format!("{}{}", self.base, path_and_query))
+                .header(PROTOCOL_HEADER, PROTOCOL_VERSION.to_string());
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            if let Some(body) = &body {
+                let schema = &operation["requestBody"]["content"]["application/json"]["schema"];
+                self.validate(schema, body, &format!("request to {} {}", method, path));
+                request = request.json(body);
+            }
+
+            let response = request.send().await.unwrap();
+            let status = response.status().as_u16();
+            let content_type = response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            let bytes = response.bytes().await.unwrap();
+
+            let documented = &operation["responses"][status.to_string()];
+            assert!(
+                documented.is_object(),
+                "{} {} answered {}, which the contract doesn't list",
+                method,
+                path,
+                status
+            );
+            let Some(content) = documented.get("content") else {
+                assert!(
+                    bytes.is_empty(),
+                    "{} {} {} has an undocumented body",
+                    method,
+                    path,
+                    status
+                );
+                return (status, Value::Null);
+            };
+
+            let format = if content_type.starts_with(CBOR_CONTENT_TYPE) {
+                Format::Cbor
+            } else {
+                Format::Json
+            };
+            let media_type = format.content_type();
+            assert!(
+                content.get(media_type).is_some(),
+                "{} {} {} answered {}, which the contract doesn't list",
+                method,
+                path,
+                status,
+                media_type
+            );
+            let body: Value = format.decode(&bytes).unwrap();
+            if path != "/v1/openapi.json" {
+                let what = format!("{} {} {}", method, path, status);
+                self.validate(&content[media_type]["schema"], &body, &what);
+            }
+            (status, body)
+        }
+
+        /// Validate against a schema of the document, disallowing properties
+        /// the contract doesn't mention
+        fn validate(&self, schema: &Value, value: &Value, what: &str) {
+            assert!(schema.is_object(), "no schema for {}", what);
+            let mut components = self.doc["components"].clone();
+            if let Some(schemas) = components["schemas"].as_object_mut() {
+                for component in schemas.values_mut() {
+                    if component.get("properties").is_some() {
+                        component["additionalProperties"] = json!(false);
+                    }
+                }
+            }
+            let mut root = schema.clone();
+            root["components"] = components;
+
+            let validator = jsonschema::draft202012::new(&root).unwrap();
+            let errors: Vec<String> = validator
+                .iter_errors(value)
+                .map(|e| format!("{} at {}", e, e.instance_path))
+                .collect();
+            assert!(
+                errors.is_empty(),
+                "{} breaks the contract: {:?}\n{}",
+                what,
+                errors,
+                value
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_document_covers_v1_routes() {
+        let contract = Contract::start().await;
+        assert_eq!(contract.doc["openapi"], "3.1.0");
+
+        let paths: Vec<&String> = contract.doc["paths"].as_object().unwrap().keys().collect();
+        assert_eq!(paths.len(), 9);
+        assert!(paths.iter().all(|p| p.starts_with("/v1/")));
+
+        // Version-checked routes take the protocol header; negotiation doesn't
+        let params = |method: Method, path: &str| -> Vec<String> {
+            contract.operation(&method, path)["parameters"]
+                .as_array()
+                .map(|params| {
+                    params
+                        .iter()
+                        .map(|p| p["name"].as_str().unwrap().to_string())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        assert!(params(Method::POST, "/v1/sync/pull").contains(&PROTOCOL_HEADER.to_string()));
+        assert!(!params(Method::GET, "/v1/sync/hello").contains(&PROTOCOL_HEADER.to_string()));
+
+        let codes = &contract.doc["components"]["schemas"]["PairingErrorCode"]["enum"];
+        assert!(codes.as_array().unwrap().contains(&json!("wrong_code")));
+    }
+
+    #[tokio::test]
+    async fn test_sync_routes_conform() {
+        let contract = Contract::start().await;
+
+        let (status, hello) = contract
+            .call(
+                Method::GET,
+                "/v1/sync/hello?minVersion=1&maxVersion=2&features=cbor",
+                None,
+                &[],
+            )
+            .await;
+        assert_eq!(status, 200);
+        assert_eq!(hello["protocolVersion"], PROTOCOL_VERSION);
+        let (status, error) = contract
+            .call(
+                Method::GET,
+                "/v1/sync/hello?minVersion=7&maxVersion=8",
+                None,
+                &[],
+            )
+            .await;
+        assert_eq!((status, &error["error"]), (426, &json!("upgrade_required")));
+
+        let (status, server) = contract
+            .call(Method::GET, "/v1/sync/status", None, &[])
+            .await;
+        assert_eq!(status, 200);
+        assert_eq!(server["capabilities"], json!(protocol::FEATURES));
+
+        let cli = contract.pair("cli-1").await;
+        let cli_auth = [("authorization", cli.as_str())];
+        let phone = contract.pair("phone-1").await;
+        let phone_auth = [("authorization", phone.as_str())];
+
+        let ops = json!([{ "id": "op-1", "hlc": "1", "entityType": "client" }, { "hlc": "2" }]);
+        let push = json!({ "device_id": "cli-1", "ops": ops });
+        let (status, pushed) = contract
+            .call(Method::POST, "/v1/sync/push", Some(push), &cli_auth)
+            .await;
+        assert_eq!(status, 200);
+        assert_eq!(pushed["rejected"][0]["reason"], "invalid_op");
+        assert_eq!(pushed["rejected"][0]["index"], 1);
+
+        let pull = json!({ "device_id": "phone-1", "since_hlc": "", "since_seq": 0 });
+        let (status, page) = contract
+            .call(
+                Method::POST,
+                "/v1/sync/pull",
+                Some(pull.clone()),
+                &phone_auth,
+            )
+            .await;
+        assert_eq!(status, 200);
+        assert_eq!(page["operations"][0]["id"], "op-1");
+        let accept_cbor = [phone_auth[0], ("accept", CBOR_CONTENT_TYPE)];
+        let (status, page) = contract
+            .call(
+                Method::POST,
+                "/v1/sync/pull",
+                Some(pull.clone()),
+                &accept_cbor,
+            )
+            .await;
+        assert_eq!((status, page["has_more"].as_bool()), (200, Some(false)));
+
+        // Error responses
+        let bad_token = [("authorization", "Bearer not-a-token")];
+        let (status, _) = contract
+            .call(
+                Method::POST,
+                "/v1/sync/pull",
+                Some(pull.clone()),
+                &bad_token,
+            )
+            .await;
+        assert_eq!(status, 401);
+        let (status, _) = contract
+            .call(Method::POST, "/v1/sync/pull", Some(pull), &[])
+            .await;
+        assert_eq!(status, 401);
+
+        let ops: Vec<Value> = (0..1001)
+            .map(|i| json!({ "id": format!("op-{}", i) }))
+            .collect();
+        let push = json!({ "device_id": "cli-1", "ops": ops });
+        let (status, error) = contract
+            .call(Method::POST, "/v1/sync/push", Some(push), &cli_auth)
+            .await;
+        assert_eq!(
+            (status, &error["error"]),
+            (413, &json!("payload_too_large"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pairing_routes_conform() {
+        let contract = Contract::start().await;
+
+        let (status, session) = contract
+            .call(Method::POST, "/v1/pair/start", None, &[])
+            .await;
+        assert_eq!(status, 200);
+        let pairing_id = session["pairingId"].as_str().unwrap();
+        let wrong_code = if session["code"] == "000000" {
+            "111111"
+        } else {
+            "000000"
+        };
+
+        let key = json!({
+            "pairingId": pairing_id,
+            "method": "code",
+            "code": wrong_code,
+            "nonce": null,
+            "keyCommitment": "AAAA",
+        });
+        let (status, error) = contract
+            .call(Method::POST, "/v1/pair/key", Some(key), &[])
+            .await;
+        assert_eq!((status, &error["error"]), (400, &json!("wrong_code")));
+
+        let confirm = json!({
+            "pairingId": pairing_id,
+            "method": "code",
+            "code": wrong_code,
+            "nonce": null,
+            "deviceName": "Phone",
+            "deviceId": "phone-1",
+        });
+        let (status, error) = contract
+            .call(Method::POST, "/v1/pair/confirm", Some(confirm), &[])
+            .await;
+        assert_eq!((status, &error["error"]), (400, &json!("wrong_code")));
+
+        let query = format!("/v1/pair/status?pairingId={}", pairing_id);
+        let (status, pairing) = contract.call(Method::GET, &query, None, &[]).await;
+        assert_eq!((status, &pairing["status"]), (200, &json!("pending")));
+        assert_eq!(pairing["attemptsRemaining"], 3);
+
+        let (status, error) = contract
+            .call(Method::GET, "/v1/pair/status?pairingId=nope", None, &[])
+            .await;
+        assert_eq!((status, &error["code"]), (404, &json!("NOT_FOUND")));
+    }
+}
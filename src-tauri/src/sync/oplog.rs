@@ -0,0 +1,703 @@
+//! Operation Log
+//!
+//! Merged log of operations waiting to be pulled by paired devices: ops
+//! created on the desktop and ops pushed by any paired device, so the desktop
+//! relays changes between phones.
+//! File location: {app_config_dir}/op_log.jsonl
+//!
+//! Operations are stored verbatim (original `createdBy` and HLC) together
+//! with their origin (the pushing device, or none for desktop ops). A device
+//! is never served ops it authored, and an op id already in the log is not
+//! appended again, even after it was pruned: pruned ids are kept in
+//! `op_log_pruned_ids.txt`, one per line.
+//!
+//! Each operation gets a sequence number. Paired devices acknowledge what
+//! they have received by pulling with `since_seq`, which moves their cursor
+//! (`PairedDevice::acked_seq`). Once every active paired device has
+//! acknowledged an operation it is pruned and the file is compacted, so the
+//! log only holds what is still undelivered. Transports that don't go through
+//! pairing (the sync folder) place a hold so ops they haven't written out yet
+//! are kept.
+//!
+//! File format: a header line `{"version":1,"nextSeq":N}` followed by one
+//! `{"seq":..,"origin":..,"op":..}` entry per line. New entries are appended; pruning
+//! rewrites the file. A file whose header can't be read is set aside as
+//! `op_log.jsonl.corrupt-<timestamp>` and the log starts empty.
+
+use super::clock::{Clock, SystemClock};
+use super::conflicts::{self, DetectedConflict};
+use super::persistence::{set_aside_corrupt, PairedDevice, PairedDeviceStatus, PersistenceError};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, MutexGuard};
+
+const OP_LOG_FILE: &str = "op_log.jsonl";
+const PRUNED_IDS_FILE: &str = "op_log_pruned_ids.txt";
+const LOG_VERSION: u32 = 1;
+
+// ============================================================================
+// Types
+// ============================================================================
+
+/// An operation in the log, tagged with its sequence number and origin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggedOp {
+    pub seq: u64,
+    /// Device that pushed the op (None for ops created on the desktop)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    pub op: serde_json::Value,
+}
+
+impl LoggedOp {
+    /// Whether the op came from (or was authored by) the given device
+    fn is_from(&self, device_id: &str) -> bool {
+        self.origin.as_deref() == Some(device_id)
+            || self.op.get("createdBy").and_then(|v| v.as_str()) == Some(device_id)
+    }
+}
+
+/// Ops served to one device, with the sequence number the page covers
+#[derive(Debug, Clone)]
+pub struct OpsPage {
+    pub ops: Vec<LoggedOp>,
+    pub has_more: bool,
+    /// Last sequence number scanned, including the device's own ops that were skipped
+    pub last_seq: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogHeader {
+    version: u32,
+    next_seq: u64,
+}
+
+struct LogState {
+    /// Sequence number assigned to the next appended op
+    next_seq: u64,
+    ops: VecDeque<LoggedOp>,
+    /// Ids of the ops in `ops` and of pruned ones, for deduplication
+    ids: HashSet<String>,
+}
+
+// ============================================================================
+// OpLog
+// ============================================================================
+
+pub struct OpLog {
+    config_dir: PathBuf,
+    state: Mutex<LogState>,
+    /// Named holds: ops after the held sequence number are never pruned
+    holds: StdMutex<HashMap<String, u64>>,
+    /// Held while ops from another device are checked and stored (see `ingest`)
+    ingest: Mutex<()>,
+    /// Source of conflict timestamps
+    clock: Arc<dyn Clock>,
+}
+
+impl OpLog {
+    /// Open the log stored in the given config directory
+    pub fn new(config_dir: PathBuf) -> Result<Arc<Self>, PersistenceError> {
+        Self::with_clock(config_dir, Arc::new(SystemClock))
+    }
+
+    /// Open the log, timestamping detected conflicts with `clock`
+    pub fn with_clock(
+        config_dir: PathBuf,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Arc<Self>, PersistenceError> {
+        std::fs::create_dir_all(&config_dir)?;
+
+        let path = config_dir.join(OP_LOG_FILE);
+        let mut next_seq = 1;
+        let mut ops = VecDeque::new();
+        let mut ids = HashSet::new();
+
+        match std::fs::read_to_string(config_dir.join(PRUNED_IDS_FILE)) {
+            Ok(pruned) => ids.extend(pruned.lines().filter(|l| !l.is_empty()).map(String::from)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)?;
+            let mut lines = content.lines().filter(|l| !l.trim().is_empty());
+            let header = lines
+                .next()
+                .map(serde_json::from_str::<LogHeader>)
+                .transpose();
+            let entries = lines.filter_map(|line| match serde_json::from_str::<LoggedOp>(line) {
+                Ok(entry) => Some(entry),
+                // A crash mid-append can leave a partial last line
+                Err(e) => {
+                    log::warn!("Skipping malformed op log entry: {}", e);
+                    None
+                }
+            });
+
+            match header {
+                Ok(header) => {
+                    if let Some(header) = header {
+                        next_seq = header.next_seq;
+                    }
+                    for entry in entries {
+                        next_seq = next_seq.max(entry.seq + 1);
+                        if let Some(id) = op_id(&entry.op) {
+                            ids.insert(id.to_string());
+                        }
+                        ops.push_back(entry);
+                    }
+                }
+                // Start empty, but never reuse a sequence number a device may
+                // already have acknowledged
+                Err(e) => {
+                    next_seq = entries.map(|entry| entry.seq + 1).fold(next_seq, u64::max);
+                    set_aside_corrupt(&path, &e, clock.as_ref())?;
+                }
+            }
+        }
+
+        let log = Self {
+            config_dir,
+            state: Mutex::new(LogState { next_seq, ops, ids }),
+            holds: StdMutex::new(HashMap::new()),
+            ingest: Mutex::new(()),
+            clock,
+        };
+        if !path.exists() {
+            std::fs::write(&path, log.header_line(next_seq)?)?;
+        }
+
+        Ok(Arc::new(log))
+    }
+
+    fn file_path(&self) -> PathBuf {
+        self.config_dir.join(OP_LOG_FILE)
+    }
+
+    fn header_line(&self, next_seq: u64) -> Result<String, PersistenceError> {
+        let mut line = serde_json::to_string(&LogHeader {
+            version: LOG_VERSION,
+            next_seq,
+        })?;
+        line.push('\n');
+        Ok(line)
+    }
+
+    /// Append an operation from `origin` (None for the desktop). Returns its
+    /// sequence number, or None if an op with the same id is already logged.
+    pub async fn append(
+        &self,
+        op: serde_json::Value,
+        origin: Option<&str>,
+    ) -> Result<Option<u64>, PersistenceError> {
+        let mut state = self.state.lock().await;
+        let id = op_id(&op).map(|id| id.to_string());
+        if id.as_ref().is_some_and(|id| state.ids.contains(id)) {
+            return Ok(None);
+        }
+
+        let entry = LoggedOp {
+            seq: state.next_seq,
+            origin: origin.map(|o| o.to_string()),
+            op,
+        };
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.file_path())
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+
+        state.next_seq += 1;
+        let seq = entry.seq;
+        state.ops.push_back(entry);
+        if let Some(id) = id {
+            state.ids.insert(id);
+        }
+        Ok(Some(seq))
+    }
+
+    /// Serializes ingesting ops from other devices: while the guard is held no
+    /// other ingest can log an op between this one's `unlogged`,
+    /// `conflicts_with` and `append`, so two deliveries of the same op can't
+    /// both be stored and each op is checked against everything logged before it
+    pub async fn ingest(&self) -> MutexGuard<'_, ()> {
+        self.ingest.lock().await
+    }
+
+    /// The ops whose id is not in the log yet, each id once (ops without an id
+    /// are kept)
+    pub async fn unlogged(&self, ops: Vec<serde_json::Value>) -> Vec<serde_json::Value> {
+        let state = self.state.lock().await;
+        let mut seen = HashSet::new();
+        ops.into_iter()
+            .filter(|op| {
+                op_id(op).map_or(true, |id| {
+                    !state.ids.contains(id) && seen.insert(id.to_string())
+                })
+            })
+            .collect()
+    }
+
+    /// Conflicts between `ops` and logged ops from other authors (see `conflicts`)
+    pub async fn conflicts_with(&self, ops: &[serde_json::Value]) -> Vec<DetectedConflict> {
+        let state = self.state.lock().await;
+
+        let mut by_entity: HashMap<(&str, &str), Vec<&serde_json::Value>> = HashMap::new();
+        for entry in state.ops.iter() {
+            if let Some(key) = conflicts::entity_key(&entry.op) {
+                by_entity.entry(key).or_default().push(&entry.op);
+            }
+        }
+
+        let now = self.clock.now();
+        let mut found = Vec::new();
+        for op in ops {
+            let Some(existing) = conflicts::entity_key(op).and_then(|key| by_entity.get(&key))
+            else {
+                continue;
+            };
+            let detect = |e: &&serde_json::Value| conflicts::detect(e, op, now);
+            found.extend(existing.iter().filter_map(detect));
+        }
+        found
+    }
+
+    /// Up to `max_ops` operations for `device_id` with a sequence number
+    /// greater than `after_seq`, skipping the device's own ops.
+    pub async fn after_seq(&self, device_id: &str, after_seq: u64, max_ops: usize) -> OpsPage {
+        let state = self.state.lock().await;
+        let start = state.ops.partition_point(|e| e.seq <= after_seq);
+        Self::page(state.ops.range(start..), |e| e.is_from(device_id), max_ops)
+    }
+
+    /// Like `after_seq`, skipping ops from any of `device_ids`
+    pub async fn after_seq_excluding(
+        &self,
+        device_ids: &[String],
+        after_seq: u64,
+        max_ops: usize,
+    ) -> OpsPage {
+        let state = self.state.lock().await;
+        let start = state.ops.partition_point(|e| e.seq <= after_seq);
+        Self::page(
+            state.ops.range(start..),
+            |e| device_ids.iter().any(|id| e.is_from(id)),
+            max_ops,
+        )
+    }
+
+    /// Up to `max_ops` operations for `device_id` with an HLC greater than
+    /// `since_hlc` (legacy pull), in HLC order. Operations without an HLC are
+    /// always included.
+    pub async fn after_hlc(&self, device_id: &str, since_hlc: &str, max_ops: usize) -> OpsPage {
+        let state = self.state.lock().await;
+        let mut newer: Vec<&LoggedOp> = state
+            .ops
+            .iter()
+            .filter(|e| match op_hlc(&e.op) {
+                Some(hlc) => hlc > since_hlc,
+                None => true,
+            })
+            .collect();
+        // The client resumes from the last HLC of the page, and ops pushed by
+        // different devices aren't logged in HLC order
+        newer.sort_by(|a, b| op_hlc(&a.op).cmp(&op_hlc(&b.op)));
+        Self::page(newer.into_iter(), |e| e.is_from(device_id), max_ops)
+    }
+
+    fn page<'a>(
+        mut entries: impl Iterator<Item = &'a LoggedOp>,
+        skip: impl Fn(&LoggedOp) -> bool,
+        max_ops: usize,
+    ) -> OpsPage {
+        let mut ops = Vec::new();
+        let mut last_seq = None;
+        let mut has_more = false;
+        for entry in entries.by_ref() {
+            if skip(entry) {
+                last_seq = Some(entry.seq);
+                continue;
+            }
+            if ops.len() == max_ops {
+                has_more = true;
+                break;
+            }
+            last_seq = Some(entry.seq);
+            ops.push(entry.clone());
+        }
+        OpsPage {
+            ops,
+            has_more,
+            last_seq,
+        }
+    }
+
+    /// Highest sequence number covered by `since_hlc`, for clients that
+    /// don't send `since_seq`: the end of the leading run of ops at or before
+    /// `since_hlc`. Ops from different devices aren't logged in HLC order, so
+    /// the run stops at the first op past it (or without an HLC), even if
+    /// later ones are covered again.
+    pub async fn seq_through_hlc(&self, since_hlc: &str) -> Option<u64> {
+        let state = self.state.lock().await;
+        state
+            .ops
+            .iter()
+            .take_while(|e| op_hlc(&e.op).is_some_and(|hlc| hlc <= since_hlc))
+            .last()
+            .map(|e| e.seq)
+    }
+
+    /// Number of operations `device_id` still has to pull after `acked_seq`
+    pub async fn count_after(&self, device_id: &str, acked_seq: u64) -> usize {
+        let state = self.state.lock().await;
+        let start = state.ops.partition_point(|e| e.seq <= acked_seq);
+        state
+            .ops
+            .range(start..)
+            .filter(|e| !e.is_from(device_id))
+            .count()
+    }
+
+    /// Sequence number of the newest op ever logged (0 before the first)
+    pub async fn last_seq(&self) -> u64 {
+        self.state.lock().await.next_seq - 1
+    }
+
+    /// Number of operations still held in the log
+    pub async fn count(&self) -> usize {
+        self.state.lock().await.ops.len()
+    }
+
+    /// Keep ops after `seq` until the hold named `name` moves past them
+    pub fn hold(&self, name: &str, seq: u64) {
+        if let Ok(mut holds) = self.holds.lock() {
+            holds.insert(name.to_string(), seq);
+        }
+    }
+
+    /// Drop operations that every active device has acknowledged (on pull or
+    /// through the relay) and compact the file. Nothing is pruned while no device is paired, or past a hold.
+    /// Returns how many operations were removed.
+    pub async fn prune_acknowledged(
+        &self,
+        devices: &[PairedDevice],
+    ) -> Result<usize, PersistenceError> {
+        let Some(mut min_acked) = devices
+            .iter()
+            .filter(|d| d.status == PairedDeviceStatus::Active)
+            .map(|d| d.delivered_seq())
+            .min()
+        else {
+            return Ok(0);
+        };
+        if let Some(held) = self
+            .holds
+            .lock()
+            .ok()
+            .and_then(|h| h.values().min().copied())
+        {
+            min_acked = min_acked.min(held);
+        }
+
+        let mut state = self.state.lock().await;
+        let count = state.ops.partition_point(|e| e.seq <= min_acked);
+        if count == 0 {
+            return Ok(0);
+        }
+        // Remember the ids first, so a retried push or an op relayed again
+        // isn't taken for a new one
+        let mut pruned_ids = String::new();
+        for entry in state.ops.range(..count) {
+            if let Some(id) = op_id(&entry.op) {
+                pruned_ids.push_str(id);
+                pruned_ids.push('\n');
+            }
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.config_dir.join(PRUNED_IDS_FILE))
+            .await?;
+        file.write_all(pruned_ids.as_bytes()).await?;
+        file.flush().await?;
+        state.ops.drain(..count);
+
+        // Compact: write remaining entries to a temp file, then rename
+        let mut content = self.header_line(state.next_seq)?;
+        for entry in state.ops.iter() {
+            content.push_str(&serde_json::to_string(entry)?);
+            content.push('\n');
+        }
+        let path = self.file_path();
+        let temp_path = path.with_extension("tmp");
+        tokio::fs::write(&temp_path, content).await?;
+        tokio::fs::rename(&temp_path, &path).await?;
+
+        log::info!("Pruned {} delivered ops ({} left)", count, state.ops.len());
+        Ok(count)
+    }
+}
+
+fn op_id(op: &serde_json::Value) -> Option<&str> {
+    op.get("id").and_then(|v| v.as_str())
+}
+
+fn op_hlc(op: &serde_json::Value) -> Option<&str> {
+    op.get("hlc").and_then(|v| v.as_str())
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn op(hlc: &str) -> serde_json::Value {
+        json!({ "id": format!("op-{}", hlc), "hlc": hlc, "createdBy": "desktop" })
+    }
+
+    fn device(id: &str, acked_seq: u64, status: PairedDeviceStatus) -> PairedDevice {
+        PairedDevice {
+            id: id.to_string(),
+            name: id.to_string(),
+            token: String::new(),
+            paired_at: chrono::Utc::now().to_rfc3339(),
+            last_sync_at: None,
+            status,
+            acked_seq,
+            relay_seq: 0,
+            relay_acked_seq: 0,
+            relay_deposited_at: None,
+            pairing_secret: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_paging_by_seq_and_hlc() {
+        let dir = tempdir().unwrap();
+        let log = OpLog::new(dir.path().to_path_buf()).unwrap();
+        for hlc in ["a", "b", "c"] {
+            log.append(op(hlc), None).await.unwrap();
+        }
+
+        let page = log.after_seq("phone", 1, 1).await;
+        assert_eq!(page.ops.len(), 1);
+        assert_eq!(page.ops[0].seq, 2);
+        assert_eq!(page.last_seq, Some(2));
+        assert!(page.has_more);
+
+        let page = log.after_hlc("phone", "a", 10).await;
+        assert_eq!(page.ops.len(), 2);
+        assert!(!page.has_more);
+
+        assert_eq!(log.seq_through_hlc("b").await, Some(2));
+        assert_eq!(log.seq_through_hlc("0").await, None);
+        assert_eq!(log.count_after("phone", 1).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_hlc_cursor_with_ops_out_of_hlc_order() {
+        let dir = tempdir().unwrap();
+        let log = OpLog::new(dir.path().to_path_buf()).unwrap();
+        // Pushed by phones whose clocks disagree
+        for (hlc, origin) in [("c", "phone-a"), ("a", "phone-b"), ("d", "phone-a")] {
+            log.append(op(hlc), Some(origin)).await.unwrap();
+        }
+
+        // A legacy client paging one op at a time sees every op
+        let mut since = String::new();
+        let mut seen = Vec::new();
+        loop {
+            let page = log.after_hlc("phone-c", &since, 1).await;
+            let Some(entry) = page.ops.first() else {
+                break;
+            };
+            since = op_hlc(&entry.op).unwrap().to_string();
+            seen.push(since.clone());
+        }
+        assert_eq!(seen, ["a", "c", "d"]);
+
+        // Its cursor only acknowledges ops logged before anything it hasn't seen
+        assert_eq!(log.seq_through_hlc("a").await, None);
+        assert_eq!(log.seq_through_hlc("b").await, None);
+        assert_eq!(log.seq_through_hlc("c").await, Some(2));
+        assert_eq!(log.seq_through_hlc("d").await, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_relays_between_devices_without_echo() {
+        let dir = tempdir().unwrap();
+        let log = OpLog::new(dir.path().to_path_buf()).unwrap();
+
+        let from_a = json!({ "id": "op-a", "hlc": "a", "createdBy": "phone-a" });
+        log.append(from_a.clone(), Some("phone-a")).await.unwrap();
+        log.append(op("b"), None).await.unwrap();
+
+        // Phone B gets both, with the original author preserved
+        let page = log.after_seq("phone-b", 0, 10).await;
+        assert_eq!(page.ops.len(), 2);
+        assert_eq!(page.ops[0].op, from_a);
+
+        // Phone A only gets the desktop op, but its cursor covers its own op
+        let page = log.after_seq("phone-a", 0, 10).await;
+        assert_eq!(page.ops.len(), 1);
+        assert_eq!(page.ops[0].seq, 2);
+        assert_eq!(page.last_seq, Some(2));
+        assert_eq!(log.count_after("phone-a", 0).await, 1);
+
+        // Nothing left for phone A after its own op
+        let page = log.after_seq("phone-a", 0, 0).await;
+        assert!(page.ops.is_empty());
+        assert_eq!(page.last_seq, Some(1));
+        assert!(page.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_op_ids_are_not_appended() {
+        let dir = tempdir().unwrap();
+        let log = OpLog::new(dir.path().to_path_buf()).unwrap();
+
+        assert_eq!(log.append(op("a"), Some("phone-a")).await.unwrap(), Some(1));
+        // Re-pushed by another device after relay
+        assert_eq!(log.append(op("a"), Some("phone-b")).await.unwrap(), None);
+        assert_eq!(log.count().await, 1);
+        assert!(log.unlogged(vec![op("a")]).await.is_empty());
+
+        // Still deduplicated after a restart
+        drop(log);
+        let reopened = OpLog::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(reopened.append(op("a"), None).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_header_is_set_aside() {
+        let dir = tempdir().unwrap();
+        let entry = LoggedOp {
+            seq: 5,
+            origin: None,
+            op: op("a"),
+        };
+        let content = format!(
+            "{{\"version\":1,\"nex\n{}\n",
+            serde_json::to_string(&entry).unwrap()
+        );
+        std::fs::write(dir.path().join(OP_LOG_FILE), content).unwrap();
+
+        let log = OpLog::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(log.count().await, 0);
+        // Numbering continues after what devices may have acknowledged
+        assert_eq!(log.append(op("b"), None).await.unwrap(), Some(6));
+
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(names.iter().any(|n| n.starts_with("op_log.jsonl.corrupt-")));
+    }
+
+    #[tokio::test]
+    async fn test_conflicts_with_logged_ops() {
+        let dir = tempdir().unwrap();
+        let log = OpLog::new(dir.path().to_path_buf()).unwrap();
+
+        let rename = |id: &str, by: &str, value: &str| {
+            json!({
+                "id": id, "hlc": id, "entityType": "client", "entityId": "c-1",
+                "opType": "update", "field": "name",
+                "previousValue": "Sara", "value": value, "createdBy": by,
+            })
+        };
+        log.append(rename("a", "desktop", "Sara H."), None)
+            .await
+            .unwrap();
+
+        let conflicts = log
+            .conflicts_with(&[rename("b", "phone", "Sarah"), op("c")])
+            .await;
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].existing_op_id, "a");
+        assert_eq!(conflicts[0].incoming_op_id, "b");
+    }
+
+    #[tokio::test]
+    async fn test_prune_waits_for_every_active_device() {
+        let dir = tempdir().unwrap();
+        let log = OpLog::new(dir.path().to_path_buf()).unwrap();
+        for hlc in ["a", "b", "c"] {
+            log.append(op(hlc), None).await.unwrap();
+        }
+
+        let devices = vec![
+            device("phone", 3, PairedDeviceStatus::Active),
+            device("tablet", 1, PairedDeviceStatus::Active),
+            device("old", 0, PairedDeviceStatus::Revoked),
+        ];
+        assert_eq!(log.prune_acknowledged(&devices).await.unwrap(), 1);
+        assert_eq!(log.count().await, 2);
+
+        // No paired devices: keep everything
+        assert_eq!(log.prune_acknowledged(&[]).await.unwrap(), 0);
+
+        // A hold keeps ops the devices already have
+        log.hold("folder", 1);
+        let devices = vec![device("phone", 3, PairedDeviceStatus::Active)];
+        assert_eq!(log.prune_acknowledged(&devices).await.unwrap(), 0);
+        log.hold("folder", 3);
+        assert_eq!(log.prune_acknowledged(&devices).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_survives_restart_after_compaction() {
+        let dir = tempdir().unwrap();
+        let log = OpLog::new(dir.path().to_path_buf()).unwrap();
+        log.append(op("a"), None).await.unwrap();
+        log.append(op("b"), None).await.unwrap();
+        log.prune_acknowledged(&[device("phone", 2, PairedDeviceStatus::Active)])
+            .await
+            .unwrap();
+        drop(log);
+
+        let reopened = OpLog::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(reopened.count().await, 0);
+        assert_eq!(reopened.append(op("c"), None).await.unwrap(), Some(3));
+        drop(reopened);
+
+        let reopened = OpLog::new(dir.path().to_path_buf()).unwrap();
+        let page = reopened.after_seq("phone", 0, 10).await;
+        assert_eq!(page.ops.len(), 1);
+        assert_eq!(page.ops[0].seq, 3);
+    }
+
+    #[tokio::test]
+    async fn test_pruned_ops_are_not_logged_again() {
+        let dir = tempdir().unwrap();
+        let log = OpLog::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(log.append(op("a"), Some("phone")).await.unwrap(), Some(1));
+        log.prune_acknowledged(&[device("tablet", 1, PairedDeviceStatus::Active)])
+            .await
+            .unwrap();
+        assert_eq!(log.count().await, 0);
+
+        // A retried push of the same op, before and after a restart
+        assert!(log.unlogged(vec![op("a")]).await.is_empty());
+        assert_eq!(log.append(op("a"), Some("phone")).await.unwrap(), None);
+        drop(log);
+        let reopened = OpLog::new(dir.path().to_path_buf()).unwrap();
+        assert!(reopened.unlogged(vec![op("a")]).await.is_empty());
+        assert_eq!(reopened.unlogged(vec![op("b")]).await.len(), 1);
+    }
+}
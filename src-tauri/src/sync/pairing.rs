@@ -5,17 +5,30 @@
 //! - CSPRNG for code generation with uniform distribution
 //! - SHA-256 hashing (code never stored in plaintext)
 //! - Rate limiting (max 5 attempts, 30s cooldown after 3 failures)
+//! - Session creation limits (5 per minute, at most 3 live sessions)
 //! - Time-limited sessions (2 minutes)
 //! - Single-use codes
-
+//! - Desktop approval: a verified code/nonce only yields a token once the user
+//!   allows the device on the desktop (`approve_pairing` / `deny_pairing`)
+//! - Key exchange: the mobile commits to an ephemeral X25519 key
+//!   (`POST /pair/key`) before the desktop reveals its own, and the user
+//!   confirms a short authentication string derived from the shared secret
+//!   on both devices (see `sas`)
+//! - Apps without the key exchange still pair, but QR pairing then only
+//!   confirms the legacy SAS, which doesn't stop a man-in-the-middle, and the
+//!   device gets no pairing secret for the relay
+
+use super::clock::{Clock, Entropy, SyncEnv};
+use super::sas::{self, KeyPair, LegacySasTranscript, Sas, SasTranscript};
+use super::supervisor::TaskSupervisor;
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::{DateTime, Duration, Utc};
-use rand::Rng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{oneshot, Mutex, RwLock};
+use utoipa::ToSchema;
 
 // ============================================================================
 // Constants
@@ -25,22 +38,29 @@ const CODE_EXPIRY_SECONDS: i64 = 120; // 2 minutes
 const MAX_ATTEMPTS: u8 = 5;
 const COOLDOWN_THRESHOLD: u8 = 3; // Cooldown kicks in after this many attempts
 const COOLDOWN_SECONDS: i64 = 30;
+const MAX_SESSIONS_PER_MINUTE: usize = 5;
+const MAX_CONCURRENT_SESSIONS: usize = 3;
+const APPROVAL_TIMEOUT_SECONDS: u64 = 60;
 const CLEANUP_INTERVAL_SECONDS: u64 = 30;
 
 // ============================================================================
 // Types
 // ============================================================================
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum PairingStatus {
     Pending,
+    /// Code/nonce verified, waiting for the desktop user to allow the device
+    #[serde(rename = "awaiting_approval")]
+    AwaitingApproval,
     Verified,
+    Denied,
     Expired,
     Failed,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum PairingMethod {
     #[serde(rename = "qr")]
@@ -61,10 +81,15 @@ pub struct PairingSession {
     pub status: PairingStatus,
     pub paired_device_id: Option<String>,
     pub paired_device_name: Option<String>,
+    /// Short authentication string the user must confirm (after a key
+    /// exchange, or for QR pairing without one)
+    pub sas: Option<Sas>,
+    /// Commitment to the mobile public key, once `/pair/key` was called
+    pub key_commitment: Option<String>,
 }
 
 /// Response for POST /pair/start
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PairStartResponse {
     pub pairing_id: String,
@@ -75,8 +100,31 @@ pub struct PairStartResponse {
     pub qr_payload: String,
 }
 
+/// Request for POST /pair/key
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PairKeyRequest {
+    pub pairing_id: Option<String>, // Optional for code-based pairing
+    pub method: PairingMethod,
+    pub code: Option<String>,
+    pub nonce: Option<String>,
+    /// base64url SHA-256 of the mobile X25519 public key, which is only
+    /// revealed in `/pair/confirm`
+    pub key_commitment: String,
+}
+
+/// Response for POST /pair/key
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PairKeyResponse {
+    pub pairing_id: String,
+    pub desktop_device_id: String,
+    /// Ephemeral X25519 public key of the desktop (base64url)
+    pub desktop_public_key: String,
+}
+
 /// Request for POST /pair/confirm
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PairConfirmRequest {
     pub pairing_id: Option<String>,  // Optional for code-based pairing
@@ -85,10 +133,14 @@ pub struct PairConfirmRequest {
     pub nonce: Option<String>,
     pub device_name: String,
     pub device_id: String,
+    /// Mobile X25519 public key (base64url) matching the `/pair/key`
+    /// commitment; absent for apps without the key exchange
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
 }
 
 /// Response for POST /pair/confirm
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PairConfirmResponse {
     pub desktop_device_id: String,
@@ -100,33 +152,95 @@ pub struct PairConfirmResponse {
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PairConfirmResponseInternal {
+    pub pairing_id: String,
     pub paired_device_id: String,
     pub desktop_device_id: String,
     pub token: String,
     pub desktop_name: String,
+    pub sas: Option<Sas>,
+    /// Secret from the key exchange, kept by both devices and never sent
+    pub pairing_secret: Option<String>,
 }
 
 /// Response for GET /pair/status
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PairStatusResponse {
     pub pairing_id: String,
     pub status: PairingStatus,
     pub remaining_seconds: u64,
     pub attempts_remaining: u8,
+    /// Name of the device that is asking to pair (once the code/nonce is verified)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    /// Short authentication string to compare with the device (QR pairing)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sas: Option<Sas>,
 }
 
-/// Simple error response for HTTP API (matches contract)
+/// Payload of the `sync:pairing_approval_requested` event
 #[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingApprovalRequest {
+    pub pairing_id: String,
+    pub device_id: String,
+    pub device_name: String,
+    pub method: PairingMethod,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sas: Option<Sas>,
+}
+
+/// Simple error response for HTTP API (matches contract)
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PairingErrorSimple {
-    pub error: String,  // "expired", "wrong_code", "invalid_pairing_id", "rate_limited"
+    pub error: PairingErrorCode,
 }
 
-/// Error types for pairing operations (internal use)
-#[derive(Debug, Clone, Serialize)]
+/// Error codes of the pairing HTTP API
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PairingErrorCode {
+    /// The pairing session has expired
+    Expired,
+    /// Wrong code, or wrong nonce for QR pairing
+    WrongCode,
+    /// No such pairing session (or it was already used)
+    InvalidPairingId,
+    /// Too many sessions or attempts; see Retry-After
+    RateLimited,
+    /// The desktop user denied the device
+    Denied,
+    /// The desktop user didn't answer in time
+    ApprovalTimeout,
+    /// Pairing sessions can only be started from the desktop itself
+    Forbidden,
+    /// The public key doesn't match the `/pair/key` commitment, or isn't a
+    /// valid X25519 key
+    InvalidKey,
+}
+
+impl PairingErrorCode {
+    /// Map an internal pairing error to the HTTP error code
+    pub fn of(error: &PairingError) -> Self {
+        match error.code.as_str() {
+            "EXPIRED" => Self::Expired,
+            "INVALID_CODE" | "INVALID_NONCE" => Self::WrongCode,
+            "RATE_LIMITED" | "TOO_MANY_ATTEMPTS" | "TOO_MANY_SESSIONS" => Self::RateLimited,
+            "DENIED" => Self::Denied,
+            "APPROVAL_TIMEOUT" => Self::ApprovalTimeout,
+            "INVALID_KEY" => Self::InvalidKey,
+            _ => Self::InvalidPairingId,
+        }
+    }
+}
+
+/// Error types for pairing operations (internal use, and GET /pair/status)
+#[derive(Debug, Clone, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PairingError {
+    /// Human-readable message
     pub error: String,
+    #[schema(example = "NOT_FOUND")]
     pub code: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub retry_after: Option<u64>,
@@ -197,6 +311,46 @@ impl PairingError {
         }
     }
 
+    pub fn denied() -> Self {
+        Self {
+            error: "Pairing was denied on the desktop".to_string(),
+            code: "DENIED".to_string(),
+            retry_after: None,
+        }
+    }
+
+    pub fn approval_timeout() -> Self {
+        Self {
+            error: "Pairing was not approved in time".to_string(),
+            code: "APPROVAL_TIMEOUT".to_string(),
+            retry_after: None,
+        }
+    }
+
+    pub fn not_awaiting_approval() -> Self {
+        Self {
+            error: "Pairing session is not awaiting approval".to_string(),
+            code: "NOT_AWAITING_APPROVAL".to_string(),
+            retry_after: None,
+        }
+    }
+
+    pub fn sas_not_confirmed() -> Self {
+        Self {
+            error: "Confirm that both devices show the same code before allowing".to_string(),
+            code: "SAS_NOT_CONFIRMED".to_string(),
+            retry_after: None,
+        }
+    }
+
+    pub fn too_many_sessions(retry_after: u64) -> Self {
+        Self {
+            error: "Too many pairing sessions".to_string(),
+            code: "TOO_MANY_SESSIONS".to_string(),
+            retry_after: Some(retry_after),
+        }
+    }
+
     pub fn missing_nonce() -> Self {
         Self {
             error: "Nonce is required for QR-based pairing".to_string(),
@@ -204,6 +358,14 @@ impl PairingError {
             retry_after: None,
         }
     }
+
+    pub fn invalid_key() -> Self {
+        Self {
+            error: "Public key doesn't match the key exchange".to_string(),
+            code: "INVALID_KEY".to_string(),
+            retry_after: None,
+        }
+    }
 }
 
 // ============================================================================
@@ -212,29 +374,56 @@ impl PairingError {
 
 pub struct PairingManager {
     sessions: RwLock<HashMap<String, PairingSession>>,
+    /// Creation times of recent sessions (for the per-minute limit)
+    recent_creations: RwLock<VecDeque<DateTime<Utc>>>,
+    /// Pending desktop decisions, keyed by pairing id
+    approvals: Mutex<HashMap<String, oneshot::Sender<bool>>>,
+    /// Desktop keys of sessions that started a key exchange, by pairing id
+    key_pairs: Mutex<HashMap<String, KeyPair>>,
     device_id: String,
     device_name: String,
+    clock: Arc<dyn Clock>,
+    entropy: Arc<dyn Entropy>,
 }
 
 impl PairingManager {
     pub fn new(device_id: String, device_name: String) -> Arc<Self> {
-        let manager = Arc::new(Self {
+        Self::with_env(device_id, device_name, SyncEnv::default())
+    }
+
+    /// Manager reading the time and randomness from `env`
+    pub fn with_env(device_id: String, device_name: String, env: SyncEnv) -> Arc<Self> {
+        Arc::new(Self {
             sessions: RwLock::new(HashMap::new()),
+            recent_creations: RwLock::new(VecDeque::new()),
+            approvals: Mutex::new(HashMap::new()),
+            key_pairs: Mutex::new(HashMap::new()),
             device_id,
             device_name,
-        });
+            clock: env.clock,
+            entropy: env.entropy,
+        })
+    }
 
-        // Spawn cleanup task
-        let manager_clone = Arc::clone(&manager);
-        tokio::spawn(async move {
+    /// Expire stale sessions periodically until the supervisor shuts down or
+    /// the manager is dropped
+    pub fn spawn_cleanup(self: &Arc<Self>, tasks: &TaskSupervisor) {
+        let manager = Arc::downgrade(self);
+        let clock = Arc::clone(&self.clock);
+        let cancel = tasks.token();
+        tasks.spawn("pairing-cleanup", async move {
+            let interval = std::time::Duration::from_secs(CLEANUP_INTERVAL_SECONDS);
             loop {
-                tokio::time::sleep(tokio::time::Duration::from_secs(CLEANUP_INTERVAL_SECONDS))
-                    .await;
-                manager_clone.cleanup_expired().await;
+                tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = clock.sleep(interval) => {}
+                }
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                manager.cleanup_expired().await;
             }
         });
-
-        manager
     }
 
     /// Create a new pairing session
@@ -242,23 +431,52 @@ impl PairingManager {
         &self,
         host_candidates: Vec<String>,
         port: u16,
-    ) -> PairStartResponse {
+    ) -> Result<PairStartResponse, PairingError> {
         let mut sessions = self.sessions.write().await;
+        let now = self.clock.now();
+
+        // Limit how fast new sessions (and therefore fresh codes) can be created
+        {
+            let mut recent = self.recent_creations.write().await;
+            let window_start = now - Duration::minutes(1);
+            while recent.front().is_some_and(|t| *t <= window_start) {
+                recent.pop_front();
+            }
+            if recent.len() >= MAX_SESSIONS_PER_MINUTE {
+                let oldest = recent.front().copied().unwrap_or(now);
+                let retry_after = (oldest - window_start).num_seconds().max(1) as u64;
+                return Err(PairingError::rate_limited(retry_after));
+            }
+            recent.push_back(now);
+        }
 
         // Cancel any existing pending sessions (only one active at a time)
         sessions.retain(|_, s| s.status != PairingStatus::Pending);
 
+        // Cap sessions still waiting on the desktop user; decided ones are
+        // only kept around for status checks
+        let approval_deadlines: Vec<DateTime<Utc>> = sessions
+            .values()
+            .filter(|s| s.status == PairingStatus::AwaitingApproval)
+            .map(|s| s.expires_at + Duration::seconds(APPROVAL_TIMEOUT_SECONDS as i64))
+            .collect();
+        if approval_deadlines.len() >= MAX_CONCURRENT_SESSIONS {
+            let first_free = approval_deadlines.iter().min().copied().unwrap_or(now);
+            let retry_after = (first_free - now).num_seconds().max(1) as u64;
+            return Err(PairingError::too_many_sessions(retry_after));
+        }
+
         // Generate session data
-        let pairing_id = uuid::Uuid::new_v4().to_string();
-        let code = Self::generate_code();
+        let pairing_id = self.generate_pairing_id();
+        let code = Self::generate_code(self.entropy.as_ref());
         let code_hash = Self::hash_code(&code);
-        let nonce = Self::generate_nonce();
-        let now = Utc::now();
+        let nonce = Self::generate_nonce(self.entropy.as_ref());
         let expires_at = now + Duration::seconds(CODE_EXPIRY_SECONDS);
 
         // Build QR payload
         let qr_payload = Self::build_qr_payload(
             &pairing_id,
+            &self.device_id,
             &host_candidates,
             port,
             &nonce,
@@ -277,17 +495,19 @@ impl PairingManager {
             status: PairingStatus::Pending,
             paired_device_id: None,
             paired_device_name: None,
+            sas: None,
+            key_commitment: None,
         };
         sessions.insert(pairing_id.clone(), session);
 
-        PairStartResponse {
+        Ok(PairStartResponse {
             pairing_id,
             code,
             expires_at: expires_at.to_rfc3339(),
             host_candidates,
             port,
             qr_payload,
-        }
+        })
     }
 
     /// Find a session by its code (for code-only pairing)
@@ -304,98 +524,236 @@ impl PairingManager {
         None
     }
 
-    /// Verify a pairing attempt
-    pub async fn verify(
+    /// Start the key exchange: check the code/nonce, keep the mobile's key
+    /// commitment and reveal the desktop public key
+    pub async fn start_key_exchange(
         &self,
-        request: &PairConfirmRequest,
-    ) -> Result<PairConfirmResponseInternal, PairingError> {
-        // Determine pairing_id based on method
-        let pairing_id = match &request.method {
-            PairingMethod::Code => {
-                if let Some(id) = &request.pairing_id {
-                    id.clone()
-                } else {
-                    // Look up session by code
-                    let code = request.code.as_ref().ok_or_else(PairingError::missing_code)?;
-                    self.find_session_by_code(code)
-                        .await
-                        .ok_or_else(PairingError::invalid_code)?
-                }
-            }
-            PairingMethod::QR => {
-                request
-                    .pairing_id
-                    .clone()
-                    .ok_or_else(PairingError::not_found)?
-            }
-        };
+        request: &PairKeyRequest,
+    ) -> Result<PairKeyResponse, PairingError> {
+        let pairing_id = self
+            .resolve_pairing_id(&request.method, &request.pairing_id, &request.code)
+            .await?;
+        let key_pair = KeyPair::generate().map_err(|_| PairingError::invalid_key())?;
 
         let mut sessions = self.sessions.write().await;
-
         let session = sessions
             .get_mut(&pairing_id)
             .ok_or_else(PairingError::not_found)?;
 
-        // Check if already paired
-        if session.status == PairingStatus::Verified {
+        // One exchange per session, so the committed key can't be swapped
+        if session.key_commitment.is_some() {
             return Err(PairingError::already_paired());
         }
+        self.check_open(session)?;
+        self.check_attempt(
+            session,
+            &request.method,
+            request.code.as_deref(),
+            request.nonce.as_deref(),
+        )?;
+
+        session.key_commitment = Some(request.key_commitment.clone());
+        let response = PairKeyResponse {
+            pairing_id: pairing_id.clone(),
+            desktop_device_id: self.device_id.clone(),
+            desktop_public_key: URL_SAFE_NO_PAD.encode(key_pair.public_key()),
+        };
+        self.key_pairs.lock().await.insert(pairing_id, key_pair);
+        Ok(response)
+    }
 
-        // Check expiry
-        if Utc::now() > session.expires_at {
-            session.status = PairingStatus::Expired;
-            return Err(PairingError::expired());
-        }
+    /// Verify a pairing attempt
+    pub async fn verify(
+        &self,
+        request: &PairConfirmRequest,
+    ) -> Result<PairConfirmResponseInternal, PairingError> {
+        let pairing_id = self
+            .resolve_pairing_id(&request.method, &request.pairing_id, &request.code)
+            .await?;
 
-        // Check rate limit
-        self.check_rate_limit(session)?;
+        let mut sessions = self.sessions.write().await;
 
-        // Record attempt
-        session.attempts += 1;
-        session.last_attempt_at = Some(Utc::now());
+        let session = sessions
+            .get_mut(&pairing_id)
+            .ok_or_else(PairingError::not_found)?;
 
-        // Verify based on method
-        let verified = match request.method {
-            PairingMethod::Code => {
-                let code = request.code.as_ref().ok_or_else(PairingError::missing_code)?;
-                Self::verify_code(&session.code_hash, code)
-            }
-            PairingMethod::QR => {
-                let nonce = request
-                    .nonce
-                    .as_ref()
-                    .ok_or_else(PairingError::missing_nonce)?;
-                Self::verify_nonce(&session.nonce, nonce)
+        self.check_open(session)?;
+
+        let (sas, pairing_secret) = match session.key_commitment.clone() {
+            None => {
+                if request.public_key.is_some() {
+                    return Err(PairingError::invalid_key());
+                }
+                self.check_attempt(
+                    session,
+                    &request.method,
+                    request.code.as_deref(),
+                    request.nonce.as_deref(),
+                )?;
+                let sas = match request.method {
+                    PairingMethod::QR => Some(sas::derive_legacy(&LegacySasTranscript {
+                        pairing_id: &pairing_id,
+                        nonce: &session.nonce,
+                        desktop_device_id: &self.device_id,
+                        mobile_device_id: &request.device_id,
+                    })),
+                    PairingMethod::Code => None,
+                };
+                (sas, None)
             }
-        };
+            Some(commitment) => {
+                // The key request already counted the attempt, so anything but
+                // the matching confirmation ends the session
+                let secret_matches = Self::matches_secret(
+                    session,
+                    &request.method,
+                    request.code.as_deref(),
+                    request.nonce.as_deref(),
+                )?;
+                if !secret_matches {
+                    session.status = PairingStatus::Failed;
+                    return Err(match request.method {
+                        PairingMethod::Code => PairingError::invalid_code(),
+                        PairingMethod::QR => PairingError::invalid_nonce(),
+                    });
+                }
 
-        if !verified {
-            if session.attempts >= MAX_ATTEMPTS {
-                session.status = PairingStatus::Failed;
-                return Err(PairingError::too_many_attempts());
+                let key_pair = self.key_pairs.lock().await.remove(&pairing_id);
+                let agreed = Self::agree(key_pair, request.public_key.as_deref(), &commitment);
+                let Some((desktop_public_key, mobile_public_key, shared_secret)) = agreed else {
+                    session.status = PairingStatus::Failed;
+                    return Err(PairingError::invalid_key());
+                };
+                let transcript = SasTranscript {
+                    pairing_id: &pairing_id,
+                    desktop_device_id: &self.device_id,
+                    mobile_device_id: &request.device_id,
+                    desktop_public_key: &desktop_public_key,
+                    mobile_public_key: &mobile_public_key,
+                    shared_secret: &shared_secret,
+                };
+                (
+                    Some(sas::derive(&transcript)),
+                    Some(sas::pairing_secret(&transcript)),
+                )
             }
-            return Err(match request.method {
-                PairingMethod::Code => PairingError::invalid_code(),
-                PairingMethod::QR => PairingError::invalid_nonce(),
-            });
-        }
+        };
 
-        // Success! Mark as verified
-        session.status = PairingStatus::Verified;
+        // Code/nonce is correct; the desktop user still has to allow the device
+        session.status = PairingStatus::AwaitingApproval;
         session.paired_device_id = Some(request.device_id.clone());
         session.paired_device_name = Some(request.device_name.clone());
+        session.sas = sas;
 
         // Generate token for future sync
-        let token = Self::generate_token();
+        let token = Self::generate_token(self.entropy.as_ref());
 
         Ok(PairConfirmResponseInternal {
+            pairing_id,
             paired_device_id: request.device_id.clone(),
             desktop_device_id: self.device_id.clone(),
             token,
             desktop_name: self.device_name.clone(),
+            sas: session.sas.clone(),
+            pairing_secret,
         })
     }
 
+    /// Register interest in the desktop decision for a verified session.
+    /// Call before announcing the request so an immediate decision isn't lost.
+    pub async fn request_approval(&self, pairing_id: &str) -> oneshot::Receiver<bool> {
+        let (tx, rx) = oneshot::channel();
+        self.approvals.lock().await.insert(pairing_id.to_string(), tx);
+        rx
+    }
+
+    /// Wait for the desktop user to approve or deny the session
+    pub async fn wait_for_approval(
+        &self,
+        pairing_id: &str,
+        decision: oneshot::Receiver<bool>,
+    ) -> Result<(), PairingError> {
+        let timeout = std::time::Duration::from_secs(APPROVAL_TIMEOUT_SECONDS);
+        let decision = tokio::select! {
+            decision = decision => decision.ok(),
+            _ = self.clock.sleep(timeout) => None,
+        };
+        match decision {
+            Some(true) => Ok(()),
+            Some(false) => Err(PairingError::denied()),
+            None => {
+                self.approvals.lock().await.remove(pairing_id);
+                let mut sessions = self.sessions.write().await;
+                if let Some(session) = sessions.get_mut(pairing_id) {
+                    if session.status == PairingStatus::AwaitingApproval {
+                        session.status = PairingStatus::Failed;
+                    }
+                }
+                Err(PairingError::approval_timeout())
+            }
+        }
+    }
+
+    /// Allow a device that is awaiting approval. Returns (device_id, device_name).
+    ///
+    /// Sessions with a SAS (after a key exchange, or QR pairing without one)
+    /// also need `sas_confirmed`: the user has checked that the device shows
+    /// the same short authentication string.
+    pub async fn approve(
+        &self,
+        pairing_id: &str,
+        sas_confirmed: bool,
+    ) -> Result<(String, String), PairingError> {
+        self.decide(pairing_id, true, sas_confirmed).await
+    }
+
+    /// Reject a device that is awaiting approval
+    pub async fn deny(&self, pairing_id: &str) -> Result<(String, String), PairingError> {
+        self.decide(pairing_id, false, false).await
+    }
+
+    async fn decide(
+        &self,
+        pairing_id: &str,
+        approved: bool,
+        sas_confirmed: bool,
+    ) -> Result<(String, String), PairingError> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(pairing_id)
+            .ok_or_else(PairingError::not_found)?;
+
+        if session.status != PairingStatus::AwaitingApproval {
+            return Err(PairingError::not_awaiting_approval());
+        }
+        if approved && session.sas.is_some() && !sas_confirmed {
+            return Err(PairingError::sas_not_confirmed());
+        }
+
+        let sender = self
+            .approvals
+            .lock()
+            .await
+            .remove(pairing_id)
+            .ok_or_else(PairingError::approval_timeout)?;
+        if sender.send(approved).is_err() {
+            // The device gave up waiting
+            session.status = PairingStatus::Failed;
+            return Err(PairingError::approval_timeout());
+        }
+
+        session.status = if approved {
+            PairingStatus::Verified
+        } else {
+            PairingStatus::Denied
+        };
+
+        Ok((
+            session.paired_device_id.clone().unwrap_or_default(),
+            session.paired_device_name.clone().unwrap_or_default(),
+        ))
+    }
+
     /// Verify and return HTTP API response format
     pub async fn verify_http(
         &self,
@@ -407,24 +765,147 @@ impl PairingManager {
                 session_token: internal.token,
                 shared_secret: None,
             }),
-            Err(e) => {
-                // Map internal error codes to simple error strings
-                let error = match e.code.as_str() {
-                    "EXPIRED" => "expired",
-                    "INVALID_CODE" => "wrong_code",
-                    "NOT_FOUND" => "invalid_pairing_id",
-                    "INVALID_NONCE" => "wrong_code",
-                    "RATE_LIMITED" => "rate_limited",
-                    "TOO_MANY_ATTEMPTS" => "rate_limited",
-                    _ => "invalid_pairing_id",
-                };
-                Err(PairingErrorSimple {
-                    error: error.to_string(),
-                })
+            Err(e) => Err(PairingErrorSimple {
+                error: PairingErrorCode::of(&e),
+            }),
+        }
+    }
+
+    /// Session the request is for: the given pairing id, or for code
+    /// pairing without one, the pending session with that code
+    async fn resolve_pairing_id(
+        &self,
+        method: &PairingMethod,
+        pairing_id: &Option<String>,
+        code: &Option<String>,
+    ) -> Result<String, PairingError> {
+        match method {
+            PairingMethod::Code => {
+                if let Some(id) = pairing_id {
+                    Ok(id.clone())
+                } else {
+                    // Look up session by code
+                    let code = code.as_ref().ok_or_else(PairingError::missing_code)?;
+                    match self.find_session_by_code(code).await {
+                        Some(id) => Ok(id),
+                        None => Err(self.record_failed_code_lookup().await),
+                    }
+                }
             }
+            PairingMethod::QR => pairing_id.clone().ok_or_else(PairingError::not_found),
         }
     }
 
+    /// Check that the session still takes confirmations
+    fn check_open(&self, session: &mut PairingSession) -> Result<(), PairingError> {
+        // Check if already paired (or waiting for the desktop to decide)
+        match session.status {
+            PairingStatus::Verified | PairingStatus::AwaitingApproval => {
+                return Err(PairingError::already_paired());
+            }
+            PairingStatus::Denied => return Err(PairingError::denied()),
+            _ => {}
+        }
+
+        // Check expiry
+        if self.clock.now() > session.expires_at {
+            session.status = PairingStatus::Expired;
+            return Err(PairingError::expired());
+        }
+        Ok(())
+    }
+
+    /// Count an attempt against the session and check its code/nonce
+    fn check_attempt(
+        &self,
+        session: &mut PairingSession,
+        method: &PairingMethod,
+        code: Option<&str>,
+        nonce: Option<&str>,
+    ) -> Result<(), PairingError> {
+        // Check rate limit
+        self.check_rate_limit(session)?;
+
+        // Record attempt
+        session.attempts += 1;
+        session.last_attempt_at = Some(self.clock.now());
+
+        if Self::matches_secret(session, method, code, nonce)? {
+            return Ok(());
+        }
+        if session.attempts >= MAX_ATTEMPTS {
+            session.status = PairingStatus::Failed;
+            return Err(PairingError::too_many_attempts());
+        }
+        Err(match method {
+            PairingMethod::Code => PairingError::invalid_code(),
+            PairingMethod::QR => PairingError::invalid_nonce(),
+        })
+    }
+
+    /// Whether the code (or QR nonce) is the session's
+    fn matches_secret(
+        session: &PairingSession,
+        method: &PairingMethod,
+        code: Option<&str>,
+        nonce: Option<&str>,
+    ) -> Result<bool, PairingError> {
+        Ok(match method {
+            PairingMethod::Code => {
+                let code = code.ok_or_else(PairingError::missing_code)?;
+                Self::verify_code(&session.code_hash, code)
+            }
+            PairingMethod::QR => {
+                let nonce = nonce.ok_or_else(PairingError::missing_nonce)?;
+                Self::verify_nonce(&session.nonce, nonce)
+            }
+        })
+    }
+
+    /// Check the revealed mobile key against its commitment and run the
+    /// X25519 agreement. Returns (desktop key, mobile key, shared secret).
+    fn agree(
+        key_pair: Option<KeyPair>,
+        public_key: Option<&str>,
+        commitment: &str,
+    ) -> Option<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+        let key_pair = key_pair?;
+        let mobile_public_key = URL_SAFE_NO_PAD.decode(public_key?).ok()?;
+        let revealed = sas::commitment(&mobile_public_key);
+        if !Self::constant_time_eq(revealed.as_bytes(), commitment.as_bytes()) {
+            return None;
+        }
+        let desktop_public_key = key_pair.public_key().to_vec();
+        let shared_secret = key_pair.agree(&mobile_public_key).ok()?;
+        Some((desktop_public_key, mobile_public_key, shared_secret))
+    }
+
+    /// Count a wrong code (sent without a pairing id) against every pending session,
+    /// so guessing codes through the lookup path is subject to the same attempt limits
+    async fn record_failed_code_lookup(&self) -> PairingError {
+        let mut sessions = self.sessions.write().await;
+        let now = self.clock.now();
+        let mut error = PairingError::invalid_code();
+
+        for session in sessions
+            .values_mut()
+            .filter(|s| s.status == PairingStatus::Pending)
+        {
+            if let Err(e) = self.check_rate_limit(session) {
+                error = e;
+                continue;
+            }
+            session.attempts += 1;
+            session.last_attempt_at = Some(now);
+            if session.attempts >= MAX_ATTEMPTS {
+                session.status = PairingStatus::Failed;
+                error = PairingError::too_many_attempts();
+            }
+        }
+
+        error
+    }
+
     /// Get session status
     pub async fn get_status(&self, pairing_id: &str) -> Result<PairStatusResponse, PairingError> {
         let sessions = self.sessions.read().await;
@@ -433,7 +914,7 @@ impl PairingManager {
             .get(pairing_id)
             .ok_or_else(PairingError::not_found)?;
 
-        let now = Utc::now();
+        let now = self.clock.now();
         let remaining = if now > session.expires_at {
             0
         } else {
@@ -451,6 +932,8 @@ impl PairingManager {
             status,
             remaining_seconds: remaining,
             attempts_remaining: MAX_ATTEMPTS.saturating_sub(session.attempts),
+            device_name: session.paired_device_name.clone(),
+            sas: session.sas.clone(),
         })
     }
 
@@ -458,6 +941,7 @@ impl PairingManager {
     pub async fn cancel(&self, pairing_id: &str) {
         let mut sessions = self.sessions.write().await;
         sessions.remove(pairing_id);
+        self.key_pairs.lock().await.remove(pairing_id);
     }
 
     /// Get paired device info from a successful session
@@ -481,42 +965,57 @@ impl PairingManager {
     /// Cleanup expired sessions
     async fn cleanup_expired(&self) {
         let mut sessions = self.sessions.write().await;
-        let now = Utc::now();
+        let now = self.clock.now();
         sessions.retain(|_, s| {
-            // Keep verified sessions for 5 minutes after pairing (for status checks)
+            // Keep decided sessions for 5 minutes after pairing (for status checks)
             // Remove expired/failed sessions immediately
             match s.status {
-                PairingStatus::Verified => {
+                PairingStatus::Verified | PairingStatus::Denied => {
                     now < s.expires_at + Duration::minutes(5)
                 }
                 PairingStatus::Pending => now < s.expires_at,
+                PairingStatus::AwaitingApproval => {
+                    now < s.expires_at + Duration::seconds(APPROVAL_TIMEOUT_SECONDS as i64)
+                }
                 _ => false, // Remove expired/failed
             }
         });
+
+        // Desktop keys are only needed until the session is confirmed
+        self.key_pairs.lock().await.retain(|id, _| {
+            sessions
+                .get(id)
+                .is_some_and(|s| s.status == PairingStatus::Pending)
+        });
     }
 
     // ========================================================================
     // Private helpers
     // ========================================================================
 
+    /// Random (v4) UUID for a new session
+    fn generate_pairing_id(&self) -> String {
+        let mut bytes = [0u8; 16];
+        self.entropy.fill_bytes(&mut bytes);
+        uuid::Builder::from_random_bytes(bytes).into_uuid().to_string()
+    }
+
     /// Generate a cryptographically secure 6-digit code (000000-999999)
-    fn generate_code() -> String {
-        let mut rng = rand::thread_rng();
-        let code: u32 = rng.gen_range(0..1_000_000);
-        format!("{:06}", code)
+    fn generate_code(entropy: &dyn Entropy) -> String {
+        format!("{:06}", entropy.below(1_000_000))
     }
 
     /// Generate a random nonce for QR payload
-    fn generate_nonce() -> String {
-        let mut rng = rand::thread_rng();
-        let bytes: [u8; 16] = rng.gen();
+    fn generate_nonce(entropy: &dyn Entropy) -> String {
+        let mut bytes = [0u8; 16];
+        entropy.fill_bytes(&mut bytes);
         URL_SAFE_NO_PAD.encode(bytes)
     }
 
     /// Generate a session token
-    fn generate_token() -> String {
-        let mut rng = rand::thread_rng();
-        let bytes: [u8; 32] = rng.gen();
+    fn generate_token(entropy: &dyn Entropy) -> String {
+        let mut bytes = [0u8; 32];
+        entropy.fill_bytes(&mut bytes);
         URL_SAFE_NO_PAD.encode(bytes)
     }
 
@@ -556,8 +1055,11 @@ impl PairingManager {
     }
 
     /// Build QR payload URL
+    ///
+    /// `desktopId` lets the mobile derive the SAS before the confirm response arrives.
     fn build_qr_payload(
         pairing_id: &str,
+        desktop_device_id: &str,
         hosts: &[String],
         port: u16,
         nonce: &str,
@@ -565,12 +1067,13 @@ impl PairingManager {
     ) -> String {
         let hosts_param = hosts.join(",");
         format!(
-            "mini-crm://pair?v=1&hosts={}&port={}&pairingId={}&nonce={}&exp={}",
+            "mini-crm://pair?v=1&hosts={}&port={}&pairingId={}&nonce={}&exp={}&desktopId={}",
             urlencoding::encode(&hosts_param),
             port,
             urlencoding::encode(pairing_id),
             urlencoding::encode(nonce),
-            exp
+            exp,
+            urlencoding::encode(desktop_device_id)
         )
     }
 
@@ -584,7 +1087,7 @@ impl PairingManager {
         // Check cooldown after threshold
         if session.attempts >= COOLDOWN_THRESHOLD {
             if let Some(last_attempt) = session.last_attempt_at {
-                let elapsed = Utc::now().signed_duration_since(last_attempt);
+                let elapsed = self.clock.now().signed_duration_since(last_attempt);
                 if elapsed.num_seconds() < COOLDOWN_SECONDS {
                     let retry_after = (COOLDOWN_SECONDS - elapsed.num_seconds()).max(1) as u64;
                     return Err(PairingError::rate_limited(retry_after));
@@ -603,11 +1106,12 @@ impl PairingManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sync::clock::{ManualClock, OsEntropy, SeededEntropy};
 
     #[test]
     fn test_code_generation_format() {
         for _ in 0..100 {
-            let code = PairingManager::generate_code();
+            let code = PairingManager::generate_code(&OsEntropy);
             assert_eq!(code.len(), 6, "Code should be 6 digits");
             assert!(
                 code.chars().all(|c| c.is_ascii_digit()),
@@ -621,7 +1125,7 @@ mod tests {
         // Run many iterations to increase chance of getting leading zeros
         let mut has_leading_zero = false;
         for _ in 0..10000 {
-            let code = PairingManager::generate_code();
+            let code = PairingManager::generate_code(&OsEntropy);
             if code.starts_with('0') {
                 has_leading_zero = true;
                 break;
@@ -643,8 +1147,8 @@ mod tests {
 
     #[test]
     fn test_nonce_generation() {
-        let nonce1 = PairingManager::generate_nonce();
-        let nonce2 = PairingManager::generate_nonce();
+        let nonce1 = PairingManager::generate_nonce(&OsEntropy);
+        let nonce2 = PairingManager::generate_nonce(&OsEntropy);
 
         assert!(!nonce1.is_empty());
         assert_ne!(nonce1, nonce2, "Nonces should be unique");
@@ -652,8 +1156,8 @@ mod tests {
 
     #[test]
     fn test_token_generation() {
-        let token1 = PairingManager::generate_token();
-        let token2 = PairingManager::generate_token();
+        let token1 = PairingManager::generate_token(&OsEntropy);
+        let token2 = PairingManager::generate_token(&OsEntropy);
 
         assert!(!token1.is_empty());
         assert_ne!(token1, token2, "Tokens should be unique");
@@ -661,10 +1165,413 @@ mod tests {
         assert_eq!(token1.len(), 43);
     }
 
+    fn test_hosts() -> Vec<String> {
+        vec!["192.168.1.100".to_string()]
+    }
+
+    #[tokio::test]
+    async fn test_session_creation_rate_limit() {
+        let manager = PairingManager::new("desktop-1".to_string(), "Desktop".to_string());
+
+        for _ in 0..MAX_SESSIONS_PER_MINUTE {
+            assert!(manager.create_session(test_hosts(), 4242).await.is_ok());
+        }
+
+        let err = manager.create_session(test_hosts(), 4242).await.unwrap_err();
+        assert_eq!(err.code, "RATE_LIMITED");
+        assert!(err.retry_after.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_code_lookup_failures_count_as_attempts() {
+        let manager = PairingManager::new("desktop-1".to_string(), "Desktop".to_string());
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+        let wrong_code = if session.code == "000000" { "000001" } else { "000000" };
+
+        let request = PairConfirmRequest {
+            pairing_id: None,
+            method: PairingMethod::Code,
+            code: Some(wrong_code.to_string()),
+            nonce: None,
+            device_name: "Phone".to_string(),
+            device_id: "phone-1".to_string(),
+            public_key: None,
+        };
+
+        let err = manager.verify(&request).await.unwrap_err();
+        assert_eq!(err.code, "INVALID_CODE");
+
+        let status = manager.get_status(&session.pairing_id).await.unwrap();
+        assert_eq!(status.attempts_remaining, MAX_ATTEMPTS - 1);
+    }
+
+    fn code_request(session: &PairStartResponse) -> PairConfirmRequest {
+        PairConfirmRequest {
+            pairing_id: Some(session.pairing_id.clone()),
+            method: PairingMethod::Code,
+            code: Some(session.code.clone()),
+            nonce: None,
+            device_name: "Phone".to_string(),
+            device_id: "phone-1".to_string(),
+            public_key: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verified_session_requires_approval() {
+        let manager = PairingManager::new("desktop-1".to_string(), "Desktop".to_string());
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+
+        let response = manager.verify(&code_request(&session)).await.unwrap();
+        assert_eq!(response.pairing_id, session.pairing_id);
+
+        let status = manager.get_status(&session.pairing_id).await.unwrap();
+        assert_eq!(status.status, PairingStatus::AwaitingApproval);
+        assert_eq!(status.device_name.as_deref(), Some("Phone"));
+
+        let decision = manager.request_approval(&session.pairing_id).await;
+        let (device_id, _) = manager.approve(&session.pairing_id, false).await.unwrap();
+        assert_eq!(device_id, "phone-1");
+        assert!(manager
+            .wait_for_approval(&session.pairing_id, decision)
+            .await
+            .is_ok());
+
+        let status = manager.get_status(&session.pairing_id).await.unwrap();
+        assert_eq!(status.status, PairingStatus::Verified);
+    }
+
+    #[tokio::test]
+    async fn test_denied_session() {
+        let manager = PairingManager::new("desktop-1".to_string(), "Desktop".to_string());
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+        manager.verify(&code_request(&session)).await.unwrap();
+
+        let decision = manager.request_approval(&session.pairing_id).await;
+        manager.deny(&session.pairing_id).await.unwrap();
+
+        let err = manager
+            .wait_for_approval(&session.pairing_id, decision)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "DENIED");
+
+        // A denied session can't be confirmed again
+        let err = manager.verify(&code_request(&session)).await.unwrap_err();
+        assert_eq!(err.code, "DENIED");
+    }
+
+    #[tokio::test]
+    async fn test_only_undecided_sessions_count_toward_cap() {
+        let (manager, clock) = manager_with_clock(1);
+
+        // Decided sessions are kept for status checks but don't take a slot
+        for _ in 0..MAX_CONCURRENT_SESSIONS {
+            let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+            manager.verify(&code_request(&session)).await.unwrap();
+            let decision = manager.request_approval(&session.pairing_id).await;
+            manager.approve(&session.pairing_id, false).await.unwrap();
+            let approved = manager.wait_for_approval(&session.pairing_id, decision);
+            assert!(approved.await.is_ok());
+        }
+        clock.advance(seconds(60));
+
+        // Sessions waiting for the desktop user do
+        for _ in 0..MAX_CONCURRENT_SESSIONS {
+            let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+            manager.verify(&code_request(&session)).await.unwrap();
+        }
+        let err = manager.create_session(test_hosts(), 4242).await.unwrap_err();
+        assert_eq!(err.code, "TOO_MANY_SESSIONS");
+        let deadline = CODE_EXPIRY_SECONDS as u64 + APPROVAL_TIMEOUT_SECONDS;
+        assert_eq!(err.retry_after, Some(deadline));
+        assert_eq!(PairingErrorCode::of(&err), PairingErrorCode::RateLimited);
+    }
+
+    #[tokio::test]
+    async fn test_approval_times_out_on_the_clock() {
+        let (manager, clock) = manager_with_clock(4);
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+        manager.verify(&code_request(&session)).await.unwrap();
+
+        let decision = manager.request_approval(&session.pairing_id).await;
+        let waiting = tokio::spawn({
+            let manager = Arc::clone(&manager);
+            let pairing_id = session.pairing_id.clone();
+            async move { manager.wait_for_approval(&pairing_id, decision).await }
+        });
+        tokio::task::yield_now().await;
+
+        clock.advance(seconds(APPROVAL_TIMEOUT_SECONDS as i64 - 1));
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        clock.advance(seconds(1));
+        let err = waiting.await.unwrap().unwrap_err();
+        assert_eq!(err.code, "APPROVAL_TIMEOUT");
+        let status = manager.get_status(&session.pairing_id).await.unwrap();
+        assert_eq!(status.status, PairingStatus::Failed);
+        let err = manager
+            .approve(&session.pairing_id, false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "NOT_AWAITING_APPROVAL");
+    }
+
+    #[tokio::test]
+    async fn test_approve_requires_verified_code() {
+        let manager = PairingManager::new("desktop-1".to_string(), "Desktop".to_string());
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+
+        let err = manager.approve(&session.pairing_id, false).await.unwrap_err();
+        assert_eq!(err.code, "NOT_AWAITING_APPROVAL");
+    }
+
+    #[tokio::test]
+    async fn test_qr_pairing_requires_sas_confirmation() {
+        let manager = PairingManager::new("desktop-1".to_string(), "Desktop".to_string());
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+        let nonce = {
+            let sessions = manager.sessions.read().await;
+            sessions[&session.pairing_id].nonce.clone()
+        };
+
+        let request = PairConfirmRequest {
+            pairing_id: Some(session.pairing_id.clone()),
+            method: PairingMethod::QR,
+            code: None,
+            nonce: Some(nonce.clone()),
+            device_name: "Phone".to_string(),
+            device_id: "phone-1".to_string(),
+            public_key: None,
+        };
+        let response = manager.verify(&request).await.unwrap();
+
+        // Without a key exchange both sides derive the legacy SAS
+        let expected = sas::derive_legacy(&LegacySasTranscript {
+            pairing_id: &session.pairing_id,
+            nonce: &nonce,
+            desktop_device_id: "desktop-1",
+            mobile_device_id: "phone-1",
+        });
+        assert_eq!(response.sas.as_ref(), Some(&expected));
+        assert_eq!(response.pairing_secret, None);
+        let status = manager.get_status(&session.pairing_id).await.unwrap();
+        assert_eq!(status.sas, Some(expected));
+
+        let _decision = manager.request_approval(&session.pairing_id).await;
+        let err = manager.approve(&session.pairing_id, false).await.unwrap_err();
+        assert_eq!(err.code, "SAS_NOT_CONFIRMED");
+        assert!(manager.approve(&session.pairing_id, true).await.is_ok());
+    }
+
+    fn key_request(session: &PairStartResponse, key_pair: &KeyPair) -> PairKeyRequest {
+        PairKeyRequest {
+            pairing_id: None,
+            method: PairingMethod::Code,
+            code: Some(session.code.clone()),
+            nonce: None,
+            key_commitment: sas::commitment(key_pair.public_key()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_key_exchange_derives_sas_and_pairing_secret() {
+        let manager = PairingManager::new("desktop-1".to_string(), "Desktop".to_string());
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+
+        let key_pair = KeyPair::generate().unwrap();
+        let request = key_request(&session, &key_pair);
+        let exchange = manager.start_key_exchange(&request).await.unwrap();
+        assert_eq!(exchange.pairing_id, session.pairing_id);
+        let err = manager.start_key_exchange(&request).await.unwrap_err();
+        assert_eq!(err.code, "ALREADY_PAIRED");
+
+        // The mobile side of the exchange
+        let mobile_public_key = key_pair.public_key().to_vec();
+        let desktop_public_key = URL_SAFE_NO_PAD
+            .decode(&exchange.desktop_public_key)
+            .unwrap();
+        let shared_secret = key_pair.agree(&desktop_public_key).unwrap();
+        let transcript = SasTranscript {
+            pairing_id: &session.pairing_id,
+            desktop_device_id: &exchange.desktop_device_id,
+            mobile_device_id: "phone-1",
+            desktop_public_key: &desktop_public_key,
+            mobile_public_key: &mobile_public_key,
+            shared_secret: &shared_secret,
+        };
+
+        let mut confirm = code_request(&session);
+        confirm.public_key = Some(URL_SAFE_NO_PAD.encode(&mobile_public_key));
+        let response = manager.verify(&confirm).await.unwrap();
+        assert_eq!(response.sas, Some(sas::derive(&transcript)));
+        assert_eq!(
+            response.pairing_secret,
+            Some(sas::pairing_secret(&transcript))
+        );
+
+        // The key request counted the only attempt
+        let status = manager.get_status(&session.pairing_id).await.unwrap();
+        assert_eq!(status.attempts_remaining, MAX_ATTEMPTS - 1);
+
+        // Code pairing with a key exchange also needs the SAS confirmed
+        let _decision = manager.request_approval(&session.pairing_id).await;
+        let err = manager.approve(&session.pairing_id, false).await.unwrap_err();
+        assert_eq!(err.code, "SAS_NOT_CONFIRMED");
+        assert!(manager.approve(&session.pairing_id, true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_revealed_key_must_match_commitment() {
+        let manager = PairingManager::new("desktop-1".to_string(), "Desktop".to_string());
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+
+        let committed = KeyPair::generate().unwrap();
+        manager
+            .start_key_exchange(&key_request(&session, &committed))
+            .await
+            .unwrap();
+
+        let other = KeyPair::generate().unwrap();
+        let mut confirm = code_request(&session);
+        confirm.public_key = Some(URL_SAFE_NO_PAD.encode(other.public_key()));
+        let err = manager.verify(&confirm).await.unwrap_err();
+        assert_eq!(err.code, "INVALID_KEY");
+        assert_eq!(PairingErrorCode::of(&err), PairingErrorCode::InvalidKey);
+
+        // A mismatch ends the session, even with the right key after it
+        let status = manager.get_status(&session.pairing_id).await.unwrap();
+        assert_eq!(status.status, PairingStatus::Failed);
+        confirm.public_key = Some(URL_SAFE_NO_PAD.encode(committed.public_key()));
+        assert!(manager.verify(&confirm).await.is_err());
+    }
+
+    fn manager_with_clock(seed: u64) -> (Arc<PairingManager>, Arc<ManualClock>) {
+        let clock = ManualClock::new();
+        let env = SyncEnv {
+            clock: clock.clone(),
+            entropy: SeededEntropy::new(seed),
+        };
+        let manager =
+            PairingManager::with_env("desktop-1".to_string(), "Desktop".to_string(), env);
+        (manager, clock)
+    }
+
+    fn wrong_code_request(session: &PairStartResponse) -> PairConfirmRequest {
+        let wrong_code = if session.code == "000000" { "000001" } else { "000000" };
+        PairConfirmRequest {
+            code: Some(wrong_code.to_string()),
+            ..code_request(session)
+        }
+    }
+
+    fn seconds(secs: i64) -> std::time::Duration {
+        std::time::Duration::from_secs(secs as u64)
+    }
+
+    #[tokio::test]
+    async fn test_code_expires() {
+        let (manager, clock) = manager_with_clock(1);
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+
+        clock.advance(seconds(CODE_EXPIRY_SECONDS - 1));
+        let status = manager.get_status(&session.pairing_id).await.unwrap();
+        assert_eq!((status.status, status.remaining_seconds), (PairingStatus::Pending, 1));
+
+        clock.advance(seconds(2));
+        let status = manager.get_status(&session.pairing_id).await.unwrap();
+        assert_eq!((status.status, status.remaining_seconds), (PairingStatus::Expired, 0));
+        let err = manager.verify(&code_request(&session)).await.unwrap_err();
+        assert_eq!(err.code, "EXPIRED");
+
+        manager.cleanup_expired().await;
+        let err = manager.get_status(&session.pairing_id).await.unwrap_err();
+        assert_eq!(err.code, "NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_task_runs_until_manager_dropped() {
+        let (manager, clock) = manager_with_clock(5);
+        let tasks = TaskSupervisor::default();
+        manager.spawn_cleanup(&tasks);
+        tokio::task::yield_now().await;
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+
+        for _ in 0..=CODE_EXPIRY_SECONDS as u64 / CLEANUP_INTERVAL_SECONDS {
+            clock.advance(std::time::Duration::from_secs(CLEANUP_INTERVAL_SECONDS));
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        let err = manager.get_status(&session.pairing_id).await.unwrap_err();
+        assert_eq!(err.code, "NOT_FOUND");
+        assert_eq!(tasks.active(), 1);
+
+        // The task only holds a weak reference and exits with the manager
+        drop(manager);
+        clock.advance(std::time::Duration::from_secs(CLEANUP_INTERVAL_SECONDS));
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        assert_eq!(tasks.active(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cooldown_after_failed_attempts() {
+        let (manager, clock) = manager_with_clock(2);
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+
+        for _ in 0..COOLDOWN_THRESHOLD {
+            let err = manager.verify(&wrong_code_request(&session)).await.unwrap_err();
+            assert_eq!(err.code, "INVALID_CODE");
+        }
+        let err = manager.verify(&code_request(&session)).await.unwrap_err();
+        assert_eq!(err.code, "RATE_LIMITED");
+        assert_eq!(err.retry_after, Some(COOLDOWN_SECONDS as u64));
+
+        clock.advance(seconds(COOLDOWN_SECONDS - 1));
+        let err = manager.verify(&code_request(&session)).await.unwrap_err();
+        assert_eq!(err.retry_after, Some(1));
+
+        // Waiting out the cooldown doesn't use up an attempt
+        clock.advance(seconds(1));
+        assert!(manager.verify(&code_request(&session)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_lockout_after_max_attempts() {
+        let (manager, clock) = manager_with_clock(3);
+        let session = manager.create_session(test_hosts(), 4242).await.unwrap();
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            if attempt > COOLDOWN_THRESHOLD {
+                clock.advance(seconds(COOLDOWN_SECONDS));
+            }
+            let err = manager.verify(&wrong_code_request(&session)).await.unwrap_err();
+            let expected = if attempt < MAX_ATTEMPTS { "INVALID_CODE" } else { "TOO_MANY_ATTEMPTS" };
+            assert_eq!(err.code, expected);
+        }
+
+        // Locked for good, even with the right code after the cooldown
+        clock.advance(seconds(COOLDOWN_SECONDS));
+        let err = manager.verify(&code_request(&session)).await.unwrap_err();
+        assert_eq!(err.code, "TOO_MANY_ATTEMPTS");
+        let status = manager.get_status(&session.pairing_id).await.unwrap();
+        assert_eq!((status.status, status.attempts_remaining), (PairingStatus::Failed, 0));
+    }
+
+    #[tokio::test]
+    async fn test_seeded_entropy_replays_sessions() {
+        let (first, _) = manager_with_clock(9);
+        let (second, _) = manager_with_clock(9);
+        let a = first.create_session(test_hosts(), 4242).await.unwrap();
+        let b = second.create_session(test_hosts(), 4242).await.unwrap();
+        assert_eq!((&a.pairing_id, &a.code), (&b.pairing_id, &b.code));
+        assert!(uuid::Uuid::parse_str(&a.pairing_id).is_ok());
+    }
+
     #[test]
     fn test_qr_payload_format() {
         let payload = PairingManager::build_qr_payload(
             "test-id",
+            "desktop-1",
             &["192.168.1.100".to_string(), "10.0.0.5".to_string()],
             8443,
             "test-nonce",
@@ -677,5 +1584,6 @@ mod tests {
         assert!(payload.contains("pairingId=test-id"));
         assert!(payload.contains("nonce=test-nonce"));
         assert!(payload.contains("exp=1704628920"));
+        assert!(payload.contains("desktopId=desktop-1"));
     }
 }
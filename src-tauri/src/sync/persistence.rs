@@ -3,11 +3,12 @@
 //! Handles saving and loading paired devices to/from disk.
 //! File location: {app_config_dir}/paired_devices.json
 
+use super::clock::{Clock, SystemClock};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tauri::Manager;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 
 const PAIRED_DEVICES_FILE: &str = "paired_devices.json";
 const CONFIG_VERSION: u32 = 1;
@@ -32,6 +33,31 @@ pub struct PairedDevice {
     pub paired_at: String,
     pub last_sync_at: Option<String>,
     pub status: PairedDeviceStatus,
+    /// Highest local op sequence number the device has acknowledged on pull
+    #[serde(default)]
+    pub acked_seq: u64,
+    /// Highest local op sequence number deposited in the device's relay mailbox
+    #[serde(default)]
+    pub relay_seq: u64,
+    /// Highest local op sequence number the device confirmed receiving
+    /// through the relay
+    #[serde(default)]
+    pub relay_acked_seq: u64,
+    /// When local ops were last deposited for the device (RFC 3339)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_deposited_at: Option<String>,
+    /// Secret from the pairing key exchange, which keys the relay mailboxes.
+    /// None for devices paired without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pairing_secret: Option<String>,
+}
+
+impl PairedDevice {
+    /// Highest local op sequence number the device is known to have, over
+    /// pulls and the relay
+    pub fn delivered_seq(&self) -> u64 {
+        self.acked_seq.max(self.relay_acked_seq)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -66,6 +92,8 @@ impl std::fmt::Display for PersistenceError {
     }
 }
 
+impl std::error::Error for PersistenceError {}
+
 impl From<std::io::Error> for PersistenceError {
     fn from(e: std::io::Error) -> Self {
         PersistenceError::Io(e)
@@ -78,6 +106,29 @@ impl From<serde_json::Error> for PersistenceError {
     }
 }
 
+/// Move an unreadable file out of the way as `<name>.corrupt-<timestamp>` so
+/// the caller can start over without losing it. Returns the new path.
+pub(crate) fn set_aside_corrupt(
+    path: &Path,
+    error: &dyn std::fmt::Display,
+    clock: &dyn Clock,
+) -> Result<PathBuf, PersistenceError> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(
+        ".corrupt-{}",
+        clock.now().format("%Y%m%dT%H%M%S%.3fZ")
+    ));
+    let aside = path.with_file_name(name);
+    std::fs::rename(path, &aside)?;
+    log::error!(
+        "{} is unreadable ({}); moved it to {} and starting empty",
+        path.display(),
+        error,
+        aside.display()
+    );
+    Ok(aside)
+}
+
 // ============================================================================
 // PersistenceManager
 // ============================================================================
@@ -85,17 +136,32 @@ impl From<serde_json::Error> for PersistenceError {
 pub struct PersistenceManager {
     config_dir: PathBuf,
     cache: RwLock<Option<Vec<PairedDevice>>>,
+    /// Held across load-modify-save so concurrent updates (a pull ack and a
+    /// revocation) don't overwrite each other
+    writes: Mutex<()>,
+    /// Source of `last_sync_at` timestamps
+    clock: Arc<dyn Clock>,
 }
 
 impl PersistenceManager {
     /// Create a new persistence manager with the given config directory
     pub fn new(config_dir: PathBuf) -> Result<Arc<Self>, PersistenceError> {
+        Self::with_clock(config_dir, Arc::new(SystemClock))
+    }
+
+    /// Persistence manager timestamping with `clock`
+    pub fn with_clock(
+        config_dir: PathBuf,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Arc<Self>, PersistenceError> {
         // Ensure directory exists
         std::fs::create_dir_all(&config_dir)?;
 
         Ok(Arc::new(Self {
             config_dir,
             cache: RwLock::new(None),
+            writes: Mutex::new(()),
+            clock,
         }))
     }
 
@@ -176,6 +242,7 @@ impl PersistenceManager {
 
     /// Add or update a paired device
     pub async fn add_device(&self, device: PairedDevice) -> Result<(), PersistenceError> {
+        let _writing = self.writes.lock().await;
         let mut devices = self.load().await?;
 
         // Remove existing device with same ID (update scenario)
@@ -187,6 +254,7 @@ impl PersistenceManager {
 
     /// Revoke a paired device (soft delete)
     pub async fn revoke_device(&self, device_id: &str) -> Result<bool, PersistenceError> {
+        let _writing = self.writes.lock().await;
         let mut devices = self.load().await?;
 
         let mut found = false;
@@ -207,6 +275,7 @@ impl PersistenceManager {
 
     /// Remove a paired device completely
     pub async fn remove_device(&self, device_id: &str) -> Result<bool, PersistenceError> {
+        let _writing = self.writes.lock().await;
         let mut devices = self.load().await?;
         let original_len = devices.len();
         devices.retain(|d| d.id != device_id);
@@ -244,12 +313,13 @@ impl PersistenceManager {
 
     /// Update last sync time for a device
     pub async fn update_last_sync(&self, device_id: &str) -> Result<bool, PersistenceError> {
+        let _writing = self.writes.lock().await;
         let mut devices = self.load().await?;
 
         let mut found = false;
         for device in devices.iter_mut() {
             if device.id == device_id {
-                device.last_sync_at = Some(chrono::Utc::now().to_rfc3339());
+                device.last_sync_at = Some(self.clock.now().to_rfc3339());
                 found = true;
                 break;
             }
@@ -262,6 +332,89 @@ impl PersistenceManager {
         Ok(found)
     }
 
+    /// Record a pull acknowledgement: advance the device's op cursor (never
+    /// backwards) and update its last sync time. Returns whether the cursor
+    /// moved; nothing is saved otherwise.
+    pub async fn update_acked_seq(
+        &self,
+        device_id: &str,
+        acked_seq: u64,
+    ) -> Result<bool, PersistenceError> {
+        let _writing = self.writes.lock().await;
+        let mut devices = self.load().await?;
+
+        let Some(device) = devices
+            .iter_mut()
+            .find(|device| device.id == device_id && device.acked_seq < acked_seq)
+        else {
+            return Ok(false);
+        };
+        device.acked_seq = acked_seq;
+        device.last_sync_at = Some(self.clock.now().to_rfc3339());
+        self.save(&devices).await?;
+
+        Ok(true)
+    }
+
+    /// Record the last local op deposited for a device at the relay. Pass
+    /// `redeposit` when ops already deposited were sent again, which moves the
+    /// mark back to `relay_seq`.
+    pub async fn update_relay_seq(
+        &self,
+        device_id: &str,
+        relay_seq: u64,
+        redeposit: bool,
+    ) -> Result<bool, PersistenceError> {
+        let _writing = self.writes.lock().await;
+        let mut devices = self.load().await?;
+
+        let Some(device) = devices.iter_mut().find(|d| d.id == device_id) else {
+            return Ok(false);
+        };
+        device.relay_seq = if redeposit {
+            relay_seq.max(device.relay_acked_seq)
+        } else {
+            device.relay_seq.max(relay_seq)
+        };
+        device.relay_deposited_at = Some(self.clock.now().to_rfc3339());
+        self.save(&devices).await?;
+        Ok(true)
+    }
+
+    /// Record that a device confirmed receiving local ops through the relay
+    /// (never backwards, and never past what was deposited)
+    pub async fn update_relay_acked_seq(
+        &self,
+        device_id: &str,
+        relay_acked_seq: u64,
+    ) -> Result<bool, PersistenceError> {
+        let _writing = self.writes.lock().await;
+        let mut devices = self.load().await?;
+
+        let Some(device) = devices.iter_mut().find(|d| d.id == device_id) else {
+            return Ok(false);
+        };
+        device.relay_acked_seq = device
+            .relay_acked_seq
+            .max(relay_acked_seq.min(device.relay_seq));
+        device.last_sync_at = Some(self.clock.now().to_rfc3339());
+        self.save(&devices).await?;
+        Ok(true)
+    }
+
+    /// Whether ops deposited for the device at `relay_seq` but not confirmed
+    /// may have expired at the relay (deposited more than `ttl` ago)
+    pub fn relay_deposit_expired(&self, device: &PairedDevice, ttl: chrono::Duration) -> bool {
+        if device.relay_seq <= device.delivered_seq() {
+            return false;
+        }
+        device
+            .relay_deposited_at
+            .as_deref()
+            .and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok())
+            .is_some_and(|at| self.clock.now() - at.with_timezone(&chrono::Utc) >= ttl)
+    }
+
     /// Clear cache (useful for testing or forced reload)
     pub async fn clear_cache(&self) {
         let mut cache = self.cache.write().await;
@@ -276,11 +429,12 @@ impl PersistenceManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sync::clock::ManualClock;
     use tempfile::tempdir;
 
     async fn create_test_manager() -> Arc<PersistenceManager> {
         let dir = tempdir().unwrap();
-        PersistenceManager::new(dir.into_path()).unwrap()
+        PersistenceManager::new(dir.keep()).unwrap()
     }
 
     fn create_test_device(id: &str) -> PairedDevice {
@@ -291,6 +445,11 @@ mod tests {
             paired_at: chrono::Utc::now().to_rfc3339(),
             last_sync_at: None,
             status: PairedDeviceStatus::Active,
+            acked_seq: 0,
+            relay_seq: 0,
+            relay_acked_seq: 0,
+            relay_deposited_at: None,
+            pairing_secret: None,
         }
     }
 
@@ -371,4 +530,32 @@ mod tests {
         assert!(!manager.validate_token("test-1", "wrong-token").await.unwrap());
         assert!(!manager.validate_token("wrong-id", "token-test-1").await.unwrap());
     }
+
+    #[tokio::test]
+    async fn test_acked_seq_only_moves_forward() {
+        let manager = create_test_manager().await;
+        manager.add_device(create_test_device("test-1")).await.unwrap();
+
+        assert!(manager.update_acked_seq("test-1", 42).await.unwrap());
+        assert!(!manager.update_acked_seq("test-1", 7).await.unwrap());
+        assert!(!manager.update_acked_seq("test-1", 42).await.unwrap());
+        assert!(!manager.update_acked_seq("wrong-id", 1).await.unwrap());
+
+        let device = manager.get_device("test-1").await.unwrap().unwrap();
+        assert_eq!(device.acked_seq, 42);
+        assert!(device.last_sync_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_last_sync_uses_clock() {
+        let clock = ManualClock::new();
+        let manager = PersistenceManager::with_clock(tempdir().unwrap().keep(), clock.clone())
+            .unwrap();
+        manager.add_device(create_test_device("test-1")).await.unwrap();
+
+        clock.advance(std::time::Duration::from_secs(3600));
+        manager.update_last_sync("test-1").await.unwrap();
+        let device = manager.get_device("test-1").await.unwrap().unwrap();
+        assert_eq!(device.last_sync_at, Some(clock.now().to_rfc3339()));
+    }
 }
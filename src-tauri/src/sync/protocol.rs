@@ -0,0 +1,346 @@
+//! Sync Protocol Versions
+//!
+//! Versions of the sync API the server speaks:
+//! - 1: `/v1/sync/*` and `/v1/pair/*` with JSON bodies and HLC pull cursors
+//! - 2: adds sequence-number pull cursors and CBOR/compressed bodies
+//!
+//! Clients negotiate with `GET /v1/sync/hello?minVersion=..&maxVersion=..&features=..`
+//! and send the agreed version in the `X-Sync-Protocol` header afterwards.
+//! Requests without the header are treated as version 1. Requests below the
+//! configured minimum get a 426 `UpgradeRequired` error instead of being
+//! served with semantics the client doesn't expect.
+//!
+//! The unversioned routes (`/sync/*`, `/pair/*`) predate versioning. Hits are
+//! counted per route so we can tell when no phone uses them any more, and they
+//! can be switched off (`legacy_routes = false`), answering 426 as well.
+//!
+//! Version 2 clients always send their bearer token on pull and push. Version
+//! 1 apps from before tokens send none; paired devices are still served
+//! without one, counted like the legacy routes, until `min_version` is 2.
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use utoipa::{IntoParams, ToSchema};
+
+/// Request header carrying the negotiated protocol version
+pub const PROTOCOL_HEADER: &str = "x-sync-protocol";
+/// Newest protocol version the server speaks
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest protocol version the server can still speak
+pub const OLDEST_PROTOCOL_VERSION: u32 = 1;
+/// First version whose clients always send a bearer token
+pub const TOKEN_REQUIRED_VERSION: u32 = 2;
+/// Features offered in hello (intersected with what the client lists)
+pub const FEATURES: [&str; 7] = [
+    "pull",
+    "push",
+    "pairing",
+    "seq-cursors",
+    "cbor",
+    "zstd",
+    "gzip",
+];
+
+/// Server-side protocol settings (`[protocol]` in the daemon config)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProtocolConfig {
+    /// Clients below this version get 426 Upgrade Required
+    pub min_version: u32,
+    /// Serve the unversioned `/sync/*` and `/pair/*` routes
+    pub legacy_routes: bool,
+}
+
+impl Default for ProtocolConfig {
+    fn default() -> Self {
+        Self {
+            min_version: OLDEST_PROTOCOL_VERSION,
+            legacy_routes: true,
+        }
+    }
+}
+
+impl ProtocolConfig {
+    /// Versions the server accepts, as (min, max)
+    pub fn supported(&self) -> (u32, u32) {
+        (
+            self.min_version
+                .clamp(OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION),
+            PROTOCOL_VERSION,
+        )
+    }
+}
+
+/// Query of `GET /v1/sync/hello`; all optional for older clients
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct HelloQuery {
+    /// Oldest version the client speaks (1 if absent)
+    pub min_version: Option<u32>,
+    /// Newest version the client speaks (1 if absent)
+    pub max_version: Option<u32>,
+    /// Comma-separated features the client can use
+    pub features: Option<String>,
+}
+
+/// Agreed version and features
+#[derive(Debug, PartialEq)]
+pub struct Negotiated {
+    pub version: u32,
+    pub features: Vec<String>,
+}
+
+/// 426 error body: the client must be updated to talk to this server
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UpgradeRequired {
+    /// Always "upgrade_required"
+    #[schema(example = "upgrade_required")]
+    pub error: String,
+    pub message: String,
+    pub min_version: u32,
+    pub max_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_version: Option<u32>,
+}
+
+impl UpgradeRequired {
+    fn new(config: &ProtocolConfig, client_version: Option<u32>, message: String) -> Self {
+        let (min_version, max_version) = config.supported();
+        Self {
+            error: "upgrade_required".to_string(),
+            message,
+            min_version,
+            max_version,
+            client_version,
+        }
+    }
+}
+
+impl IntoResponse for UpgradeRequired {
+    fn into_response(self) -> Response {
+        (StatusCode::UPGRADE_REQUIRED, Json(self)).into_response()
+    }
+}
+
+/// Pick the highest version both sides speak, and the shared features
+pub fn negotiate(
+    config: &ProtocolConfig,
+    query: &HelloQuery,
+) -> Result<Negotiated, UpgradeRequired> {
+    let (server_min, server_max) = config.supported();
+    let client_min = query.min_version.unwrap_or(OLDEST_PROTOCOL_VERSION);
+    let client_max = query.max_version.unwrap_or(OLDEST_PROTOCOL_VERSION);
+
+    let version = client_max.min(server_max);
+    if version < server_min || version < client_min {
+        return Err(UpgradeRequired::new(
+            config,
+            Some(client_max),
+            format!(
+                "Client speaks protocol {}-{}, server {}-{}",
+                client_min, client_max, server_min, server_max
+            ),
+        ));
+    }
+
+    let features = match &query.features {
+        Some(wanted) => {
+            let wanted: Vec<&str> = wanted.split(',').map(str::trim).collect();
+            FEATURES
+                .iter()
+                .filter(|f| wanted.contains(f))
+                .map(|f| f.to_string())
+                .collect()
+        }
+        None => FEATURES.iter().map(|f| f.to_string()).collect(),
+    };
+    Ok(Negotiated { version, features })
+}
+
+/// Protocol version a request was made with (1 without the header)
+pub fn request_version(headers: &HeaderMap) -> u32 {
+    headers
+        .get(PROTOCOL_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(OLDEST_PROTOCOL_VERSION)
+}
+
+/// Middleware: reject requests below the configured minimum version
+pub async fn require_version(
+    State(config): State<Arc<ProtocolConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let version = request_version(request.headers());
+    let (min_version, _) = config.supported();
+    if version < min_version {
+        log::warn!(
+            "Rejected {} from a protocol {} client (minimum {})",
+            request.uri().path(),
+            version,
+            min_version
+        );
+        return UpgradeRequired::new(
+            &config,
+            Some(version),
+            format!(
+                "Protocol {} is no longer supported; update the app",
+                version
+            ),
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
+
+// ============================================================================
+// Legacy routes
+// ============================================================================
+
+/// Hits on the unversioned routes since the server started
+#[derive(Default)]
+pub struct LegacyRouteUsage {
+    hits: Mutex<BTreeMap<String, u64>>,
+}
+
+impl LegacyRouteUsage {
+    pub fn record(&self, path: &str) {
+        if self.count(path.to_string()) {
+            log::warn!(
+                "Legacy route {} used; clients should move to /v1{}",
+                path,
+                path
+            );
+        }
+    }
+
+    /// Count a pull or push served without a bearer token
+    pub fn record_tokenless(&self, path: &str) {
+        if self.count(format!("{} without token", path)) {
+            log::warn!(
+                "{} served without a token to a protocol 1 client; \
+                 set min_version = 2 once every phone is updated",
+                path
+            );
+        }
+    }
+
+    /// Add a hit, returning whether it was the first
+    fn count(&self, key: String) -> bool {
+        let Ok(mut hits) = self.hits.lock() else {
+            return false;
+        };
+        let count = hits.entry(key).or_insert(0);
+        *count += 1;
+        *count == 1
+    }
+
+    /// Hit count per route path (and per path served without a token)
+    pub fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.hits.lock().map(|h| h.clone()).unwrap_or_default()
+    }
+}
+
+/// State for the legacy route middleware
+#[derive(Clone)]
+pub struct LegacyRoutes {
+    pub config: Arc<ProtocolConfig>,
+    pub usage: Arc<LegacyRouteUsage>,
+}
+
+/// Middleware for the unversioned routes: count the hit, and answer 426 when
+/// they are switched off
+pub async fn legacy_route(
+    State(legacy): State<LegacyRoutes>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    legacy.usage.record(&path);
+    if !legacy.config.legacy_routes {
+        return UpgradeRequired::new(
+            &legacy.config,
+            None,
+            format!("{} has been retired; use /v1{}", path, path),
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(min: Option<u32>, max: Option<u32>, features: Option<&str>) -> HelloQuery {
+        HelloQuery {
+            min_version: min,
+            max_version: max,
+            features: features.map(|f| f.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_negotiates_highest_shared_version() {
+        let config = ProtocolConfig::default();
+
+        // Clients that don't negotiate get version 1 and every feature
+        let old = negotiate(&config, &HelloQuery::default()).unwrap();
+        assert_eq!(old.version, 1);
+        assert_eq!(old.features.len(), FEATURES.len());
+
+        let new = negotiate(
+            &config,
+            &query(Some(1), Some(9), Some("cbor, zstd, teleport")),
+        )
+        .unwrap();
+        assert_eq!(new.version, PROTOCOL_VERSION);
+        assert_eq!(new.features, vec!["cbor", "zstd"]);
+
+        // A client that only speaks versions the server doesn't yet
+        let err = negotiate(&config, &query(Some(5), Some(6), None)).unwrap_err();
+        assert_eq!(err.error, "upgrade_required");
+    }
+
+    #[test]
+    fn test_minimum_version_rejects_old_clients() {
+        let config = ProtocolConfig {
+            min_version: 2,
+            ..Default::default()
+        };
+        let err = negotiate(&config, &HelloQuery::default()).unwrap_err();
+        assert_eq!((err.min_version, err.max_version), (2, PROTOCOL_VERSION));
+        assert_eq!(err.client_version, Some(1));
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(request_version(&headers), 1);
+        headers.insert(PROTOCOL_HEADER, "2".parse().unwrap());
+        assert_eq!(request_version(&headers), 2);
+    }
+
+    #[test]
+    fn test_counts_legacy_route_hits() {
+        let usage = LegacyRouteUsage::default();
+        usage.record("/sync/pull");
+        usage.record("/sync/pull");
+        usage.record("/pair/start");
+        let hits = usage.snapshot();
+        assert_eq!(hits["/sync/pull"], 2);
+        assert_eq!(hits["/pair/start"], 1);
+    }
+}
@@ -0,0 +1,173 @@
+//! Rate Limiting
+//!
+//! Token-bucket limiter keyed by an arbitrary value (source IP, device id).
+//! Each key gets `capacity` tokens that refill continuously at `refill_per_sec`;
+//! a request costs one token. When the bucket is empty the caller is told how
+//! long to wait before retrying (used for `Retry-After`).
+
+use super::clock::Clock;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most keys tracked at once; past this idle keys are dropped first, then
+/// the least recently used
+const MAX_TRACKED_KEYS: usize = 4096;
+
+/// Request limits of the sync server
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    /// Burst of requests per source IP, all routes
+    pub ip_burst: u32,
+    pub ip_refill_per_sec: f64,
+    /// Burst of pull/push requests per device id
+    pub device_burst: u32,
+    pub device_refill_per_sec: f64,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            ip_burst: 60,
+            ip_refill_per_sec: 10.0,
+            device_burst: 30,
+            device_refill_per_sec: 5.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct TokenBucketLimiter<K> {
+    capacity: f64,
+    refill_per_sec: f64,
+    clock: Arc<dyn Clock>,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Eq + Hash + Clone> TokenBucketLimiter<K> {
+    /// Create a limiter allowing bursts of `capacity` requests, refilling at `refill_per_sec`
+    /// as measured by `clock`
+    pub fn new(capacity: u32, refill_per_sec: f64, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            capacity: f64::from(capacity),
+            refill_per_sec,
+            clock,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take one token for `key`. Returns the time to wait if the bucket is empty.
+    pub fn check(&self, key: &K) -> Result<(), Duration> {
+        let now = self.clock.instant();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() >= MAX_TRACKED_KEYS && !buckets.contains_key(key) {
+            self.prune(&mut buckets, now);
+        }
+        if buckets.len() >= MAX_TRACKED_KEYS && !buckets.contains_key(key) {
+            let oldest = buckets
+                .iter()
+                .min_by_key(|(_, b)| b.updated)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                buckets.remove(&oldest);
+            }
+        }
+
+        let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - bucket.tokens;
+            Err(Duration::from_secs_f64(missing / self.refill_per_sec))
+        }
+    }
+
+    /// Drop buckets that have refilled completely (idle keys)
+    fn prune(&self, buckets: &mut HashMap<K, Bucket>, now: Instant) {
+        buckets.retain(|_, b| {
+            let elapsed = now.saturating_duration_since(b.updated).as_secs_f64();
+            b.tokens + elapsed * self.refill_per_sec < self.capacity
+        });
+    }
+}
+
+/// Whole seconds to put in a `Retry-After` header (at least 1)
+pub fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs_f64().ceil().max(1.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::clock::ManualClock;
+
+    #[test]
+    fn test_burst_then_reject() {
+        let limiter = TokenBucketLimiter::new(3, 1.0, ManualClock::new());
+
+        for _ in 0..3 {
+            assert!(limiter.check(&"ip").is_ok());
+        }
+        let wait = limiter.check(&"ip").unwrap_err();
+        assert!(wait <= Duration::from_secs(1));
+        assert_eq!(retry_after_secs(wait), 1);
+    }
+
+    #[test]
+    fn test_refill_over_time() {
+        let clock = ManualClock::new();
+        let limiter = TokenBucketLimiter::new(2, 0.5, clock.clone());
+
+        assert!(limiter.check(&"ip").is_ok());
+        assert!(limiter.check(&"ip").is_ok());
+        assert!(limiter.check(&"ip").is_err());
+
+        // 0.5 tokens/sec -> one token after 2 seconds
+        clock.advance(Duration::from_secs(1));
+        assert!(limiter.check(&"ip").is_err());
+        clock.advance(Duration::from_secs(2));
+        assert!(limiter.check(&"ip").is_ok());
+    }
+
+    #[test]
+    fn test_keys_are_independent() {
+        let limiter = TokenBucketLimiter::new(1, 0.1, ManualClock::new());
+
+        assert!(limiter.check(&"a").is_ok());
+        assert!(limiter.check(&"a").is_err());
+        assert!(limiter.check(&"b").is_ok());
+    }
+
+    #[test]
+    fn test_tracked_keys_stay_bounded_under_a_flood() {
+        let clock = ManualClock::new();
+        let limiter = TokenBucketLimiter::new(1, 0.001, clock.clone());
+
+        // Every key is mid-burst, so none refills and can be pruned
+        for key in 0..MAX_TRACKED_KEYS + 100 {
+            assert!(limiter.check(&key).is_ok());
+            clock.advance(Duration::from_millis(1));
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_TRACKED_KEYS);
+
+        // The least recently used keys went first
+        assert!(limiter.check(&(MAX_TRACKED_KEYS + 99)).is_err());
+        assert!(limiter.check(&0).is_ok());
+    }
+}
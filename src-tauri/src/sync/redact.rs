@@ -0,0 +1,177 @@
+//! Log Redaction
+//!
+//! All sync logging goes through these helpers so that operation payloads,
+//! client names and amounts never reach the log, and device ids only appear
+//! as short hashes. Full values are logged only when developer diagnostics
+//! are enabled explicitly (`set_sync_diagnostics` command or the
+//! `MUTABA3A_SYNC_DIAGNOSTICS=1` environment variable).
+
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+const DIAGNOSTICS_ENV: &str = "MUTABA3A_SYNC_DIAGNOSTICS";
+
+/// Operation fields that carry user data
+const PAYLOAD_FIELDS: [&str; 2] = ["value", "previousValue"];
+
+static DIAGNOSTICS: AtomicBool = AtomicBool::new(false);
+static ENV_INIT: Once = Once::new();
+
+/// Whether developer diagnostics (unredacted sync logging) are enabled
+pub fn diagnostics_enabled() -> bool {
+    ENV_INIT.call_once(|| {
+        let from_env = std::env::var(DIAGNOSTICS_ENV)
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        if from_env {
+            DIAGNOSTICS.store(true, Ordering::Relaxed);
+        }
+    });
+    DIAGNOSTICS.load(Ordering::Relaxed)
+}
+
+/// Enable or disable developer diagnostics at runtime
+pub fn set_diagnostics(enabled: bool) {
+    // Make sure a later env lookup doesn't override the explicit setting
+    ENV_INIT.call_once(|| {});
+    DIAGNOSTICS.store(enabled, Ordering::Relaxed);
+    log::warn!(
+        "Sync diagnostics {}",
+        if enabled {
+            "ENABLED - operation payloads will be logged"
+        } else {
+            "disabled"
+        }
+    );
+}
+
+/// Short stable hash of an identifier, e.g. `dev:3fa2c81b`
+fn short_hash(prefix: &str, value: &str) -> String {
+    let digest = Sha256::digest(value.as_bytes());
+    let hex: String = digest[..4].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}:{}", prefix, hex)
+}
+
+/// Device id for logging
+pub fn device(device_id: &str) -> String {
+    if diagnostics_enabled() {
+        device_id.to_string()
+    } else {
+        short_hash("dev", device_id)
+    }
+}
+
+/// Free text (device names, client names) for logging
+pub fn text(value: &str) -> String {
+    if diagnostics_enabled() {
+        value.to_string()
+    } else {
+        format!("<{} chars>", value.chars().count())
+    }
+}
+
+/// Serialized HLC for logging. The trailing node id segment is hashed.
+pub fn hlc(hlc: &str) -> String {
+    if diagnostics_enabled() {
+        hlc.to_string()
+    } else {
+        hash_hlc_node(hlc)
+    }
+}
+
+fn hash_hlc_node(hlc: &str) -> String {
+    let mut parts = hlc.splitn(3, '-');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(ts), Some(counter), Some(node)) => {
+            format!("{}-{}-{}", ts, counter, short_hash("n", node))
+        }
+        _ => "<hlc>".to_string(),
+    }
+}
+
+/// Type/size summary of a JSON value, without its contents
+pub fn summarize(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({ "type": "null" }),
+        Value::Bool(_) => json!({ "type": "bool" }),
+        Value::Number(_) => json!({ "type": "number" }),
+        Value::String(s) => json!({ "type": "string", "len": s.chars().count() }),
+        Value::Array(items) => json!({ "type": "array", "len": items.len() }),
+        Value::Object(map) => json!({ "type": "object", "keys": map.len() }),
+    }
+}
+
+/// Redacted copy of an operation: payload fields become summaries and
+/// device ids / HLCs are hashed.
+pub fn redact_op(op: &Value) -> Value {
+    let Some(fields) = op.as_object() else {
+        return summarize(op);
+    };
+
+    let mut redacted = Map::new();
+    for (key, value) in fields {
+        let replacement = match (key.as_str(), value) {
+            (k, v) if PAYLOAD_FIELDS.contains(&k) => summarize(v),
+            ("createdBy", Value::String(s)) => Value::String(short_hash("dev", s)),
+            ("hlc", Value::String(s)) => Value::String(hash_hlc_node(s)),
+            _ => value.clone(),
+        };
+        redacted.insert(key.clone(), replacement);
+    }
+    Value::Object(redacted)
+}
+
+/// Operation for logging (redacted unless diagnostics are enabled)
+pub fn op(op: &Value) -> String {
+    if diagnostics_enabled() {
+        op.to_string()
+    } else {
+        redact_op(op).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_op_hides_payload() {
+        let op = json!({
+            "id": "op-1",
+            "entityType": "client",
+            "entityId": "c-1",
+            "opType": "update",
+            "field": "name",
+            "value": "Sara Haddad",
+            "previousValue": { "amountMinor": 125000 },
+            "createdBy": "device-1234",
+            "hlc": "00lr5x0q3k0-00000-device-1"
+        });
+
+        let redacted = redact_op(&op);
+        let text = redacted.to_string();
+
+        assert!(!text.contains("Sara"));
+        assert!(!text.contains("125000"));
+        assert!(!text.contains("device-1234"));
+        assert_eq!(redacted["value"], json!({ "type": "string", "len": 11 }));
+        assert_eq!(
+            redacted["previousValue"],
+            json!({ "type": "object", "keys": 1 })
+        );
+        assert_eq!(redacted["entityId"], "c-1");
+        assert!(redacted["hlc"]
+            .as_str()
+            .unwrap()
+            .starts_with("00lr5x0q3k0-00000-n:"));
+    }
+
+    #[test]
+    fn test_short_hash_is_stable() {
+        assert_eq!(short_hash("dev", "abc"), short_hash("dev", "abc"));
+        assert_ne!(short_hash("dev", "abc"), short_hash("dev", "abd"));
+        assert_eq!(short_hash("dev", "abc").len(), "dev:".len() + 8);
+    }
+}
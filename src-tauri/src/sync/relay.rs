@@ -0,0 +1,802 @@
+//! Mailbox Relay
+//!
+//! Store-and-forward relay for paired devices that are not on the same LAN at
+//! the same time. A device deposits an end-to-end encrypted op batch in the
+//! other device's mailbox, which fetches and acknowledges it when it next comes
+//! online (see `mailbox` for the device side).
+//!
+//! The relay only sees mailbox ids, message sizes and ciphertext. Mailbox ids
+//! are derived from the pairing secret, so they can't be linked to device ids,
+//! and fetching requires the key whose SHA-256 is the mailbox id. Mailboxes
+//! are bounded by quotas and messages expire after `message_ttl_hours`, which
+//! deposit and fetch responses report so senders know when to deposit again.
+//!
+//! Storage: {data_dir}/relay/{mailbox_id}/{seq}-{deposited_at}.msg
+//!
+//! Routes:
+//! - POST /v1/relay/mailboxes/{id}: deposit a message (raw body)
+//! - GET  /v1/relay/mailboxes/{id}?after=&limit=: fetch (Bearer fetch key)
+//! - POST /v1/relay/mailboxes/{id}/ack: delete up to `upToSeq` (Bearer fetch key)
+
+use super::clock::SystemClock;
+use super::persistence::PersistenceError;
+use super::ratelimit::{retry_after_secs, TokenBucketLimiter};
+use super::supervisor::TaskSupervisor;
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, DefaultBodyLimit, Path as UrlPath, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+const RELAY_DIR: &str = "relay";
+const DEFAULT_RELAY_PORT: u16 = 4280;
+const DEFAULT_FETCH_LIMIT: usize = 50;
+const MAX_FETCH_LIMIT: usize = 500;
+
+// Token bucket per source IP
+const IP_BURST: u32 = 60;
+const IP_REFILL_PER_SEC: f64 = 10.0;
+
+// ============================================================================
+// Configuration
+// ============================================================================
+
+/// Relay port and quotas
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    pub port: u16,
+    pub max_message_bytes: usize,
+    pub mailbox_max_messages: usize,
+    pub mailbox_max_bytes: u64,
+    /// Across all mailboxes
+    pub total_max_bytes: u64,
+    pub message_ttl_hours: u64,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_RELAY_PORT,
+            max_message_bytes: 1024 * 1024,
+            mailbox_max_messages: 1000,
+            mailbox_max_bytes: 50 * 1024 * 1024,
+            total_max_bytes: 1024 * 1024 * 1024,
+            message_ttl_hours: 14 * 24,
+        }
+    }
+}
+
+// ============================================================================
+// Types
+// ============================================================================
+
+/// A stored message as returned by fetch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayMessage {
+    pub seq: u64,
+    /// Unix seconds
+    pub deposited_at: i64,
+    pub size: u64,
+    /// Ciphertext (base64)
+    pub payload: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DepositResponse {
+    pub seq: u64,
+    /// How long the relay keeps messages (absent from older relays)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_ttl_hours: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchResponse {
+    pub messages: Vec<RelayMessage>,
+    pub has_more: bool,
+    /// How long the relay keeps messages (absent from older relays)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_ttl_hours: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AckRequest {
+    pub up_to_seq: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AckResponse {
+    pub removed: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RelayError {
+    InvalidMailbox,
+    Unauthorized,
+    MessageTooLarge(usize),
+    MailboxFull,
+    RelayFull,
+    Io(String),
+}
+
+impl std::fmt::Display for RelayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RelayError::InvalidMailbox => write!(f, "Invalid mailbox id"),
+            RelayError::Unauthorized => write!(f, "Wrong fetch key"),
+            RelayError::MessageTooLarge(limit) => {
+                write!(f, "Message larger than {} bytes", limit)
+            }
+            RelayError::MailboxFull => write!(f, "Mailbox is full"),
+            RelayError::RelayFull => write!(f, "Relay is full"),
+            RelayError::Io(e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for RelayError {
+    fn from(e: std::io::Error) -> Self {
+        RelayError::Io(e.to_string())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RelayErrorBody {
+    error: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+}
+
+impl IntoResponse for RelayError {
+    fn into_response(self) -> Response {
+        let (status, error, limit) = match self {
+            RelayError::InvalidMailbox => (StatusCode::BAD_REQUEST, "invalid_mailbox", None),
+            RelayError::Unauthorized => (StatusCode::UNAUTHORIZED, "unauthorized", None),
+            RelayError::MessageTooLarge(limit) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+                Some(limit),
+            ),
+            RelayError::MailboxFull => (StatusCode::INSUFFICIENT_STORAGE, "mailbox_full", None),
+            RelayError::RelayFull => (StatusCode::INSUFFICIENT_STORAGE, "relay_full", None),
+            RelayError::Io(e) => {
+                log::error!("Relay storage error: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal", None)
+            }
+        };
+        (status, Json(RelayErrorBody { error, limit })).into_response()
+    }
+}
+
+/// Mailbox ids are hex SHA-256 digests (also keeps them safe as directory names)
+pub fn is_valid_mailbox_id(id: &str) -> bool {
+    id.len() == 64
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Mailbox id for a fetch key (hex)
+pub fn mailbox_id_for_key(fetch_key: &str) -> String {
+    format!("{:x}", Sha256::digest(fetch_key.as_bytes()))
+}
+
+/// Short mailbox id prefix for logs
+fn short(id: &str) -> &str {
+    &id[..8.min(id.len())]
+}
+
+// ============================================================================
+// MailboxStore
+// ============================================================================
+
+#[derive(Debug, Clone, Copy)]
+struct MessageMeta {
+    seq: u64,
+    deposited_at: i64,
+    size: u64,
+}
+
+#[derive(Debug, Default)]
+struct Mailbox {
+    next_seq: u64,
+    messages: VecDeque<MessageMeta>,
+    bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct StoreIndex {
+    mailboxes: HashMap<String, Mailbox>,
+    total_bytes: u64,
+}
+
+pub struct MailboxStore {
+    root: PathBuf,
+    config: RelayConfig,
+    index: Mutex<StoreIndex>,
+}
+
+impl MailboxStore {
+    /// Open the relay storage in the given data directory
+    pub fn open(data_dir: &Path, config: RelayConfig) -> Result<Arc<Self>, PersistenceError> {
+        let root = data_dir.join(RELAY_DIR);
+        std::fs::create_dir_all(&root)?;
+
+        let mut index = StoreIndex::default();
+        for entry in std::fs::read_dir(&root)? {
+            let entry = entry?;
+            let id = entry.file_name().to_string_lossy().to_string();
+            if !is_valid_mailbox_id(&id) || !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let mut messages = Vec::new();
+            for file in std::fs::read_dir(entry.path())? {
+                let file = file?;
+                let name = file.file_name().to_string_lossy().to_string();
+                let Some((seq, deposited_at)) = parse_message_name(&name) else {
+                    continue;
+                };
+                let size = file.metadata()?.len();
+                messages.push(MessageMeta {
+                    seq,
+                    deposited_at,
+                    size,
+                });
+            }
+            messages.sort_by_key(|m| m.seq);
+
+            let bytes = messages.iter().map(|m| m.size).sum();
+            index.total_bytes += bytes;
+            index.mailboxes.insert(
+                id,
+                Mailbox {
+                    next_seq: messages.last().map_or(1, |m| m.seq + 1),
+                    messages: messages.into(),
+                    bytes,
+                },
+            );
+        }
+
+        let count: usize = index.mailboxes.values().map(|m| m.messages.len()).sum();
+        if count > 0 {
+            log::info!(
+                "Relay holds {} messages in {} mailboxes",
+                count,
+                index.mailboxes.len()
+            );
+        }
+
+        Ok(Arc::new(Self {
+            root,
+            config,
+            index: Mutex::new(index),
+        }))
+    }
+
+    /// Store a message. Returns its sequence number in the mailbox.
+    pub async fn deposit(&self, mailbox_id: &str, payload: &[u8]) -> Result<u64, RelayError> {
+        self.deposit_at(mailbox_id, payload, chrono::Utc::now().timestamp())
+            .await
+    }
+
+    async fn deposit_at(
+        &self,
+        mailbox_id: &str,
+        payload: &[u8],
+        now: i64,
+    ) -> Result<u64, RelayError> {
+        if !is_valid_mailbox_id(mailbox_id) {
+            return Err(RelayError::InvalidMailbox);
+        }
+        if payload.len() > self.config.max_message_bytes {
+            return Err(RelayError::MessageTooLarge(self.config.max_message_bytes));
+        }
+
+        let mut index = self.index.lock().await;
+        self.expire(&mut index, now).await;
+
+        let size = payload.len() as u64;
+        if index.total_bytes + size > self.config.total_max_bytes {
+            return Err(RelayError::RelayFull);
+        }
+        let mailbox = index.mailboxes.entry(mailbox_id.to_string()).or_default();
+        if mailbox.messages.len() >= self.config.mailbox_max_messages
+            || mailbox.bytes + size > self.config.mailbox_max_bytes
+        {
+            return Err(RelayError::MailboxFull);
+        }
+
+        let seq = mailbox.next_seq.max(1);
+        let dir = self.root.join(mailbox_id);
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join(message_name(seq, now));
+        let temp_path = path.with_extension("tmp");
+        tokio::fs::write(&temp_path, payload).await?;
+        tokio::fs::rename(&temp_path, &path).await?;
+
+        mailbox.next_seq = seq + 1;
+        mailbox.bytes += size;
+        mailbox.messages.push_back(MessageMeta {
+            seq,
+            deposited_at: now,
+            size,
+        });
+        index.total_bytes += size;
+
+        log::info!(
+            "Relay: stored {} bytes in mailbox {} (seq {})",
+            size,
+            short(mailbox_id),
+            seq
+        );
+        Ok(seq)
+    }
+
+    /// Messages with a sequence number greater than `after`, oldest first
+    pub async fn fetch(
+        &self,
+        mailbox_id: &str,
+        after: u64,
+        limit: Option<usize>,
+    ) -> Result<FetchResponse, RelayError> {
+        self.fetch_at(mailbox_id, after, limit, chrono::Utc::now().timestamp())
+            .await
+    }
+
+    async fn fetch_at(
+        &self,
+        mailbox_id: &str,
+        after: u64,
+        limit: Option<usize>,
+        now: i64,
+    ) -> Result<FetchResponse, RelayError> {
+        if !is_valid_mailbox_id(mailbox_id) {
+            return Err(RelayError::InvalidMailbox);
+        }
+        let limit = limit
+            .unwrap_or(DEFAULT_FETCH_LIMIT)
+            .clamp(1, MAX_FETCH_LIMIT);
+
+        let mut index = self.index.lock().await;
+        self.expire(&mut index, now).await;
+        let Some(mailbox) = index.mailboxes.get(mailbox_id) else {
+            return Ok(FetchResponse {
+                messages: Vec::new(),
+                has_more: false,
+                message_ttl_hours: Some(self.config.message_ttl_hours),
+            });
+        };
+
+        let start = mailbox.messages.partition_point(|m| m.seq <= after);
+        let metas: Vec<MessageMeta> = mailbox
+            .messages
+            .iter()
+            .skip(start)
+            .take(limit)
+            .copied()
+            .collect();
+        let has_more = mailbox.messages.len() - start > metas.len();
+
+        let dir = self.root.join(mailbox_id);
+        let mut messages = Vec::with_capacity(metas.len());
+        for meta in metas {
+            let payload =
+                tokio::fs::read(dir.join(message_name(meta.seq, meta.deposited_at))).await?;
+            messages.push(RelayMessage {
+                seq: meta.seq,
+                deposited_at: meta.deposited_at,
+                size: meta.size,
+                payload: BASE64.encode(payload),
+            });
+        }
+
+        Ok(FetchResponse {
+            messages,
+            has_more,
+            message_ttl_hours: Some(self.config.message_ttl_hours),
+        })
+    }
+
+    /// Delete messages up to and including `up_to_seq`. Returns how many were removed.
+    pub async fn ack(&self, mailbox_id: &str, up_to_seq: u64) -> Result<usize, RelayError> {
+        if !is_valid_mailbox_id(mailbox_id) {
+            return Err(RelayError::InvalidMailbox);
+        }
+
+        let mut index = self.index.lock().await;
+        let removed = self
+            .remove_where(&mut index, mailbox_id, |m| m.seq <= up_to_seq)
+            .await;
+        if removed > 0 {
+            log::info!(
+                "Relay: mailbox {} acknowledged {} messages",
+                short(mailbox_id),
+                removed
+            );
+        }
+        Ok(removed)
+    }
+
+    /// Delete expired messages in every mailbox
+    async fn expire(&self, index: &mut StoreIndex, now: i64) {
+        let cutoff = now - (self.config.message_ttl_hours * 3600) as i64;
+        let expired: Vec<String> = index
+            .mailboxes
+            .iter()
+            .filter(|(_, m)| m.messages.front().is_some_and(|m| m.deposited_at < cutoff))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            let removed = self
+                .remove_where(index, &id, |m| m.deposited_at < cutoff)
+                .await;
+            log::info!(
+                "Relay: expired {} messages in mailbox {}",
+                removed,
+                short(&id)
+            );
+        }
+    }
+
+    /// Remove leading messages matching `remove` from a mailbox
+    async fn remove_where(
+        &self,
+        index: &mut StoreIndex,
+        mailbox_id: &str,
+        remove: impl Fn(&MessageMeta) -> bool,
+    ) -> usize {
+        let Some(mailbox) = index.mailboxes.get_mut(mailbox_id) else {
+            return 0;
+        };
+
+        let dir = self.root.join(mailbox_id);
+        let mut removed = 0;
+        let mut freed = 0;
+        while let Some(meta) = mailbox.messages.front().copied() {
+            if !remove(&meta) {
+                break;
+            }
+            let path = dir.join(message_name(meta.seq, meta.deposited_at));
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::error!("Relay: failed to delete {}: {}", path.display(), e);
+                    break;
+                }
+            }
+            mailbox.messages.pop_front();
+            mailbox.bytes -= meta.size;
+            freed += meta.size;
+            removed += 1;
+        }
+        index.total_bytes -= freed;
+
+        // Keep the entry (and its next_seq) while the process runs
+        if mailbox.messages.is_empty() {
+            let _ = tokio::fs::remove_dir(&dir).await;
+        }
+        removed
+    }
+}
+
+fn message_name(seq: u64, deposited_at: i64) -> String {
+    format!("{}-{}.msg", seq, deposited_at)
+}
+
+fn parse_message_name(name: &str) -> Option<(u64, i64)> {
+    let (seq, deposited_at) = name.strip_suffix(".msg")?.split_once('-')?;
+    Some((seq.parse().ok()?, deposited_at.parse().ok()?))
+}
+
+// ============================================================================
+// HTTP
+// ============================================================================
+
+struct RelayState {
+    store: Arc<MailboxStore>,
+    ip_limiter: TokenBucketLimiter<IpAddr>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FetchQuery {
+    after: Option<u64>,
+    limit: Option<usize>,
+}
+
+/// Relay routes, to serve standalone or next to the sync server
+pub fn router(store: Arc<MailboxStore>) -> Router {
+    let body_limit = store.config.max_message_bytes;
+    let state = Arc::new(RelayState {
+        store,
+        ip_limiter: TokenBucketLimiter::new(IP_BURST, IP_REFILL_PER_SEC, Arc::new(SystemClock)),
+    });
+
+    Router::new()
+        .route("/health", get(|| async { "OK" }))
+        .route(
+            "/v1/relay/mailboxes/:id",
+            post(handle_deposit).get(handle_fetch),
+        )
+        .route("/v1/relay/mailboxes/:id/ack", post(handle_ack))
+        .layer(middleware::from_fn_with_state(
+            Arc::clone(&state),
+            limit_by_ip,
+        ))
+        // Let oversized deposits through to the handler so it can report the quota
+        .layer(DefaultBodyLimit::max(body_limit + 1))
+        .with_state(state)
+}
+
+async fn limit_by_ip(
+    State(state): State<Arc<RelayState>>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Err(wait) = state.ip_limiter.check(&remote_addr.ip()) {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after_secs(wait).to_string())],
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Require `Authorization: Bearer <fetch key>` matching the mailbox id
+fn check_fetch_key(mailbox_id: &str, headers: &HeaderMap) -> Result<(), RelayError> {
+    let key = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(RelayError::Unauthorized)?;
+    if mailbox_id_for_key(key) == mailbox_id {
+        Ok(())
+    } else {
+        Err(RelayError::Unauthorized)
+    }
+}
+
+/// POST /v1/relay/mailboxes/{id} - Deposit a message
+async fn handle_deposit(
+    State(state): State<Arc<RelayState>>,
+    UrlPath(mailbox_id): UrlPath<String>,
+    body: Bytes,
+) -> Result<Json<DepositResponse>, RelayError> {
+    let seq = state.store.deposit(&mailbox_id, &body).await?;
+    Ok(Json(DepositResponse {
+        seq,
+        message_ttl_hours: Some(state.store.config.message_ttl_hours),
+    }))
+}
+
+/// GET /v1/relay/mailboxes/{id} - Fetch messages
+async fn handle_fetch(
+    State(state): State<Arc<RelayState>>,
+    UrlPath(mailbox_id): UrlPath<String>,
+    Query(query): Query<FetchQuery>,
+    headers: HeaderMap,
+) -> Result<Json<FetchResponse>, RelayError> {
+    check_fetch_key(&mailbox_id, &headers)?;
+    let response = state
+        .store
+        .fetch(&mailbox_id, query.after.unwrap_or(0), query.limit)
+        .await?;
+    Ok(Json(response))
+}
+
+/// POST /v1/relay/mailboxes/{id}/ack - Delete fetched messages
+async fn handle_ack(
+    State(state): State<Arc<RelayState>>,
+    UrlPath(mailbox_id): UrlPath<String>,
+    headers: HeaderMap,
+    Json(request): Json<AckRequest>,
+) -> Result<Json<AckResponse>, RelayError> {
+    check_fetch_key(&mailbox_id, &headers)?;
+    let removed = state.store.ack(&mailbox_id, request.up_to_seq).await?;
+    Ok(Json(AckResponse { removed }))
+}
+
+// ============================================================================
+// RelayServer
+// ============================================================================
+
+/// Relay server handle
+pub struct RelayServer {
+    port: u16,
+    /// Stops the listener
+    cancel: CancellationToken,
+}
+
+impl RelayServer {
+    /// Open the relay storage in `data_dir` and serve it on the configured port.
+    /// The listener is tracked by `tasks`.
+    pub async fn start(
+        data_dir: &Path,
+        config: RelayConfig,
+        tasks: &TaskSupervisor,
+    ) -> Result<(Self, u16), String> {
+        let port = config.port;
+        let store = MailboxStore::open(data_dir, config)
+            .map_err(|e| format!("Relay storage error: {}", e))?;
+
+        let listener = TcpListener::bind(("0.0.0.0", port))
+            .await
+            .map_err(|e| format!("Failed to bind relay port {}: {}", port, e))?;
+        let actual_port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        let app = router(store);
+
+        let cancel = tasks.token();
+        let stopped = cancel.clone();
+        tasks.spawn("relay-server", async move {
+            let server = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            );
+            tokio::select! {
+                result = server => {
+                    if let Err(e) = result {
+                        log::error!("Relay server error: {}", e);
+                    }
+                }
+                _ = stopped.cancelled() => {
+                    log::info!("Relay server shutting down");
+                }
+            }
+        });
+
+        log::info!("Relay listening on port {}", actual_port);
+        Ok((
+            Self {
+                port: actual_port,
+                cancel,
+            },
+            actual_port,
+        ))
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn stop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Drop for RelayServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn mailbox(n: u8) -> String {
+        mailbox_id_for_key(&format!("key-{}", n))
+    }
+
+    #[tokio::test]
+    async fn test_deposit_fetch_ack_and_reopen() {
+        let dir = tempdir().unwrap();
+        let store = MailboxStore::open(dir.path(), RelayConfig::default()).unwrap();
+        let id = mailbox(1);
+
+        assert_eq!(store.deposit(&id, b"one").await.unwrap(), 1);
+        assert_eq!(store.deposit(&id, b"two").await.unwrap(), 2);
+        assert_eq!(
+            store.deposit("../etc", b"x").await,
+            Err(RelayError::InvalidMailbox)
+        );
+
+        let page = store.fetch(&id, 0, Some(1)).await.unwrap();
+        assert_eq!(page.messages.len(), 1);
+        assert!(page.has_more);
+        assert_eq!(BASE64.decode(&page.messages[0].payload).unwrap(), b"one");
+
+        assert_eq!(store.ack(&id, 1).await.unwrap(), 1);
+        drop(store);
+
+        // Unacknowledged messages survive a restart, and seq keeps increasing
+        let store = MailboxStore::open(dir.path(), RelayConfig::default()).unwrap();
+        let page = store.fetch(&id, 0, None).await.unwrap();
+        assert_eq!(page.messages.len(), 1);
+        assert_eq!(page.messages[0].seq, 2);
+        assert_eq!(store.deposit(&id, b"three").await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_quotas_and_expiry() {
+        let dir = tempdir().unwrap();
+        let config = RelayConfig {
+            max_message_bytes: 8,
+            mailbox_max_messages: 2,
+            total_max_bytes: 12,
+            message_ttl_hours: 1,
+            ..RelayConfig::default()
+        };
+        let store = MailboxStore::open(dir.path(), config).unwrap();
+        let (a, b) = (mailbox(1), mailbox(2));
+
+        assert_eq!(
+            store.deposit_at(&a, b"123456789", 0).await,
+            Err(RelayError::MessageTooLarge(8))
+        );
+        store.deposit_at(&a, b"1234", 0).await.unwrap();
+        store.deposit_at(&a, b"1234", 0).await.unwrap();
+        assert_eq!(
+            store.deposit_at(&a, b"1234", 0).await,
+            Err(RelayError::MailboxFull)
+        );
+        store.deposit_at(&b, b"1234", 0).await.unwrap();
+        assert_eq!(
+            store.deposit_at(&b, b"1234", 0).await,
+            Err(RelayError::RelayFull)
+        );
+
+        // After the TTL everything is gone and there is room again
+        let later = 2 * 3600;
+        store.deposit_at(&b, b"1234", later).await.unwrap();
+        assert!(store
+            .fetch_at(&a, 0, None, later)
+            .await
+            .unwrap()
+            .messages
+            .is_empty());
+        assert_eq!(
+            store
+                .fetch_at(&b, 0, None, later)
+                .await
+                .unwrap()
+                .messages
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_fetch_key_must_match_mailbox() {
+        let id = mailbox_id_for_key("secret");
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            check_fetch_key(&id, &headers),
+            Err(RelayError::Unauthorized)
+        );
+
+        headers.insert(header::AUTHORIZATION, "Bearer other".parse().unwrap());
+        assert_eq!(
+            check_fetch_key(&id, &headers),
+            Err(RelayError::Unauthorized)
+        );
+
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        assert!(check_fetch_key(&id, &headers).is_ok());
+    }
+}
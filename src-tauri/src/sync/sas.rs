@@ -0,0 +1,316 @@
+//! Short Authentication String (SAS) and the pairing key exchange
+//!
+//! The code or QR nonce crosses the network in the clear, and a photo of the
+//! screen is enough to learn the nonce. So during pairing both devices also
+//! run an ephemeral X25519 exchange, derive a short string from the shared
+//! secret and both public keys, and the desktop user confirms that both
+//! devices show the same one before the session is accepted.
+//!
+//! The mobile commits to its public key (`commitment`) before the desktop
+//! reveals its own, and reveals the key itself only with the confirmation. A
+//! man-in-the-middle has to fix the key it gives each side before it learns
+//! the other's, so it gets a single guess at making the two strings match
+//! instead of grinding keys or device ids until they do.
+//!
+//! The exchange also yields the pairing secret (`pairing_secret`) both devices
+//! keep. It never crosses the network and keys the relay mailboxes.
+//!
+//! Apps from before the key exchange confirm QR pairings with the legacy SAS
+//! (`derive_legacy`), computed from public values only: it keeps a device that
+//! only saw the QR code from pairing in place of the user's phone, but is no
+//! defense against an active man-in-the-middle on the local network.
+//!
+//! Derivation (mobile clients must implement the same). Fields are each
+//! prefixed with their byte length as a big-endian u32:
+//! - commitment = base64url(SHA-256(mobile public key)), without padding
+//! - transcript = "mutaba3a-sas-v3", pairing id, desktop device id, mobile
+//!   device id, desktop public key, mobile public key, X25519 shared secret
+//! - digest = SHA-256(transcript)
+//! - number = big-endian u32 of digest[0..4] modulo 1,000,000, as 6 digits
+//! - emoji = digest[4..8], each byte modulo 64 indexing `EMOJI_TABLE`
+//! - pairing secret = base64url(SHA-256 of the same transcript under
+//!   "mutaba3a-pairing-secret-v1")
+//! - legacy SAS = as above over "mutaba3a-sas-v2", pairing id, nonce, desktop
+//!   device id and mobile device id
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+const SAS_CONTEXT: &str = "mutaba3a-sas-v3";
+const LEGACY_SAS_CONTEXT: &str = "mutaba3a-sas-v2";
+const PAIRING_SECRET_CONTEXT: &str = "mutaba3a-pairing-secret-v1";
+const SAS_EMOJI_COUNT: usize = 4;
+
+/// 64 visually distinct emoji. The order is part of the protocol.
+const EMOJI_TABLE: [&str; 64] = [
+    "🐶", "🐱", "🦁", "🐴", "🦄", "🐷", "🐘", "🐰", "🐼", "🐓", "🐧", "🐢", "🐟", "🐙", "🦋", "🌷",
+    "🌳", "🌵", "🍄", "🌏", "🌙", "☁️", "🔥", "🍌", "🍎", "🍓", "🌽", "🍕", "🎂", "❤️", "😀", "🤖",
+    "🎩", "👓", "🔧", "🎅", "👍", "☂️", "⌛", "⏰", "🎁", "💡", "📕", "✏️", "📎", "✂️", "🔒", "🔑",
+    "🔨", "☎️", "🏁", "🚂", "🚲", "✈️", "🚀", "🏆", "⚽", "🎸", "🎺", "🔔", "⚓", "🎧", "📁", "📌",
+];
+
+/// One side's ephemeral X25519 key for a pairing session.
+///
+/// Drawn from the system CSPRNG: ring doesn't take the injected entropy, so
+/// the keys (and the SAS) differ between runs even with a seeded environment.
+pub struct KeyPair {
+    private_key: EphemeralPrivateKey,
+    public_key: Vec<u8>,
+}
+
+impl KeyPair {
+    pub fn generate() -> Result<Self, String> {
+        let rng = ring::rand::SystemRandom::new();
+        let private_key = EphemeralPrivateKey::generate(&X25519, &rng)
+            .map_err(|_| "Failed to generate a pairing key".to_string())?;
+        let public_key = private_key
+            .compute_public_key()
+            .map_err(|_| "Failed to generate a pairing key".to_string())?
+            .as_ref()
+            .to_vec();
+        Ok(Self {
+            private_key,
+            public_key,
+        })
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// X25519 shared secret with the other side's public key
+    pub fn agree(self, peer_public_key: &[u8]) -> Result<Vec<u8>, String> {
+        let peer_public_key = UnparsedPublicKey::new(&X25519, peer_public_key);
+        agreement::agree_ephemeral(self.private_key, &peer_public_key, |secret| secret.to_vec())
+            .map_err(|_| "Invalid pairing public key".to_string())
+    }
+}
+
+impl std::fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyPair")
+            .field("public_key", &URL_SAFE_NO_PAD.encode(&self.public_key))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Commitment the mobile sends before the desktop reveals its public key
+pub fn commitment(public_key: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(public_key))
+}
+
+/// Inputs both devices know once the mobile has revealed its public key
+#[derive(Debug, Clone)]
+pub struct SasTranscript<'a> {
+    pub pairing_id: &'a str,
+    pub desktop_device_id: &'a str,
+    pub mobile_device_id: &'a str,
+    pub desktop_public_key: &'a [u8],
+    pub mobile_public_key: &'a [u8],
+    pub shared_secret: &'a [u8],
+}
+
+impl SasTranscript<'_> {
+    fn digest(&self, context: &str) -> [u8; 32] {
+        hash_fields(&[
+            context.as_bytes(),
+            self.pairing_id.as_bytes(),
+            self.desktop_device_id.as_bytes(),
+            self.mobile_device_id.as_bytes(),
+            self.desktop_public_key,
+            self.mobile_public_key,
+            self.shared_secret,
+        ])
+    }
+}
+
+/// Public inputs of the legacy SAS, for apps that skip the key exchange
+#[derive(Debug, Clone)]
+pub struct LegacySasTranscript<'a> {
+    pub pairing_id: &'a str,
+    pub nonce: &'a str,
+    pub desktop_device_id: &'a str,
+    pub mobile_device_id: &'a str,
+}
+
+/// Short authentication string shown on both devices
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Sas {
+    /// Six digits, e.g. "042917"
+    pub number: String,
+    pub emoji: Vec<String>,
+}
+
+/// Derive the SAS for a pairing key exchange
+pub fn derive(transcript: &SasTranscript) -> Sas {
+    sas_of(&transcript.digest(SAS_CONTEXT))
+}
+
+/// Secret both devices keep after pairing (base64url); never sent
+pub fn pairing_secret(transcript: &SasTranscript) -> String {
+    URL_SAFE_NO_PAD.encode(transcript.digest(PAIRING_SECRET_CONTEXT))
+}
+
+/// Derive the legacy SAS from public values only
+pub fn derive_legacy(transcript: &LegacySasTranscript) -> Sas {
+    sas_of(&hash_fields(&[
+        LEGACY_SAS_CONTEXT.as_bytes(),
+        transcript.pairing_id.as_bytes(),
+        transcript.nonce.as_bytes(),
+        transcript.desktop_device_id.as_bytes(),
+        transcript.mobile_device_id.as_bytes(),
+    ]))
+}
+
+/// SHA-256 over length-prefixed fields
+fn hash_fields(fields: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for field in fields {
+        hasher.update((field.len() as u32).to_be_bytes());
+        hasher.update(field);
+    }
+    hasher.finalize().into()
+}
+
+fn sas_of(digest: &[u8; 32]) -> Sas {
+    let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+    let number = format!("{:06}", value % 1_000_000);
+    let emoji = digest[4..4 + SAS_EMOJI_COUNT]
+        .iter()
+        .map(|b| EMOJI_TABLE[(*b as usize) % EMOJI_TABLE.len()].to_string())
+        .collect();
+
+    Sas { number, emoji }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transcript<'a>(mobile_device_id: &'a str) -> SasTranscript<'a> {
+        SasTranscript {
+            pairing_id: "pairing-1",
+            desktop_device_id: "desktop-1",
+            mobile_device_id,
+            desktop_public_key: &[1; 32],
+            mobile_public_key: &[2; 32],
+            shared_secret: &[3; 32],
+        }
+    }
+
+    #[test]
+    fn test_sas_format() {
+        let sas = derive(&transcript("phone-1"));
+        assert_eq!(sas.number.len(), 6);
+        assert!(sas.number.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(sas.emoji.len(), SAS_EMOJI_COUNT);
+    }
+
+    #[test]
+    fn test_both_sides_agree() {
+        let desktop = KeyPair::generate().unwrap();
+        let mobile = KeyPair::generate().unwrap();
+        let desktop_public_key = desktop.public_key().to_vec();
+        let mobile_public_key = mobile.public_key().to_vec();
+        let desktop_secret = desktop.agree(&mobile_public_key).unwrap();
+        let mobile_secret = mobile.agree(&desktop_public_key).unwrap();
+        assert_eq!(desktop_secret, mobile_secret);
+
+        let transcript = |shared_secret| SasTranscript {
+            pairing_id: "pairing-1",
+            desktop_device_id: "desktop-1",
+            mobile_device_id: "phone-1",
+            desktop_public_key: &desktop_public_key,
+            mobile_public_key: &mobile_public_key,
+            shared_secret,
+        };
+        let desktop_side = transcript(&desktop_secret);
+        let mobile_side = transcript(&mobile_secret);
+        assert_eq!(derive(&desktop_side), derive(&mobile_side));
+        assert_eq!(pairing_secret(&desktop_side), pairing_secret(&mobile_side));
+    }
+
+    #[test]
+    fn test_invalid_public_key_is_rejected() {
+        let key = KeyPair::generate().unwrap();
+        assert!(key.agree(&[0; 16]).is_err());
+    }
+
+    #[test]
+    fn test_commitment_binds_the_key() {
+        let key = KeyPair::generate().unwrap();
+        let other = KeyPair::generate().unwrap();
+        assert_eq!(commitment(key.public_key()), commitment(key.public_key()));
+        assert_ne!(commitment(key.public_key()), commitment(other.public_key()));
+    }
+
+    #[test]
+    fn test_sas_depends_on_every_field() {
+        let base = derive(&transcript("phone-1"));
+        assert_ne!(base, derive(&transcript("phone-2")));
+
+        let mut other_desktop = transcript("phone-1");
+        other_desktop.desktop_device_id = "desktop-2";
+        assert_ne!(base, derive(&other_desktop));
+
+        let mut other_desktop_key = transcript("phone-1");
+        other_desktop_key.desktop_public_key = &[4; 32];
+        assert_ne!(base, derive(&other_desktop_key));
+
+        let mut other_mobile_key = transcript("phone-1");
+        other_mobile_key.mobile_public_key = &[4; 32];
+        assert_ne!(base, derive(&other_mobile_key));
+
+        let mut other_secret = transcript("phone-1");
+        other_secret.shared_secret = &[4; 32];
+        assert_ne!(base, derive(&other_secret));
+    }
+
+    #[test]
+    fn test_pairing_secret_is_not_the_sas_digest() {
+        let transcript = transcript("phone-1");
+        let secret = URL_SAFE_NO_PAD.decode(pairing_secret(&transcript)).unwrap();
+        assert_ne!(secret, transcript.digest(SAS_CONTEXT));
+    }
+
+    #[test]
+    fn test_length_prefix_prevents_field_shifting() {
+        let mut a = transcript("phone-1");
+        a.pairing_id = "ab";
+        a.desktop_device_id = "c";
+        let mut b = transcript("phone-1");
+        b.pairing_id = "a";
+        b.desktop_device_id = "bc";
+        assert_ne!(derive(&a), derive(&b));
+    }
+
+    #[test]
+    fn test_legacy_sas_covers_the_nonce() {
+        let legacy = |nonce| LegacySasTranscript {
+            pairing_id: "pairing-1",
+            nonce,
+            desktop_device_id: "desktop-1",
+            mobile_device_id: "phone-1",
+        };
+        assert_eq!(
+            derive_legacy(&legacy("nonce-1")),
+            derive_legacy(&legacy("nonce-1"))
+        );
+        assert_ne!(
+            derive_legacy(&legacy("nonce-1")),
+            derive_legacy(&legacy("nonce-2"))
+        );
+    }
+
+    #[test]
+    fn test_emoji_table_is_unique() {
+        let mut seen = std::collections::HashSet::new();
+        for emoji in EMOJI_TABLE {
+            assert!(seen.insert(emoji), "duplicate emoji {}", emoji);
+        }
+    }
+}
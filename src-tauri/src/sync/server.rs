@@ -2,39 +2,67 @@
 //!
 //! HTTP server for LAN sync operations.
 
+use super::audit::{AuditEntry, AuditEventKind, AuditLog};
+use super::clock::{Clock, SyncEnv};
+use super::conflicts::DetectedConflict;
+use super::encoding::{Encoded, Format, Payload, CONTENT_ENCODINGS, CONTENT_TYPES};
+use super::inbox::PendingOpsStore;
+use super::network::{self, LocalAddress, NetworkConfig};
+use super::openapi;
+use super::oplog::OpLog;
 use super::pairing::{
-    PairConfirmRequest, PairConfirmResponse, PairStartResponse, PairStatusResponse, PairingError,
-    PairingErrorSimple, PairingManager,
+    PairConfirmRequest, PairConfirmResponse, PairKeyRequest, PairKeyResponse, PairStartResponse,
+    PairStatusResponse, PairingApprovalRequest, PairingError, PairingErrorCode, PairingErrorSimple,
+    PairingManager, PairingMethod,
 };
-use super::persistence::{PairedDevice, PairedDeviceStatus, PersistenceManager};
+use super::persistence::{PairedDevice, PairedDeviceStatus, PersistenceError, PersistenceManager};
+use super::protocol::{
+    self, HelloQuery, LegacyRouteUsage, LegacyRoutes, ProtocolConfig, UpgradeRequired,
+    PROTOCOL_VERSION, TOKEN_REQUIRED_VERSION,
+};
+use super::ratelimit::{retry_after_secs, RateLimits, TokenBucketLimiter};
+use super::redact;
+use super::status::SyncStatusTracker;
+use super::supervisor::TaskSupervisor;
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    extract::{ConnectInfo, DefaultBodyLimit, Query, Request, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tauri::{AppHandle, Emitter};
 use tokio::net::TcpListener;
-use tokio::sync::{oneshot, Mutex, RwLock};
+use tokio::sync::RwLock;
+use tokio::task::JoinSet;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use tower_http::compression::CompressionLayer;
+use tower_http::decompression::RequestDecompressionLayer;
+use utoipa::{IntoParams, ToSchema};
 
 // ============================================================================
 // Constants
 // ============================================================================
 
-const DEFAULT_PORT: u16 = 4242;
+pub(crate) const DEFAULT_PORT: u16 = 4242;
 const MAX_PORT_ATTEMPTS: u16 = 10;
 
-/// Shared queue for pending sync operations (shared with Tauri state)
-pub type PendingOpsQueue = Arc<Mutex<VecDeque<serde_json::Value>>>;
+// Request limits
+const MAX_BODY_BYTES: usize = 5 * 1024 * 1024;
+const MAX_PUSH_OPS: usize = 1000;
+
+// Pairing attempts token bucket: burst size and refill rate (tokens per second)
+const PAIR_CONFIRM_BURST: u32 = 5;
+const PAIR_CONFIRM_REFILL_PER_SEC: f64 = 5.0 / 60.0;
 
-/// Shared storage for local operations (created on desktop, to be synced to mobile)
-pub type LocalOpsStore = Arc<Mutex<Vec<serde_json::Value>>>;
+/// How often the idle timer is checked
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 
 /// Server state shared between handlers
 pub struct ServerState {
@@ -44,153 +72,346 @@ pub struct ServerState {
     pub last_activity: RwLock<Instant>,
     pub pairing_manager: Arc<PairingManager>,
     pub persistence: Arc<PersistenceManager>,
-    /// Shared queue of pending operations received from mobile
-    pub pending_ops: PendingOpsQueue,
+    /// Audit log of pairing and sync activity
+    pub audit: Arc<AuditLog>,
+    /// Inbox of operations received from mobile (shared with Tauri state)
+    pub pending_ops: Arc<PendingOpsStore>,
     /// Tauri app handle for emitting events
     pub app_handle: Option<AppHandle>,
-    /// Local operations created on desktop (for pull by mobile)
-    pub local_ops: LocalOpsStore,
+    /// Ops from the desktop and every paired device (for pull by mobile)
+    pub op_log: Arc<OpLog>,
+    /// Request limiter per source IP (all routes)
+    pub ip_limiter: TokenBucketLimiter<IpAddr>,
+    /// Request limiter per device id (pull/push)
+    pub device_limiter: TokenBucketLimiter<String>,
+    /// Pairing confirmation attempts per source IP
+    pub pair_confirm_limiter: TokenBucketLimiter<IpAddr>,
+    /// Supported protocol versions and legacy route settings
+    pub protocol: Arc<ProtocolConfig>,
+    /// Legacy route hits and requests served without a token
+    pub legacy_usage: Arc<LegacyRouteUsage>,
+    /// Time source for timestamps and the idle timer
+    pub clock: Arc<dyn Clock>,
+    /// Phase reported to the UI (pairing, syncing)
+    pub status: Arc<SyncStatusTracker>,
+    /// Who may connect
+    pub network: NetworkConfig,
+    /// Local addresses listened on and advertised
+    pub addresses: Vec<LocalAddress>,
 }
 
 impl ServerState {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device_id: String,
         device_name: String,
         port: u16,
         persistence: Arc<PersistenceManager>,
+        audit: Arc<AuditLog>,
         app_handle: Option<AppHandle>,
-        pending_ops: PendingOpsQueue,
-        local_ops: LocalOpsStore,
+        pending_ops: Arc<PendingOpsStore>,
+        op_log: Arc<OpLog>,
+        status: Arc<SyncStatusTracker>,
+        protocol: Arc<ProtocolConfig>,
+        legacy_usage: Arc<LegacyRouteUsage>,
+        limits: &RateLimits,
+        network: NetworkConfig,
+        addresses: Vec<LocalAddress>,
+        env: &SyncEnv,
     ) -> Self {
-        let pairing_manager = PairingManager::new(device_id.clone(), device_name.clone());
+        let pairing_manager =
+            PairingManager::with_env(device_id.clone(), device_name.clone(), env.clone());
         Self {
             device_id,
             device_name,
             port,
-            last_activity: RwLock::new(Instant::now()),
+            last_activity: RwLock::new(env.clock.instant()),
             pairing_manager,
             persistence,
+            audit,
             pending_ops,
             app_handle,
-            local_ops,
+            op_log,
+            ip_limiter: TokenBucketLimiter::new(
+                limits.ip_burst,
+                limits.ip_refill_per_sec,
+                Arc::clone(&env.clock),
+            ),
+            device_limiter: TokenBucketLimiter::new(
+                limits.device_burst,
+                limits.device_refill_per_sec,
+                Arc::clone(&env.clock),
+            ),
+            pair_confirm_limiter: TokenBucketLimiter::new(
+                PAIR_CONFIRM_BURST,
+                PAIR_CONFIRM_REFILL_PER_SEC,
+                Arc::clone(&env.clock),
+            ),
+            protocol,
+            legacy_usage,
+            clock: Arc::clone(&env.clock),
+            status,
+            network,
+            addresses,
         }
     }
 
     pub async fn touch(&self) {
         let mut last = self.last_activity.write().await;
-        *last = Instant::now();
+        *last = self.clock.instant();
+        self.status.activity();
     }
 
     pub async fn elapsed(&self) -> Duration {
         let last = self.last_activity.read().await;
-        last.elapsed()
+        self.clock.instant().saturating_duration_since(*last)
+    }
+}
+
+/// What the sync server serves and how
+pub struct ServerOptions {
+    /// Port to try first (0 for the default); the next ones are tried if it is taken
+    pub port: u16,
+    pub device_id: String,
+    pub device_name: String,
+    /// Stop after this many minutes without requests (0 = never)
+    pub auto_shutdown_minutes: u64,
+    pub config_dir: PathBuf,
+    /// Tauri app handle for emitting events
+    pub app_handle: Option<AppHandle>,
+    pub pending_ops: Arc<PendingOpsStore>,
+    pub op_log: Arc<OpLog>,
+    pub status: Arc<SyncStatusTracker>,
+    pub protocol: ProtocolConfig,
+    /// Request limits, the defaults outside tests
+    #[cfg(test)]
+    pub limits: RateLimits,
+    pub network: NetworkConfig,
+    pub env: SyncEnv,
+}
+
+impl ServerOptions {
+    /// Options with the default port, limits, protocol and network, and no idle shutdown
+    pub fn new(
+        device_id: impl Into<String>,
+        device_name: impl Into<String>,
+        config_dir: PathBuf,
+        pending_ops: Arc<PendingOpsStore>,
+        op_log: Arc<OpLog>,
+        status: Arc<SyncStatusTracker>,
+    ) -> Self {
+        Self {
+            port: 0,
+            device_id: device_id.into(),
+            device_name: device_name.into(),
+            auto_shutdown_minutes: 0,
+            config_dir,
+            app_handle: None,
+            pending_ops,
+            op_log,
+            status,
+            protocol: Default::default(),
+            #[cfg(test)]
+            limits: Default::default(),
+            network: Default::default(),
+            env: Default::default(),
+        }
     }
 }
 
 /// Sync server handle
 pub struct SyncServer {
     port: u16,
-    shutdown_tx: Option<oneshot::Sender<()>>,
+    device_id: String,
+    device_name: String,
+    /// Local addresses listened on and advertised
+    addresses: Vec<LocalAddress>,
+    /// Stops the listeners
+    cancel: CancellationToken,
+    /// Cancelled once the listeners are closed, whatever the reason
+    stopped: CancellationToken,
     pub pairing_manager: Arc<PairingManager>,
     pub persistence: Arc<PersistenceManager>,
+    /// Hits on the unversioned routes
+    pub legacy_usage: Arc<LegacyRouteUsage>,
 }
 
 impl SyncServer {
-    /// Start the sync server on the specified port (default 4242 with fallback)
+    /// Start the sync server on the specified port (default 4242 with fallback),
+    /// listening on loopback and the addresses `network` selects.
+    /// Its background tasks are tracked by `tasks`.
     pub async fn start(
-        port: u16,
-        device_id: String,
-        device_name: String,
-        auto_shutdown_minutes: u64,
-        config_dir: PathBuf,
-        app_handle: Option<AppHandle>,
-        pending_ops: PendingOpsQueue,
-        local_ops: LocalOpsStore,
+        options: ServerOptions,
+        tasks: &TaskSupervisor,
     ) -> Result<(Self, u16), String> {
+        let ServerOptions {
+            port,
+            device_id,
+            device_name,
+            auto_shutdown_minutes,
+            config_dir,
+            app_handle,
+            pending_ops,
+            op_log,
+            status,
+            protocol,
+            #[cfg(test)]
+            limits,
+            network,
+            env,
+        } = options;
+        #[cfg(not(test))]
+        let limits = RateLimits::default();
+
         // Initialize persistence
         let persistence =
-            PersistenceManager::new(config_dir).map_err(|e| format!("Persistence error: {}", e))?;
+            PersistenceManager::with_clock(config_dir.clone(), Arc::clone(&env.clock))
+                .map_err(|e| format!("Persistence error: {}", e))?;
+        let audit = AuditLog::with_clock(config_dir, Arc::clone(&env.clock))
+            .map_err(|e| format!("Audit log error: {}", e))?;
 
         // Determine starting port (use default 4242 if 0 is passed)
         let start_port = if port == 0 { DEFAULT_PORT } else { port };
 
         // Try to bind with fallback to next ports
-        let listener = bind_with_fallback(start_port).await?;
-        let actual_port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        let (listeners, actual_port, addresses) =
+            bind_with_fallback(start_port, &network.selected()).await?;
 
         // Now create state with the actual port
+        let protocol = Arc::new(protocol);
+        let legacy_usage = Arc::new(LegacyRouteUsage::default());
         let state = Arc::new(ServerState::new(
-            device_id,
-            device_name,
+            device_id.clone(),
+            device_name.clone(),
             actual_port,
             Arc::clone(&persistence),
+            audit,
             app_handle,
             pending_ops,
-            local_ops,
+            op_log,
+            status,
+            Arc::clone(&protocol),
+            Arc::clone(&legacy_usage),
+            &limits,
+            network,
+            addresses.clone(),
+            &env,
         ));
         let state_clone = state.clone();
+        let status = Arc::clone(&state.status);
         let pairing_manager = Arc::clone(&state.pairing_manager);
 
-        let app = Router::new()
-            // Health check (both legacy and v1)
-            .route("/health", get(handle_health))
-            .route("/v1/sync/hello", get(handle_hello))
-            // Sync routes (v1)
+        let require_version =
+            middleware::from_fn_with_state(Arc::clone(&protocol), protocol::require_version);
+
+        // Sync routes (v1), with compressed bodies in both directions
+        let sync_v1 = Router::new()
             .route("/v1/sync/status", get(handle_status))
             .route("/v1/sync/pull", post(handle_pull))
             .route("/v1/sync/push", post(handle_push))
+            .layer(RequestDecompressionLayer::new())
+            .layer(CompressionLayer::new());
+
+        let v1 = Router::new()
+            .merge(sync_v1)
             // Pairing routes (v1)
             .route("/v1/pair/start", post(handle_pair_start))
+            .route("/v1/pair/key", post(handle_pair_key))
             .route("/v1/pair/confirm", post(handle_pair_confirm))
             .route("/v1/pair/status", get(handle_pair_status))
-            // Legacy routes (deprecated, keeping for backwards compatibility)
+            .route_layer(require_version.clone())
+            // Negotiation and the contract are open to every version
+            .route("/v1/sync/hello", get(handle_hello))
+            .route("/v1/openapi.json", get(handle_openapi));
+
+        // Legacy routes (deprecated): counted, and answer 426 once disabled
+        let legacy = Router::new()
             .route("/sync/status", get(handle_status))
             .route("/sync/pull", post(handle_pull))
             .route("/sync/push", post(handle_push))
             .route("/pair/start", post(handle_pair_start))
             .route("/pair/confirm", post(handle_pair_confirm))
             .route("/pair/status", get(handle_pair_status))
-            .with_state(state);
-
-        // Create shutdown channel
-        let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+            .route_layer(require_version)
+            .route_layer(middleware::from_fn_with_state(
+                LegacyRoutes {
+                    config: Arc::clone(&protocol),
+                    usage: Arc::clone(&legacy_usage),
+                },
+                protocol::legacy_route,
+            ));
 
-        // Spawn plain HTTP server
-        tokio::spawn(async move {
-            let server = axum::serve(listener, app);
+        let app = Router::new()
+            // Health check (both legacy and v1)
+            .route("/health", get(handle_health))
+            .merge(v1)
+            .merge(legacy)
+            .layer(middleware::from_fn_with_state(Arc::clone(&state), limit_by_ip))
+            .layer(middleware::from_fn_with_state(Arc::clone(&state), restrict_network))
+            .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+            .with_state(state);
 
-            tokio::select! {
-                result = server => {
-                    if let Err(e) = result {
-                        log::error!("Sync server error: {}", e);
+        let cancel = tasks.token();
+        let idle_timeout = Duration::from_secs(auto_shutdown_minutes * 60);
+
+        // Spawn plain HTTP server; open connections are closed on shutdown
+        let stopping = cancel.clone();
+        let stopped = CancellationToken::new();
+        let done = stopped.clone();
+        tasks.spawn("sync-server", async move {
+            let shutdown = async move {
+                tokio::select! {
+                    _ = stopping.cancelled() => {
+                        log::info!("Sync server shutting down");
+                    }
+                    _ = wait_until_idle(Arc::clone(&state_clone), idle_timeout) => {
+                        log::info!("Sync server auto-shutdown due to inactivity");
+                        state_clone.status.auto_stopped().await;
                     }
                 }
-                _ = &mut shutdown_rx => {
-                    log::info!("Sync server shutting down");
-                }
+            };
+            let serving = CancellationToken::new();
+            let mut servers = JoinSet::new();
+            for listener in listeners {
+                let service = app.clone().into_make_service_with_connect_info::<SocketAddr>();
+                let serving = serving.clone();
+                servers.spawn(async move {
+                    axum::serve(listener, service)
+                        .with_graceful_shutdown(serving.cancelled_owned())
+                        .await
+                });
             }
-        });
 
-        // Spawn auto-shutdown task
-        if auto_shutdown_minutes > 0 {
-            tokio::spawn(async move {
-                let timeout = Duration::from_secs(auto_shutdown_minutes * 60);
-                loop {
-                    tokio::time::sleep(Duration::from_secs(60)).await;
-                    if state_clone.elapsed().await > timeout {
-                        log::info!("Sync server auto-shutdown due to inactivity");
-                        break;
-                    }
+            // Runs until stopped or idle, or until a listener fails
+            let mut result = Ok(());
+            tokio::select! {
+                _ = shutdown => {}
+                Some(joined) = servers.join_next() => {
+                    result = joined.unwrap_or_else(|e| Err(std::io::Error::other(e)));
                 }
-            });
-        }
+            }
+            serving.cancel();
+            while servers.join_next().await.is_some() {}
+
+            if let Err(e) = result {
+                log::error!("Sync server error: {}", e);
+                status.failed(format!("Sync server error: {}", e)).await;
+            }
+            done.cancel();
+        });
+        pairing_manager.spawn_cleanup(tasks);
 
         Ok((
             Self {
                 port: actual_port,
-                shutdown_tx: Some(shutdown_tx),
+                device_id,
+                device_name,
+                addresses,
+                cancel,
+                stopped,
                 pairing_manager,
                 persistence,
+                legacy_usage,
             },
             actual_port,
         ))
@@ -201,11 +422,38 @@ impl SyncServer {
         self.port
     }
 
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
+    /// Local addresses listened on and advertised, besides loopback
+    pub fn addresses(&self) -> &[LocalAddress] {
+        &self.addresses
+    }
+
+    /// Addresses a phone can try when pairing
+    pub fn host_candidates(&self) -> Vec<String> {
+        network::host_candidates(&self.addresses)
+    }
+
     /// Stop the server
     pub fn stop(&mut self) {
-        if let Some(tx) = self.shutdown_tx.take() {
-            let _ = tx.send(());
-        }
+        self.cancel.cancel();
+    }
+
+    /// Resolves once the server has stopped listening, on `stop`, after being
+    /// idle or on a listener error
+    pub fn stopped(&self) -> WaitForCancellationFutureOwned {
+        self.stopped.clone().cancelled_owned()
+    }
+
+    /// Whether the listeners are closed
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.is_cancelled()
     }
 }
 
@@ -215,84 +463,160 @@ impl Drop for SyncServer {
     }
 }
 
-/// Try to bind to a port, falling back to subsequent ports if occupied
-async fn bind_with_fallback(start_port: u16) -> Result<TcpListener, String> {
-    for offset in 0..MAX_PORT_ATTEMPTS {
-        let port = start_port.saturating_add(offset);
-        let addr = SocketAddr::from(([0, 0, 0, 0], port));
-        match TcpListener::bind(addr).await {
-            Ok(listener) => {
-                if offset > 0 {
+/// Resolves once the server has had no requests for `timeout`; never if the
+/// timeout is zero
+async fn wait_until_idle(state: Arc<ServerState>, timeout: Duration) {
+    if timeout.is_zero() {
+        return std::future::pending().await;
+    }
+    loop {
+        state.clock.sleep(IDLE_CHECK_INTERVAL).await;
+        if state.elapsed().await > timeout {
+            return;
+        }
+    }
+}
+
+/// Bind loopback and the selected addresses to one port, moving on to the
+/// next port while it is taken. An address that can't be bound for any other
+/// reason (a tentative IPv6 address, one that just went away) is skipped;
+/// only loopback is required. Returns the selected addresses that were bound.
+pub(crate) async fn bind_with_fallback(
+    start_port: u16,
+    addresses: &[LocalAddress],
+) -> Result<(Vec<TcpListener>, u16, Vec<LocalAddress>), String> {
+    let last_port = start_port.saturating_add(MAX_PORT_ATTEMPTS - 1);
+    for port in start_port..=last_port {
+        match bind_all(addresses, port).await {
+            Ok((listeners, bound)) => {
+                if port != start_port {
                     log::info!(
                         "Port {} was in use, bound to port {} instead",
                         start_port,
                         port
                     );
                 }
-                return Ok(listener);
+                return Ok((listeners, port, bound));
             }
-            Err(e) if offset < MAX_PORT_ATTEMPTS - 1 => {
-                log::debug!("Port {} in use, trying next: {}", port, e);
-                continue;
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                log::debug!("Port {} in use, trying next", port);
             }
-            Err(e) => {
-                return Err(format!(
-                    "Failed to bind to any port ({}-{}): {}",
-                    start_port,
-                    start_port.saturating_add(MAX_PORT_ATTEMPTS - 1),
-                    e
-                ));
+            Err(e) => return Err(format!("Failed to listen on loopback: {}", e)),
+        }
+    }
+    Err(format!(
+        "Failed to bind to any port ({}-{}): all in use",
+        start_port, last_port
+    ))
+}
+
+/// Bind loopback and each address to `port`. The port being taken on any of
+/// them fails the attempt; other errors only skip that address.
+async fn bind_all(
+    addresses: &[LocalAddress],
+    port: u16,
+) -> std::io::Result<(Vec<TcpListener>, Vec<LocalAddress>)> {
+    let loopback = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut listeners = vec![TcpListener::bind(loopback).await?];
+    let mut bound = Vec::new();
+    for address in addresses {
+        match TcpListener::bind(address.socket_addr(port)).await {
+            Ok(listener) => {
+                listeners.push(listener);
+                bound.push(address.clone());
             }
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => return Err(e),
+            Err(e) => log::warn!(
+                "Not listening on {} ({}): {}",
+                address.ip,
+                address.interface,
+                e
+            ),
         }
     }
-    unreachable!()
+    Ok((listeners, bound))
 }
 
 // ============================================================================
 // Request/Response Types
 // ============================================================================
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct StatusResponse {
     device_id: String,
     device_name: String,
     server_version: &'static str,
+    protocol_version: u32,
+    min_protocol_version: u32,
     capabilities: Vec<&'static str>,
+    content_types: Vec<&'static str>,
+    content_encodings: Vec<&'static str>,
 }
 
-#[derive(Debug, Deserialize)]
-struct PullRequest {
-    device_id: String,
-    since_hlc: String,
-    since_seq: Option<i64>,
-    max_ops: Option<usize>,
+// Pull/push bodies are shared with the command-line client (`client`)
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PullRequest {
+    pub device_id: String,
+    /// HLC cursor of legacy clients ("" for everything)
+    pub since_hlc: String,
+    /// Sequence cursor (`next_seq` of the previous page); acknowledges what it covers
+    pub since_seq: Option<i64>,
+    /// Page size (100 if absent)
+    pub max_ops: Option<usize>,
 }
 
-#[derive(Serialize)]
-struct PullResponse {
-    operations: Vec<serde_json::Value>,
-    has_more: bool,
-    next_hlc: Option<String>,
-    next_seq: Option<i64>,
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PullResponse {
+    /// Operation objects, oldest first
+    #[schema(value_type = Vec<Object>)]
+    pub operations: Vec<serde_json::Value>,
+    pub has_more: bool,
+    pub next_hlc: Option<String>,
+    pub next_seq: Option<i64>,
 }
 
-#[derive(Debug, Deserialize)]
-struct PushRequest {
-    device_id: String,
-    ops: Vec<serde_json::Value>,
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PushRequest {
+    pub device_id: String,
+    /// Operation objects; each needs a string `id`
+    #[schema(value_type = Vec<Object>)]
+    pub ops: Vec<serde_json::Value>,
 }
 
-#[derive(Serialize)]
-struct PushResponse {
-    accepted: usize,
-    rejected: Vec<RejectedOp>,
-    server_hlc: String,
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PushResponse {
+    pub accepted: usize,
+    pub rejected: Vec<RejectedOp>,
+    pub server_hlc: String,
 }
 
-#[derive(Serialize)]
-struct RejectedOp {
-    op_id: String,
-    reason: String,
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RejectedOp {
+    /// Position of the op in the pushed `ops`
+    pub index: usize,
+    #[schema(example = "invalid_op")]
+    pub reason: String,
+}
+
+/// Error body for rate limit and size limit responses
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct LimitError {
+    error: LimitErrorCode,
+    /// Seconds to wait, as in the Retry-After header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+    /// The limit that was exceeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum LimitErrorCode {
+    RateLimited,
+    PayloadTooLarge,
 }
 
 #[derive(Deserialize)]
@@ -321,6 +645,66 @@ struct PairVerifyResponse {
     message: String,
 }
 
+// ============================================================================
+// Limits
+// ============================================================================
+
+/// 429 response with a Retry-After header
+fn too_many_requests(wait: Duration) -> Response {
+    let secs = retry_after_secs(wait);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, secs.to_string())],
+        Json(LimitError {
+            error: LimitErrorCode::RateLimited,
+            retry_after: Some(secs),
+            limit: None,
+        }),
+    )
+        .into_response()
+}
+
+/// 413 response for requests over a size or count limit
+fn payload_too_large(limit: usize) -> Response {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(LimitError {
+            error: LimitErrorCode::PayloadTooLarge,
+            retry_after: None,
+            limit: Some(limit),
+        }),
+    )
+        .into_response()
+}
+
+/// Middleware: refuse peers outside the allowed ranges
+async fn restrict_network(
+    State(state): State<Arc<ServerState>>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.network.allows(remote_addr.ip()) {
+        log::warn!("Refused request from {} (outside allowed networks)", remote_addr.ip());
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(request).await
+}
+
+/// Middleware: per-IP token bucket applied to every route
+async fn limit_by_ip(
+    State(state): State<Arc<ServerState>>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Err(wait) = state.ip_limiter.check(&remote_addr.ip()) {
+        log::warn!("Rate limited request from {}", remote_addr.ip());
+        return too_many_requests(wait);
+    }
+    next.run(request).await
+}
+
 // ============================================================================
 // Handlers
 // ============================================================================
@@ -330,140 +714,509 @@ async fn handle_health() -> &'static str {
     "OK"
 }
 
-/// GET /v1/sync/hello - Connection check for mobile
-async fn handle_hello(State(state): State<Arc<ServerState>>) -> Json<HelloResponse> {
+/// GET /v1/sync/hello - Connection check for mobile, and protocol negotiation
+#[utoipa::path(
+    get,
+    path = "/v1/sync/hello",
+    tag = "sync",
+    params(HelloQuery),
+    responses(
+        (status = 200, description = "Server identity and the agreed version", body = HelloResponse),
+        (status = 426, description = "No protocol version in common", body = UpgradeRequired),
+    )
+)]
+async fn handle_hello(
+    State(state): State<Arc<ServerState>>,
+    Query(query): Query<HelloQuery>,
+) -> Result<Json<HelloResponse>, UpgradeRequired> {
     state.touch().await;
-    Json(HelloResponse {
+    let negotiated = protocol::negotiate(&state.protocol, &query)?;
+    Ok(Json(HelloResponse {
         status: "ok".to_string(),
         device_id: state.device_id.clone(),
         device_name: state.device_name.clone(),
         version: "1.0.0".to_string(),
-    })
+        content_types: CONTENT_TYPES.iter().map(|t| t.to_string()).collect(),
+        content_encodings: CONTENT_ENCODINGS.iter().map(|e| e.to_string()).collect(),
+        protocol_version: Some(negotiated.version),
+        features: negotiated.features,
+    }))
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct HelloResponse {
-    status: String,
-    device_id: String,
-    device_name: String,
-    version: String,
+pub struct HelloResponse {
+    pub status: String,
+    pub device_id: String,
+    pub device_name: String,
+    pub version: String,
+    /// Body formats for pull/push (see `encoding`); empty from older servers
+    #[serde(default)]
+    pub content_types: Vec<String>,
+    /// Compression for pull/push bodies; empty from older servers
+    #[serde(default)]
+    pub content_encodings: Vec<String>,
+    /// Negotiated protocol version (see `protocol`); absent from older servers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u32>,
+    /// Features both sides support
+    #[serde(default)]
+    pub features: Vec<String>,
 }
 
+/// GET /v1/openapi.json - The contract of the v1 routes
+#[utoipa::path(
+    get,
+    path = "/v1/openapi.json",
+    tag = "sync",
+    responses((status = 200, description = "This document", content_type = "application/json"))
+)]
+async fn handle_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(openapi::document())
+}
+
+/// GET /v1/sync/status - Server identity and capabilities
+#[utoipa::path(
+    get,
+    path = "/v1/sync/status",
+    tag = "sync",
+    responses(
+        (status = 200, description = "Server identity and capabilities", body = StatusResponse),
+        (status = 426, description = "Protocol version below the minimum", body = UpgradeRequired),
+    )
+)]
 async fn handle_status(State(state): State<Arc<ServerState>>) -> Json<StatusResponse> {
     state.touch().await;
 
     Json(StatusResponse {
         device_id: state.device_id.clone(),
         device_name: state.device_name.clone(),
-        server_version: "1.0.0",
-        capabilities: vec!["pull", "push", "pairing"],
+        server_version: env!("CARGO_PKG_VERSION"),
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: state.protocol.supported().0,
+        capabilities: protocol::FEATURES.to_vec(),
+        content_types: CONTENT_TYPES.to_vec(),
+        content_encodings: CONTENT_ENCODINGS.to_vec(),
     })
 }
 
+/// Check the `Authorization: Bearer <token>` header against the paired device.
+///
+/// A token that doesn't match an active paired device, or a missing one from
+/// a protocol 2 client, is rejected with 401 and recorded in the audit log.
+/// Protocol 1 clients predate tokens: an active paired device is served
+/// without one, and the request counted in the legacy usage.
+async fn authenticate(
+    state: &ServerState,
+    headers: &HeaderMap,
+    uri: &Uri,
+    device_id: &str,
+    remote_addr: SocketAddr,
+) -> Result<(), StatusCode> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    let valid = match token {
+        Some(token) => state.persistence.validate_token(device_id, token).await,
+        None if protocol::request_version(headers) < TOKEN_REQUIRED_VERSION => state
+            .persistence
+            .get_device(device_id)
+            .await
+            .map(|device| device.is_some_and(|d| d.status == PairedDeviceStatus::Active)),
+        None => Ok(false),
+    };
+    match valid {
+        Ok(true) => {
+            if token.is_none() {
+                state.legacy_usage.record_tokenless(uri.path());
+            }
+            Ok(())
+        }
+        Ok(false) => {
+            let reason = if token.is_some() {
+                "invalid_token"
+            } else {
+                "missing_token"
+            };
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEventKind::AuthFailed)
+                        .device(device_id, None)
+                        .remote(remote_addr)
+                        .reason(reason),
+                )
+                .await;
+            Err(StatusCode::UNAUTHORIZED)
+        }
+        Err(e) => {
+            log::error!("Failed to validate device token: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// HLC string of an operation, if present
+fn op_hlc(op: Option<&serde_json::Value>) -> Option<&str> {
+    op.and_then(|op| op.get("hlc")).and_then(|v| v.as_str())
+}
+
+/// Look up the paired device name for audit entries
+async fn paired_device_name(state: &ServerState, device_id: &str) -> Option<String> {
+    state
+        .persistence
+        .get_device(device_id)
+        .await
+        .ok()
+        .flatten()
+        .map(|d| d.name)
+}
+
+/// POST /v1/sync/pull - Ops recorded since the cursor
+///
+/// Excludes ops the device pushed itself. `since_seq` acknowledges delivery
+/// up to it.
+#[utoipa::path(
+    post,
+    path = "/v1/sync/pull",
+    tag = "sync",
+    request_body(content(
+        (PullRequest = "application/json"),
+        (PullRequest = "application/cbor"),
+    )),
+    responses(
+        (status = 200, description = "A page of ops", content(
+            (PullResponse = "application/json"),
+            (PullResponse = "application/cbor"),
+        )),
+        (status = 401, description = "Not the token of an active paired device, or none from a protocol 2 client"),
+        (status = 426, description = "Protocol version below the minimum", body = UpgradeRequired),
+        (status = 429, description = "Rate limited; see Retry-After", body = LimitError),
+    ),
+    security(("bearer" = []))
+)]
 async fn handle_pull(
     State(state): State<Arc<ServerState>>,
-    Json(request): Json<PullRequest>,
-) -> Json<PullResponse> {
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    uri: Uri,
+    Payload(request): Payload<PullRequest>,
+) -> Result<Encoded<PullResponse>, Response> {
     state.touch().await;
 
-    log::info!("=== PULL REQUEST ===");
-    log::info!("From device: {}", request.device_id);
-    log::info!("Since HLC: {}", request.since_hlc);
-    log::info!("Since seq: {:?}", request.since_seq);
-    log::info!("Max ops: {:?}", request.max_ops);
-
-    // Get local operations that should be synced to mobile
-    let ops = {
-        let local_ops = state.local_ops.lock().await;
-        let max_ops = request.max_ops.unwrap_or(100);
-
-        // Filter ops with HLC > since_hlc
-        local_ops
-            .iter()
-            .filter(|op| {
-                // Extract hlc from operation and compare
-                if let Some(hlc) = op.get("hlc").and_then(|v| v.as_str()) {
-                    hlc > request.since_hlc.as_str()
-                } else {
-                    true // Include ops without HLC
-                }
-            })
-            .take(max_ops)
-            .cloned()
-            .collect::<Vec<_>>()
-    };
+    authenticate(&state, &headers, &uri, &request.device_id, remote_addr)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    // Charged to the verified device, so others can't use up its requests
+    state
+        .device_limiter
+        .check(&request.device_id)
+        .map_err(too_many_requests)?;
+
+    let device_name = paired_device_name(&state, &request.device_id).await;
+    state
+        .status
+        .sync_started(&request.device_id, device_name.as_deref())
+        .await;
 
-    let has_more = {
-        let local_ops = state.local_ops.lock().await;
-        ops.len() < local_ops.len()
+    log::info!("Pull request from device {}", redact::device(&request.device_id));
+    log::debug!(
+        "Pull since HLC {} (seq {:?}, max ops {:?})",
+        redact::hlc(&request.since_hlc),
+        request.since_seq,
+        request.max_ops
+    );
+
+    // Pulling with since_seq acknowledges everything up to it. Legacy clients
+    // only send since_hlc, which covers the leading ops at or before it.
+    let max_ops = request.max_ops.unwrap_or(100);
+    let (page, acked_seq) = match request.since_seq {
+        Some(since_seq) => {
+            let since_seq = since_seq.max(0) as u64;
+            let page = state
+                .op_log
+                .after_seq(&request.device_id, since_seq, max_ops)
+                .await;
+            (page, Some(since_seq))
+        }
+        None => {
+            let page = state
+                .op_log
+                .after_hlc(&request.device_id, &request.since_hlc, max_ops)
+                .await;
+            let acked_seq = state.op_log.seq_through_hlc(&request.since_hlc).await;
+            (page, acked_seq)
+        }
     };
 
+    if let Some(acked_seq) = acked_seq {
+        record_pull_ack(&state, &request.device_id, acked_seq).await;
+    }
+
+    // next_seq also covers the device's own ops that were skipped
+    let next_seq = page.last_seq.map(|seq| seq as i64);
+    let has_more = page.has_more;
+    let ops: Vec<serde_json::Value> = page.ops.into_iter().map(|e| e.op).collect();
+
     let next_hlc = ops.last().and_then(|op| {
         op.get("hlc").and_then(|v| v.as_str()).map(|s| s.to_string())
     });
 
     log::info!("Returning {} operations (has_more: {})", ops.len(), has_more);
-    if !ops.is_empty() {
-        log::info!("First op HLC: {:?}", ops.first().and_then(|op| op.get("hlc")));
-        log::info!("Last op HLC: {:?}", ops.last().and_then(|op| op.get("hlc")));
+    if let (Some(first), Some(last)) = (op_hlc(ops.first()), op_hlc(ops.last())) {
+        log::debug!("Pull HLC range {} .. {}", redact::hlc(first), redact::hlc(last));
     }
-    log::info!("=== END PULL ===");
 
-    Json(PullResponse {
-        operations: ops,
-        has_more,
-        next_hlc,
-        next_seq: None,
-    })
+    state
+        .audit
+        .record(
+            AuditEntry::new(AuditEventKind::Pull)
+                .device(&request.device_id, device_name.as_deref())
+                .remote(remote_addr)
+                .ops(ops.len())
+                .authenticated(true),
+        )
+        .await;
+    state.status.sync_finished(&request.device_id).await;
+
+    Ok(Encoded(
+        Format::accepted(&headers),
+        PullResponse {
+            operations: ops,
+            has_more,
+            next_hlc,
+            next_seq,
+        },
+    ))
 }
 
+/// Advance a device's delivery cursor and prune ops every device has received.
+/// Nothing is written while the cursor stays put.
+async fn record_pull_ack(state: &ServerState, device_id: &str, acked_seq: u64) {
+    // No client can have received ops that weren't logged yet
+    let acked_seq = acked_seq.min(state.op_log.last_seq().await);
+    match state.persistence.update_acked_seq(device_id, acked_seq).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            log::error!(
+                "Failed to record pull cursor for {}: {}",
+                redact::device(device_id),
+                e
+            );
+            return;
+        }
+    }
+
+    let pruned = match state.persistence.load().await {
+        Ok(devices) => state.op_log.prune_acknowledged(&devices).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = pruned {
+        log::error!("Failed to prune delivered local ops: {}", e);
+    }
+}
+
+/// POST /v1/sync/push - Store ops from a device
+///
+/// Ops the server already has are acknowledged without being stored again.
+#[utoipa::path(
+    post,
+    path = "/v1/sync/push",
+    tag = "sync",
+    request_body(content(
+        (PushRequest = "application/json"),
+        (PushRequest = "application/cbor"),
+    )),
+    responses(
+        (status = 200, description = "Ops stored; malformed ones are listed as rejected", content(
+            (PushResponse = "application/json"),
+            (PushResponse = "application/cbor"),
+        )),
+        (status = 401, description = "Not the token of an active paired device, or none from a protocol 2 client"),
+        (status = 413, description = "More ops than the server takes in one push", body = LimitError),
+        (status = 426, description = "Protocol version below the minimum", body = UpgradeRequired),
+        (status = 429, description = "Rate limited; see Retry-After", body = LimitError),
+    ),
+    security(("bearer" = []))
+)]
 async fn handle_push(
     State(state): State<Arc<ServerState>>,
-    Json(request): Json<PushRequest>,
-) -> Json<PushResponse> {
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    uri: Uri,
+    Payload(request): Payload<PushRequest>,
+) -> Result<Encoded<PushResponse>, Response> {
     state.touch().await;
 
-    log::info!("=== PUSH REQUEST ===");
-    log::info!("From device: {}", request.device_id);
-    log::info!("Operations count: {}", request.ops.len());
+    authenticate(&state, &headers, &uri, &request.device_id, remote_addr)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    // Charged to the verified device, so others can't use up its requests
+    state
+        .device_limiter
+        .check(&request.device_id)
+        .map_err(too_many_requests)?;
+
+    if request.ops.len() > MAX_PUSH_OPS {
+        state
+            .audit
+            .record(
+                AuditEntry::new(AuditEventKind::OpsRejected)
+                    .device(&request.device_id, None)
+                    .remote(remote_addr)
+                    .ops(request.ops.len())
+                    .reason("too_many_ops"),
+            )
+            .await;
+        return Err(payload_too_large(MAX_PUSH_OPS));
+    }
+
+    let device_name = paired_device_name(&state, &request.device_id).await;
+    state
+        .status
+        .sync_started(&request.device_id, device_name.as_deref())
+        .await;
 
-    // Log each operation in detail
-    for (i, op) in request.ops.iter().enumerate() {
-        log::info!("--- Operation {} ---", i + 1);
-        log::info!("{}", serde_json::to_string_pretty(op).unwrap_or_else(|_| format!("{:?}", op)));
+    log::info!(
+        "Push request from device {} with {} operations",
+        redact::device(&request.device_id),
+        request.ops.len()
+    );
+    for op in request.ops.iter() {
+        log::debug!("Pushed op: {}", redact::op(op));
     }
 
-    // Store operations in the pending queue
-    let ops_count = request.ops.len();
-    {
-        let mut pending = state.pending_ops.lock().await;
-        for op in request.ops.iter() {
-            pending.push_back(op.clone());
+    // Reject anything that isn't an operation object with an id
+    let mut valid_ops = Vec::new();
+    let mut rejected = Vec::new();
+    for (index, op) in request.ops.into_iter().enumerate() {
+        if op.get("id").and_then(|v| v.as_str()).is_some() {
+            valid_ops.push(op);
+        } else {
+            rejected.push(RejectedOp {
+                index,
+                reason: "invalid_op".to_string(),
+            });
         }
-        log::info!("Stored {} ops in pending queue (total now: {})", ops_count, pending.len());
     }
 
-    // Emit event to frontend to notify about new ops
-    if let Some(ref app) = state.app_handle {
-        log::info!("Emitting sync:ops_received event to frontend");
-        if let Err(e) = app.emit("sync:ops_received", ops_count) {
-            log::error!("Failed to emit event: {}", e);
+    let accepted = valid_ops.len();
+    let stored = store_incoming_ops(
+        &state.pending_ops,
+        &state.op_log,
+        &request.device_id,
+        valid_ops,
+    )
+    .await;
+    state.status.sync_finished(&request.device_id).await;
+    let (ops_count, conflicts) = match stored {
+        Ok(stored) => stored,
+        Err(e) => {
+            log::error!("Failed to store pushed operations: {}", e);
+            state
+                .status
+                .record_error(format!("Failed to store pushed operations: {}", e))
+                .await;
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
         }
-    } else {
-        log::warn!("No app handle available to emit event");
-    }
+    };
+    notify_ops_received(state.app_handle.as_ref(), ops_count, &conflicts);
 
     let response = PushResponse {
-        accepted: ops_count,
-        rejected: vec![],
-        server_hlc: chrono::Utc::now().timestamp_millis().to_string(),
+        accepted,
+        rejected,
+        server_hlc: state.clock.now().timestamp_millis().to_string(),
     };
 
     log::info!("Response: accepted={}, rejected={}", response.accepted, response.rejected.len());
-    log::info!("=== END PUSH ===");
 
-    Json(response)
+    state
+        .audit
+        .record(
+            AuditEntry::new(AuditEventKind::Push)
+                .device(&request.device_id, device_name.as_deref())
+                .remote(remote_addr)
+                .ops(response.accepted)
+                .authenticated(true),
+        )
+        .await;
+    if !response.rejected.is_empty() {
+        state
+            .audit
+            .record(
+                AuditEntry::new(AuditEventKind::OpsRejected)
+                    .device(&request.device_id, device_name.as_deref())
+                    .remote(remote_addr)
+                    .ops(response.rejected.len())
+                    .reason("invalid_op"),
+            )
+            .await;
+    }
+
+    Ok(Encoded(Format::accepted(&headers), response))
+}
+
+/// Store ops received from a device before acknowledging them. Ops already in
+/// the op log (retried pushes, ops relayed from another device) are skipped;
+/// new ones go to the inbox, with any concurrent edits detected, and to the op
+/// log for relay to other devices, all under the op log's ingest lock so
+/// concurrent deliveries of the same op store it once. Returns how many ops
+/// were new and the conflicts.
+pub(crate) async fn store_incoming_ops(
+    pending_ops: &PendingOpsStore,
+    op_log: &OpLog,
+    device_id: &str,
+    ops: Vec<serde_json::Value>,
+) -> Result<(usize, Vec<DetectedConflict>), PersistenceError> {
+    let _ingesting = op_log.ingest().await;
+    let new_ops = op_log.unlogged(ops).await;
+    let ops_count = new_ops.len();
+
+    // Detect concurrent edits against ops not yet delivered everywhere
+    let conflicts = op_log.conflicts_with(&new_ops).await;
+    if !conflicts.is_empty() {
+        log::warn!(
+            "Detected {} conflicting ops from device {}",
+            conflicts.len(),
+            redact::device(device_id)
+        );
+    }
+
+    if ops_count > 0 {
+        let last_seq = pending_ops.push(new_ops.clone(), &conflicts).await?;
+        log::info!("Stored {} ops in inbox (last seq: {})", ops_count, last_seq);
+
+        for op in new_ops {
+            op_log.append(op, Some(device_id)).await?;
+        }
+    }
+
+    Ok((ops_count, conflicts))
+}
+
+/// Tell the frontend that ops arrived (and about any conflicts)
+pub(crate) fn notify_ops_received(
+    app_handle: Option<&AppHandle>,
+    ops_count: usize,
+    conflicts: &[DetectedConflict],
+) {
+    let Some(app) = app_handle else {
+        log::warn!("No app handle available to emit event");
+        return;
+    };
+
+    log::info!("Emitting sync:ops_received event to frontend");
+    if let Err(e) = app.emit("sync:ops_received", ops_count) {
+        log::error!("Failed to emit event: {}", e);
+    }
+    if !conflicts.is_empty() {
+        if let Err(e) = app.emit("sync:conflicts_detected", conflicts) {
+            log::error!("Failed to emit event: {}", e);
+        }
+    }
 }
 
 async fn handle_pair_initiate(
@@ -499,98 +1252,271 @@ async fn handle_pair_verify(
 // ============================================================================
 
 /// Query params for GET /pair/status
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
 #[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
 struct PairStatusQuery {
     pairing_id: String,
 }
 
-/// Get local IP addresses for LAN pairing
-fn get_local_ips() -> Vec<String> {
-    let mut ips = Vec::new();
-
-    // Try to get local IP using the crate
-    if let Ok(ip) = local_ip_address::local_ip() {
-        ips.push(ip.to_string());
-    }
-
-    // Also try to get all local IPs
-    if let Ok(list) = local_ip_address::list_afinet_netifas() {
-        for (_, ip) in list {
-            let ip_str = ip.to_string();
-            // Filter for IPv4 addresses, skip loopback
-            if !ip_str.starts_with("127.") && !ip_str.contains(':') && !ips.contains(&ip_str) {
-                ips.push(ip_str);
-            }
-        }
+/// Pairing error response, with Retry-After when the client should back off
+fn pairing_error_response(
+    status: StatusCode,
+    error: PairingErrorSimple,
+    retry_after: Option<u64>,
+) -> Response {
+    match retry_after {
+        Some(secs) => (status, [(header::RETRY_AFTER, secs.to_string())], Json(error)).into_response(),
+        None => (status, Json(error)).into_response(),
     }
+}
 
-    // Fallback if no IPs found
-    if ips.is_empty() {
-        ips.push("127.0.0.1".to_string());
-    }
+/// Map a pairing error to the simple HTTP error contract
+fn pairing_failure_response(e: PairingError) -> Response {
+    let status = match e.code.as_str() {
+        "NOT_FOUND" | "INVALID_CODE" => StatusCode::BAD_REQUEST,
+        "EXPIRED" => StatusCode::BAD_REQUEST,
+        "RATE_LIMITED" | "TOO_MANY_ATTEMPTS" => StatusCode::TOO_MANY_REQUESTS,
+        "DENIED" => StatusCode::FORBIDDEN,
+        "APPROVAL_TIMEOUT" => StatusCode::REQUEST_TIMEOUT,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    let error = PairingErrorSimple {
+        error: PairingErrorCode::of(&e),
+    };
+    pairing_error_response(status, error, e.retry_after)
+}
 
-    ips
+async fn record_pairing_failure(
+    state: &ServerState,
+    request: &PairConfirmRequest,
+    remote_addr: SocketAddr,
+    code: &str,
+) {
+    state
+        .audit
+        .record(
+            AuditEntry::new(AuditEventKind::PairingFailed)
+                .device(&request.device_id, Some(&request.device_name))
+                .remote(remote_addr)
+                .reason(code),
+        )
+        .await;
 }
 
-/// POST /pair/start - Start a new pairing session
+/// POST /pair/start - Start a new pairing session (loopback only)
+#[utoipa::path(
+    post,
+    path = "/v1/pair/start",
+    tag = "pairing",
+    responses(
+        (status = 200, description = "New session with its code and QR payload", body = PairStartResponse),
+        (status = 403, description = "Not called from the desktop itself (`forbidden`)", body = PairingErrorSimple),
+        (status = 426, description = "Protocol version below the minimum", body = UpgradeRequired),
+        (status = 429, description = "Too many sessions (`rate_limited`); see Retry-After", body = PairingErrorSimple),
+    )
+)]
 async fn handle_pair_start(
     State(state): State<Arc<ServerState>>,
-) -> Json<PairStartResponse> {
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+) -> Result<Json<PairStartResponse>, Response> {
     state.touch().await;
 
-    let host_candidates = get_local_ips();
+    // Pairing sessions are started from the desktop UI; over HTTP only local
+    // tools may start one (the response contains the code)
+    if !remote_addr.ip().is_loopback() {
+        state
+            .audit
+            .record(
+                AuditEntry::new(AuditEventKind::PairingFailed)
+                    .remote(remote_addr)
+                    .reason("remote_pair_start"),
+            )
+            .await;
+        let error = PairingErrorSimple {
+            error: PairingErrorCode::Forbidden,
+        };
+        return Err(pairing_error_response(StatusCode::FORBIDDEN, error, None));
+    }
+
+    let host_candidates = network::host_candidates(&state.addresses);
     let response = state
         .pairing_manager
         .create_session(host_candidates, state.port)
+        .await
+        .map_err(|e| {
+            let error = PairingErrorSimple {
+                error: PairingErrorCode::RateLimited,
+            };
+            pairing_error_response(StatusCode::TOO_MANY_REQUESTS, error, e.retry_after)
+        })?;
+
+    state
+        .audit
+        .record(AuditEntry::new(AuditEventKind::PairingStarted).remote(remote_addr))
         .await;
+    state.status.pairing_started(&response.pairing_id).await;
+
+    Ok(Json(response))
+}
+
+/// POST /pair/key - Exchange ephemeral keys before confirming (from mobile)
+///
+/// The mobile commits to its public key here and reveals it in `/pair/confirm`;
+/// both devices then show the SAS derived from the exchange.
+#[utoipa::path(
+    post,
+    path = "/v1/pair/key",
+    tag = "pairing",
+    request_body = PairKeyRequest,
+    responses(
+        (status = 200, description = "Desktop public key for the session", body = PairKeyResponse),
+        (status = 400, description = "`wrong_code`, `expired` or `invalid_pairing_id`", body = PairingErrorSimple),
+        (status = 426, description = "Protocol version below the minimum", body = UpgradeRequired),
+        (status = 429, description = "Too many attempts (`rate_limited`); see Retry-After", body = PairingErrorSimple),
+    )
+)]
+async fn handle_pair_key(
+    State(state): State<Arc<ServerState>>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<PairKeyRequest>,
+) -> Result<Json<PairKeyResponse>, Response> {
+    state.touch().await;
+
+    if let Err(wait) = state.pair_confirm_limiter.check(&remote_addr.ip()) {
+        let error = PairingErrorSimple {
+            error: PairingErrorCode::RateLimited,
+        };
+        return Err(pairing_error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            error,
+            Some(retry_after_secs(wait)),
+        ));
+    }
 
-    Json(response)
+    let exchange = state.pairing_manager.start_key_exchange(&request).await;
+    if let Err(e) = &exchange {
+        state
+            .audit
+            .record(
+                AuditEntry::new(AuditEventKind::PairingFailed)
+                    .remote(remote_addr)
+                    .reason(&e.code),
+            )
+            .await;
+    }
+    exchange.map(Json).map_err(pairing_failure_response)
 }
 
 /// POST /pair/confirm - Confirm pairing (from mobile)
+///
+/// Holds the request open until the desktop user approves or denies the device.
+#[utoipa::path(
+    post,
+    path = "/v1/pair/confirm",
+    tag = "pairing",
+    request_body = PairConfirmRequest,
+    responses(
+        (status = 200, description = "Device paired; keep the session token", body = PairConfirmResponse),
+        (status = 400, description = "`wrong_code`, `expired`, `invalid_pairing_id`, or `invalid_key` when the public key doesn't match the `/v1/pair/key` commitment", body = PairingErrorSimple),
+        (status = 403, description = "The desktop user denied the device (`denied`)", body = PairingErrorSimple),
+        (status = 408, description = "The desktop user didn't answer (`approval_timeout`)", body = PairingErrorSimple),
+        (status = 426, description = "Protocol version below the minimum", body = UpgradeRequired),
+        (status = 429, description = "Too many attempts (`rate_limited`); see Retry-After", body = PairingErrorSimple),
+    )
+)]
 async fn handle_pair_confirm(
     State(state): State<Arc<ServerState>>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
     Json(request): Json<PairConfirmRequest>,
-) -> Result<Json<PairConfirmResponse>, (StatusCode, Json<PairingErrorSimple>)> {
+) -> Result<Json<PairConfirmResponse>, Response> {
     state.touch().await;
 
+    if let Err(wait) = state.pair_confirm_limiter.check(&remote_addr.ip()) {
+        let error = PairingErrorSimple {
+            error: PairingErrorCode::RateLimited,
+        };
+        return Err(pairing_error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            error,
+            Some(retry_after_secs(wait)),
+        ));
+    }
+
     // First verify using internal method (to get token for persistence)
-    let internal_response = state
+    let verification = state.pairing_manager.verify(&request).await;
+    if let Err(e) = &verification {
+        record_pairing_failure(&state, &request, remote_addr, &e.code).await;
+    }
+    let internal_response = verification.map_err(pairing_failure_response)?;
+
+    // Ask the desktop user to allow the device before the token becomes active
+    let decision = state
         .pairing_manager
-        .verify(&request)
-        .await
-        .map_err(|e| {
-            let status = match e.code.as_str() {
-                "NOT_FOUND" | "INVALID_CODE" => StatusCode::BAD_REQUEST,
-                "EXPIRED" => StatusCode::BAD_REQUEST,
-                "RATE_LIMITED" | "TOO_MANY_ATTEMPTS" => StatusCode::TOO_MANY_REQUESTS,
-                _ => StatusCode::BAD_REQUEST,
-            };
-            let error = match e.code.as_str() {
-                "EXPIRED" => "expired",
-                "INVALID_CODE" | "INVALID_NONCE" => "wrong_code",
-                "RATE_LIMITED" | "TOO_MANY_ATTEMPTS" => "rate_limited",
-                _ => "invalid_pairing_id",
-            };
-            (status, Json(PairingErrorSimple { error: error.to_string() }))
-        })?;
+        .request_approval(&internal_response.pairing_id)
+        .await;
+    if let Some(ref app) = state.app_handle {
+        let approval_request = PairingApprovalRequest {
+            pairing_id: internal_response.pairing_id.clone(),
+            device_id: request.device_id.clone(),
+            device_name: request.device_name.clone(),
+            method: request.method.clone(),
+            sas: internal_response.sas.clone(),
+        };
+        if let Err(e) = app.emit("sync:pairing_approval_requested", approval_request) {
+            log::error!("Failed to emit pairing approval event: {}", e);
+        }
+    }
+    let approval = state
+        .pairing_manager
+        .wait_for_approval(&internal_response.pairing_id, decision)
+        .await;
+    state
+        .status
+        .pairing_ended(&internal_response.pairing_id)
+        .await;
+    if let Err(e) = approval {
+        record_pairing_failure(&state, &request, remote_addr, &e.code).await;
+        return Err(pairing_failure_response(e));
+    }
 
     // Persist the paired device
     let device = PairedDevice {
         id: request.device_id.clone(),
         name: request.device_name.clone(),
         token: internal_response.token.clone(),
-        paired_at: chrono::Utc::now().to_rfc3339(),
+        paired_at: state.clock.now().to_rfc3339(),
         last_sync_at: None,
         status: PairedDeviceStatus::Active,
+        acked_seq: 0,
+        relay_seq: 0,
+        relay_acked_seq: 0,
+        relay_deposited_at: None,
+        pairing_secret: internal_response.pairing_secret.clone(),
     };
 
     if let Err(e) = state.persistence.add_device(device).await {
-        log::error!("Failed to persist paired device: {}", e);
+        log::error!(
+            "Failed to persist paired device {}: {}",
+            redact::device(&request.device_id),
+            e
+        );
         // Don't fail the pairing, just log the error
     }
 
+    state
+        .audit
+        .record(
+            AuditEntry::new(AuditEventKind::PairingSucceeded)
+                .device(&request.device_id, Some(&request.device_name))
+                .remote(remote_addr)
+                .reason(match request.method {
+                    PairingMethod::Code => "code",
+                    PairingMethod::QR => "qr",
+                }),
+        )
+        .await;
+
     // Return HTTP API response format
     Ok(Json(PairConfirmResponse {
         desktop_device_id: internal_response.desktop_device_id,
@@ -600,6 +1526,17 @@ async fn handle_pair_confirm(
 }
 
 /// GET /pair/status - Get pairing session status
+#[utoipa::path(
+    get,
+    path = "/v1/pair/status",
+    tag = "pairing",
+    params(PairStatusQuery),
+    responses(
+        (status = 200, description = "Session status", body = PairStatusResponse),
+        (status = 404, description = "No such session", body = PairingError),
+        (status = 426, description = "Protocol version below the minimum", body = UpgradeRequired),
+    )
+)]
 async fn handle_pair_status(
     State(state): State<Arc<ServerState>>,
     Query(params): Query<PairStatusQuery>,
@@ -614,3 +1551,392 @@ async fn handle_pair_status(
 
     Ok(Json(response))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::client::SyncClient;
+    use crate::sync::clock::ManualClock;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    /// Pair `device_id` with the server; returns its token
+    async fn pair(server: &SyncServer, device_id: &str) -> String {
+        let token = format!("token-{}", device_id);
+        server
+            .persistence
+            .add_device(PairedDevice {
+                id: device_id.to_string(),
+                name: device_id.to_string(),
+                token: token.clone(),
+                paired_at: chrono::Utc::now().to_rfc3339(),
+                last_sync_at: None,
+                status: PairedDeviceStatus::Active,
+                acked_seq: 0,
+                relay_seq: 0,
+                relay_acked_seq: 0,
+                relay_deposited_at: None,
+                pairing_secret: None,
+            })
+            .await
+            .unwrap();
+        token
+    }
+
+    /// Pull as `device_id` from the start, with `token` if given
+    async fn pull(url: &str, device_id: &str, token: Option<&str>) -> reqwest::Response {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/v1/sync/pull", url))
+            .header(protocol::PROTOCOL_HEADER, PROTOCOL_VERSION)
+            .json(&json!({ "device_id": device_id, "since_hlc": "", "since_seq": 0 }));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_server_stops_after_inactivity() {
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let clock = ManualClock::new();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let options = ServerOptions {
+            auto_shutdown_minutes: 30,
+            env: SyncEnv {
+                clock: clock.clone(),
+                ..Default::default()
+            },
+            ..ServerOptions::new(
+                "desktop-1",
+                "Desktop",
+                config_dir.clone(),
+                Arc::clone(&pending_ops),
+                Arc::clone(&op_log),
+                SyncStatusTracker::new(pending_ops, op_log, None),
+            )
+        };
+        let (server, port) = SyncServer::start(options, &Default::default())
+            .await
+            .unwrap();
+        let url = format!("http://127.0.0.1:{}", port);
+        let client = SyncClient::new(&url, "cli-1", None);
+        assert!(client.hello().await.is_ok());
+
+        // Just inside the timeout the server keeps answering, and each
+        // request resets the idle timer
+        for _ in 0..2 {
+            clock.advance(Duration::from_secs(29 * 60));
+            assert!(client.hello().await.is_ok());
+        }
+
+        // Past it, an idle check stops the listener. Keep the clock moving
+        // until the idle task has run one.
+        clock.advance(Duration::from_secs(31 * 60));
+        let stopped = server.stopped();
+        tokio::pin!(stopped);
+        loop {
+            tokio::select! {
+                biased;
+                _ = &mut stopped => break,
+                _ = tokio::task::yield_now() => clock.advance(IDLE_CHECK_INTERVAL),
+            }
+        }
+        let fresh = SyncClient::new(&url, "cli-1", None);
+        assert!(fresh.hello().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_restarts_leave_no_tasks_behind() {
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let tasks = TaskSupervisor::default();
+
+        let mut managers = Vec::new();
+        for _ in 0..5 {
+            let options = ServerOptions {
+                auto_shutdown_minutes: 30,
+                ..ServerOptions::new(
+                    "desktop-1",
+                    "Desktop",
+                    config_dir.clone(),
+                    Arc::clone(&pending_ops),
+                    Arc::clone(&op_log),
+                    SyncStatusTracker::new(Arc::clone(&pending_ops), Arc::clone(&op_log), None),
+                )
+            };
+            let (mut server, port) = SyncServer::start(options, &tasks).await.unwrap();
+            let client = SyncClient::new(&format!("http://127.0.0.1:{}", port), "cli-1", None);
+            assert!(client.hello().await.is_ok());
+            assert_eq!(tasks.active(), 2);
+            managers.push(Arc::downgrade(&server.pairing_manager));
+
+            server.stop();
+            drop(server);
+            tasks.shutdown().await;
+            assert_eq!(tasks.active(), 0);
+            assert!(client.hello().await.is_err());
+        }
+
+        // Nothing is left holding the servers' state
+        assert!(managers.iter().all(|manager| manager.upgrade().is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_device_limit_counts_authenticated_requests_only() {
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let options = ServerOptions {
+            limits: RateLimits {
+                device_burst: 1,
+                device_refill_per_sec: 0.01,
+                ..Default::default()
+            },
+            ..ServerOptions::new(
+                "desktop-1",
+                "Desktop",
+                config_dir.clone(),
+                Arc::clone(&pending_ops),
+                Arc::clone(&op_log),
+                SyncStatusTracker::new(pending_ops, op_log, None),
+            )
+        };
+        let (server, port) = SyncServer::start(options, &Default::default())
+            .await
+            .unwrap();
+        let url = format!("http://127.0.0.1:{}", port);
+        let token = pair(&server, "phone-1").await;
+
+        // Requests naming the phone without its token don't use up its budget
+        for _ in 0..3 {
+            let response = pull(&url, "phone-1", None).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = pull(&url, "phone-1", Some(&token)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = pull(&url, "phone-1", Some(&token)).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "100");
+    }
+
+    #[tokio::test]
+    async fn test_revoked_device_is_refused_while_running() {
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let options = ServerOptions::new(
+            "desktop-1",
+            "Desktop",
+            config_dir.clone(),
+            Arc::clone(&pending_ops),
+            Arc::clone(&op_log),
+            SyncStatusTracker::new(Arc::clone(&pending_ops), Arc::clone(&op_log), None),
+        );
+        let (server, port) = SyncServer::start(options, &Default::default())
+            .await
+            .unwrap();
+        let url = format!("http://127.0.0.1:{}", port);
+        let token = pair(&server, "phone-1").await;
+        op_log
+            .append(json!({ "id": "op-1", "hlc": "1" }), None)
+            .await
+            .unwrap();
+        let response = pull(&url, "phone-1", Some(&token)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Revoke (as the revoke command does, through the server's store)
+        // while the phone keeps pulling and acknowledging
+        let pulls: Vec<_> = (0..5)
+            .map(|_| {
+                let (url, token) = (url.clone(), token.clone());
+                tokio::spawn(async move { pull(&url, "phone-1", Some(&token)).await })
+            })
+            .collect();
+        assert!(server.persistence.revoke_device("phone-1").await.unwrap());
+        for pulled in pulls {
+            pulled.await.unwrap();
+        }
+
+        let response = pull(&url, "phone-1", Some(&token)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let on_disk = PersistenceManager::new(config_dir).unwrap();
+        let device = on_disk.get_device("phone-1").await.unwrap().unwrap();
+        assert_eq!(device.status, PairedDeviceStatus::Revoked);
+    }
+
+    #[tokio::test]
+    async fn test_protocol_1_clients_pull_without_a_token() {
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let options = ServerOptions::new(
+            "desktop-1",
+            "Desktop",
+            config_dir,
+            Arc::clone(&pending_ops),
+            Arc::clone(&op_log),
+            SyncStatusTracker::new(pending_ops, op_log, None),
+        );
+        let (server, port) = SyncServer::start(options, &Default::default())
+            .await
+            .unwrap();
+        let url = format!("http://127.0.0.1:{}", port);
+        pair(&server, "phone-1").await;
+        let old_app_pull = |path: &str, device_id: &str| {
+            reqwest::Client::new()
+                .post(format!("{}{}", url, path))
+                .json(&json!({ "device_id": device_id, "since_hlc": "" }))
+                .send()
+        };
+
+        let response = old_app_pull("/v1/sync/pull", "phone-1").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = old_app_pull("/sync/pull", "phone-1").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let usage = server.legacy_usage.snapshot();
+        assert_eq!(usage["/v1/sync/pull without token"], 1);
+        assert_eq!(usage["/sync/pull without token"], 1);
+
+        // Still refused: unpaired devices, and protocol 2 clients
+        let response = old_app_pull("/v1/sync/pull", "phone-2").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = pull(&url, "phone-1", None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_bind_skips_unusable_addresses_and_taken_ports() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+        // Not an address of this machine
+        let gone = LocalAddress::new("eth9", "192.0.2.1".parse().unwrap(), 0);
+
+        let (listeners, bound_port, bound) = bind_with_fallback(port, &[gone]).await.unwrap();
+        assert!(bound_port > port);
+        assert_eq!(listeners.len(), 1);
+        assert!(bound.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pull_cursor_is_clamped_to_logged_ops() {
+        let dir = tempdir().unwrap();
+        let config_dir = dir.path().to_path_buf();
+        let pending_ops = PendingOpsStore::new(config_dir.clone()).unwrap();
+        let op_log = OpLog::new(config_dir.clone()).unwrap();
+        let options = ServerOptions::new(
+            "desktop-1",
+            "Desktop",
+            config_dir,
+            Arc::clone(&pending_ops),
+            Arc::clone(&op_log),
+            SyncStatusTracker::new(Arc::clone(&pending_ops), Arc::clone(&op_log), None),
+        );
+        let (server, port) = SyncServer::start(options, &Default::default())
+            .await
+            .unwrap();
+        let token = pair(&server, "phone-1").await;
+        op_log
+            .append(json!({ "id": "op-1", "hlc": "1" }), None)
+            .await
+            .unwrap();
+
+        // A buggy client acknowledging ops that don't exist yet
+        let response = reqwest::Client::new()
+            .post(format!("http://127.0.0.1:{}/v1/sync/pull", port))
+            .header(protocol::PROTOCOL_HEADER, PROTOCOL_VERSION)
+            .bearer_auth(&token)
+            .json(&json!({ "device_id": "phone-1", "since_hlc": "", "since_seq": 1000 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let device = server.persistence.get_device("phone-1").await.unwrap();
+        assert_eq!(device.unwrap().acked_seq, 1);
+
+        // Ops logged later are still delivered and not pruned unseen
+        op_log
+            .append(json!({ "id": "op-2", "hlc": "2" }), None)
+            .await
+            .unwrap();
+        assert_eq!(op_log.count().await, 1);
+        let url = format!("http://127.0.0.1:{}", port);
+        let page: PullResponse = pull(&url, "phone-1", Some(&token))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(page.operations[0]["id"], "op-2");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_deliveries_store_an_op_once() {
+        let dir = tempdir().unwrap();
+        let pending_ops = PendingOpsStore::new(dir.path().to_path_buf()).unwrap();
+        let op_log = OpLog::new(dir.path().to_path_buf()).unwrap();
+        let rename = json!({
+            "id": "op-1", "hlc": "b", "entityType": "client", "entityId": "c-1",
+            "opType": "update", "field": "name", "value": "Sarah", "createdBy": "phone-1",
+        });
+
+        // The same op pushed by its author and relayed by another phone at once
+        let deliveries: Vec<_> = ["phone-1", "phone-2", "phone-1", "phone-2"]
+            .into_iter()
+            .map(|from| {
+                let (pending_ops, op_log) = (Arc::clone(&pending_ops), Arc::clone(&op_log));
+                let ops = vec![rename.clone(), rename.clone()];
+                tokio::spawn(async move {
+                    store_incoming_ops(&pending_ops, &op_log, from, ops)
+                        .await
+                        .unwrap()
+                        .0
+                })
+            })
+            .collect();
+        let mut stored = 0;
+        for delivery in deliveries {
+            stored += delivery.await.unwrap();
+        }
+
+        assert_eq!(stored, 1);
+        assert_eq!(pending_ops.count().await, 1);
+        assert_eq!(op_log.count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_push_retried_after_prune_is_not_stored_again() {
+        let dir = tempdir().unwrap();
+        let pending_ops = PendingOpsStore::new(dir.path().to_path_buf()).unwrap();
+        let op_log = OpLog::new(dir.path().to_path_buf()).unwrap();
+        let op = json!({ "id": "op-1", "hlc": "a", "createdBy": "phone-1" });
+
+        let push = || store_incoming_ops(&pending_ops, &op_log, "phone-1", vec![op.clone()]);
+        assert_eq!(push().await.unwrap().0, 1);
+        let tablet = PairedDevice {
+            id: "tablet-1".to_string(),
+            name: "Tablet".to_string(),
+            token: String::new(),
+            paired_at: chrono::Utc::now().to_rfc3339(),
+            last_sync_at: None,
+            status: PairedDeviceStatus::Active,
+            acked_seq: 1,
+            relay_seq: 0,
+            relay_acked_seq: 0,
+            relay_deposited_at: None,
+            pairing_secret: None,
+        };
+        assert_eq!(op_log.prune_acknowledged(&[tablet]).await.unwrap(), 1);
+
+        // The push response was lost and the phone sends the op again
+        assert_eq!(push().await.unwrap().0, 0);
+        assert_eq!(pending_ops.count().await, 1);
+        assert_eq!(op_log.count().await, 0);
+    }
+}
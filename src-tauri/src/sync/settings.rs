@@ -0,0 +1,464 @@
+//! Sync Settings
+//!
+//! Desktop sync settings kept in `sync_settings.json` next to
+//! `paired_devices.json`: the server port and idle timeout, which interfaces
+//! to listen on, which transports are enabled, the log level and whether the
+//! legacy routes are served. `start_sync_server` fills in anything its caller
+//! leaves out from here.
+//!
+//! The file carries a `version`; a file written by a newer app is ignored
+//! rather than misread, and copied to `sync_settings.v<version>.json` before
+//! it is first overwritten, so going back to the newer app can restore it.
+//! `update_sync_settings` validates the whole settings
+//! object before saving it, then applies what it can to the running server:
+//! the log level and transports immediately, while changes to how the server
+//! listens or introduces itself restart it.
+
+use super::commands::ServerStartOptions;
+use super::error::SyncCommandError;
+use super::folder::write_atomic;
+use super::network::NetworkConfig;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const SETTINGS_VERSION: u32 = 1;
+
+const SETTINGS_FILE: &str = "sync_settings.json";
+const MAX_AUTO_SHUTDOWN_MINUTES: u64 = 24 * 60;
+const MAX_DEVICE_NAME_CHARS: usize = 64;
+
+// ============================================================================
+// Types
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn filter(self) -> log::LevelFilter {
+        match self {
+            Self::Off => log::LevelFilter::Off,
+            Self::Error => log::LevelFilter::Error,
+            Self::Warn => log::LevelFilter::Warn,
+            Self::Info => log::LevelFilter::Info,
+            Self::Debug => log::LevelFilter::Debug,
+            Self::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+/// A way of syncing the user can switch off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Lan,
+    Mdns,
+    Relay,
+    Folder,
+}
+
+impl Transport {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lan => "lan",
+            Self::Mdns => "mdns",
+            Self::Relay => "relay",
+            Self::Folder => "folder",
+        }
+    }
+}
+
+/// Which transports are switched on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Transports {
+    /// The LAN server phones connect to
+    pub lan: bool,
+    /// Advertising the LAN server and finding peers over mDNS
+    pub mdns: bool,
+    /// Mailbox relay sync
+    pub relay: bool,
+    /// Shared sync folder
+    pub folder: bool,
+}
+
+impl Transports {
+    pub fn enabled(&self, transport: Transport) -> bool {
+        match transport {
+            Transport::Lan => self.lan,
+            Transport::Mdns => self.mdns,
+            Transport::Relay => self.relay,
+            Transport::Folder => self.folder,
+        }
+    }
+}
+
+impl Default for Transports {
+    fn default() -> Self {
+        Self {
+            lan: true,
+            mdns: true,
+            relay: true,
+            folder: true,
+        }
+    }
+}
+
+/// Contents of `sync_settings.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SyncSettings {
+    pub version: u32,
+    /// Used when the server is started without a device id
+    pub device_id: Option<String>,
+    /// Name shown to phones; the hostname when unset
+    pub device_name: Option<String>,
+    /// 0 picks 4242, or the next free port after it
+    pub port: u16,
+    /// Minutes without requests before the server stops; 0 = never
+    pub auto_shutdown_minutes: u64,
+    /// Interfaces to listen on and peers allowed to connect
+    pub network: NetworkConfig,
+    pub transports: Transports,
+    pub log_level: LogLevel,
+    /// Serve the unversioned `/sync/*` and `/pair/*` routes
+    pub legacy_routes: bool,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            device_id: None,
+            device_name: None,
+            port: 0,
+            auto_shutdown_minutes: 30,
+            network: NetworkConfig::default(),
+            transports: Transports::default(),
+            log_level: LogLevel::Info,
+            legacy_routes: true,
+        }
+    }
+}
+
+impl SyncSettings {
+    pub fn load(config_dir: &Path) -> Option<Self> {
+        let content = std::fs::read_to_string(config_dir.join(SETTINGS_FILE)).ok()?;
+        match serde_json::from_str::<Self>(&content) {
+            Ok(settings) if settings.version > SETTINGS_VERSION => {
+                log::warn!(
+                    "Ignoring {} from a newer version (v{})",
+                    SETTINGS_FILE,
+                    settings.version
+                );
+                None
+            }
+            Ok(settings) => Some(settings),
+            Err(e) => {
+                log::warn!("Ignoring unreadable {}: {}", SETTINGS_FILE, e);
+                None
+            }
+        }
+    }
+
+    fn save(&self, config_dir: &Path) -> Result<(), String> {
+        let path = config_dir.join(SETTINGS_FILE);
+        backup_newer(&path)?;
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        write_atomic(&path, &json)
+    }
+
+    pub fn validate(&self) -> Result<(), SyncCommandError> {
+        let invalid = |field: &'static str, detail: &str| SyncCommandError::InvalidSetting {
+            field,
+            detail: detail.to_string(),
+        };
+
+        if self
+            .device_id
+            .as_ref()
+            .is_some_and(|id| id.trim().is_empty())
+        {
+            return Err(invalid("deviceId", "must not be empty"));
+        }
+        if let Some(name) = &self.device_name {
+            let chars = name.trim().chars().count();
+            if chars == 0 || chars > MAX_DEVICE_NAME_CHARS {
+                return Err(invalid("deviceName", "must be between 1 and 64 characters"));
+            }
+        }
+        if self.port != 0 && self.port < 1024 {
+            return Err(invalid("port", "must be 0 (automatic) or at least 1024"));
+        }
+        if self.auto_shutdown_minutes > MAX_AUTO_SHUTDOWN_MINUTES {
+            return Err(invalid(
+                "autoShutdownMinutes",
+                "must be at most 1440 (24 hours)",
+            ));
+        }
+        if self.network.interfaces.iter().any(|i| i.trim().is_empty()) {
+            return Err(invalid(
+                "network.interfaces",
+                "must not contain empty names",
+            ));
+        }
+        if self.network.allowed_peers.is_empty() {
+            return Err(invalid(
+                "network.allowed_peers",
+                "must allow at least one range",
+            ));
+        }
+        Ok(())
+    }
+
+    /// `options` with everything it leaves out taken from the settings
+    pub fn start_options(&self, options: ServerStartOptions) -> ServerStartOptions {
+        ServerStartOptions {
+            device_id: options.device_id.or_else(|| self.device_id.clone()),
+            device_name: options.device_name.or_else(|| self.device_name.clone()),
+            port: options.port.or(Some(self.port)),
+            auto_shutdown_minutes: options
+                .auto_shutdown_minutes
+                .or(Some(self.auto_shutdown_minutes)),
+            legacy_routes: options.legacy_routes.or(Some(self.legacy_routes)),
+            network: options.network.or_else(|| Some(self.network.clone())),
+        }
+    }
+
+    /// Whether going from `previous` to these settings needs the server
+    /// restarted: they change how it listens or what it advertises
+    pub fn needs_restart(&self, previous: &Self) -> bool {
+        self.device_id != previous.device_id
+            || self.device_name != previous.device_name
+            || self.port != previous.port
+            || self.auto_shutdown_minutes != previous.auto_shutdown_minutes
+            || self.network != previous.network
+            || self.legacy_routes != previous.legacy_routes
+    }
+}
+
+/// Copy a settings file from a newer version aside, unless already done
+fn backup_newer(path: &Path) -> Result<(), String> {
+    #[derive(Deserialize)]
+    struct Versioned {
+        version: u32,
+    }
+
+    let Ok(content) = std::fs::read_to_string(path) else {
+        return Ok(());
+    };
+    let version = match serde_json::from_str::<Versioned>(&content) {
+        Ok(file) if file.version > SETTINGS_VERSION => file.version,
+        _ => return Ok(()),
+    };
+    let backup = path.with_file_name(format!("sync_settings.v{}.json", version));
+    if backup.exists() {
+        return Ok(());
+    }
+    log::warn!(
+        "Keeping {} from a newer version as {}",
+        SETTINGS_FILE,
+        backup.display()
+    );
+    std::fs::copy(path, &backup)
+        .map(|_| ())
+        .map_err(|e| format!("Failed to back up {}: {}", SETTINGS_FILE, e))
+}
+
+// ============================================================================
+// Store
+// ============================================================================
+
+/// The settings in memory, saved on every change
+pub struct SettingsStore {
+    config_dir: PathBuf,
+    settings: Mutex<SyncSettings>,
+}
+
+impl SettingsStore {
+    pub fn new(config_dir: PathBuf) -> Self {
+        let settings = SyncSettings::load(&config_dir).unwrap_or_default();
+        Self {
+            config_dir,
+            settings: Mutex::new(settings),
+        }
+    }
+
+    pub fn get(&self) -> Result<SyncSettings, SyncCommandError> {
+        Ok(self.settings.lock()?.clone())
+    }
+
+    /// Validate and save `settings`, returning the ones they replace
+    pub fn replace(&self, mut settings: SyncSettings) -> Result<SyncSettings, SyncCommandError> {
+        settings.validate()?;
+        settings.version = SETTINGS_VERSION;
+
+        let mut current = self.settings.lock()?;
+        settings
+            .save(&self.config_dir)
+            .map_err(|detail| SyncCommandError::Storage {
+                operation: "save_sync_settings",
+                detail,
+            })?;
+        Ok(std::mem::replace(&mut *current, settings))
+    }
+
+    /// Fail with `TransportDisabled` unless `transport` is switched on
+    pub fn require(&self, transport: Transport) -> Result<(), SyncCommandError> {
+        if self.get()?.transports.enabled(transport) {
+            Ok(())
+        } else {
+            Err(SyncCommandError::TransportDisabled { transport })
+        }
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_save_and_versioning() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SettingsStore::new(dir.path().to_path_buf());
+        assert_eq!(store.get().unwrap(), SyncSettings::default());
+
+        let settings = SyncSettings {
+            version: 0,
+            device_name: Some("Office PC".to_string()),
+            port: 8731,
+            log_level: LogLevel::Debug,
+            transports: Transports {
+                relay: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let previous = store.replace(settings.clone()).unwrap();
+        assert_eq!(previous, SyncSettings::default());
+
+        let reloaded = SyncSettings::load(dir.path()).unwrap();
+        assert_eq!(reloaded.version, SETTINGS_VERSION);
+        assert_eq!(reloaded.port, 8731);
+        assert_eq!(reloaded.log_level, LogLevel::Debug);
+        assert!(matches!(
+            SettingsStore::new(dir.path().to_path_buf()).require(Transport::Relay),
+            Err(SyncCommandError::TransportDisabled {
+                transport: Transport::Relay
+            })
+        ));
+
+        // Missing fields take their defaults; newer files are not misread
+        std::fs::write(dir.path().join(SETTINGS_FILE), r#"{"port": 9000}"#).unwrap();
+        let partial = SyncSettings::load(dir.path()).unwrap();
+        assert_eq!(partial.port, 9000);
+        assert_eq!(partial.auto_shutdown_minutes, 30);
+        let newer = r#"{"version": 99, "port": 9100}"#;
+        std::fs::write(dir.path().join(SETTINGS_FILE), newer).unwrap();
+        assert!(SyncSettings::load(dir.path()).is_none());
+
+        // ...and kept aside before this version overwrites them
+        let store = SettingsStore::new(dir.path().to_path_buf());
+        store.replace(SyncSettings::default()).unwrap();
+        let backup = dir.path().join("sync_settings.v99.json");
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), newer);
+        assert_eq!(
+            SyncSettings::load(dir.path()).unwrap().version,
+            SETTINGS_VERSION
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SettingsStore::new(dir.path().to_path_buf());
+
+        let cases = [
+            (
+                SyncSettings {
+                    port: 80,
+                    ..Default::default()
+                },
+                "port",
+            ),
+            (
+                SyncSettings {
+                    auto_shutdown_minutes: 2000,
+                    ..Default::default()
+                },
+                "autoShutdownMinutes",
+            ),
+            (
+                SyncSettings {
+                    device_name: Some("  ".to_string()),
+                    ..Default::default()
+                },
+                "deviceName",
+            ),
+            (
+                SyncSettings {
+                    network: NetworkConfig {
+                        allowed_peers: Vec::new(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                "network.allowed_peers",
+            ),
+        ];
+        for (settings, expected) in cases {
+            match store.replace(settings) {
+                Err(SyncCommandError::InvalidSetting { field, .. }) => assert_eq!(field, expected),
+                other => panic!("expected invalid {}, got {:?}", expected, other),
+            }
+        }
+        // Nothing invalid was saved
+        assert!(SyncSettings::load(dir.path()).is_none());
+    }
+
+    #[test]
+    fn test_start_options_and_restart() {
+        let settings = SyncSettings {
+            device_id: Some("desktop-1".to_string()),
+            port: 8731,
+            ..Default::default()
+        };
+        let options = settings.start_options(ServerStartOptions {
+            device_name: Some("Laptop".to_string()),
+            port: Some(9000),
+            ..Default::default()
+        });
+        assert_eq!(options.device_id.as_deref(), Some("desktop-1"));
+        assert_eq!(options.device_name.as_deref(), Some("Laptop"));
+        assert_eq!(options.port, Some(9000));
+        assert_eq!(options.auto_shutdown_minutes, Some(30));
+
+        let quieter = SyncSettings {
+            log_level: LogLevel::Warn,
+            transports: Transports {
+                mdns: false,
+                ..Default::default()
+            },
+            ..settings.clone()
+        };
+        assert!(!quieter.needs_restart(&settings));
+        let moved = SyncSettings {
+            port: 9100,
+            ..settings.clone()
+        };
+        assert!(moved.needs_restart(&settings));
+    }
+}
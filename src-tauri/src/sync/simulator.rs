@@ -0,0 +1,824 @@
+//! Sync Simulator
+//!
+//! Deterministic convergence harness. Runs a `SyncServer` in-process (no app
+//! handle) and drives a desktop replica and a number of simulated phones
+//! through a seeded random schedule of edits, pushes, pulls and restarts, with
+//! injected network failures and skewed phone clocks. Afterwards the network
+//! heals and everyone syncs until quiet, and the run checks that:
+//! - every replica holds the same ops, with the same contents
+//! - every replica orders them the same way by HLC
+//! - every op's HLC is after every op its device had seen when creating it
+//!
+//! Requests go over real HTTP one at a time and every random choice comes
+//! from the seed, so a seed always replays the same run. Failures name the
+//! seed; rerun it with `SimConfig { seed, ..Default::default() }`.
+//!
+//! Phones keep their ops, outbox and clock durably, but save their pull cursor
+//! only now and then, so a restart re-pulls ops they already have. Some
+//! phones negotiate CBOR, others stay on protocol 1 JSON, and some pull by
+//! HLC alone (no `since_seq`) like apps from before seq cursors, which also
+//! acknowledges delivery by HLC. Those never see ops logged after their
+//! cursor passed their HLC; the run reports those in
+//! `SimReport::legacy_cursor_missed` and requires every other op. The desktop writes
+//! its edits to the op log and applies the inbox, like the app does.
+
+use super::client::SyncClient;
+use super::inbox::PendingOpsStore;
+use super::oplog::OpLog;
+use super::persistence::{PairedDevice, PairedDeviceStatus};
+use super::ratelimit::RateLimits;
+use super::server::{ServerOptions, SyncServer};
+use super::status::SyncStatusTracker;
+use super::supervisor::TaskSupervisor;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const DESKTOP_ID: &str = "desktop-1";
+/// Wall clock at the start of every run (ms)
+const START_MS: i64 = 1_700_000_000_000;
+/// Largest gap between two scheduled actions (ms)
+const MAX_STEP_MS: i64 = 2_000;
+/// Ops edit this many entities, so concurrent edits collide
+const ENTITIES: u32 = 4;
+/// Small pages, so pulls exercise paging
+const PULL_PAGE: usize = 7;
+const MAX_HEAL_ROUNDS: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub seed: u64,
+    pub phones: usize,
+    /// Scheduled actions before the network heals
+    pub steps: usize,
+    /// Chance that a request is lost before reaching the server
+    pub drop_request_rate: f64,
+    /// Chance that the server handles a request but the response is lost
+    pub drop_response_rate: f64,
+    /// Phone clocks are off by up to this much either way (ms)
+    pub max_skew_ms: i64,
+    /// Chance that a phone's action is a restart
+    pub phone_restart_rate: f64,
+    /// Chance per step that the server restarts
+    pub server_restart_rate: f64,
+    /// How many of the phones (the last ones) pull by HLC alone
+    pub legacy_cursor_phones: usize,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            seed: 1,
+            phones: 3,
+            steps: 200,
+            drop_request_rate: 0.1,
+            drop_response_rate: 0.1,
+            max_skew_ms: 5 * 60 * 1000,
+            phone_restart_rate: 0.05,
+            server_restart_rate: 0.01,
+            legacy_cursor_phones: 1,
+        }
+    }
+}
+
+/// What happened during a run; equal for equal configs
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SimReport {
+    pub ops_created: usize,
+    pub pushes: usize,
+    pub pulls: usize,
+    pub dropped_requests: usize,
+    pub dropped_responses: usize,
+    pub phone_restarts: usize,
+    pub server_restarts: usize,
+    /// Ops pulled by a phone that already had them
+    pub redelivered: usize,
+    /// Ops each legacy-cursor phone never got, because they were logged after
+    /// its HLC cursor had passed them
+    pub legacy_cursor_missed: BTreeMap<String, Vec<String>>,
+}
+
+// ============================================================================
+// Hybrid logical clock
+// ============================================================================
+
+/// Hybrid logical clock with the rules and serialization of the frontend's
+/// (`src/sync/core/hlc.ts`): base-36 timestamp (11 chars), base-36 counter
+/// (5 chars) and node id (8 chars), so serialized HLCs sort by string
+#[derive(Debug, Clone)]
+struct Hlc {
+    node: String,
+    ts: i64,
+    counter: i64,
+}
+
+impl Hlc {
+    fn new(device_id: &str, now: i64) -> Self {
+        let mut node: String = device_id.chars().take(8).collect();
+        while node.len() < 8 {
+            node.push('0');
+        }
+        Self {
+            node,
+            ts: now,
+            counter: 0,
+        }
+    }
+
+    /// Timestamp for a local event
+    fn tick(&mut self, now: i64) -> String {
+        if now > self.ts {
+            self.ts = now;
+            self.counter = 0;
+        } else {
+            self.counter += 1;
+        }
+        self.to_string()
+    }
+
+    /// Move past a timestamp received from another device
+    fn receive(&mut self, remote: &str, now: i64) {
+        let Some((ts, counter)) = parse_hlc(remote) else {
+            return;
+        };
+        if now > self.ts && now > ts {
+            self.ts = now;
+            self.counter = 0;
+        } else if self.ts == ts {
+            self.counter = self.counter.max(counter) + 1;
+        } else if ts > self.ts {
+            self.ts = ts;
+            self.counter = counter + 1;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl std::fmt::Display for Hlc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:0>11}-{:0>5}-{}",
+            base36(self.ts),
+            base36(self.counter),
+            self.node
+        )
+    }
+}
+
+fn base36(mut n: i64) -> String {
+    const DIGITS: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    let mut digits = Vec::new();
+    loop {
+        digits.push(DIGITS[(n % 36) as usize]);
+        n /= 36;
+        if n == 0 {
+            break;
+        }
+    }
+    digits.reverse();
+    String::from_utf8(digits).unwrap_or_default()
+}
+
+/// (timestamp, counter) of a serialized HLC
+fn parse_hlc(hlc: &str) -> Option<(i64, i64)> {
+    let mut parts = hlc.splitn(3, '-');
+    let ts = i64::from_str_radix(parts.next()?, 36).ok()?;
+    let counter = i64::from_str_radix(parts.next()?, 36).ok()?;
+    Some((ts, counter))
+}
+
+fn op_hlc(op: &Value) -> &str {
+    op["hlc"].as_str().unwrap_or_default()
+}
+
+// ============================================================================
+// Devices
+// ============================================================================
+
+/// Ops a device holds, by id
+type Replica = BTreeMap<String, Value>;
+
+/// Newest HLC in a replica ("" if empty)
+fn newest_hlc(ops: &Replica) -> String {
+    ops.values()
+        .map(op_hlc)
+        .max()
+        .unwrap_or_default()
+        .to_string()
+}
+
+struct Desktop {
+    hlc: Hlc,
+    ops: Replica,
+    created: usize,
+}
+
+struct Phone {
+    id: String,
+    token: String,
+    skew_ms: i64,
+    /// Negotiates protocol 2 (CBOR, compression); otherwise legacy JSON
+    negotiates: bool,
+    /// Pulls by `since_hlc` alone; otherwise by `since_seq`
+    legacy_cursor: bool,
+    hlc: Hlc,
+    ops: Replica,
+    /// Ops created here that the server hasn't acknowledged
+    outbox: Vec<Value>,
+    cursor: u64,
+    cursor_hlc: String,
+    /// Ops logged after `cursor_hlc` had passed them; a legacy cursor may
+    /// never pull these
+    passed: BTreeSet<String>,
+    /// Cursor as last saved; a restart goes back to it
+    saved_cursor: (u64, String),
+    /// Dropped on restart (and server restart), reconnected on demand
+    client: Option<SyncClient>,
+    created: usize,
+}
+
+/// The desktop's server and the stores behind it
+struct Hub {
+    config_dir: PathBuf,
+    server: SyncServer,
+    tasks: TaskSupervisor,
+    url: String,
+    pending_ops: Arc<PendingOpsStore>,
+    op_log: Arc<OpLog>,
+}
+
+impl Hub {
+    async fn start(config_dir: &Path, port: u16) -> Self {
+        let pending_ops = PendingOpsStore::new(config_dir.to_path_buf()).unwrap();
+        let op_log = OpLog::new(config_dir.to_path_buf()).unwrap();
+        // Every phone connects from 127.0.0.1; rate limits aren't under test
+        let limits = RateLimits {
+            ip_burst: 1_000_000,
+            ip_refill_per_sec: 1_000_000.0,
+            device_burst: 1_000_000,
+            device_refill_per_sec: 1_000_000.0,
+        };
+        let tasks = TaskSupervisor::default();
+        let options = ServerOptions {
+            port,
+            limits,
+            ..ServerOptions::new(
+                DESKTOP_ID,
+                "Desktop",
+                config_dir.to_path_buf(),
+                Arc::clone(&pending_ops),
+                Arc::clone(&op_log),
+                SyncStatusTracker::new(Arc::clone(&pending_ops), Arc::clone(&op_log), None),
+            )
+        };
+        let (server, port) = SyncServer::start(options, &tasks).await.unwrap();
+        Self {
+            config_dir: config_dir.to_path_buf(),
+            server,
+            tasks,
+            url: format!("http://127.0.0.1:{}", port),
+            pending_ops,
+            op_log,
+        }
+    }
+
+    /// Stop the server and start it again from what is on disk
+    async fn restart(&mut self) {
+        self.server.stop();
+        self.tasks.shutdown().await;
+        let port = self.server.port();
+        *self = Self::start(&self.config_dir.clone(), port).await;
+    }
+}
+
+/// Free port to start from, so parallel runs don't contend for 4242
+fn free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+// ============================================================================
+// Simulation
+// ============================================================================
+
+enum Fault {
+    None,
+    DropRequest,
+    DropResponse,
+}
+
+pub struct Simulation {
+    config: SimConfig,
+    rng: StdRng,
+    /// Simulated wall clock (ms)
+    now: i64,
+    /// No faults once the network has healed
+    healed: bool,
+    hub: Hub,
+    desktop: Desktop,
+    phones: Vec<Phone>,
+    /// Newest HLC the creating device had seen, per op id
+    seen_before: BTreeMap<String, String>,
+    report: SimReport,
+}
+
+impl Simulation {
+    /// Start the server (state under `dir`) and pair the phones
+    pub async fn new(config: SimConfig, dir: &Path) -> Self {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let hub = Hub::start(dir, free_port()).await;
+
+        let mut phones = Vec::new();
+        for i in 0..config.phones {
+            let id = format!("phone-{:02}", i);
+            let token = format!("token-{}", i);
+            hub.server
+                .persistence
+                .add_device(PairedDevice {
+                    id: id.clone(),
+                    name: format!("Phone {}", i),
+                    token: token.clone(),
+                    paired_at: chrono::Utc::now().to_rfc3339(),
+                    last_sync_at: None,
+                    status: PairedDeviceStatus::Active,
+                    acked_seq: 0,
+                    relay_seq: 0,
+                    relay_acked_seq: 0,
+                    relay_deposited_at: None,
+                    pairing_secret: None,
+                })
+                .await
+                .unwrap();
+            let skew_ms = rng.gen_range(-config.max_skew_ms..=config.max_skew_ms);
+            phones.push(Phone {
+                hlc: Hlc::new(&id, START_MS + skew_ms),
+                id,
+                token,
+                skew_ms,
+                negotiates: i % 2 == 0,
+                legacy_cursor: i + config.legacy_cursor_phones >= config.phones,
+                ops: Replica::new(),
+                outbox: Vec::new(),
+                cursor: 0,
+                cursor_hlc: String::new(),
+                passed: BTreeSet::new(),
+                saved_cursor: (0, String::new()),
+                client: None,
+                created: 0,
+            });
+        }
+
+        Self {
+            config,
+            rng,
+            now: START_MS,
+            healed: false,
+            hub,
+            desktop: Desktop {
+                hlc: Hlc::new(DESKTOP_ID, START_MS),
+                ops: Replica::new(),
+                created: 0,
+            },
+            phones,
+            seen_before: BTreeMap::new(),
+            report: SimReport::default(),
+        }
+    }
+
+    /// Run the schedule, heal the network, sync until quiet and check the
+    /// replicas. Panics (naming the seed) if they don't converge.
+    pub async fn run(mut self) -> SimReport {
+        for _ in 0..self.config.steps {
+            self.now += self.rng.gen_range(0..MAX_STEP_MS);
+            if self.rng.gen_bool(self.config.server_restart_rate) {
+                self.hub.restart().await;
+                self.report.server_restarts += 1;
+                // Pooled connections still reach the old server
+                for phone in &mut self.phones {
+                    phone.client = None;
+                }
+                continue;
+            }
+
+            let actor = self.rng.gen_range(0..=self.phones.len());
+            if actor == self.phones.len() {
+                if self.rng.gen_bool(0.6) {
+                    self.desktop_edit().await;
+                } else {
+                    self.desktop_apply_inbox().await;
+                }
+                continue;
+            }
+            let action: f64 = self.rng.gen();
+            if action < self.config.phone_restart_rate {
+                self.restart_phone(actor);
+            } else if action < 0.5 {
+                self.phone_edit(actor);
+            } else {
+                self.phone_sync(actor).await;
+            }
+        }
+
+        self.healed = true;
+        for _ in 0..MAX_HEAL_ROUNDS {
+            self.desktop_apply_inbox().await;
+            for i in 0..self.phones.len() {
+                self.phone_sync(i).await;
+            }
+            if self.converged() {
+                break;
+            }
+        }
+        self.check();
+        self.report
+    }
+
+    fn fault(&mut self) -> Fault {
+        if self.healed {
+            return Fault::None;
+        }
+        if self.rng.gen_bool(self.config.drop_request_rate) {
+            self.report.dropped_requests += 1;
+            Fault::DropRequest
+        } else if self.rng.gen_bool(self.config.drop_response_rate) {
+            self.report.dropped_responses += 1;
+            Fault::DropResponse
+        } else {
+            Fault::None
+        }
+    }
+
+    /// A field update on one of a few shared entities
+    fn new_op(&mut self, device_id: &str, n: usize, hlc: String) -> Value {
+        json!({
+            "id": format!("{}-op-{}", device_id, n),
+            "hlc": hlc,
+            "entityType": "client",
+            "entityId": format!("client-{}", self.rng.gen_range(0..ENTITIES)),
+            "opType": "update",
+            "field": "name",
+            "value": format!("{} edit {}", device_id, n),
+            "createdBy": device_id,
+        })
+    }
+
+    async fn desktop_edit(&mut self) {
+        let seen = newest_hlc(&self.desktop.ops);
+        let hlc = self.desktop.hlc.tick(self.now);
+        self.desktop.created += 1;
+        let op = self.new_op(DESKTOP_ID, self.desktop.created, hlc);
+        let id = op["id"].as_str().unwrap_or_default().to_string();
+        self.hub.op_log.append(op.clone(), None).await.unwrap();
+        self.logged(std::slice::from_ref(&op));
+        self.seen_before.insert(id.clone(), seen);
+        self.desktop.ops.insert(id, op);
+        self.report.ops_created += 1;
+    }
+
+    async fn desktop_apply_inbox(&mut self) {
+        let page = self.hub.pending_ops.page(0, None).await;
+        for pending in page.ops {
+            let id = pending.op["id"].as_str().unwrap_or_default().to_string();
+            self.desktop.hlc.receive(op_hlc(&pending.op), self.now);
+            self.desktop.ops.insert(id, pending.op);
+        }
+        if let Some(last_seq) = page.last_seq {
+            self.hub.pending_ops.ack(last_seq).await.unwrap();
+        }
+    }
+
+    fn phone_edit(&mut self, i: usize) {
+        let now = self.now + self.phones[i].skew_ms;
+        let phone = &mut self.phones[i];
+        let seen = newest_hlc(&phone.ops);
+        let hlc = phone.hlc.tick(now);
+        phone.created += 1;
+        let (id, n) = (phone.id.clone(), phone.created);
+
+        let op = self.new_op(&id, n, hlc);
+        let op_id = op["id"].as_str().unwrap_or_default().to_string();
+        let phone = &mut self.phones[i];
+        phone.outbox.push(op.clone());
+        phone.ops.insert(op_id.clone(), op);
+        self.seen_before.insert(op_id, seen);
+        self.report.ops_created += 1;
+    }
+
+    /// Forget the connection and unsaved cursor progress
+    fn restart_phone(&mut self, i: usize) {
+        let phone = &mut self.phones[i];
+        phone.client = None;
+        (phone.cursor, phone.cursor_hlc) = phone.saved_cursor.clone();
+        self.report.phone_restarts += 1;
+    }
+
+    async fn connect(&mut self, i: usize) -> bool {
+        if self.phones[i].client.is_some() {
+            return true;
+        }
+        let phone = &self.phones[i];
+        let mut client = SyncClient::new(&self.hub.url, &phone.id, Some(&phone.token));
+        if phone.negotiates {
+            if let Fault::DropRequest | Fault::DropResponse = self.fault() {
+                return false;
+            }
+            if let Err(e) = client.negotiate().await {
+                panic!("seed {}: hello failed: {}", self.config.seed, e);
+            }
+        }
+        self.phones[i].client = Some(client);
+        true
+    }
+
+    /// Push the outbox, then pull until there is nothing more
+    async fn phone_sync(&mut self, i: usize) {
+        if !self.connect(i).await {
+            return;
+        }
+        if !self.phones[i].outbox.is_empty() && !self.phone_push(i).await {
+            return;
+        }
+        while self.phone_pull(i).await {}
+    }
+
+    /// Whether the push went through and was acknowledged
+    async fn phone_push(&mut self, i: usize) -> bool {
+        let fault = self.fault();
+        if let Fault::DropRequest = fault {
+            return false;
+        }
+        let phone = &self.phones[i];
+        let Some(client) = &phone.client else {
+            return false;
+        };
+        let ops = phone.outbox.clone();
+        let response = client.push(ops.clone()).await;
+        self.report.pushes += 1;
+        self.logged(&ops);
+        let response =
+            response.unwrap_or_else(|e| panic!("seed {}: push failed: {}", self.config.seed, e));
+        if let Fault::DropResponse = fault {
+            return false;
+        }
+        assert!(
+            response.rejected.is_empty(),
+            "seed {}: ops rejected: {:?}",
+            self.config.seed,
+            response.rejected
+        );
+        self.phones[i].outbox.clear();
+        true
+    }
+
+    /// Pull one page; whether there is more to pull
+    async fn phone_pull(&mut self, i: usize) -> bool {
+        let fault = self.fault();
+        if let Fault::DropRequest = fault {
+            return false;
+        }
+        let phone = &self.phones[i];
+        let Some(client) = &phone.client else {
+            return false;
+        };
+        let since_seq = (!phone.legacy_cursor).then_some(phone.cursor);
+        let page = client
+            .pull(since_seq, &phone.cursor_hlc, Some(PULL_PAGE))
+            .await;
+        self.report.pulls += 1;
+        let page = page.unwrap_or_else(|e| panic!("seed {}: pull failed: {}", self.config.seed, e));
+        if let Fault::DropResponse = fault {
+            return false;
+        }
+
+        let now = self.now + self.phones[i].skew_ms;
+        let save_cursor = self.rng.gen_bool(0.5);
+        let phone = &mut self.phones[i];
+        for op in page.operations {
+            let id = op["id"].as_str().unwrap_or_default().to_string();
+            phone.hlc.receive(op_hlc(&op), now);
+            if phone.ops.insert(id, op).is_some() {
+                self.report.redelivered += 1;
+            }
+        }
+        if let Some(next_seq) = page.next_seq {
+            phone.cursor = next_seq as u64;
+        }
+        if let Some(next_hlc) = page.next_hlc {
+            phone.cursor_hlc = next_hlc;
+        }
+        if save_cursor {
+            phone.saved_cursor = (phone.cursor, phone.cursor_hlc.clone());
+        }
+        page.has_more
+    }
+
+    /// Note ops the hub just logged that legacy cursors have already passed
+    fn logged(&mut self, ops: &[Value]) {
+        for phone in self.phones.iter_mut().filter(|p| p.legacy_cursor) {
+            for op in ops
+                .iter()
+                .filter(|op| op_hlc(op) <= phone.cursor_hlc.as_str())
+            {
+                let id = op["id"].as_str().unwrap_or_default().to_string();
+                phone.passed.insert(id);
+            }
+        }
+    }
+
+    /// The desktop's ops that `phone` doesn't hold
+    fn missing(&self, phone: &Phone) -> Vec<String> {
+        self.desktop
+            .ops
+            .keys()
+            .filter(|id| !phone.ops.contains_key(*id))
+            .cloned()
+            .collect()
+    }
+
+    /// Every phone holds the desktop's copy of every op, short of those a
+    /// legacy cursor passed before they were logged, and nothing else
+    fn converged(&self) -> bool {
+        self.phones.iter().all(|p| {
+            p.outbox.is_empty()
+                && p.ops
+                    .iter()
+                    .all(|(id, op)| self.desktop.ops.get(id) == Some(op))
+                && self.missing(p).iter().all(|id| p.passed.contains(id))
+        })
+    }
+
+    fn check(&mut self) {
+        let seed = self.config.seed;
+        assert_eq!(
+            self.desktop.ops.len(),
+            self.report.ops_created,
+            "seed {}: desktop is missing ops",
+            seed
+        );
+        let order = |ops: &Replica| -> Vec<String> {
+            let mut ops: Vec<(&str, &String)> =
+                ops.iter().map(|(id, op)| (op_hlc(op), id)).collect();
+            ops.sort();
+            ops.into_iter().map(|(_, id)| id.clone()).collect()
+        };
+        let mut missed = BTreeMap::new();
+        for phone in &self.phones {
+            assert!(
+                phone.outbox.is_empty(),
+                "seed {}: {} has unsent ops",
+                seed,
+                phone.id
+            );
+            let (passed, missing): (Vec<String>, Vec<String>) = self
+                .missing(phone)
+                .into_iter()
+                .partition(|id| phone.passed.contains(id));
+            assert!(
+                missing.is_empty(),
+                "seed {}: {} is missing {:?}",
+                seed,
+                phone.id,
+                missing
+            );
+            if !passed.is_empty() {
+                missed.insert(phone.id.clone(), passed);
+            }
+            let expected: Replica = self
+                .desktop
+                .ops
+                .iter()
+                .filter(|(id, _)| phone.ops.contains_key(*id))
+                .map(|(id, op)| (id.clone(), op.clone()))
+                .collect();
+            assert_eq!(phone.ops, expected, "seed {}: {} diverged", seed, phone.id);
+            assert_eq!(
+                order(&phone.ops),
+                order(&expected),
+                "seed {}: {} orders ops differently",
+                seed,
+                phone.id
+            );
+        }
+
+        let mut hlcs: Vec<&str> = self.desktop.ops.values().map(op_hlc).collect();
+        hlcs.sort();
+        hlcs.dedup();
+        assert_eq!(
+            hlcs.len(),
+            self.desktop.ops.len(),
+            "seed {}: duplicate HLCs",
+            seed
+        );
+        for (id, op) in &self.desktop.ops {
+            let seen = &self.seen_before[id];
+            assert!(
+                op_hlc(op) > seen.as_str(),
+                "seed {}: {} ({}) is not after {}, which its device had seen",
+                seed,
+                id,
+                op_hlc(op),
+                seen
+            );
+        }
+        self.report.legacy_cursor_missed = missed;
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    async fn simulate(config: SimConfig) -> SimReport {
+        let dir = tempdir().unwrap();
+        Simulation::new(config, dir.path()).await.run().await
+    }
+
+    #[test]
+    fn test_hlc_matches_frontend_format() {
+        let mut hlc = Hlc::new("phone-01", 0);
+        assert_eq!(hlc.tick(1_700_000_000_000), "000loyw3v28-00000-phone-01");
+        assert_eq!(hlc.tick(1_700_000_000_000), "000loyw3v28-00001-phone-01");
+        assert_eq!(
+            parse_hlc("000loyw3v28-00001-phone-01"),
+            Some((1_700_000_000_000, 1))
+        );
+
+        // A clock that is behind still moves past what it receives
+        let mut behind = Hlc::new("desktop-1", 0);
+        behind.receive("000loyw3v28-00001-phone-01", 1_000);
+        assert!(behind.tick(2_000).as_str() > "000loyw3v28-00001-phone-01");
+    }
+
+    #[tokio::test]
+    async fn test_devices_converge_under_faults() {
+        for seed in 1..=6 {
+            let report = simulate(SimConfig {
+                seed,
+                ..Default::default()
+            })
+            .await;
+            assert!(report.ops_created > 0);
+            assert!(report.dropped_requests + report.dropped_responses > 0);
+            // Only the last phone pulls by HLC alone
+            assert!(report
+                .legacy_cursor_missed
+                .keys()
+                .all(|id| id == "phone-02"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_seq_cursors_miss_nothing() {
+        for seed in 1..=3 {
+            let report = simulate(SimConfig {
+                seed,
+                max_skew_ms: 24 * 60 * 60 * 1000,
+                legacy_cursor_phones: 0,
+                ..Default::default()
+            })
+            .await;
+            assert!(report.legacy_cursor_missed.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_devices_converge_across_restarts_and_skew() {
+        let report = simulate(SimConfig {
+            seed: 42,
+            phones: 4,
+            steps: 300,
+            max_skew_ms: 24 * 60 * 60 * 1000,
+            phone_restart_rate: 0.15,
+            server_restart_rate: 0.05,
+            ..Default::default()
+        })
+        .await;
+        assert!(report.server_restarts > 0);
+        assert!(report.phone_restarts > 0);
+        // A day of skew leaves the legacy cursor behind ops logged late
+        assert_eq!(
+            report.legacy_cursor_missed.keys().collect::<Vec<_>>(),
+            vec!["phone-03"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_same_seed_replays_same_run() {
+        let config = SimConfig {
+            seed: 7,
+            steps: 120,
+            ..Default::default()
+        };
+        assert_eq!(simulate(config.clone()).await, simulate(config).await);
+    }
+}
@@ -0,0 +1,419 @@
+//! Sync Status
+//!
+//! One view of what the sync subsystem is doing. Commands and the server
+//! report transitions to the `SyncStatusTracker` held in `SyncState`; every
+//! change emits a `sync:status_changed` event carrying the full snapshot, and
+//! `get_sync_status` returns the same snapshot on demand.
+
+use super::inbox::PendingOpsStore;
+use super::oplog::OpLog;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+pub const STATUS_EVENT: &str = "sync:status_changed";
+
+/// Devices that pulled or pushed this recently count as connected
+const CONNECTED_WINDOW_SECONDS: i64 = 300;
+
+// ============================================================================
+// Types
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SyncPhase {
+    Stopped,
+    Listening,
+    Pairing {
+        #[serde(rename = "pairingId")]
+        pairing_id: String,
+    },
+    Syncing {
+        #[serde(rename = "deviceId")]
+        device_id: String,
+        #[serde(rename = "deviceName")]
+        device_name: Option<String>,
+    },
+    Error {
+        message: String,
+    },
+    /// Stopped after the idle timeout
+    AutoStopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectedDevice {
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// Snapshot sent with every status event
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    pub phase: SyncPhase,
+    pub port: Option<u16>,
+    /// When the server stops if nothing connects (RFC 3339)
+    pub expires_at: Option<String>,
+    pub connected_devices: Vec<ConnectedDevice>,
+    /// Ops received but not yet acknowledged by the frontend
+    pub pending_inbound: usize,
+    /// Ops kept until every paired device has pulled them
+    pub pending_outbound: usize,
+    pub last_error: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+struct Tracked {
+    phase: SyncPhase,
+    port: Option<u16>,
+    /// How long the server may sit idle before it stops itself
+    idle_timeout: Option<chrono::Duration>,
+    /// Last request the server handled
+    last_activity: DateTime<Utc>,
+    devices: HashMap<String, ConnectedDevice>,
+    last_error: Option<String>,
+    changed_at: DateTime<Utc>,
+}
+
+// ============================================================================
+// SyncStatusTracker
+// ============================================================================
+
+pub struct SyncStatusTracker {
+    tracked: Mutex<Tracked>,
+    pending_ops: Arc<PendingOpsStore>,
+    op_log: Arc<OpLog>,
+    /// None when there is no UI to notify (daemon, tests)
+    app_handle: Option<AppHandle>,
+}
+
+impl SyncStatusTracker {
+    pub fn new(
+        pending_ops: Arc<PendingOpsStore>,
+        op_log: Arc<OpLog>,
+        app_handle: Option<AppHandle>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            tracked: Mutex::new(Tracked {
+                phase: SyncPhase::Stopped,
+                port: None,
+                idle_timeout: None,
+                last_activity: Utc::now(),
+                devices: HashMap::new(),
+                last_error: None,
+                changed_at: Utc::now(),
+            }),
+            pending_ops,
+            op_log,
+            app_handle,
+        })
+    }
+
+    pub async fn snapshot(&self) -> SyncStatus {
+        let now = Utc::now();
+        let mut status = match self.tracked.lock() {
+            Ok(tracked) => {
+                let cutoff = now - chrono::Duration::seconds(CONNECTED_WINDOW_SECONDS);
+                let mut connected_devices: Vec<ConnectedDevice> = tracked
+                    .devices
+                    .values()
+                    .filter(|d| d.last_seen_at >= cutoff)
+                    .cloned()
+                    .collect();
+                connected_devices.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+                SyncStatus {
+                    phase: tracked.phase.clone(),
+                    port: tracked.port,
+                    expires_at: tracked
+                        .idle_timeout
+                        .map(|timeout| (tracked.last_activity + timeout).to_rfc3339()),
+                    connected_devices,
+                    pending_inbound: 0,
+                    pending_outbound: 0,
+                    last_error: tracked.last_error.clone(),
+                    changed_at: tracked.changed_at,
+                }
+            }
+            Err(e) => SyncStatus {
+                phase: SyncPhase::Error {
+                    message: e.to_string(),
+                },
+                port: None,
+                expires_at: None,
+                connected_devices: Vec::new(),
+                pending_inbound: 0,
+                pending_outbound: 0,
+                last_error: Some(e.to_string()),
+                changed_at: now,
+            },
+        };
+        status.pending_inbound = self.pending_ops.count().await;
+        status.pending_outbound = self.op_log.count().await;
+        status
+    }
+
+    /// The server is accepting connections, and stops after `idle_timeout`
+    /// without requests (never if None)
+    pub async fn listening(&self, port: u16, idle_timeout: Option<Duration>) {
+        self.transition(|t| {
+            t.phase = SyncPhase::Listening;
+            t.port = Some(port);
+            t.idle_timeout = idle_timeout.and_then(|d| chrono::Duration::from_std(d).ok());
+            t.last_activity = Utc::now();
+            t.devices.clear();
+            true
+        })
+        .await;
+    }
+
+    /// The server handled a request, pushing back its idle stop. Doesn't
+    /// emit an event; the next snapshot has the new expiry.
+    pub fn activity(&self) {
+        if let Ok(mut tracked) = self.tracked.lock() {
+            tracked.last_activity = Utc::now();
+        }
+    }
+
+    pub async fn stopped(&self) {
+        self.transition(|t| t.stop(SyncPhase::Stopped)).await;
+    }
+
+    /// The server stopped itself after the idle timeout
+    pub async fn auto_stopped(&self) {
+        self.transition(|t| t.stop(SyncPhase::AutoStopped)).await;
+    }
+
+    /// The server could not start or stopped on an error
+    pub async fn failed(&self, message: impl Into<String>) {
+        let message = message.into();
+        self.transition(|t| {
+            t.stop(SyncPhase::Error {
+                message: message.clone(),
+            });
+            t.last_error = Some(message);
+            true
+        })
+        .await;
+    }
+
+    /// A request failed; the server keeps running
+    pub async fn record_error(&self, message: impl Into<String>) {
+        let message = message.into();
+        self.transition(|t| {
+            t.last_error = Some(message);
+            true
+        })
+        .await;
+    }
+
+    pub async fn pairing_started(&self, pairing_id: &str) {
+        self.transition(|t| {
+            if !t.is_serving() {
+                return false;
+            }
+            t.phase = SyncPhase::Pairing {
+                pairing_id: pairing_id.to_string(),
+            };
+            true
+        })
+        .await;
+    }
+
+    /// The session was confirmed, denied, cancelled or expired
+    pub async fn pairing_ended(&self, pairing_id: &str) {
+        self.transition(|t| match &t.phase {
+            SyncPhase::Pairing { pairing_id: id } if id == pairing_id => {
+                t.phase = SyncPhase::Listening;
+                true
+            }
+            _ => false,
+        })
+        .await;
+    }
+
+    /// A device started a pull or push. Pairing stays the visible phase.
+    pub async fn sync_started(&self, device_id: &str, device_name: Option<&str>) {
+        self.transition(|t| {
+            t.seen(device_id, device_name);
+            let phase = SyncPhase::Syncing {
+                device_id: device_id.to_string(),
+                device_name: device_name.map(|n| n.to_string()),
+            };
+            match t.phase {
+                SyncPhase::Listening | SyncPhase::Syncing { .. } if t.phase != phase => {
+                    t.phase = phase;
+                    true
+                }
+                _ => false,
+            }
+        })
+        .await;
+    }
+
+    pub async fn sync_finished(&self, device_id: &str) {
+        self.transition(|t| match &t.phase {
+            SyncPhase::Syncing { device_id: id, .. } if id == device_id => {
+                t.phase = SyncPhase::Listening;
+                true
+            }
+            _ => false,
+        })
+        .await;
+    }
+
+    /// Apply `change` and emit the new snapshot if it reports a change
+    async fn transition(&self, change: impl FnOnce(&mut Tracked) -> bool) {
+        let changed = match self.tracked.lock() {
+            Ok(mut tracked) => {
+                let changed = change(&mut tracked);
+                if changed {
+                    tracked.changed_at = Utc::now();
+                }
+                changed
+            }
+            Err(_) => false,
+        };
+        if !changed {
+            return;
+        }
+
+        let Some(app) = &self.app_handle else {
+            return;
+        };
+        let status = self.snapshot().await;
+        if let Err(e) = app.emit(STATUS_EVENT, &status) {
+            log::error!("Failed to emit sync status event: {}", e);
+        }
+    }
+}
+
+impl Tracked {
+    fn is_serving(&self) -> bool {
+        matches!(
+            self.phase,
+            SyncPhase::Listening | SyncPhase::Pairing { .. } | SyncPhase::Syncing { .. }
+        )
+    }
+
+    fn stop(&mut self, phase: SyncPhase) -> bool {
+        if self.phase == phase {
+            return false;
+        }
+        self.phase = phase;
+        self.port = None;
+        self.idle_timeout = None;
+        self.devices.clear();
+        true
+    }
+
+    fn seen(&mut self, device_id: &str, device_name: Option<&str>) {
+        self.devices.insert(
+            device_id.to_string(),
+            ConnectedDevice {
+                device_id: device_id.to_string(),
+                device_name: device_name.map(|n| n.to_string()),
+                last_seen_at: Utc::now(),
+            },
+        );
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn tracker(dir: &Path) -> Arc<SyncStatusTracker> {
+        let op_log = OpLog::new(dir.to_path_buf()).unwrap();
+        SyncStatusTracker::new(PendingOpsStore::in_memory(), op_log, None)
+    }
+
+    #[tokio::test]
+    async fn test_pairing_and_syncing_return_to_listening() {
+        let dir = tempdir().unwrap();
+        let status = tracker(dir.path());
+        assert_eq!(status.snapshot().await.phase, SyncPhase::Stopped);
+
+        // Nothing happens on a server that isn't running
+        status.pairing_started("p-1").await;
+        assert_eq!(status.snapshot().await.phase, SyncPhase::Stopped);
+
+        status.listening(4242, None).await;
+        status.pairing_started("p-1").await;
+        status.sync_started("phone-1", Some("Phone")).await;
+        let snapshot = status.snapshot().await;
+        assert_eq!(
+            snapshot.phase,
+            SyncPhase::Pairing {
+                pairing_id: "p-1".to_string()
+            }
+        );
+        assert_eq!(snapshot.connected_devices[0].device_id, "phone-1");
+
+        status.pairing_ended("other").await;
+        assert!(matches!(
+            status.snapshot().await.phase,
+            SyncPhase::Pairing { .. }
+        ));
+        status.pairing_ended("p-1").await;
+        status.sync_started("phone-1", Some("Phone")).await;
+        assert!(matches!(
+            status.snapshot().await.phase,
+            SyncPhase::Syncing { .. }
+        ));
+        status.sync_finished("phone-1").await;
+        assert_eq!(status.snapshot().await.phase, SyncPhase::Listening);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_follows_activity() {
+        let dir = tempdir().unwrap();
+        let status = tracker(dir.path());
+        status.listening(4242, Some(Duration::from_secs(600))).await;
+        let expiry = |s: &SyncStatus| {
+            DateTime::parse_from_rfc3339(s.expires_at.as_deref().unwrap()).unwrap()
+        };
+        let first = expiry(&status.snapshot().await);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        status.activity();
+        assert!(expiry(&status.snapshot().await) > first);
+
+        status.listening(4242, None).await;
+        assert_eq!(status.snapshot().await.expires_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_stopping_clears_the_session() {
+        let dir = tempdir().unwrap();
+        let status = tracker(dir.path());
+        status.listening(4242, Some(Duration::from_secs(600))).await;
+        status.sync_started("phone-1", None).await;
+        status.auto_stopped().await;
+
+        let snapshot = status.snapshot().await;
+        assert_eq!(snapshot.phase, SyncPhase::AutoStopped);
+        assert_eq!((snapshot.port, snapshot.expires_at), (None, None));
+        assert!(snapshot.connected_devices.is_empty());
+
+        status.failed("address in use").await;
+        let snapshot = status.snapshot().await;
+        assert_eq!(snapshot.last_error.as_deref(), Some("address in use"));
+        assert_eq!(
+            serde_json::to_value(&snapshot.phase).unwrap(),
+            serde_json::json!({ "state": "error", "message": "address in use" })
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! Background Task Supervision
+//!
+//! Long-running tasks of the sync subsystem (the HTTP listeners, pairing
+//! session cleanup, the folder and network watchers) are spawned through a
+//! `TaskSupervisor` rather than a bare `tokio::spawn`. Tasks watch a
+//! cancellation token from `token()`; `shutdown()` cancels them all and waits
+//! for them to return, so stopping or restarting the server leaves nothing
+//! running. `health()` reports each tracked task for diagnostics.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// How long `shutdown` waits for a task before aborting it
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// Returned on its own
+    Finished,
+    /// Panicked or was aborted
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskHealth {
+    pub name: String,
+    pub state: TaskState,
+    pub started_at: DateTime<Utc>,
+}
+
+struct Task {
+    name: String,
+    started_at: DateTime<Utc>,
+    /// Set when the future returns; left empty if it panics
+    returned: Arc<OnceLock<()>>,
+    handle: JoinHandle<()>,
+}
+
+impl Task {
+    fn state(&self) -> TaskState {
+        if !self.handle.is_finished() {
+            TaskState::Running
+        } else if self.returned.get().is_some() {
+            TaskState::Finished
+        } else {
+            TaskState::Failed
+        }
+    }
+}
+
+/// Tracks spawned tasks and cancels them together
+#[derive(Default)]
+pub struct TaskSupervisor {
+    token: Mutex<CancellationToken>,
+    tasks: Mutex<Vec<Task>>,
+}
+
+impl TaskSupervisor {
+    /// Token cancelled by the next `shutdown`; cancelling it only stops the
+    /// tasks watching it
+    pub fn token(&self) -> CancellationToken {
+        self.token
+            .lock()
+            .map(|token| token.child_token())
+            .unwrap_or_default()
+    }
+
+    /// Spawn and track `task`, which should return once its token is cancelled
+    pub fn spawn<F>(&self, name: &str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let returned = Arc::new(OnceLock::new());
+        let done = Arc::clone(&returned);
+        let handle = tokio::spawn(async move {
+            task.await;
+            let _ = done.set(());
+        });
+
+        if let Ok(mut tasks) = self.tasks.lock() {
+            // Forget tasks that returned cleanly; keep failures for `health`
+            tasks.retain(|task| task.state() != TaskState::Finished);
+            tasks.push(Task {
+                name: name.to_string(),
+                started_at: Utc::now(),
+                returned,
+                handle,
+            });
+        }
+    }
+
+    /// State of every tracked task
+    pub fn health(&self) -> Vec<TaskHealth> {
+        self.tasks
+            .lock()
+            .map(|tasks| {
+                tasks
+                    .iter()
+                    .map(|task| TaskHealth {
+                        name: task.name.clone(),
+                        state: task.state(),
+                        started_at: task.started_at,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Number of tasks still running
+    pub fn active(&self) -> usize {
+        self.tasks
+            .lock()
+            .map(|tasks| {
+                tasks
+                    .iter()
+                    .filter(|task| task.state() == TaskState::Running)
+                    .count()
+            })
+            .unwrap_or(0)
+    }
+
+    /// Cancel every task and wait for them to return, aborting any that take
+    /// longer than `SHUTDOWN_GRACE`. Tasks spawned afterwards get a fresh token.
+    pub async fn shutdown(&self) {
+        if let Ok(mut token) = self.token.lock() {
+            token.cancel();
+            *token = CancellationToken::new();
+        }
+        let tasks = match self.tasks.lock() {
+            Ok(mut tasks) => std::mem::take(&mut *tasks),
+            Err(_) => return,
+        };
+
+        for mut task in tasks {
+            if tokio::time::timeout(SHUTDOWN_GRACE, &mut task.handle)
+                .await
+                .is_err()
+            {
+                log::warn!("Task {} did not stop in time; aborting", task.name);
+                task.handle.abort();
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_tracked_tasks() {
+        let tasks = TaskSupervisor::default();
+        for name in ["a", "b"] {
+            let token = tasks.token();
+            tasks.spawn(name, async move { token.cancelled().await });
+        }
+        assert_eq!(tasks.active(), 2);
+
+        tasks.shutdown().await;
+        assert_eq!(tasks.active(), 0);
+        assert!(tasks.health().is_empty());
+
+        // The supervisor is reusable after a shutdown
+        let token = tasks.token();
+        assert!(!token.is_cancelled());
+        tasks.spawn("c", async move { token.cancelled().await });
+        assert_eq!(tasks.active(), 1);
+        tasks.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_failed_tasks() {
+        let tasks = TaskSupervisor::default();
+        tasks.spawn("done", async {});
+        tasks.spawn("panics", async { panic!("boom") });
+        let token = tasks.token();
+        tasks.spawn("running", async move { token.cancelled().await });
+        tokio::task::yield_now().await;
+
+        let states: Vec<_> = tasks
+            .health()
+            .into_iter()
+            .map(|t| (t.name, t.state))
+            .collect();
+        assert!(states.contains(&("panics".to_string(), TaskState::Failed)));
+        assert!(states.contains(&("running".to_string(), TaskState::Running)));
+        assert_eq!(tasks.active(), 1);
+
+        // Tasks that returned cleanly are forgotten on the next spawn
+        tasks.spawn("next", async {});
+        assert!(tasks.health().iter().all(|t| t.name != "done"));
+        tasks.shutdown().await;
+    }
+}
@@ -0,0 +1,775 @@
+//! Trusted Networks
+//!
+//! Lets the user mark the network they are on as trusted, and starts the
+//! sync server (and its mDNS advertiser) on its own when the machine joins a
+//! trusted network. On any other network, or with no network at all, a
+//! running server is stopped as soon as the change is noticed.
+//!
+//! A network is recognised by its fingerprint: the interface and subnet of
+//! the primary address, plus the default gateway's address and MAC. The MAC
+//! is what tells two home routers on `192.168.1.0/24` apart, so a network
+//! trusted with one only matches while that MAC is seen again. A background
+//! watcher re-checks the fingerprint when the OS reports an address or route
+//! change (netlink on Linux, a routing socket on macOS, `NotifyAddrChange` on
+//! Windows), or every few seconds where there is no such notification. It
+//! emits a `sync:network_changed` event describing the network, whether it
+//! is trusted and what was done, so the UI can explain why sync is on or off.
+//!
+//! Trusted networks and the auto-start settings live in
+//! `trusted_networks.json` in the config directory.
+
+use super::clock::{Clock, SystemClock};
+use super::commands::{ServerStartOptions, SyncState};
+use super::error::SyncCommandError;
+use super::folder::write_atomic;
+use super::network::Subnet;
+use super::supervisor::TaskSupervisor;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+
+pub const NETWORK_EVENT: &str = "sync:network_changed";
+
+const TRUSTED_FILE: &str = "trusted_networks.json";
+/// How often the watcher looks for a network change without notifications
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How often it looks anyway with notifications, for what they don't report
+/// (such as the gateway's MAC showing up some time after the switch)
+const RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Notifications come in bursts while an interface comes up
+const SETTLE_DELAY: Duration = Duration::from_secs(1);
+
+// ============================================================================
+// Types
+// ============================================================================
+
+/// What identifies the network the machine is on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkFingerprint {
+    pub interface: String,
+    pub subnet: Subnet,
+    pub gateway: Option<IpAddr>,
+    /// Lowercase, colon-separated
+    pub gateway_mac: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustedNetwork {
+    pub id: String,
+    pub name: String,
+    pub fingerprint: NetworkFingerprint,
+    pub trusted_at: DateTime<Utc>,
+}
+
+/// Contents of `trusted_networks.json`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TrustedNetworks {
+    /// How to start the server on a trusted network; `None` turns automatic
+    /// start and stop off
+    pub auto_start: Option<ServerStartOptions>,
+    pub networks: Vec<TrustedNetwork>,
+}
+
+/// What the watcher does after checking the network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkAction {
+    /// Automatic start is off; the server is left alone
+    Disabled,
+    Start,
+    /// Moved between trusted networks; rebind to the new addresses
+    Restart,
+    Stop,
+    KeepRunning,
+    KeepStopped,
+}
+
+/// Payload of `sync:network_changed`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkChange {
+    /// `None` when not connected
+    pub network: Option<NetworkFingerprint>,
+    /// The trusted network `network` matched
+    pub trusted: Option<TrustedNetwork>,
+    pub auto_start: bool,
+    pub action: NetworkAction,
+    /// Why starting or stopping the server failed
+    pub error: Option<SyncCommandError>,
+    pub checked_at: DateTime<Utc>,
+}
+
+// ============================================================================
+// Fingerprint
+// ============================================================================
+
+impl NetworkFingerprint {
+    /// Fingerprint of the network the primary address is on, or `None` when
+    /// not connected. Runs platform tools on macOS and Windows, so call it
+    /// from a blocking task.
+    pub fn current() -> Option<Self> {
+        let ip = local_ip_address::local_ip().ok()?;
+        let iface = if_addrs::get_if_addrs()
+            .ok()?
+            .into_iter()
+            .find(|iface| iface.ip() == ip)?;
+        let netmask = match &iface.addr {
+            if_addrs::IfAddr::V4(addr) => IpAddr::V4(addr.netmask),
+            if_addrs::IfAddr::V6(addr) => IpAddr::V6(addr.netmask),
+        };
+        let gateway = default_gateway(&iface.name);
+        let gateway_mac = gateway.and_then(gateway_mac);
+
+        Some(Self {
+            interface: iface.name,
+            subnet: Subnet::from_netmask(ip, netmask),
+            gateway,
+            gateway_mac,
+        })
+    }
+
+    /// Whether `other` is the network this (trusted) fingerprint describes.
+    /// With a gateway MAC here, `other` must have the same one: a missed ARP
+    /// lookup must not make a hotel's `192.168.1.1` pass for home. Without
+    /// one the gateway address decides, and without either there is nothing
+    /// to recognise the network by.
+    pub fn same_network(&self, other: &Self) -> bool {
+        if self.subnet != other.subnet {
+            return false;
+        }
+        match (&self.gateway_mac, self.gateway) {
+            (Some(mac), _) => other.gateway_mac.as_ref() == Some(mac),
+            (None, Some(gateway)) => other.gateway == Some(gateway),
+            (None, None) => false,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn default_gateway(interface: &str) -> Option<IpAddr> {
+    let table = std::fs::read_to_string("/proc/net/route").ok()?;
+    parse_proc_route(&table, interface).map(IpAddr::V4)
+}
+
+#[cfg(target_os = "linux")]
+fn gateway_mac(gateway: IpAddr) -> Option<String> {
+    let table = std::fs::read_to_string("/proc/net/arp").ok()?;
+    parse_proc_arp(&table, gateway)
+}
+
+#[cfg(target_os = "macos")]
+fn default_gateway(_interface: &str) -> Option<IpAddr> {
+    parse_route_get(&run_tool("route", &["-n", "get", "default"])?)
+}
+
+#[cfg(target_os = "macos")]
+fn gateway_mac(gateway: IpAddr) -> Option<String> {
+    parse_arp_output(&run_tool("arp", &["-n", &gateway.to_string()])?, gateway)
+}
+
+#[cfg(windows)]
+fn default_gateway(_interface: &str) -> Option<IpAddr> {
+    parse_route_print(&run_tool("route", &["print", "-4", "0.0.0.0"])?)
+}
+
+#[cfg(windows)]
+fn gateway_mac(gateway: IpAddr) -> Option<String> {
+    parse_arp_output(&run_tool("arp", &["-a", &gateway.to_string()])?, gateway)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn default_gateway(_interface: &str) -> Option<IpAddr> {
+    None
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn gateway_mac(_gateway: IpAddr) -> Option<String> {
+    None
+}
+
+/// Wakes the watcher whenever the OS reports a network change. `None` where
+/// there is no such notification or it couldn't be set up; the receiver
+/// closes if it fails later.
+fn network_changes() -> Option<tokio::sync::mpsc::Receiver<()>> {
+    let mut wait = change_waiter()?;
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    std::thread::Builder::new()
+        .name("network-changes".to_string())
+        .spawn(move || {
+            while wait() {
+                // A full channel already holds a wake-up
+                if let Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) = tx.try_send(()) {
+                    break;
+                }
+            }
+        })
+        .ok()?;
+    Some(rx)
+}
+
+/// Blocks until the next change; `false` once it can't wait any more
+type ChangeWaiter = Box<dyn FnMut() -> bool + Send>;
+
+/// Netlink socket subscribed to link, address and route changes
+#[cfg(target_os = "linux")]
+fn change_waiter() -> Option<ChangeWaiter> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    let groups = libc::RTMGRP_LINK
+        | libc::RTMGRP_IPV4_IFADDR
+        | libc::RTMGRP_IPV4_ROUTE
+        | libc::RTMGRP_IPV6_IFADDR
+        | libc::RTMGRP_IPV6_ROUTE;
+    // SAFETY: socket(2) and bind(2) with a zeroed, filled-in sockaddr_nl of
+    // the size passed; the descriptor is owned from here on
+    let socket = unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        );
+        if fd < 0 {
+            return None;
+        }
+        let socket = OwnedFd::from_raw_fd(fd);
+        let mut addr: libc::sockaddr_nl = std::mem::zeroed();
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = groups as u32;
+        let bound = libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        );
+        if bound < 0 {
+            return None;
+        }
+        socket
+    };
+    Some(read_socket_events(std::fs::File::from(socket)))
+}
+
+/// Routing socket, which reports address, interface and route changes
+#[cfg(target_os = "macos")]
+fn change_waiter() -> Option<ChangeWaiter> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    // SAFETY: socket(2); the descriptor is owned from here on
+    let socket = unsafe {
+        let fd = libc::socket(libc::PF_ROUTE, libc::SOCK_RAW, libc::AF_UNSPEC);
+        if fd < 0 {
+            return None;
+        }
+        OwnedFd::from_raw_fd(fd)
+    };
+    Some(read_socket_events(std::fs::File::from(socket)))
+}
+
+/// Each message read is a change; an overrun means some were missed
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn read_socket_events(mut socket: std::fs::File) -> ChangeWaiter {
+    use std::io::Read;
+    let mut buf = vec![0u8; 8192];
+    Box::new(move || match socket.read(&mut buf) {
+        Ok(_) => true,
+        Err(e) => {
+            e.kind() == std::io::ErrorKind::Interrupted || e.raw_os_error() == Some(libc::ENOBUFS)
+        }
+    })
+}
+
+/// IPv4 address changes, which come with joining or leaving a network
+#[cfg(windows)]
+fn change_waiter() -> Option<ChangeWaiter> {
+    use windows_sys::Win32::Foundation::NO_ERROR;
+    use windows_sys::Win32::NetworkManagement::IpHelper::NotifyAddrChange;
+    // SAFETY: without a handle or OVERLAPPED the call blocks until the next
+    // change and keeps no pointers
+    Some(Box::new(|| unsafe {
+        NotifyAddrChange(std::ptr::null_mut(), std::ptr::null()) == NO_ERROR
+    }))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn change_waiter() -> Option<ChangeWaiter> {
+    None
+}
+
+/// Stdout of a system tool, if it ran successfully
+#[cfg(any(target_os = "macos", windows))]
+fn run_tool(program: &str, args: &[&str]) -> Option<String> {
+    let mut command = std::process::Command::new(program);
+    command.args(args);
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW: don't flash a console every poll
+        command.creation_flags(0x0800_0000);
+    }
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Default route of `interface` (or of any interface) from `/proc/net/route`,
+/// whose addresses are little-endian hex
+#[cfg(any(target_os = "linux", test))]
+fn parse_proc_route(table: &str, interface: &str) -> Option<std::net::Ipv4Addr> {
+    let defaults: Vec<(&str, std::net::Ipv4Addr)> = table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            if cols.len() < 3 || cols[1] != "00000000" {
+                return None;
+            }
+            let gateway = u32::from_str_radix(cols[2], 16).ok()?;
+            (gateway != 0).then(|| (cols[0], std::net::Ipv4Addr::from(gateway.to_le_bytes())))
+        })
+        .collect();
+    defaults
+        .iter()
+        .find(|(name, _)| *name == interface)
+        .or_else(|| defaults.first())
+        .map(|(_, gateway)| *gateway)
+}
+
+#[cfg(any(target_os = "linux", test))]
+fn parse_proc_arp(table: &str, gateway: IpAddr) -> Option<String> {
+    table.lines().skip(1).find_map(|line| {
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        if cols.len() < 4 || cols[0].parse::<IpAddr>().ok()? != gateway {
+            return None;
+        }
+        normalize_mac(cols[3])
+    })
+}
+
+/// `gateway:` line of macOS `route -n get default`
+#[cfg(any(target_os = "macos", test))]
+fn parse_route_get(output: &str) -> Option<IpAddr> {
+    output.lines().find_map(|line| {
+        let value = line.trim().strip_prefix("gateway:")?;
+        value.trim().parse().ok()
+    })
+}
+
+/// First `0.0.0.0 0.0.0.0 <gateway>` row of Windows `route print`
+#[cfg(any(windows, test))]
+fn parse_route_print(output: &str) -> Option<IpAddr> {
+    output.lines().find_map(|line| {
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        if cols.len() < 3 || cols[0] != "0.0.0.0" || cols[1] != "0.0.0.0" {
+            return None;
+        }
+        cols[2].parse().ok()
+    })
+}
+
+/// MAC on the line for `gateway` in `arp` output: macOS prints
+/// `? (192.168.1.1) at a4:2b:b0:1:2:3 on en0`, Windows
+/// `192.168.1.1   a4-2b-b0-01-02-03   dynamic`
+#[cfg(any(target_os = "macos", windows, test))]
+fn parse_arp_output(output: &str, gateway: IpAddr) -> Option<String> {
+    output.lines().find_map(|line| {
+        let tokens: Vec<&str> = line
+            .split_whitespace()
+            .map(|t| t.trim_matches(|c| c == '(' || c == ')'))
+            .collect();
+        if !tokens
+            .iter()
+            .any(|t| t.parse::<IpAddr>().ok() == Some(gateway))
+        {
+            return None;
+        }
+        tokens.iter().find_map(|t| normalize_mac(t))
+    })
+}
+
+/// `A4-2B-B0-1-2-3` as `a4:2b:b0:01:02:03`; `None` for anything else,
+/// including the all-zero address of an incomplete ARP entry
+fn normalize_mac(raw: &str) -> Option<String> {
+    let parts: Vec<&str> = raw.split([':', '-']).collect();
+    if parts.len() != 6 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(6);
+    for part in parts {
+        if part.is_empty() || part.len() > 2 {
+            return None;
+        }
+        bytes.push(u8::from_str_radix(part, 16).ok()?);
+    }
+    if bytes.iter().all(|b| *b == 0) {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(":"),
+    )
+}
+
+// ============================================================================
+// Settings
+// ============================================================================
+
+impl TrustedNetworks {
+    pub fn load(config_dir: &Path) -> Option<Self> {
+        let content = std::fs::read_to_string(config_dir.join(TRUSTED_FILE)).ok()?;
+        match serde_json::from_str(&content) {
+            Ok(networks) => Some(networks),
+            Err(e) => {
+                log::warn!("Ignoring unreadable {}: {}", TRUSTED_FILE, e);
+                None
+            }
+        }
+    }
+
+    fn save(&self, config_dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        write_atomic(&config_dir.join(TRUSTED_FILE), &json)
+    }
+
+    /// The trusted network `network` is, if any
+    pub fn find(&self, network: &NetworkFingerprint) -> Option<&TrustedNetwork> {
+        self.networks
+            .iter()
+            .find(|trusted| trusted.fingerprint.same_network(network))
+    }
+}
+
+/// Decide what to do with the server after checking the network. `moved`
+/// means the network differs from the previous check.
+pub fn decide(auto_start: bool, trusted: bool, running: bool, moved: bool) -> NetworkAction {
+    match (auto_start, trusted, running) {
+        (false, _, _) => NetworkAction::Disabled,
+        (true, true, false) => NetworkAction::Start,
+        (true, true, true) if moved => NetworkAction::Restart,
+        (true, true, true) => NetworkAction::KeepRunning,
+        (true, false, true) => NetworkAction::Stop,
+        (true, false, false) => NetworkAction::KeepStopped,
+    }
+}
+
+// ============================================================================
+// Watcher
+// ============================================================================
+
+/// Trust settings and the watcher's view of the network, held in `SyncState`
+pub struct TrustedNetworkState {
+    config_dir: PathBuf,
+    settings: Mutex<TrustedNetworks>,
+    /// Network seen on the last check
+    current: Mutex<Option<NetworkFingerprint>>,
+    last_change: Mutex<Option<NetworkChange>>,
+    /// Keeps the watcher and settings commands from starting or stopping the
+    /// server at the same time
+    applying: tokio::sync::Mutex<()>,
+    /// Runs the watcher; unlike the server's tasks it outlives server restarts
+    pub watchers: TaskSupervisor,
+    clock: Arc<dyn Clock>,
+}
+
+impl TrustedNetworkState {
+    pub fn new(config_dir: PathBuf) -> Self {
+        Self::with_clock(config_dir, Arc::new(SystemClock))
+    }
+
+    /// Trust state timestamping network checks with `clock`
+    pub fn with_clock(config_dir: PathBuf, clock: Arc<dyn Clock>) -> Self {
+        let settings = TrustedNetworks::load(&config_dir).unwrap_or_default();
+        Self {
+            config_dir,
+            settings: Mutex::new(settings),
+            current: Mutex::new(None),
+            last_change: Mutex::new(None),
+            applying: tokio::sync::Mutex::new(()),
+            watchers: TaskSupervisor::default(),
+            clock,
+        }
+    }
+
+    pub fn settings(&self) -> Result<TrustedNetworks, SyncCommandError> {
+        Ok(self.settings.lock()?.clone())
+    }
+
+    /// Change the settings and persist them
+    pub fn update<T>(
+        &self,
+        change: impl FnOnce(&mut TrustedNetworks) -> T,
+    ) -> Result<T, SyncCommandError> {
+        let mut settings = self.settings.lock()?;
+        let mut updated = settings.clone();
+        let result = change(&mut updated);
+        updated
+            .save(&self.config_dir)
+            .map_err(|detail| SyncCommandError::Storage {
+                operation: "save_trusted_networks",
+                detail,
+            })?;
+        *settings = updated;
+        Ok(result)
+    }
+
+    /// Result of the last check, if the watcher has run
+    pub fn last_change(&self) -> Option<NetworkChange> {
+        self.last_change.lock().ok()?.clone()
+    }
+}
+
+/// Check the network and start or stop the server to match the trust
+/// settings, emitting `sync:network_changed`. Unless `force` is set nothing
+/// happens while the network stays the same, so a server the user started
+/// or stopped by hand is left alone until the next change.
+pub async fn apply(app: &AppHandle, state: &SyncState, force: bool) -> Option<NetworkChange> {
+    let trust = &state.trusted;
+    let _applying = trust.applying.lock().await;
+
+    let network = tokio::task::spawn_blocking(NetworkFingerprint::current)
+        .await
+        .ok()
+        .flatten();
+    let moved = {
+        let mut current = trust.current.lock().ok()?;
+        let moved = *current != network;
+        *current = network.clone();
+        moved
+    };
+    if !moved && !force {
+        return None;
+    }
+
+    let (trusted, auto_start) = {
+        let settings = trust.settings.lock().ok()?;
+        let trusted = network
+            .as_ref()
+            .and_then(|network| settings.find(network))
+            .cloned();
+        (trusted, settings.auto_start.clone())
+    };
+    let running = state.server_running().unwrap_or(false);
+    let action = decide(auto_start.is_some(), trusted.is_some(), running, moved);
+
+    let result = match (action, &auto_start) {
+        (NetworkAction::Start | NetworkAction::Restart, Some(options)) => {
+            log::info!("Starting sync server on trusted network");
+            state.start_server(app, options.clone()).await.map(|_| ())
+        }
+        (NetworkAction::Stop, _) => {
+            log::info!("Stopping sync server: network is not trusted");
+            state.stop_server().await
+        }
+        _ => Ok(()),
+    };
+    if let Err(e) = &result {
+        log::warn!("Failed to apply network trust: {}", e);
+    }
+
+    let change = NetworkChange {
+        network,
+        trusted,
+        auto_start: auto_start.is_some(),
+        action,
+        error: result.err(),
+        checked_at: trust.clock.now(),
+    };
+    if let Ok(mut last) = trust.last_change.lock() {
+        *last = Some(change.clone());
+    }
+    if let Err(e) = app.emit(NETWORK_EVENT, &change) {
+        log::error!("Failed to emit network change event: {}", e);
+    }
+    Some(change)
+}
+
+/// Watch for network changes until the app exits. Must be called from within
+/// the async runtime.
+pub fn spawn_watcher(app: AppHandle) {
+    let state = app.state::<SyncState>();
+    let token = state.trusted.watchers.token();
+    let watcher_app = app.clone();
+    state
+        .trusted
+        .watchers
+        .spawn("trusted-network-watcher", async move {
+            let app = watcher_app;
+            let mut changes = network_changes();
+            if changes.is_none() {
+                log::info!("No network change notifications; polling instead");
+            }
+            let mut force = true;
+            loop {
+                apply(&app, &app.state::<SyncState>(), force).await;
+                force = false;
+                let interval = if changes.is_some() {
+                    RECHECK_INTERVAL
+                } else {
+                    POLL_INTERVAL
+                };
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = next_change(&mut changes) => {}
+                }
+            }
+        });
+}
+
+/// Wait for a change notification and for its burst to settle. Never
+/// returns without notifications; if they stop, returns once and drops them.
+async fn next_change(changes: &mut Option<tokio::sync::mpsc::Receiver<()>>) {
+    let Some(rx) = changes.as_mut() else {
+        return std::future::pending().await;
+    };
+    if rx.recv().await.is_none() {
+        log::warn!("Network change notifications stopped; polling instead");
+        *changes = None;
+        return;
+    }
+    tokio::time::sleep(SETTLE_DELAY).await;
+    while rx.try_recv().is_ok() {}
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn fingerprint(subnet: &str, gateway: Option<&str>, mac: Option<&str>) -> NetworkFingerprint {
+        NetworkFingerprint {
+            interface: "en0".to_string(),
+            subnet: subnet.parse().unwrap(),
+            gateway: gateway.map(ip),
+            gateway_mac: mac.map(|m| m.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_parses_gateway_and_mac_tables() {
+        let route = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+                     docker0\t000011AC\t00000000\t0001\t0\t0\t0\t0000FFFF\n\
+                     wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\n";
+        assert_eq!(
+            parse_proc_route(route, "wlan0"),
+            Some(Ipv4Addr::new(192, 168, 1, 1))
+        );
+        assert_eq!(
+            parse_proc_route(route, "eth0"),
+            Some(Ipv4Addr::new(192, 168, 1, 1))
+        );
+
+        let arp =
+            "IP address       HW type     Flags       HW address            Mask     Device\n\
+                   192.168.1.9      0x1         0x0         00:00:00:00:00:00     *        wlan0\n\
+                   192.168.1.1      0x1         0x2         A4:2B:B0:01:02:03     *        wlan0\n";
+        assert_eq!(
+            parse_proc_arp(arp, ip("192.168.1.1")).as_deref(),
+            Some("a4:2b:b0:01:02:03")
+        );
+        assert_eq!(parse_proc_arp(arp, ip("192.168.1.9")), None);
+
+        let route_get =
+            "   route to: default\ndestination: default\n    gateway: 10.0.0.1\n  interface: en0\n";
+        assert_eq!(parse_route_get(route_get), Some(ip("10.0.0.1")));
+        let mac_arp = "? (10.0.0.1) at a4:2b:b0:1:2:3 on en0 ifscope [ethernet]\n";
+        assert_eq!(
+            parse_arp_output(mac_arp, ip("10.0.0.1")).as_deref(),
+            Some("a4:2b:b0:01:02:03")
+        );
+
+        let route_print = "IPv4 Route Table\n\
+                           Network Destination        Netmask          Gateway       Interface  Metric\n\
+                           0.0.0.0          0.0.0.0      192.168.0.1    192.168.0.20     25\n";
+        assert_eq!(parse_route_print(route_print), Some(ip("192.168.0.1")));
+        let win_arp = "Interface: 192.168.0.20 --- 0x5\n  \
+                       Internet Address      Physical Address      Type\n  \
+                       192.168.0.1           a4-2b-b0-01-02-03     dynamic\n";
+        assert_eq!(
+            parse_arp_output(win_arp, ip("192.168.0.1")).as_deref(),
+            Some("a4:2b:b0:01:02:03")
+        );
+    }
+
+    #[test]
+    fn test_recognises_networks_by_gateway() {
+        let home = fingerprint(
+            "192.168.1.0/24",
+            Some("192.168.1.1"),
+            Some("a4:2b:b0:01:02:03"),
+        );
+        let cafe = fingerprint(
+            "192.168.1.0/24",
+            Some("192.168.1.1"),
+            Some("00:11:22:33:44:55"),
+        );
+        assert!(home.same_network(&home.clone()));
+        // Same addressing, different router
+        assert!(!home.same_network(&cafe));
+
+        // A missed ARP lookup doesn't pass for the trusted router
+        let home_no_mac = fingerprint("192.168.1.0/24", Some("192.168.1.1"), None);
+        assert!(!home.same_network(&home_no_mac));
+        // Trusted without a MAC, the gateway address decides
+        assert!(home_no_mac.same_network(&home));
+        assert!(home_no_mac.same_network(&cafe));
+        assert!(!home_no_mac.same_network(&fingerprint("10.0.0.0/24", Some("192.168.1.1"), None)));
+        // Nothing to recognise a gateway-less network by
+        let isolated = fingerprint("192.168.1.0/24", None, None);
+        assert!(!isolated.same_network(&isolated.clone()));
+    }
+
+    #[test]
+    fn test_decides_server_action() {
+        assert_eq!(decide(false, true, false, true), NetworkAction::Disabled);
+        assert_eq!(decide(true, true, false, true), NetworkAction::Start);
+        assert_eq!(decide(true, true, true, true), NetworkAction::Restart);
+        assert_eq!(decide(true, true, true, false), NetworkAction::KeepRunning);
+        assert_eq!(decide(true, false, true, true), NetworkAction::Stop);
+        assert_eq!(decide(true, false, false, true), NetworkAction::KeepStopped);
+    }
+
+    #[test]
+    fn test_settings_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(TrustedNetworks::load(dir.path()).is_none());
+
+        let settings = TrustedNetworks {
+            auto_start: Some(ServerStartOptions {
+                device_id: Some("desktop-1".to_string()),
+                auto_shutdown_minutes: Some(0),
+                ..Default::default()
+            }),
+            networks: vec![TrustedNetwork {
+                id: "home".to_string(),
+                name: "Home".to_string(),
+                fingerprint: fingerprint("192.168.1.0/24", Some("192.168.1.1"), None),
+                trusted_at: Utc::now(),
+            }],
+        };
+        settings.save(dir.path()).unwrap();
+        assert_eq!(TrustedNetworks::load(dir.path()), Some(settings.clone()));
+
+        let state = TrustedNetworkState::new(dir.path().to_path_buf());
+        state.update(|s| s.networks.clear()).unwrap();
+        let reloaded = TrustedNetworks::load(dir.path()).unwrap();
+        assert!(reloaded.networks.is_empty());
+        assert_eq!(reloaded.auto_start, settings.auto_start);
+    }
+}
@@ -30,9 +30,13 @@ export function PairingModal() {
     isExpired,
     isVerified,
     isFailed,
+    isDenied,
+    isAwaitingApproval,
     startSession,
     cancelSession,
     regenerateSession,
+    approveDevice,
+    denyDevice,
   } = usePairingSession();
 
   const remainingSeconds = useCountdown(session?.expiresAt);
@@ -118,6 +122,42 @@ export function PairingModal() {
                 Your mobile device has been successfully paired.
               </p>
             </div>
+          ) : isAwaitingApproval ? (
+            <div className="pairing-success">
+              <h3 className="pairing-success__title">
+                Allow &lsquo;{status?.deviceName ?? 'this device'}&rsquo; to sync?
+              </h3>
+              {status?.sas ? (
+                <>
+                  <p className="pairing-success__message">
+                    Check that the device shows the same code. If it doesn&rsquo;t, deny it.
+                  </p>
+                  <div className="pairing-modal__code-section pairing-modal__sas">
+                    <span className="pairing-modal__code">{status.sas.number}</span>
+                    <span className="pairing-modal__sas-emoji">{status.sas.emoji.join(' ')}</span>
+                  </div>
+                </>
+              ) : (
+                <p className="pairing-success__message">
+                  This device entered the correct pairing code. Only allow it if you recognize it.
+                </p>
+              )}
+              <div className="pairing-modal__footer">
+                <Button onClick={denyDevice} variant="secondary">
+                  {status?.sas ? 'They don\u2019t match' : 'Deny'}
+                </Button>
+                <Button onClick={approveDevice}>
+                  {status?.sas ? 'They match \u2014 Allow' : 'Allow'}
+                </Button>
+              </div>
+            </div>
+          ) : isDenied ? (
+            <div className="pairing-modal__error">
+              <p>The device was not allowed to sync.</p>
+              <Button onClick={regenerateSession} variant="secondary">
+                Start New Session
+              </Button>
+            </div>
           ) : isFailed ? (
             <div className="pairing-modal__error">
               <p>Too many failed attempts. Please try again.</p>
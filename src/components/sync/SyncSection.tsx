@@ -8,13 +8,36 @@ import { useState, useEffect } from 'react';
 import { useSyncStore, useSyncStatus, useSyncActions } from '../../sync/stores/syncStore';
 import { Button } from '../ui/Button';
 import { Card } from '../ui/Card';
+import { Input } from '../ui/Input';
 import { isTauri } from '../../lib/platform';
+import { useT } from '../../lib/i18n';
+import { describeSyncError } from '../../sync/transport/command-errors';
 import './SyncSection.css';
 
 // Cache the isTauri check at module load time
 const IS_TAURI = isTauri();
 
+const RELAY_URL_KEY = 'mutaba3a_relay_url';
+const SYNC_FOLDER_KEY = 'mutaba3a_sync_folder';
+
+interface RelaySyncReport {
+  received: number;
+  stored: number;
+  sent: number;
+  conflicts: number;
+  failed: { device_id: string; error: string }[];
+}
+
+interface FolderSyncReport {
+  imported: number;
+  stored: number;
+  exported: number;
+  removedSegments: number;
+  errors: string[];
+}
+
 export function SyncSection() {
+  const t = useT();
   const { status, pendingConflicts, lastSyncAt, serverRunning } = useSyncStatus();
   const { openExportModal, openImportModal, openPairingModal, startDiscovery, refreshCounts } = useSyncActions();
   const trustedPeers = useSyncStore((s) => s.trustedPeers);
@@ -23,12 +46,30 @@ export function SyncSection() {
   const isDiscovering = useSyncStore((s) => s.isDiscovering);
 
   const [serverStarting, setServerStarting] = useState(false);
+  const [relayUrl, setRelayUrl] = useState(() => localStorage.getItem(RELAY_URL_KEY) ?? '');
+  const [relaySyncing, setRelaySyncing] = useState(false);
+  const [relayResult, setRelayResult] = useState<string | null>(null);
+  const [relayError, setRelayError] = useState<string | undefined>(undefined);
+  const [folderPath, setFolderPath] = useState(() => localStorage.getItem(SYNC_FOLDER_KEY) ?? '');
+  const [folderPassphrase, setFolderPassphrase] = useState('');
+  const [folderActive, setFolderActive] = useState(false);
+  const [folderStarting, setFolderStarting] = useState(false);
+  const [folderResult, setFolderResult] = useState<string | null>(null);
+  const [folderError, setFolderError] = useState<string | undefined>(undefined);
 
   useEffect(() => {
     refreshCounts();
     // eslint-disable-next-line react-hooks/exhaustive-deps
   }, []); // Run once on mount
 
+  useEffect(() => {
+    if (!IS_TAURI) return;
+    import('@tauri-apps/api/core')
+      .then(({ invoke }) => invoke<string | null>('get_sync_folder'))
+      .then((folder) => setFolderActive(folder !== null))
+      .catch((error) => console.error('Failed to get sync folder:', error));
+  }, []);
+
   const handleStartServer = async () => {
     if (!IS_TAURI) return;
 
@@ -40,7 +81,6 @@ export function SyncSection() {
       const result = await invoke<{ port: number; expires_at: string | null }>('start_sync_server', {
         deviceId: device.id,
         deviceName: device.name,
-        autoShutdownMinutes: 30,
       });
 
       useSyncStore.getState().setServerRunning(true, result.port, result.expires_at ?? undefined);
@@ -63,6 +103,82 @@ export function SyncSection() {
     }
   };
 
+  const handleRelaySync = async () => {
+    if (!IS_TAURI || !relayUrl.trim()) return;
+
+    setRelaySyncing(true);
+    setRelayError(undefined);
+    setRelayResult(null);
+    try {
+      const { invoke } = await import('@tauri-apps/api/core');
+      const device = await getLocalDevice();
+      const url = relayUrl.trim();
+      localStorage.setItem(RELAY_URL_KEY, url);
+
+      const report = await invoke<RelaySyncReport>('sync_via_relay', {
+        relayUrl: url,
+        deviceId: device.id,
+      });
+
+      if (report.failed.length > 0) {
+        setRelayError(report.failed.map((f) => f.error).join('; '));
+      }
+      setRelayResult(`Received ${report.stored} changes, sent ${report.sent}.`);
+      refreshCounts();
+    } catch (error) {
+      console.error('Failed to sync via relay:', error);
+      setRelayError(describeSyncError(error, t));
+    } finally {
+      setRelaySyncing(false);
+    }
+  };
+
+  const handleStartFolderSync = async () => {
+    if (!IS_TAURI || !folderPath.trim() || !folderPassphrase) return;
+
+    setFolderStarting(true);
+    setFolderError(undefined);
+    setFolderResult(null);
+    try {
+      const { invoke } = await import('@tauri-apps/api/core');
+      const device = await getLocalDevice();
+      const path = folderPath.trim();
+      localStorage.setItem(SYNC_FOLDER_KEY, path);
+
+      const report = await invoke<FolderSyncReport>('start_folder_sync', {
+        folderPath: path,
+        passphrase: folderPassphrase,
+        deviceId: device.id,
+      });
+
+      setFolderActive(true);
+      setFolderPassphrase('');
+      if (report.errors.length > 0) {
+        setFolderError(report.errors.join('; '));
+      }
+      setFolderResult(`Received ${report.stored} changes, wrote ${report.exported}.`);
+      refreshCounts();
+    } catch (error) {
+      console.error('Failed to start folder sync:', error);
+      setFolderError(describeSyncError(error, t));
+    } finally {
+      setFolderStarting(false);
+    }
+  };
+
+  const handleStopFolderSync = async () => {
+    if (!IS_TAURI) return;
+
+    try {
+      const { invoke } = await import('@tauri-apps/api/core');
+      await invoke('stop_folder_sync');
+      setFolderActive(false);
+      setFolderResult(null);
+    } catch (error) {
+      console.error('Failed to stop folder sync:', error);
+    }
+  };
+
   const handleDiscoverPeers = () => {
     startDiscovery();
   };
@@ -159,6 +275,81 @@ export function SyncSection() {
         </Card>
       )}
 
+      {/* Relay Sync (Tauri only) */}
+      {IS_TAURI && trustedPeers.length > 0 && (
+        <Card className="sync-section__card">
+          <h3 className="sync-section__subtitle">Relay Sync</h3>
+          <p className="sync-section__description">
+            Exchange changes with paired devices through a relay when you're not on the same
+            network. The relay only stores encrypted data.
+          </p>
+
+          <Input
+            label="Relay URL"
+            placeholder="http://relay.example.com:4280"
+            value={relayUrl}
+            onChange={(e) => setRelayUrl(e.target.value)}
+            error={relayError}
+            hint={relayResult ?? undefined}
+          />
+
+          <div className="sync-fallback-actions">
+            <Button
+              variant="secondary"
+              onClick={handleRelaySync}
+              disabled={relaySyncing || !relayUrl.trim()}
+            >
+              {relaySyncing ? 'Syncing...' : 'Sync via Relay'}
+            </Button>
+          </div>
+        </Card>
+      )}
+
+      {/* Sync Folder (Tauri only) */}
+      {IS_TAURI && (
+        <Card className="sync-section__card">
+          <h3 className="sync-section__subtitle">Sync Folder</h3>
+          <p className="sync-section__description">
+            Sync through a shared folder (Syncthing, a network share or a USB stick). Changes are
+            encrypted with the folder passphrase; use the same passphrase on every device.
+          </p>
+
+          <Input
+            label="Folder"
+            placeholder="/path/to/shared/folder"
+            value={folderPath}
+            onChange={(e) => setFolderPath(e.target.value)}
+            disabled={folderActive}
+            error={folderError}
+            hint={folderResult ?? undefined}
+          />
+          {!folderActive && (
+            <Input
+              label="Folder passphrase"
+              type="password"
+              value={folderPassphrase}
+              onChange={(e) => setFolderPassphrase(e.target.value)}
+            />
+          )}
+
+          <div className="sync-fallback-actions">
+            {folderActive ? (
+              <Button variant="secondary" onClick={handleStopFolderSync}>
+                Stop Folder Sync
+              </Button>
+            ) : (
+              <Button
+                variant="secondary"
+                onClick={handleStartFolderSync}
+                disabled={folderStarting || !folderPath.trim() || !folderPassphrase}
+              >
+                {folderStarting ? 'Starting...' : 'Start Folder Sync'}
+              </Button>
+            )}
+          </div>
+        </Card>
+      )}
+
       {/* Export / Import Fallback */}
       <Card className="sync-section__card">
         <h3 className="sync-section__subtitle">Export / Import</h3>
@@ -1,12 +1,15 @@
 /**
  * Hook for managing device pairing sessions
  *
- * Handles starting/stopping pairing sessions and polling for status updates.
+ * Handles starting/stopping pairing sessions, polling for status updates and
+ * approving or denying devices that have entered the correct code.
  */
 
 import { useState, useEffect, useCallback, useRef } from 'react';
 import { useSyncStore } from '../sync/stores/syncStore';
 import { isTauri } from '../lib/platform';
+import { useT } from '../lib/i18n';
+import { describeSyncError } from '../sync/transport/command-errors';
 
 // ============================================================================
 // Types
@@ -21,13 +24,39 @@ export interface PairingSession {
   qrPayload: string;
 }
 
-export type PairingStatus = 'pending' | 'verified' | 'expired' | 'failed';
+export type PairingStatus =
+  | 'pending'
+  | 'awaiting_approval'
+  | 'verified'
+  | 'denied'
+  | 'expired'
+  | 'failed';
+
+/** Short authentication string shown on both devices during QR pairing */
+export interface PairingSas {
+  /** Six digits, e.g. "042917" */
+  number: string;
+  emoji: string[];
+}
 
 export interface PairingStatusResponse {
   pairingId: string;
   status: PairingStatus;
   remainingSeconds: number;
   attemptsRemaining: number;
+  /** Name of the device asking to pair (once it has entered the right code) */
+  deviceName?: string;
+  /** Present for QR pairing; the user must confirm it matches the device */
+  sas?: PairingSas;
+}
+
+/** Payload of the `sync:pairing_approval_requested` event */
+export interface PairingApprovalRequest {
+  pairingId: string;
+  deviceId: string;
+  deviceName: string;
+  method: 'qr' | 'code';
+  sas?: PairingSas;
 }
 
 // ============================================================================
@@ -43,6 +72,7 @@ const IS_TAURI = isTauri();
 // ============================================================================
 
 export function usePairingSession() {
+  const t = useT();
   const [session, setSession] = useState<PairingSession | null>(null);
   const [status, setStatus] = useState<PairingStatusResponse | null>(null);
   const [isLoading, setIsLoading] = useState(false);
@@ -68,13 +98,12 @@ export function usePairingSession() {
       setSession(result);
       startPolling(result.pairingId);
     } catch (err) {
-      const message = err instanceof Error ? err.message : String(err);
-      setError(message);
+      setError(describeSyncError(err, t));
       setSession(null);
     } finally {
       setIsLoading(false);
     }
-  }, []);
+  }, [t]);
 
   // Cancel the current session
   const cancelSession = useCallback(async () => {
@@ -115,8 +144,8 @@ export function usePairingSession() {
 
         setStatus(statusResult);
 
-        // Stop polling if session is no longer pending
-        if (statusResult.status !== 'pending') {
+        // Stop polling once the session is decided
+        if (statusResult.status !== 'pending' && statusResult.status !== 'awaiting_approval') {
           stopPolling();
 
           if (statusResult.status === 'verified') {
@@ -147,6 +176,72 @@ export function usePairingSession() {
     }
   }, []);
 
+  // Allow or reject the device that is asking to pair
+  const decideApproval = useCallback(
+    async (approve: boolean) => {
+      if (!session || !IS_TAURI) return;
+
+      try {
+        const { invoke } = await import('@tauri-apps/api/core');
+        // Allowing a QR session means the user confirmed the SAS matches
+        await invoke(approve ? 'approve_pairing' : 'deny_pairing', {
+          pairingId: session.pairingId,
+          ...(approve ? { sasConfirmed: true } : {}),
+        });
+        setStatus((prev) =>
+          prev ? { ...prev, status: approve ? 'verified' : 'denied' } : null
+        );
+        if (approve) {
+          await refreshTrustedPeers();
+        }
+      } catch (err) {
+        setError(describeSyncError(err, t));
+      } finally {
+        stopPolling();
+      }
+    },
+    [session, refreshTrustedPeers, stopPolling, t]
+  );
+
+  const approveDevice = useCallback(() => decideApproval(true), [decideApproval]);
+  const denyDevice = useCallback(() => decideApproval(false), [decideApproval]);
+
+  // Show the approval prompt as soon as the desktop is asked
+  useEffect(() => {
+    if (!IS_TAURI || !session) return;
+
+    let unlisten: (() => void) | undefined;
+    let cancelled = false;
+
+    (async () => {
+      const { listen } = await import('@tauri-apps/api/event');
+      const stop = await listen<PairingApprovalRequest>(
+        'sync:pairing_approval_requested',
+        (event) => {
+          if (event.payload.pairingId !== session.pairingId) return;
+          setStatus((prev) => ({
+            pairingId: session.pairingId,
+            remainingSeconds: prev?.remainingSeconds ?? 0,
+            attemptsRemaining: prev?.attemptsRemaining ?? 0,
+            status: 'awaiting_approval',
+            deviceName: event.payload.deviceName,
+            sas: event.payload.sas,
+          }));
+        }
+      );
+      if (cancelled) {
+        stop();
+      } else {
+        unlisten = stop;
+      }
+    })();
+
+    return () => {
+      cancelled = true;
+      unlisten?.();
+    };
+  }, [session]);
+
   // Cleanup on unmount
   useEffect(() => {
     return () => {
@@ -158,6 +253,8 @@ export function usePairingSession() {
   const isExpired = status?.status === 'expired';
   const isVerified = status?.status === 'verified';
   const isFailed = status?.status === 'failed';
+  const isDenied = status?.status === 'denied';
+  const isAwaitingApproval = status?.status === 'awaiting_approval';
   const isPending = status?.status === 'pending' || (!status && session !== null);
 
   return {
@@ -171,12 +268,16 @@ export function usePairingSession() {
     isExpired,
     isVerified,
     isFailed,
+    isDenied,
+    isAwaitingApproval,
     isPending,
 
     // Actions
     startSession,
     cancelSession,
     regenerateSession,
+    approveDevice,
+    denyDevice,
   };
 }
 
@@ -1,3 +1,4 @@
+import Dexie from 'dexie';
 import { db } from '../../db/database';
 import { HybridLogicalClock, tick, receive } from './hlc';
 import { isTauri } from '../../lib/platform';
@@ -114,6 +115,7 @@ export async function captureOp(params: CaptureParams): Promise<Operation | null
   }
 
   const device = cachedDevice;
+  const baseHlc = await latestAppliedHlc(params);
   const hlc = tick();
 
   const op: Operation = {
@@ -125,6 +127,7 @@ export async function captureOp(params: CaptureParams): Promise<Operation | null
     field: params.field,
     value: params.value,
     previousValue: params.previousValue,
+    baseHlc,
     createdBy: device.id,
     createdAt: new Date().toISOString(),
     appliedAt: new Date().toISOString(), // Applied immediately for local ops
@@ -152,6 +155,30 @@ export async function captureOp(params: CaptureParams): Promise<Operation | null
   return op;
 }
 
+/**
+ * HLC of the latest write this device has applied to the field being updated
+ * (or to any field of the entity being deleted).
+ */
+async function latestAppliedHlc(params: CaptureParams): Promise<string | undefined> {
+  if (params.opType === 'update' && params.field) {
+    const meta = await db.entityFieldMeta
+      .where('[entityType+entityId+field]')
+      .equals([params.entityType, params.entityId, params.field])
+      .first();
+    return meta?.hlc;
+  }
+
+  if (params.opType === 'delete') {
+    const metas = await db.entityFieldMeta
+      .where('[entityType+entityId+field]')
+      .between([params.entityType, params.entityId, Dexie.minKey], [params.entityType, params.entityId, Dexie.maxKey])
+      .toArray();
+    return metas.reduce<string | undefined>((latest, meta) => (!latest || meta.hlc > latest ? meta.hlc : latest), undefined);
+  }
+
+  return undefined;
+}
+
 /**
  * Capture a create operation with all fields.
  */
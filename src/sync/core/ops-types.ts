@@ -118,6 +118,12 @@ export interface Operation {
   value?: unknown;
   /** Previous value for conflict detection */
   previousValue?: unknown;
+  /**
+   * HLC of the latest write to the same field (the entity, for deletes) that
+   * the author had applied, so a receiver can tell a sequential edit from a
+   * concurrent one
+   */
+  baseHlc?: string;
   /** Device ID that created this operation */
   createdBy: string;
   /** ISO timestamp when operation was created */
@@ -106,6 +106,15 @@ export {
   clearPassphraseHint,
 } from './transport/crypto';
 
+// Command Errors
+export { isSyncCommandError, describeSyncError } from './transport/command-errors';
+
+export type {
+  SyncCommandError,
+  SyncCommandErrorSeverity,
+  SyncRecoveryAction,
+} from './transport/command-errors';
+
 // Conflict Resolution
 export {
   resolveProfileConflict,
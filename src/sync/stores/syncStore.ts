@@ -15,6 +15,31 @@ import type {
   TrustedPeer,
   Operation,
 } from '../core/ops-types';
+import { isSyncCommandError } from '../transport/command-errors';
+
+/** Concurrent edit detected by the backend when an op arrived */
+export interface DetectedConflict {
+  conflictType: 'profile_field' | 'money_event_version' | 'delete_vs_update';
+  entityType: string;
+  entityId: string;
+  field?: string;
+  existingOpId: string;
+  incomingOpId: string;
+  existingCreatedBy: string;
+  incomingCreatedBy: string;
+  existingValue: unknown;
+  incomingValue: unknown;
+  detectedAt: string;
+}
+
+/** Page of the backend inbox returned by `get_pending_sync_ops` */
+interface PendingOpsPage {
+  ops: { seq: number; op: Operation; conflicts?: DetectedConflict[] }[];
+  lastSeq: number | null;
+  hasMore: boolean;
+}
+
+const INBOX_PAGE_SIZE = 200;
 
 // ============================================================================
 // Store Types
@@ -246,35 +271,80 @@ export const useSyncStore = create<SyncStore>((set, get) => ({
       const { listen } = await import('@tauri-apps/api/event');
       const { invoke } = await import('@tauri-apps/api/core');
 
-      await listen<number>('sync:ops_received', async (event) => {
-        console.log('[SyncStore] Received sync:ops_received event, count:', event.payload);
-
+      // Apply inbox pages in order, acknowledging each page only once applied.
+      // Unacknowledged ops stay in the backend inbox and are redelivered.
+      let draining = false;
+      let drainRequested = false;
+      const drainInbox = async () => {
+        if (draining) {
+          drainRequested = true;
+          return;
+        }
+        draining = true;
         try {
-          // Fetch pending operations from Rust backend
-          const ops = await invoke<Operation[]>('get_pending_sync_ops');
-          console.log('[SyncStore] Fetched pending ops:', ops.length);
-
-          // Apply each operation to the database
-          for (const op of ops) {
-            console.log('[SyncStore] Applying op:', op.id, op.opType, op.entityType);
-            await applyOp(op);
-          }
-
-          // Clear the pending queue
-          const cleared = await invoke<number>('clear_pending_sync_ops');
-          console.log('[SyncStore] Cleared pending ops:', cleared);
-
-          // Refresh counts to update UI
-          await get().refreshCounts();
-
-          // Update last sync time
-          set({ lastSyncAt: new Date().toISOString() });
+          do {
+            drainRequested = false;
+            let afterSeq = 0;
+            let applied = 0;
+            let hasMore = true;
+            while (hasMore) {
+              const page = await invoke<PendingOpsPage>('get_pending_sync_ops', {
+                afterSeq,
+                limit: INBOX_PAGE_SIZE,
+              });
+              console.log('[SyncStore] Fetched pending ops:', page.ops.length);
+              if (page.lastSeq == null) break;
+
+              let detected = 0;
+              for (const { op, conflicts } of page.ops) {
+                console.log('[SyncStore] Applying op:', op.id, op.opType, op.entityType);
+                await applyOp(op);
+                detected += conflicts?.length ?? 0;
+              }
+              if (detected > 0) {
+                // Show the banner again; the ops engine queues the conflicts on apply
+                console.warn('[SyncStore] Backend detected conflicts:', detected);
+                get().resetConflictBanner();
+              }
+
+              const acked = await invoke<number>('ack_pending_sync_ops', {
+                upToSeq: page.lastSeq,
+              });
+              console.log('[SyncStore] Acknowledged pending ops:', acked);
+
+              applied += page.ops.length;
+              afterSeq = page.lastSeq;
+              hasMore = page.hasMore;
+            }
+
+            if (applied > 0) {
+              // Refresh counts to update UI
+              await get().refreshCounts();
+
+              // Update last sync time
+              set({ lastSyncAt: new Date().toISOString() });
+            }
+          } while (drainRequested);
         } catch (error) {
           console.error('[SyncStore] Failed to process sync operations:', error);
-          set({ lastError: String(error) });
+          set({ lastError: isSyncCommandError(error) ? error.message : String(error) });
+        } finally {
+          draining = false;
         }
+      };
+
+      await listen<DetectedConflict[]>('sync:conflicts_detected', (event) => {
+        console.warn('[SyncStore] Received sync:conflicts_detected event, count:', event.payload.length);
       });
 
+      await listen<number>('sync:ops_received', async (event) => {
+        console.log('[SyncStore] Received sync:ops_received event, count:', event.payload);
+        await drainInbox();
+      });
+
+      // Apply anything received but not acknowledged before the last shutdown
+      await drainInbox();
+
       console.log('[SyncStore] Tauri event listener registered for sync:ops_received');
     }
   },
@@ -0,0 +1,63 @@
+/**
+ * Sync Command Errors
+ *
+ * The Tauri sync commands reject with a structured error rather than a
+ * string: a stable `code`, `params` for interpolation, a `severity`, an
+ * optional recovery `action` and an English `message`. The UI translates the
+ * code under `syncErrors.*` and falls back to the message for codes it has no
+ * text for.
+ */
+
+export type SyncCommandErrorSeverity = 'warning' | 'error';
+
+export type SyncRecoveryAction =
+  | 'start_server'
+  | 'retry'
+  | 'retry_later'
+  | 'restart_pairing'
+  | 'check_passphrase'
+  | 'choose_folder';
+
+export interface SyncCommandError {
+  code: string;
+  params: Record<string, string | number | null>;
+  severity: SyncCommandErrorSeverity;
+  action: SyncRecoveryAction | null;
+  message: string;
+}
+
+type Translate = (key: string, vars?: Record<string, string | number>) => string;
+
+export function isSyncCommandError(error: unknown): error is SyncCommandError {
+  return (
+    typeof error === 'object' &&
+    error !== null &&
+    typeof (error as SyncCommandError).code === 'string' &&
+    typeof (error as SyncCommandError).message === 'string'
+  );
+}
+
+/**
+ * Localized text for an error thrown by a sync command
+ */
+export function describeSyncError(error: unknown, t: Translate): string {
+  if (!isSyncCommandError(error)) {
+    return error instanceof Error ? error.message : String(error);
+  }
+
+  const vars: Record<string, string | number> = {};
+  for (const [key, value] of Object.entries(error.params ?? {})) {
+    if (value !== null) vars[key] = value;
+  }
+
+  const keys =
+    error.code === 'pairing'
+      ? [`syncErrors.pairing.${vars.reason}`, 'syncErrors.pairing.default']
+      : [`syncErrors.${error.code}`];
+  for (const key of keys) {
+    // t() returns the key itself when there is no translation
+    const text = t(key, vars);
+    if (text !== key) return text;
+  }
+  return error.message;
+}